@@ -0,0 +1,46 @@
+//! This example loads a classic Lua C module, such as luasocket's `socket.core`, and uses it from
+//! both Lua and Rust.
+//!
+//! C modules call the Lua C API themselves, so they must use the same Lua library as rlua.  Build
+//! with the `system-lua` feature against a shared Lua 5.3, then run for example:
+//!
+//!     cargo run --example c_module --no-default-features --features system-lua -- \
+//!         /usr/lib/x86_64-linux-gnu/lua/5.3/socket/core.so socket.core
+
+use std::env;
+
+use rlua::{AnyUserData, Error, Function, Lua, Table};
+
+fn main() -> rlua::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <path to library> <module name>", args[0]);
+        return Ok(());
+    }
+    let (path, name) = (&args[1], &args[2]);
+
+    Lua::new().context(|lua| {
+        // After loading, `require` returns the same module, just as if the package searchers had
+        // found it.
+        unsafe { lua.load_c_module(path, name)? };
+        let module: Table = lua.load(&format!("return require '{}'", name)).eval()?;
+        for pair in module.clone().pairs::<String, rlua::Value>() {
+            let (key, value) = pair?;
+            println!("{}.{}: {:?}", name, key, value);
+        }
+
+        // Functions in C modules usually report failures as `nil, message`.
+        if let Ok(connect) = module.get::<_, Function>("connect") {
+            match connect.call_checked::<_, AnyUserData>(("localhost", 1)) {
+                Ok(socket) => {
+                    let ptr = socket.foreign_ptr("tcp{client}")?;
+                    println!("connected, socket object at {:?}", ptr);
+                }
+                Err(Error::RuntimeError(message)) => println!("connect failed: {}", message),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    })
+}
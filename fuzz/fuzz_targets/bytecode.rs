@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Run with `cargo fuzz run bytecode --features fuzz-bytecode`.
+fuzz_target!(|data: &[u8]| rlua::fuzz::fuzz_bytecode(data));
@@ -0,0 +1,630 @@
+//! Derive macros for implementing [`rlua::UserData`].
+//!
+//! `#[derive(UserData)]` implements `UserData` for a struct, exposing fields marked with
+//! `#[lua(getter)]` and / or `#[lua(setter)]` as Lua fields through the `__index` and
+//! `__newindex` metamethods.  Getters convert a clone of the field with `ToLua`, setters convert
+//! the assigned value with `FromLua`.
+//!
+//! Methods are exposed by putting `#[user_data_methods]` on an inherent `impl` block and marking
+//! the functions to expose with `#[lua(method)]`, then adding `#[lua(methods)]` to the struct.
+//! Functions taking `&self` become methods, functions taking `&mut self` become mutable methods,
+//! and functions without a receiver become plain functions.  A leading `Context` parameter is
+//! passed the calling context, the remaining parameters are converted with `FromLuaMulti`, and
+//! the return value is converted with `ToLuaMulti`.  A function returning a type named `Result`
+//! is assumed to return `rlua::Result`, and its errors are propagated to Lua.
+//!
+//! Methods working with Lua values should name their lifetime `'lua`, as in
+//! `fn get<'lua>(&self, lua: Context<'lua>, key: Value<'lua>) -> Result<Value<'lua>>`.
+//!
+//! Both fields and methods may be renamed on the Lua side with `#[lua(name = "...")]`.
+//!
+//! ```
+//! use rlua::{Lua, Result};
+//! use rlua_derive::{user_data_methods, UserData};
+//!
+//! #[derive(UserData)]
+//! #[lua(methods)]
+//! struct Player {
+//!     #[lua(getter)]
+//!     name: String,
+//!     #[lua(getter, setter)]
+//!     health: i64,
+//! }
+//!
+//! #[user_data_methods]
+//! impl Player {
+//!     #[lua(method)]
+//!     fn heal(&mut self, amount: i64) {
+//!         self.health += amount;
+//!     }
+//!
+//!     #[lua(method, name = "is_alive")]
+//!     fn alive(&self) -> bool {
+//!         self.health > 0
+//!     }
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! Lua::new().context(|lua| {
+//!     let player = Player {
+//!         name: "bob".to_owned(),
+//!         health: 10,
+//!     };
+//!     lua.globals().set("player", player)?;
+//!     lua.load(
+//!         r#"
+//!             player:heal(5)
+//!             player.health = player.health - 20
+//!             assert(player.name == "bob" and not player:is_alive())
+//!         "#,
+//!     )
+//!     .exec()
+//! })
+//! # }
+//! ```
+//!
+//! `#[user_data_trait]` does the same for the methods of a trait, making `Box<dyn Trait>` and
+//! `Arc<dyn Trait>` userdata which dispatch to whichever type implements the trait.
+//!
+//! `#[lua_module]` turns a function creating a table into the entry point of a native module
+//! which stock Lua can load with `require`.
+//!
+//! [`rlua::UserData`]: https://docs.rs/rlua/*/rlua/trait.UserData.html
+
+#![recursion_limit = "128"]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, FnArg, GenericParam, Ident,
+    ImplItem, ItemFn, ItemImpl, ItemTrait, LitStr, Pat, ReturnType, TraitItem, Type,
+};
+
+/// Implements `rlua::UserData` for a struct.
+///
+/// See the [crate level documentation](index.html) for the supported attributes.
+#[proc_macro_derive(UserData, attributes(lua))]
+pub fn derive_user_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_derive(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Collects the functions of an inherent `impl` block marked with `#[lua(method)]`, so that they
+/// can be registered by a `#[derive(UserData)]` carrying `#[lua(methods)]`.
+#[proc_macro_attribute]
+pub fn user_data_methods(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "user_data_methods does not take arguments",
+        )
+        .into_compile_error()
+        .into();
+    }
+    let input = parse_macro_input!(input as ItemImpl);
+    expand_methods(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implements `rlua::UserDataTrait` for the trait objects of a trait, exposing the trait methods
+/// marked with `#[lua(method)]`.
+///
+/// This makes `Box<dyn Trait>` and `Arc<dyn Trait>` userdata, along with their `+ Send` and
+/// `+ Send + Sync` variants, whose methods dispatch dynamically.  Methods are declared like with
+/// `#[user_data_methods]`, except that they must take `&self` or `&mut self`, and methods taking
+/// `&mut self` are only available on `Box`.  The trait cannot have type or lifetime parameters.
+///
+/// ```
+/// use rlua::{Lua, Result};
+/// use rlua_derive::user_data_trait;
+/// use std::sync::Arc;
+///
+/// #[user_data_trait]
+/// trait Shape {
+///     #[lua(method)]
+///     fn area(&self) -> f64;
+///
+///     #[lua(method, name = "scale")]
+///     fn scale_by(&mut self, factor: f64);
+/// }
+///
+/// struct Square(f64);
+///
+/// impl Shape for Square {
+///     fn area(&self) -> f64 {
+///         self.0 * self.0
+///     }
+///
+///     fn scale_by(&mut self, factor: f64) {
+///         self.0 *= factor;
+///     }
+/// }
+///
+/// # fn main() -> Result<()> {
+/// Lua::new().context(|lua| {
+///     let boxed: Box<dyn Shape + Send> = Box::new(Square(2.0));
+///     let shared: Arc<dyn Shape + Send + Sync> = Arc::new(Square(3.0));
+///     lua.globals().set("boxed", boxed)?;
+///     lua.globals().set("shared", shared)?;
+///     lua.load(
+///         r#"
+///             boxed:scale(2)
+///             assert(boxed:area() == 16 and shared:area() == 9)
+///         "#,
+///     )
+///     .exec()
+/// })
+/// # }
+/// ```
+#[proc_macro_attribute]
+pub fn user_data_trait(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new(Span::call_site(), "user_data_trait does not take arguments")
+            .into_compile_error()
+            .into();
+    }
+    let input = parse_macro_input!(input as ItemTrait);
+    expand_trait(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Turns a function creating a module table into a native Lua module, by generating the
+/// `luaopen_` function which `require` looks for in shared libraries.
+///
+/// The function must have the signature `fn(Context<'lua>) -> rlua::Result<Table<'lua>>`.  The
+/// module is named after the function, or after `name` as in `#[lua_module(name = "game.util")]`,
+/// where dots become underscores in the exported symbol as `require` expects.  The crate has to be
+/// built with `crate-type = ["cdylib"]`, and must link to the same Lua library as the host, which
+/// generally means using the `system-lua` feature.
+///
+/// The first module built with rlua which a state loads sets up rlua in the state as with
+/// `Lua::init_from_ptr`, and rlua's data then lives until the state is closed.  Loading a module
+/// into a state set up by a different copy of rlua, such as one from another module or the host,
+/// raises a Lua error.
+///
+/// ```
+/// use rlua::{Context, Result, Table};
+/// use rlua_derive::lua_module;
+///
+/// #[lua_module]
+/// fn greeter(lua: Context) -> Result<Table> {
+///     let module = lua.create_table()?;
+///     module.set(
+///         "greet",
+///         lua.create_function(|_, name: String| Ok(format!("hello, {}", name)))?,
+///     )?;
+///     Ok(module)
+/// }
+/// ```
+///
+/// After which `require("greeter").greet("world")` works from Lua when the library is on
+/// `package.cpath`.
+#[proc_macro_attribute]
+pub fn lua_module(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut name: Option<LitStr> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported lua_module attribute"))
+        }
+    });
+    parse_macro_input!(args with parser);
+    let input = parse_macro_input!(input as ItemFn);
+    expand_module(name, input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct LuaAttrs {
+    getter: bool,
+    setter: bool,
+    method: bool,
+    methods: bool,
+    name: Option<LitStr>,
+}
+
+// Parses every `#[lua(...)]` attribute in `attrs`, rejecting any flag not in `allowed`.
+fn parse_lua_attrs(attrs: &[Attribute], allowed: &[&str]) -> syn::Result<LuaAttrs> {
+    let mut parsed = LuaAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("lua")) {
+        attr.parse_nested_meta(|meta| {
+            let flag = match meta.path.get_ident() {
+                Some(ident) if allowed.iter().any(|allowed| ident == allowed) => ident.to_string(),
+                _ => return Err(meta.error("unsupported lua attribute here")),
+            };
+            match flag.as_str() {
+                "getter" => parsed.getter = true,
+                "setter" => parsed.setter = true,
+                "method" => parsed.method = true,
+                "methods" => parsed.methods = true,
+                "name" => parsed.name = Some(meta.value()?.parse()?),
+                _ => unreachable!(),
+            }
+            Ok(())
+        })?;
+    }
+    Ok(parsed)
+}
+
+fn expand_derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = parse_lua_attrs(&input.attrs, &["methods"])?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "UserData can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut getters = Vec::new();
+    let mut setters = Vec::new();
+    for field in fields {
+        let field_attrs = parse_lua_attrs(&field.attrs, &["getter", "setter", "name"])?;
+        if !field_attrs.getter && !field_attrs.setter {
+            continue;
+        }
+        let ident = match (&field.ident, &fields) {
+            (Some(ident), Fields::Named(_)) => ident,
+            _ => {
+                return Err(Error::new(
+                    field.span(),
+                    "lua getters and setters require a named field",
+                ))
+            }
+        };
+        let name = field_attrs
+            .name
+            .unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span()));
+        let name = syn::LitByteStr::new(name.value().as_bytes(), name.span());
+        if field_attrs.getter {
+            getters.push((name.clone(), ident));
+        }
+        if field_attrs.setter {
+            setters.push((name, ident));
+        }
+    }
+
+    let ty = &input.ident;
+    let type_name = ty.to_string();
+
+    let index = if getters.is_empty() {
+        quote!()
+    } else {
+        let names = getters.iter().map(|(name, _)| name);
+        let idents = getters.iter().map(|(_, ident)| ident);
+        quote! {
+            methods.add_meta_method(
+                ::rlua::MetaMethod::Index,
+                |lua, this, key: ::rlua::Value<'lua>| {
+                    if let ::rlua::Value::String(key) = &key {
+                        match key.as_bytes() {
+                            #(#names => {
+                                return ::rlua::ToLua::to_lua(
+                                    ::std::clone::Clone::clone(&this.#idents),
+                                    lua,
+                                );
+                            })*
+                            _ => {}
+                        }
+                    }
+                    ::std::result::Result::Ok(::rlua::Value::Nil)
+                },
+            );
+        }
+    };
+
+    let new_index = if setters.is_empty() {
+        quote!()
+    } else {
+        let names = setters.iter().map(|(name, _)| name);
+        let idents = setters.iter().map(|(_, ident)| ident);
+        quote! {
+            methods.add_meta_method_mut(
+                ::rlua::MetaMethod::NewIndex,
+                |lua, this, (key, value): (::rlua::String<'lua>, ::rlua::Value<'lua>)| {
+                    match key.as_bytes() {
+                        #(#names => this.#idents = ::rlua::FromLua::from_lua(value, lua)?,)*
+                        key => {
+                            return ::std::result::Result::Err(::rlua::Error::RuntimeError(
+                                ::std::format!(
+                                    "cannot set field '{}' of {}",
+                                    ::std::string::String::from_utf8_lossy(key),
+                                    #type_name,
+                                ),
+                            ));
+                        }
+                    }
+                    ::std::result::Result::Ok(())
+                },
+            );
+        }
+    };
+
+    let methods = if attrs.methods {
+        quote!(Self::__rlua_user_data_methods(methods);)
+    } else {
+        quote!()
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rlua::UserData for #ty #ty_generics #where_clause {
+            fn add_methods<'lua, M: ::rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+                #index
+                #new_index
+                #methods
+            }
+        }
+    })
+}
+
+fn expand_methods(mut input: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &input.trait_ {
+        return Err(Error::new(
+            path.span(),
+            "user_data_methods must be used on an inherent impl block",
+        ));
+    }
+
+    let mut registrations = Vec::new();
+    for item in &mut input.items {
+        let method = match item {
+            ImplItem::Fn(method) => method,
+            _ => continue,
+        };
+        let attrs = parse_lua_attrs(&method.attrs, &["method", "name"])?;
+        method.attrs.retain(|attr| !attr.path().is_ident("lua"));
+        if !attrs.method {
+            if attrs.name.is_some() {
+                return Err(Error::new(
+                    method.sig.ident.span(),
+                    "lua(name) requires lua(method)",
+                ));
+            }
+            continue;
+        }
+        registrations.push(register_method(&method.sig, attrs.name)?);
+    }
+
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    let self_ty = &input.self_ty;
+    Ok(quote! {
+        #input
+
+        impl #impl_generics #self_ty #where_clause {
+            #[doc(hidden)]
+            pub fn __rlua_user_data_methods<'lua, M: ::rlua::UserDataMethods<'lua, Self>>(
+                methods: &mut M,
+            ) {
+                #(#registrations)*
+            }
+        }
+    })
+}
+
+fn expand_trait(mut input: ItemTrait) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "user_data_trait cannot be used on a generic trait",
+        ));
+    }
+
+    let mut boxed = Vec::new();
+    let mut shared = Vec::new();
+    for item in &mut input.items {
+        let method = match item {
+            TraitItem::Fn(method) => method,
+            _ => continue,
+        };
+        let attrs = parse_lua_attrs(&method.attrs, &["method", "name"])?;
+        method.attrs.retain(|attr| !attr.path().is_ident("lua"));
+        if !attrs.method {
+            if attrs.name.is_some() {
+                return Err(Error::new(
+                    method.sig.ident.span(),
+                    "lua(name) requires lua(method)",
+                ));
+            }
+            continue;
+        }
+        let mutable = match method.sig.inputs.first() {
+            Some(FnArg::Receiver(receiver)) => receiver.mutability.is_some(),
+            _ => {
+                return Err(Error::new(
+                    method.sig.span(),
+                    "lua methods of a trait must take `&self` or `&mut self`",
+                ))
+            }
+        };
+        let registration = register_method(&method.sig, attrs.name)?;
+        if !mutable {
+            shared.push(registration.clone());
+        }
+        boxed.push(registration);
+    }
+
+    let add_boxed_methods = if boxed.is_empty() {
+        quote!()
+    } else {
+        quote! {
+            fn add_boxed_methods<'lua, M>(methods: &mut M)
+            where
+                M: ::rlua::UserDataMethods<'lua, ::std::boxed::Box<Self>>,
+            {
+                #(#boxed)*
+            }
+        }
+    };
+    let add_shared_methods = if shared.is_empty() {
+        quote!()
+    } else {
+        quote! {
+            fn add_shared_methods<'lua, M>(methods: &mut M)
+            where
+                M: ::rlua::UserDataMethods<'lua, ::std::sync::Arc<Self>>,
+            {
+                #(#shared)*
+            }
+        }
+    };
+
+    let ident = &input.ident;
+    let impls = [
+        quote!(dyn #ident),
+        quote!(dyn #ident + ::std::marker::Send),
+        quote!(dyn #ident + ::std::marker::Send + ::std::marker::Sync),
+    ]
+    .iter()
+    .map(|object| {
+        quote! {
+            impl ::rlua::UserDataTrait for #object {
+                #add_boxed_methods
+                #add_shared_methods
+            }
+        }
+    })
+    .collect::<Vec<_>>();
+    Ok(quote! {
+        #input
+
+        #(#impls)*
+    })
+}
+
+fn register_method(sig: &syn::Signature, name: Option<LitStr>) -> syn::Result<TokenStream2> {
+    // Lifetime parameters are allowed so that `'lua` can tie Lua values in the arguments and
+    // return type to the `Context` they belong to.
+    let generic = sig
+        .generics
+        .params
+        .iter()
+        .any(|param| !matches!(param, GenericParam::Lifetime(_)));
+    if generic || sig.asyncness.is_some() {
+        return Err(Error::new(
+            sig.span(),
+            "lua methods cannot have type parameters or be async",
+        ));
+    }
+
+    let ident = &sig.ident;
+    let name = name.unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span()));
+
+    let mut inputs = sig.inputs.iter().peekable();
+    let receiver = match inputs.peek() {
+        Some(FnArg::Receiver(receiver)) => {
+            if receiver.reference.is_none() {
+                return Err(Error::new(
+                    receiver.span(),
+                    "lua methods must take `&self` or `&mut self`",
+                ));
+            }
+            let mutable = receiver.mutability.is_some();
+            inputs.next();
+            Some(mutable)
+        }
+        _ => None,
+    };
+
+    let mut call_args = Vec::new();
+    let mut arg_idents = Vec::new();
+    let mut arg_types = Vec::new();
+    let mut uses_context = false;
+    for (i, input) in inputs.enumerate() {
+        let input = match input {
+            FnArg::Typed(input) => input,
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new(receiver.span(), "unexpected receiver"))
+            }
+        };
+        if i == 0 && is_named(&input.ty, "Context") {
+            uses_context = true;
+            call_args.push(quote!(lua));
+            continue;
+        }
+        let arg = match &*input.pat {
+            Pat::Ident(pat) => Ident::new(&format!("__{}", pat.ident), Span::call_site()),
+            _ => Ident::new(&format!("__arg{}", i), Span::call_site()),
+        };
+        call_args.push(quote!(#arg));
+        arg_idents.push(arg);
+        arg_types.push(&input.ty);
+    }
+
+    let lua = if uses_context { quote!(lua) } else { quote!(_) };
+    let args = quote!((#(#arg_idents,)*): (#(#arg_types,)*));
+
+    let call = match receiver {
+        Some(_) => quote!(this.#ident(#(#call_args),*)),
+        None => quote!(Self::#ident(#(#call_args),*)),
+    };
+    let body = match &sig.output {
+        ReturnType::Type(_, ty) if is_named(ty, "Result") => call,
+        ReturnType::Type(..) => quote!(::std::result::Result::Ok(#call)),
+        ReturnType::Default => quote!({
+            #call;
+            ::std::result::Result::Ok(())
+        }),
+    };
+
+    Ok(match receiver {
+        Some(false) => quote!(methods.add_method(#name, |#lua, this, #args| #body);),
+        Some(true) => quote!(methods.add_method_mut(#name, |#lua, this, #args| #body);),
+        None => quote!(methods.add_function(#name, |#lua, #args| #body);),
+    })
+}
+
+// Whether `ty` is a path whose last segment is `name`, ignoring any generic arguments.
+fn expand_module(name: Option<LitStr>, input: ItemFn) -> syn::Result<TokenStream2> {
+    let ident = &input.sig.ident;
+    let (name, span) = match &name {
+        Some(name) => (name.value(), name.span()),
+        None => (ident.to_string(), ident.span()),
+    };
+    let symbol = format!("luaopen_{}", name.replace('.', "_"));
+    if name.is_empty()
+        || !symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(Error::new(span, "invalid Lua module name"));
+    }
+    let symbol = Ident::new(&symbol, span);
+
+    Ok(quote! {
+        #input
+
+        #[doc(hidden)]
+        #[no_mangle]
+        pub unsafe extern "C" fn #symbol(
+            state: *mut ::rlua::lua_State,
+        ) -> ::std::os::raw::c_int {
+            ::rlua::open_module(state, #ident)
+        }
+    })
+}
+
+fn is_named(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(ty) => ty
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == name),
+        _ => false,
+    }
+}
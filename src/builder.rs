@@ -0,0 +1,300 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::hook::{Debug, HookTriggers};
+use crate::lua::{
+    ConversionOptions, DurationFormat, Lua, OomBehavior, StdLib, DEFAULT_CONVERSION_DEPTH_LIMIT,
+};
+use crate::package::PackagePolicy;
+use crate::table::Table;
+
+type HookCallback = dyn Fn(Context, Debug) -> Result<()> + Send + Sync;
+
+/// Creates Lua states which are all set up the same way.
+///
+/// The builder gathers the options which are otherwise set one by one on a new [`Lua`]: the
+/// standard libraries to load, the memory and conversion limits, the [`OomBehavior`] and a hook.
+/// They are checked when a state is built, so a state is never handed out half configured.
+///
+/// Besides these options, the builder collects preludes:
+/// Lua chunks such as host helpers, compatibility shims or strict mode checks which are run in
+/// every state it builds.  Preludes run in order of priority, lowest first, and preludes with the
+/// same priority run in the order they were added.  A state is only returned once all preludes
+/// ran successfully, so states built from the same builder cannot drift apart in what got
+/// initialized.
+///
+/// Preludes can also be run in a separate environment table with [`run_preludes`], such as the
+/// environment of a sandboxed script.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{LuaBuilder, Result, StdLib};
+/// # fn main() -> Result<()> {
+/// let builder = LuaBuilder::new()
+///     .std_lib(StdLib::BASE | StdLib::STRING)
+///     .prelude("function greet(name) return greeting .. ', ' .. name end")
+///     .prelude_with_priority(-1, "greeting = 'hello'");
+///
+/// for _ in 0..2 {
+///     let lua = builder.build()?;
+///     lua.context(|lua_context| {
+///         let greeting = lua_context.load("greet('world')").eval::<String>()?;
+///         assert_eq!(greeting, "hello, world");
+///         Ok(())
+///     })?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Lua`]: struct.Lua.html
+/// [`OomBehavior`]: enum.OomBehavior.html
+/// [`run_preludes`]: #method.run_preludes
+#[derive(Clone)]
+pub struct LuaBuilder {
+    std_lib: StdLib,
+    memory_limit: Option<usize>,
+    string_length_limit: Option<usize>,
+    table_size_limit: Option<usize>,
+    conversion_depth_limit: Option<usize>,
+    duration_format: DurationFormat,
+    conversion_options: ConversionOptions,
+    oom_behavior: OomBehavior,
+    package_policy: Option<PackagePolicy>,
+    hook: Option<(HookTriggers, Arc<HookCallback>)>,
+    preludes: Vec<Prelude>,
+}
+
+#[derive(Clone, Debug)]
+struct Prelude {
+    priority: i32,
+    source: Vec<u8>,
+}
+
+impl LuaBuilder {
+    /// Returns a builder for states with the standard libraries except `debug`, like
+    /// [`Lua::new`], and no preludes.
+    ///
+    /// [`Lua::new`]: struct.Lua.html#method.new
+    pub fn new() -> LuaBuilder {
+        LuaBuilder {
+            std_lib: StdLib::ALL_NO_DEBUG,
+            memory_limit: None,
+            string_length_limit: None,
+            table_size_limit: None,
+            conversion_depth_limit: Some(DEFAULT_CONVERSION_DEPTH_LIMIT),
+            duration_format: DurationFormat::Seconds,
+            conversion_options: ConversionOptions::default(),
+            oom_behavior: OomBehavior::Catchable,
+            package_policy: None,
+            hook: None,
+            preludes: Vec::new(),
+        }
+    }
+
+    /// Sets the standard libraries loaded into built states.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `std_lib` contains `StdLib::DEBUG`, as with [`Lua::new_with`].
+    ///
+    /// [`Lua::new_with`]: struct.Lua.html#method.new_with
+    pub fn std_lib(mut self, std_lib: StdLib) -> LuaBuilder {
+        assert!(
+            !std_lib.contains(StdLib::DEBUG),
+            "The lua debug module can't be loaded using `LuaBuilder`."
+        );
+        self.std_lib = std_lib;
+        self
+    }
+
+    /// Sets the standard libraries loaded into built states, which may include the `debug`
+    /// library.
+    ///
+    /// # Safety
+    ///
+    /// The debug library can be used to break the safety guarantees of rlua, as with
+    /// [`Lua::unsafe_new_with`].
+    ///
+    /// [`Lua::unsafe_new_with`]: struct.Lua.html#method.unsafe_new_with
+    pub unsafe fn unsafe_std_lib(mut self, std_lib: StdLib) -> LuaBuilder {
+        self.std_lib = std_lib;
+        self
+    }
+
+    /// Sets the memory limit of built states, see [`Lua::set_memory_limit`].  The limit is set
+    /// before the preludes run, so it applies to them too.
+    ///
+    /// Building fails with `Error::MemoryError` if the standard libraries alone already use more
+    /// memory than the limit allows.
+    ///
+    /// [`Lua::set_memory_limit`]: struct.Lua.html#method.set_memory_limit
+    pub fn memory_limit(mut self, memory_limit: Option<usize>) -> LuaBuilder {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// Sets the string length limit of built states, see [`Lua::set_string_length_limit`].
+    ///
+    /// [`Lua::set_string_length_limit`]: struct.Lua.html#method.set_string_length_limit
+    pub fn string_length_limit(mut self, limit: Option<usize>) -> LuaBuilder {
+        self.string_length_limit = limit;
+        self
+    }
+
+    /// Sets the table size limit of built states, see [`Lua::set_table_size_limit`].
+    ///
+    /// [`Lua::set_table_size_limit`]: struct.Lua.html#method.set_table_size_limit
+    pub fn table_size_limit(mut self, limit: Option<usize>) -> LuaBuilder {
+        self.table_size_limit = limit;
+        self
+    }
+
+    /// Sets the conversion depth limit of built states, see [`Lua::set_conversion_depth_limit`].
+    ///
+    /// [`Lua::set_conversion_depth_limit`]: struct.Lua.html#method.set_conversion_depth_limit
+    pub fn conversion_depth_limit(mut self, limit: Option<usize>) -> LuaBuilder {
+        self.conversion_depth_limit = limit;
+        self
+    }
+
+    /// Sets how durations are converted in built states, see [`Lua::set_duration_format`].
+    ///
+    /// [`Lua::set_duration_format`]: struct.Lua.html#method.set_duration_format
+    pub fn duration_format(mut self, format: DurationFormat) -> LuaBuilder {
+        self.duration_format = format;
+        self
+    }
+
+    /// Sets how `Option` values and missing arguments are converted in built states, see
+    /// [`Lua::set_conversion_options`].
+    ///
+    /// [`Lua::set_conversion_options`]: struct.Lua.html#method.set_conversion_options
+    pub fn conversion_options(mut self, options: ConversionOptions) -> LuaBuilder {
+        self.conversion_options = options;
+        self
+    }
+
+    /// Sets whether scripts in built states may catch memory errors, see
+    /// [`Lua::set_oom_behavior`].
+    ///
+    /// [`Lua::set_oom_behavior`]: struct.Lua.html#method.set_oom_behavior
+    pub fn oom_behavior(mut self, behavior: OomBehavior) -> LuaBuilder {
+        self.oom_behavior = behavior;
+        self
+    }
+
+    /// Sets how `require` finds modules in built states, see [`Lua::set_package_policy`].  The
+    /// policy is applied before the preludes run.
+    ///
+    /// Building fails if the `package` library is not among the loaded standard libraries.
+    ///
+    /// [`Lua::set_package_policy`]: struct.Lua.html#method.set_package_policy
+    pub fn package_policy(mut self, policy: PackagePolicy) -> LuaBuilder {
+        self.package_policy = Some(policy);
+        self
+    }
+
+    /// Sets a hook which is installed in every built state, see [`Lua::set_hook`].
+    ///
+    /// All states share the same callback, so unlike with `Lua::set_hook` it must be `Fn` and
+    /// `Sync`.  The hook is installed before the preludes run.
+    ///
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    pub fn hook<F>(mut self, triggers: HookTriggers, callback: F) -> LuaBuilder
+    where
+        F: 'static + Send + Sync + Fn(Context, Debug) -> Result<()>,
+    {
+        self.hook = Some((triggers, Arc::new(callback)));
+        self
+    }
+
+    /// Adds a prelude with priority 0.
+    pub fn prelude<S: AsRef<[u8]>>(self, source: S) -> LuaBuilder {
+        self.prelude_with_priority(0, source)
+    }
+
+    /// Adds a prelude which runs before all preludes with a higher priority, and after those with a
+    /// lower one.
+    ///
+    /// Preludes are named `prelude #1`, `prelude #2` and so on in error messages, numbered in the
+    /// order they were added.
+    pub fn prelude_with_priority<S: AsRef<[u8]>>(mut self, priority: i32, source: S) -> LuaBuilder {
+        self.preludes.push(Prelude {
+            priority,
+            source: source.as_ref().to_vec(),
+        });
+        self
+    }
+
+    /// Creates a new Lua state with the configured options and runs the preludes in it.
+    pub fn build(&self) -> Result<Lua> {
+        // The debug library can only have been requested through `unsafe_std_lib`.
+        let lua = unsafe { Lua::unsafe_new_with(self.std_lib) };
+        if let Some(limit) = self.memory_limit {
+            if lua.used_memory() > limit {
+                return Err(Error::MemoryError(format!(
+                    "memory limit of {} bytes is below the {} bytes used by a new state",
+                    limit,
+                    lua.used_memory()
+                )));
+            }
+        }
+        lua.set_memory_limit(self.memory_limit);
+        lua.set_string_length_limit(self.string_length_limit);
+        lua.set_table_size_limit(self.table_size_limit);
+        lua.set_conversion_depth_limit(self.conversion_depth_limit);
+        lua.set_duration_format(self.duration_format);
+        lua.set_conversion_options(self.conversion_options);
+        lua.set_oom_behavior(self.oom_behavior);
+        if let Some(policy) = &self.package_policy {
+            lua.set_package_policy(policy.clone())?;
+        }
+        if let Some((triggers, callback)) = &self.hook {
+            let callback = callback.clone();
+            lua.set_hook(*triggers, move |lua, debug| callback(lua, debug));
+        }
+        lua.context(|lua_context| self.run_preludes(lua_context, lua_context.globals()))?;
+        Ok(lua)
+    }
+
+    /// Runs the preludes with `env` as their environment, stopping at the first one which fails.
+    pub fn run_preludes<'lua>(&self, lua: Context<'lua>, env: Table<'lua>) -> Result<()> {
+        let mut order: Vec<usize> = (0..self.preludes.len()).collect();
+        order.sort_by_key(|&i| self.preludes[i].priority);
+        for i in order {
+            lua.load(&self.preludes[i].source)
+                .set_name(&format!("=prelude #{}", i + 1))?
+                .set_environment(env.clone())?
+                .exec()?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LuaBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LuaBuilder")
+            .field("std_lib", &self.std_lib)
+            .field("memory_limit", &self.memory_limit)
+            .field("string_length_limit", &self.string_length_limit)
+            .field("table_size_limit", &self.table_size_limit)
+            .field("conversion_depth_limit", &self.conversion_depth_limit)
+            .field("duration_format", &self.duration_format)
+            .field("conversion_options", &self.conversion_options)
+            .field("oom_behavior", &self.oom_behavior)
+            .field("package_policy", &self.package_policy)
+            .field("hook", &self.hook.as_ref().map(|(triggers, _)| triggers))
+            .field("preludes", &self.preludes)
+            .finish()
+    }
+}
+
+impl Default for LuaBuilder {
+    fn default() -> LuaBuilder {
+        LuaBuilder::new()
+    }
+}
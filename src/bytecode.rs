@@ -0,0 +1,216 @@
+use std::fmt;
+use std::mem;
+use std::os::raw::c_int;
+
+use crate::error::{Error, Result};
+use crate::ffi;
+
+const SIGNATURE: &[u8] = b"\x1bLua";
+// The check bytes after the version and format of Lua 5.3 and later, which catch chunks mangled by
+// text-mode conversions.
+const DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const CHECK_INTEGER: u64 = 0x5678;
+const CHECK_NUMBER: f64 = 370.5;
+
+/// The byte order of the numbers in a binary chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endianness {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl Endianness {
+    fn native() -> Endianness {
+        if cfg!(target_endian = "little") {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        }
+    }
+}
+
+/// The sizes of the C types and the byte order which a binary chunk was compiled with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BytecodeLayout {
+    /// The size of a C `int`.
+    pub int_size: u8,
+    /// The size of a C `size_t`.
+    pub size_t_size: u8,
+    /// The size of a virtual machine instruction.
+    pub instruction_size: u8,
+    /// The size of a Lua integer.
+    pub integer_size: u8,
+    /// The size of a Lua float.
+    pub number_size: u8,
+    /// The byte order, or `None` if the check integer and float in the header decode in neither
+    /// byte order, as when the floats are not in the IEEE 754 format.
+    pub endianness: Option<Endianness>,
+}
+
+/// What a binary chunk was compiled for, as recorded in its header.
+///
+/// [`Context::load_bytecode`] compares the header of a chunk with the one `native` returns, and
+/// rejects chunks compiled for another version or configuration of Lua with an
+/// [`Error::IncompatibleBytecode`] holding both.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{BytecodeHeader, Lua, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let function = lua_context.load("return 1").into_function()?;
+/// let bytecode = function.dump(true)?;
+/// assert_eq!(BytecodeHeader::read(&bytecode), Some(BytecodeHeader::native()));
+/// assert_eq!(BytecodeHeader::read(b"return 1"), None);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`Context::load_bytecode`]: struct.Context.html#method.load_bytecode
+/// [`Error::IncompatibleBytecode`]: enum.Error.html#variant.IncompatibleBytecode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BytecodeHeader {
+    /// The major and minor Lua version, such as `(5, 3)`.
+    pub version: (u8, u8),
+    /// The format of the chunk, 0 for the official one.
+    pub format: u8,
+    /// The layout of the chunk, or `None` if the header is truncated or laid out differently, as
+    /// it is before Lua 5.3.
+    pub layout: Option<BytecodeLayout>,
+}
+
+impl BytecodeHeader {
+    /// Returns the header of the chunks which the Lua used by rlua compiles and loads.
+    pub fn native() -> BytecodeHeader {
+        BytecodeHeader {
+            version: (5, 3),
+            format: 0,
+            layout: Some(BytecodeLayout {
+                int_size: mem::size_of::<c_int>() as u8,
+                size_t_size: mem::size_of::<usize>() as u8,
+                instruction_size: 4,
+                integer_size: mem::size_of::<ffi::lua_Integer>() as u8,
+                number_size: mem::size_of::<ffi::lua_Number>() as u8,
+                endianness: Some(Endianness::native()),
+            }),
+        }
+    }
+
+    /// Reads the header of a binary chunk, returning `None` if `bytecode` is not one.
+    pub fn read(bytecode: &[u8]) -> Option<BytecodeHeader> {
+        if bytecode.len() < SIGNATURE.len() + 2 || !bytecode.starts_with(SIGNATURE) {
+            return None;
+        }
+        let version = bytecode[4];
+        Some(BytecodeHeader {
+            version: (version >> 4, version & 0xf),
+            format: bytecode[5],
+            layout: if version >= 0x53 {
+                read_layout(&bytecode[6..])
+            } else {
+                None
+            },
+        })
+    }
+}
+
+// Reads the Lua 5.3 header from after the version and format.
+fn read_layout(header: &[u8]) -> Option<BytecodeLayout> {
+    let rest = header.strip_prefix(DATA)?;
+    if rest.len() < 5 {
+        return None;
+    }
+    let (sizes, rest) = rest.split_at(5);
+    let (integer_size, number_size) = (sizes[3] as usize, sizes[4] as usize);
+    if rest.len() < integer_size + number_size {
+        return None;
+    }
+    let (integer, number) = rest.split_at(integer_size);
+    let number = &number[..number_size];
+    let endianness = [Endianness::Little, Endianness::Big]
+        .iter()
+        .cloned()
+        .find(|&endianness| {
+            integer == encode_integer(CHECK_INTEGER, integer_size, endianness).as_slice()
+                && encode_number(number_size, endianness)
+                    .is_some_and(|check| number == check.as_slice())
+        });
+    Some(BytecodeLayout {
+        int_size: sizes[0],
+        size_t_size: sizes[1],
+        instruction_size: sizes[2],
+        integer_size: sizes[3],
+        number_size: sizes[4],
+        endianness,
+    })
+}
+
+fn encode_integer(value: u64, size: usize, endianness: Endianness) -> Vec<u8> {
+    let mut bytes = value.to_le_bytes().to_vec();
+    bytes.resize(size, 0);
+    if endianness == Endianness::Big {
+        bytes.reverse();
+    }
+    bytes
+}
+
+fn encode_number(size: usize, endianness: Endianness) -> Option<Vec<u8>> {
+    let mut bytes = match size {
+        4 => (CHECK_NUMBER as f32).to_le_bytes().to_vec(),
+        8 => CHECK_NUMBER.to_le_bytes().to_vec(),
+        _ => return None,
+    };
+    if endianness == Endianness::Big {
+        bytes.reverse();
+    }
+    Some(bytes)
+}
+
+// Rejects binary chunks compiled for another Lua.  Chunks with a malformed header which still
+// claim to be for this version are left for Lua to reject.
+pub(crate) fn check_header(bytecode: &[u8]) -> Result<()> {
+    let found = match BytecodeHeader::read(bytecode) {
+        Some(found) => found,
+        None => return Ok(()),
+    };
+    let expected = BytecodeHeader::native();
+    let compatible = found.version == expected.version
+        && found.format == expected.format
+        && (found.layout.is_none() || found.layout == expected.layout);
+    if compatible {
+        Ok(())
+    } else {
+        Err(Error::IncompatibleBytecode { found, expected })
+    }
+}
+
+impl fmt::Display for BytecodeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Lua {}.{}", self.version.0, self.version.1)?;
+        if self.format != 0 {
+            write!(f, " (format {})", self.format)?;
+        }
+        if let Some(layout) = self.layout {
+            write!(
+                f,
+                " with {}-byte ints, {}-byte size_t, {}-byte instructions, {}-byte integers, \
+                 {}-byte floats, ",
+                layout.int_size,
+                layout.size_t_size,
+                layout.instruction_size,
+                layout.integer_size,
+                layout.number_size
+            )?;
+            match layout.endianness {
+                Some(Endianness::Little) => write!(f, "little-endian")?,
+                Some(Endianness::Big) => write!(f, "big-endian")?,
+                None => write!(f, "unknown number format")?,
+            }
+        }
+        Ok(())
+    }
+}
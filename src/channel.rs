@@ -0,0 +1,365 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::sync::Mutex;
+use crate::table::Table;
+use crate::types::{Integer, RegistryKey};
+use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::value::{MultiValue, ToLuaMulti, Value};
+
+const CHANNEL_METHODS_KEY: &str = "rlua.channel.methods";
+
+// The state of a channel, shared by its userdata and the operations waiting on it.  Values are kept
+// in the registry, so they stay the very same Lua values when received.
+struct Shared {
+    queue: VecDeque<RegistryKey>,
+    capacity: Option<usize>,
+    closed: bool,
+    senders: Vec<Waker>,
+    receivers: Vec<Waker>,
+}
+
+impl Shared {
+    // Queues `value`, handing it back if the channel is full.
+    fn try_send(&mut self, value: RegistryKey) -> Result<Option<RegistryKey>> {
+        if self.closed {
+            return Err(Error::RuntimeError(
+                "attempt to send on a closed channel".to_owned(),
+            ));
+        }
+        if self
+            .capacity
+            .is_some_and(|capacity| self.queue.len() >= capacity)
+        {
+            return Ok(Some(value));
+        }
+        self.queue.push_back(value);
+        wake_all(&mut self.receivers);
+        Ok(None)
+    }
+
+    // Takes the oldest value, returning `None` if there is none yet, or `Some(None)` once the
+    // channel is closed and drained.
+    fn try_receive(&mut self) -> Option<Option<RegistryKey>> {
+        match self.queue.pop_front() {
+            Some(value) => {
+                wake_all(&mut self.senders);
+                Some(Some(value))
+            }
+            None if self.closed => Some(None),
+            None => None,
+        }
+    }
+}
+
+fn wake_all(wakers: &mut Vec<Waker>) {
+    for waker in wakers.drain(..) {
+        waker.wake();
+    }
+}
+
+fn add_waker(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+// The userdata handed to scripts for a channel.
+struct Channel(Arc<Mutex<Shared>>);
+
+impl UserData for Channel {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Like the sockets of the `net` module, the methods are async functions kept in a table
+        // created along with the module.
+        methods.add_meta_function(MetaMethod::Index, |lua, (_, key): (AnyUserData, Value)| {
+            lua.named_registry_value::<_, Table>(CHANNEL_METHODS_KEY)?
+                .get::<_, Value>(key)
+        });
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.0.lock().queue.len()));
+    }
+}
+
+fn get_channel(channel: &AnyUserData) -> Result<Arc<Mutex<Shared>>> {
+    Ok(channel.borrow::<Channel>()?.0.clone())
+}
+
+// A received value, or its absence once the channel is closed, returned to scripts as the value and
+// whether there was one.
+struct Received(Option<RegistryKey>);
+
+impl<'lua> ToLuaMulti<'lua> for Received {
+    fn to_lua_multi(self, lua: Context<'lua>) -> Result<MultiValue<'lua>> {
+        match self.0 {
+            Some(key) => {
+                let value: Value = lua.registry_value(&key)?;
+                lua.remove_registry_value(key)?;
+                (value, true).to_lua_multi(lua)
+            }
+            None => (Value::Nil, false).to_lua_multi(lua),
+        }
+    }
+}
+
+struct Sending {
+    channel: Arc<Mutex<Shared>>,
+    value: Option<RegistryKey>,
+}
+
+impl Future for Sending {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let value = match this.value.take() {
+            Some(value) => value,
+            None => {
+                rlua_panic!("channel send polled after completion");
+            }
+        };
+        let mut channel = this.channel.lock();
+        match channel.try_send(value)? {
+            None => Poll::Ready(Ok(())),
+            Some(value) => {
+                this.value = Some(value);
+                add_waker(&mut channel.senders, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+struct Receiving(Arc<Mutex<Shared>>);
+
+impl Future for Receiving {
+    type Output = Result<Received>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Result<Received>> {
+        let mut channel = self.0.lock();
+        match channel.try_receive() {
+            Some(received) => Poll::Ready(Ok(Received(received))),
+            None => {
+                add_waker(&mut channel.receivers, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+enum Case {
+    Receive(Arc<Mutex<Shared>>),
+    Send(Arc<Mutex<Shared>>, Option<RegistryKey>),
+}
+
+// The case of a `select` which completed, returned to scripts as its index followed by the received
+// value and whether there was one for receive cases, or nothing if the timeout expired.
+enum Selected {
+    Received(usize, Received),
+    Sent(usize),
+    TimedOut,
+}
+
+impl<'lua> ToLuaMulti<'lua> for Selected {
+    fn to_lua_multi(self, lua: Context<'lua>) -> Result<MultiValue<'lua>> {
+        match self {
+            Selected::Received(index, received) => {
+                let mut results = received.to_lua_multi(lua)?;
+                results.push_front(Value::Integer(index as Integer));
+                Ok(results)
+            }
+            Selected::Sent(index) => index.to_lua_multi(lua),
+            Selected::TimedOut => Ok(MultiValue::new()),
+        }
+    }
+}
+
+struct Select {
+    cases: Vec<Case>,
+    deadline: Option<Instant>,
+    timer: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+impl Future for Select {
+    type Output = Result<Selected>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Result<Selected>> {
+        let this = &mut *self;
+        for (i, case) in this.cases.iter_mut().enumerate() {
+            match case {
+                Case::Receive(channel) => {
+                    if let Some(received) = channel.lock().try_receive() {
+                        return Poll::Ready(Ok(Selected::Received(i + 1, Received(received))));
+                    }
+                }
+                Case::Send(channel, value) => {
+                    let pending = match value.take() {
+                        Some(pending) => pending,
+                        None => {
+                            rlua_panic!("channel select polled after completion");
+                        }
+                    };
+                    match channel.lock().try_send(pending) {
+                        Ok(None) => return Poll::Ready(Ok(Selected::Sent(i + 1))),
+                        Ok(Some(pending)) => *value = Some(pending),
+                        Err(err) => return Poll::Ready(Err(err)),
+                    }
+                }
+            }
+        }
+
+        let deadline = match this.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                return Poll::Ready(Ok(Selected::TimedOut))
+            }
+            deadline => deadline,
+        };
+        for case in &this.cases {
+            match case {
+                Case::Receive(channel) => add_waker(&mut channel.lock().receivers, cx.waker()),
+                Case::Send(channel, _) => add_waker(&mut channel.lock().senders, cx.waker()),
+            }
+        }
+        if let Some(deadline) = deadline {
+            match &this.timer {
+                Some(timer) => *timer.lock() = Some(cx.waker().clone()),
+                None => this.timer = Some(start_timer(deadline, cx.waker().clone())?),
+            }
+        }
+        Poll::Pending
+    }
+}
+
+// Wakes the latest waker stored in the returned slot once `deadline` has passed.  There is no timer
+// driver to rely on, so this uses a sleeping thread, as the `net` module does for blocking I/O.
+fn start_timer(deadline: Instant, waker: Waker) -> Result<Arc<Mutex<Option<Waker>>>> {
+    let slot = Arc::new(Mutex::new(Some(waker)));
+    let timer_slot = slot.clone();
+    thread::Builder::new()
+        .name("rlua-timer".to_owned())
+        .spawn(move || {
+            let now = Instant::now();
+            if deadline > now {
+                thread::sleep(deadline - now);
+            }
+            if let Some(waker) = timer_slot.lock().take() {
+                waker.wake();
+            }
+        })
+        .map_err(Error::external)?;
+    Ok(slot)
+}
+
+fn parse_capacity(capacity: Option<i64>) -> Result<Option<usize>> {
+    match capacity {
+        None => Ok(None),
+        Some(capacity) if capacity >= 1 => Ok(Some(capacity as usize)),
+        Some(_) => Err(Error::RuntimeError(
+            "channel capacity must be a positive integer".to_owned(),
+        )),
+    }
+}
+
+// Timeouts too large to represent are treated the same as no timeout.
+fn parse_timeout(timeout: Option<f64>) -> Result<Option<Instant>> {
+    match timeout {
+        None => Ok(None),
+        Some(timeout) if timeout >= 0.0 && timeout.is_finite() => {
+            Ok(Duration::try_from_secs_f64(timeout)
+                .ok()
+                .and_then(|timeout| Instant::now().checked_add(timeout)))
+        }
+        Some(_) => Err(Error::RuntimeError(
+            "timeout must be a non-negative number of seconds".to_owned(),
+        )),
+    }
+}
+
+fn parse_cases<'lua>(lua: Context<'lua>, cases: Table<'lua>) -> Result<Vec<Case>> {
+    cases
+        .sequence_values::<Value>()
+        .map(|case| match case? {
+            Value::UserData(channel) => Ok(Case::Receive(get_channel(&channel)?)),
+            Value::Table(case) => {
+                let channel: AnyUserData = case.get(1)?;
+                let value: Value = case.get(2)?;
+                Ok(Case::Send(
+                    get_channel(&channel)?,
+                    Some(lua.create_registry_value(value)?),
+                ))
+            }
+            case => Err(Error::RuntimeError(format!(
+                "select case must be a channel or a {{channel, value}} table, not {}",
+                case.type_name()
+            ))),
+        })
+        .collect()
+}
+
+pub(crate) fn create_channel_module<'lua>(lua: Context<'lua>) -> Result<Table<'lua>> {
+    let methods = lua.create_table()?;
+    methods.set(
+        "send",
+        lua.create_async_function(|lua, (channel, value): (AnyUserData, Value)| {
+            let prepared = get_channel(&channel).and_then(|channel| {
+                Ok(Sending {
+                    channel,
+                    value: Some(lua.create_registry_value(value)?),
+                })
+            });
+            async move { prepared?.await }
+        })?,
+    )?;
+    methods.set(
+        "receive",
+        lua.create_async_function(|_, channel: AnyUserData| {
+            let prepared = get_channel(&channel).map(Receiving);
+            async move { prepared?.await }
+        })?,
+    )?;
+    methods.set(
+        "close",
+        lua.create_function(|_, channel: AnyUserData| {
+            let channel = get_channel(&channel)?;
+            let mut channel = channel.lock();
+            channel.closed = true;
+            wake_all(&mut channel.senders);
+            wake_all(&mut channel.receivers);
+            Ok(())
+        })?,
+    )?;
+    lua.set_named_registry_value(CHANNEL_METHODS_KEY, methods)?;
+
+    let module = lua.create_table()?;
+    module.set(
+        "new",
+        lua.create_function(|lua, capacity: Option<i64>| {
+            lua.create_userdata(Channel(Arc::new(Mutex::new(Shared {
+                queue: VecDeque::new(),
+                capacity: parse_capacity(capacity)?,
+                closed: false,
+                senders: Vec::new(),
+                receivers: Vec::new(),
+            }))))
+        })?,
+    )?;
+    module.set(
+        "select",
+        lua.create_async_function(|lua, (cases, timeout): (Table, Option<f64>)| {
+            let prepared = parse_cases(lua, cases).and_then(|cases| {
+                Ok(Select {
+                    cases,
+                    deadline: parse_timeout(timeout)?,
+                    timer: None,
+                })
+            });
+            async move { prepared?.await }
+        })?,
+    )?;
+    Ok(module)
+}
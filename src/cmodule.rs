@@ -0,0 +1,106 @@
+use std::os::raw::{c_int, c_void};
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::lua::{share_extra_data, Lua, STATE_REGISTRY_KEY, STATE_REGISTRY_NAME};
+use crate::package;
+use crate::table::Table;
+use crate::util::{assert_stack, callback_error, check_stack};
+use crate::value::Value;
+
+// Loads a module the way the C searcher of `require` does, but from an explicit path, and records
+// it in `package.loaded` so that later calls to `require` find it.
+pub(crate) fn load_c_module<'lua>(
+    lua: Context<'lua>,
+    path: &str,
+    name: &str,
+) -> Result<Value<'lua>> {
+    let loaded: Table = lua.named_registry_value("_LOADED")?;
+    let loadlib = match package::loadlib(lua)? {
+        Some(loadlib) => loadlib,
+        None => {
+            return Err(Error::RuntimeError(
+                "loading C modules requires the package library".to_owned(),
+            ))
+        }
+    };
+
+    // As with `require`, a module "a.b-c" is opened by the function "luaopen_a_b_c".
+    let symbol = format!("luaopen_{}", name.replace(['.', '-'], "_"));
+    let (opener, message): (Value, Option<String>) = loadlib.call((path, symbol))?;
+    let opener = match opener {
+        Value::Function(opener) => opener,
+        _ => {
+            return Err(Error::RuntimeError(format!(
+                "cannot load C module '{}' from '{}': {}",
+                name,
+                path,
+                message.unwrap_or_default()
+            )))
+        }
+    };
+
+    let module = match opener.call::<_, Value>((name, path))? {
+        Value::Nil => match loaded.get::<_, Value>(name)? {
+            Value::Nil => Value::Boolean(true),
+            module => module,
+        },
+        module => module,
+    };
+    loaded.set(name, module.clone())?;
+    Ok(module)
+}
+
+// Turns the `nil, message` results used by C modules to report failures into an error.
+pub(crate) fn check_nil_error<'lua>(lua: Context<'lua>, results: &[Value<'lua>]) -> Result<()> {
+    match results {
+        [Value::Nil, Value::Nil, ..] => Ok(()),
+        [Value::Nil, message, ..] => {
+            let message = match lua.coerce_string(message.clone())? {
+                Some(message) => StdString::from_utf8_lossy(message.as_bytes()).into_owned(),
+                None => format!("error object is a {} value", message.type_name()),
+            };
+            Err(Error::RuntimeError(message))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The body of the `luaopen_` functions generated by `rlua_derive::lua_module`.
+///
+/// Sets up rlua in `state` unless this copy of rlua already has, then returns the table created by
+/// `open` to Lua.
+#[doc(hidden)]
+pub unsafe fn open_module<F>(state: *mut ffi::lua_State, open: F) -> c_int
+where
+    F: for<'lua> FnOnce(Context<'lua>) -> Result<Table<'lua>>,
+{
+    assert_stack(state, 1);
+    ffi::lua_pushstring(state, STATE_REGISTRY_NAME);
+    ffi::lua_rawget(state, ffi::LUA_REGISTRYINDEX);
+    let owner = ffi::lua_touserdata(state, -1);
+    ffi::lua_pop(state, 1);
+
+    if owner.is_null() {
+        // The state now lives as long as the host keeps it open, which rlua's data has to outlive.
+        Lua::init_from_ptr(state).into_raw();
+    } else if owner == &STATE_REGISTRY_KEY as *const u8 as *mut c_void {
+        share_extra_data(state);
+    } else {
+        ffi::lua_pushstring(
+            state,
+            cstr!("Lua state is already used by a different copy of rlua"),
+        );
+        ffi::lua_error(state);
+    }
+
+    callback_error(state, |_| {
+        let lua = Context::new(state);
+        let module = open(lua)?;
+        check_stack(state, 1)?;
+        lua.push_value(Value::Table(module))?;
+        Ok(1)
+    })
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::{Error, Result};
+use crate::types::Integer;
+use crate::userdata::{MetaMethod, UserData, UserDataMethods};
+use crate::value::{FromLua, ToLua, Value};
+
+/// A `Vec` shared between Rust and Lua, which scripts use like a sequence.
+///
+/// Passing a `SharedVec` to Lua gives scripts a userdata supporting `v[i]`, `v[i] = x`, `#v`,
+/// `ipairs` and `pairs`, with 1-based indices, as well as the methods `push(x)`, `pop()`,
+/// `insert(i, x)`, `remove(i)` and `clear()`.  Assigning to the index right after the end of the
+/// vector appends to it, any other index out of bounds is an error.
+///
+/// The elements stay Rust values and are converted when scripts read or write them.  Clones of a
+/// `SharedVec` share the same vector, so Rust code keeping a clone sees what scripts did to it.
+///
+/// Requires the `collections` feature.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result, SharedVec};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let scores = SharedVec::new(vec![3, 1]);
+/// lua_context.globals().set("scores", scores.clone())?;
+/// lua_context
+///     .load("scores:push(4) scores[1] = scores[1] + #scores")
+///     .exec()?;
+/// assert_eq!(*scores.lock(), vec![6, 1, 4]);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SharedVec<T>(Arc<Mutex<Vec<T>>>);
+
+impl<T> SharedVec<T> {
+    /// Wraps `vec` to be shared with Lua.
+    pub fn new(vec: Vec<T>) -> SharedVec<T> {
+        SharedVec(Arc::new(Mutex::new(vec)))
+    }
+
+    /// Locks the vector for access from Rust.
+    ///
+    /// Scripts using the vector on other threads block until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, Vec<T>> {
+        lock(&self.0)
+    }
+}
+
+impl<T> Clone for SharedVec<T> {
+    fn clone(&self) -> SharedVec<T> {
+        SharedVec(self.0.clone())
+    }
+}
+
+impl<T> Default for SharedVec<T> {
+    fn default() -> SharedVec<T> {
+        SharedVec::new(Vec::new())
+    }
+}
+
+impl<T> From<Vec<T>> for SharedVec<T> {
+    fn from(vec: Vec<T>) -> SharedVec<T> {
+        SharedVec::new(vec)
+    }
+}
+
+impl<T> From<Arc<Mutex<Vec<T>>>> for SharedVec<T> {
+    fn from(vec: Arc<Mutex<Vec<T>>>) -> SharedVec<T> {
+        SharedVec(vec)
+    }
+}
+
+impl<T> UserData for SharedVec<T>
+where
+    T: 'static + Send + Clone + for<'lua> ToLua<'lua> + for<'lua> FromLua<'lua>,
+{
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("push", |_, this, value: T| {
+            this.lock().push(value);
+            Ok(())
+        });
+        methods.add_method("pop", |_, this, ()| Ok(this.lock().pop()));
+        methods.add_method("insert", |_, this, (index, value): (Integer, T)| {
+            let mut vec = this.lock();
+            let i = position(index, vec.len() + 1)?;
+            vec.insert(i, value);
+            Ok(())
+        });
+        methods.add_method("remove", |_, this, index: Integer| {
+            let mut vec = this.lock();
+            let i = position(index, vec.len())?;
+            Ok(vec.remove(i))
+        });
+        methods.add_method("clear", |_, this, ()| {
+            this.lock().clear();
+            Ok(())
+        });
+
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: Value| {
+            let value = match to_index(&key) {
+                Some(index) if index >= 1 => this.lock().get(index as usize - 1).cloned(),
+                _ => None,
+            };
+            value.to_lua(lua)
+        });
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |_, this, (index, value): (Integer, T)| {
+                let mut vec = this.lock();
+                let i = position(index, vec.len() + 1)?;
+                if i == vec.len() {
+                    vec.push(value);
+                } else {
+                    vec[i] = value;
+                }
+                Ok(())
+            },
+        );
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.lock().len()));
+        methods.add_meta_method(MetaMethod::Pairs, |lua, this, ()| {
+            let this = this.clone();
+            let next = lua.create_function(move |lua, (_, index): (Value, Option<Integer>)| {
+                let index = index.unwrap_or(0);
+                let value = match this.lock().get(index as usize) {
+                    Some(value) => value.clone(),
+                    None => return Ok((Value::Nil, Value::Nil)),
+                };
+                Ok((Value::Integer(index + 1), value.to_lua(lua)?))
+            })?;
+            Ok((next, Value::Nil, Value::Nil))
+        });
+    }
+}
+
+/// A `HashMap` shared between Rust and Lua, which scripts use like a table.
+///
+/// Passing a `SharedMap` to Lua gives scripts a userdata supporting `m[k]`, `#m` and `pairs`.
+/// Assigning `m[k] = v` inserts into the map and assigning nil removes the key.  Reading a key
+/// which does not convert to `K` returns nil, as for any other missing key, and `#m` is the number
+/// of entries.  There are no methods, so that every key is available for entries.
+///
+/// Clones of a `SharedMap` share the same map, so Rust code keeping a clone sees what scripts did
+/// to it.  `pairs` walks the keys the map had when the loop started.
+///
+/// Requires the `collections` feature.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use rlua::{Lua, Result, SharedMap};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let settings: SharedMap<String, i64> = SharedMap::new(HashMap::new());
+/// lua_context.globals().set("settings", settings.clone())?;
+/// lua_context.load("settings.volume = 7").exec()?;
+/// assert_eq!(settings.lock().get("volume"), Some(&7));
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SharedMap<K, V>(Arc<Mutex<HashMap<K, V>>>);
+
+impl<K, V> SharedMap<K, V> {
+    /// Wraps `map` to be shared with Lua.
+    pub fn new(map: HashMap<K, V>) -> SharedMap<K, V> {
+        SharedMap(Arc::new(Mutex::new(map)))
+    }
+
+    /// Locks the map for access from Rust.
+    ///
+    /// Scripts using the map on other threads block until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, HashMap<K, V>> {
+        lock(&self.0)
+    }
+}
+
+impl<K, V> Clone for SharedMap<K, V> {
+    fn clone(&self) -> SharedMap<K, V> {
+        SharedMap(self.0.clone())
+    }
+}
+
+impl<K, V> Default for SharedMap<K, V> {
+    fn default() -> SharedMap<K, V> {
+        SharedMap::new(HashMap::new())
+    }
+}
+
+impl<K, V> From<HashMap<K, V>> for SharedMap<K, V> {
+    fn from(map: HashMap<K, V>) -> SharedMap<K, V> {
+        SharedMap::new(map)
+    }
+}
+
+impl<K, V> From<Arc<Mutex<HashMap<K, V>>>> for SharedMap<K, V> {
+    fn from(map: Arc<Mutex<HashMap<K, V>>>) -> SharedMap<K, V> {
+        SharedMap(map)
+    }
+}
+
+impl<K, V> UserData for SharedMap<K, V>
+where
+    K: 'static + Send + Eq + Hash + Clone + for<'lua> ToLua<'lua> + for<'lua> FromLua<'lua>,
+    V: 'static + Send + Clone + for<'lua> ToLua<'lua> + for<'lua> FromLua<'lua>,
+{
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: Value| {
+            let value = match K::from_lua(key, lua) {
+                Ok(key) => this.lock().get(&key).cloned(),
+                Err(_) => None,
+            };
+            value.to_lua(lua)
+        });
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |_, this, (key, value): (K, Option<V>)| {
+                let mut map = this.lock();
+                match value {
+                    Some(value) => map.insert(key, value),
+                    None => map.remove(&key),
+                };
+                Ok(())
+            },
+        );
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.lock().len()));
+        methods.add_meta_method(MetaMethod::Pairs, |lua, this, ()| {
+            let this = this.clone();
+            let keys: Vec<K> = this.lock().keys().cloned().collect();
+            let position = AtomicUsize::new(0);
+            let next = lua.create_function(move |lua, ()| {
+                // Skips the keys which were removed since the loop started.
+                while let Some(key) = keys.get(position.fetch_add(1, Ordering::Relaxed)) {
+                    let value = this.lock().get(key).cloned();
+                    if let Some(value) = value {
+                        return Ok((key.clone().to_lua(lua)?, value.to_lua(lua)?));
+                    }
+                }
+                Ok((Value::Nil, Value::Nil))
+            })?;
+            Ok((next, Value::Nil, Value::Nil))
+        });
+    }
+}
+
+// Locks a shared collection, which stays usable if a panic poisoned the lock.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Converts a 1-based index to a position below `len`.
+fn position(index: Integer, len: usize) -> Result<usize> {
+    if index >= 1 && (index as u64) <= len as u64 {
+        Ok(index as usize - 1)
+    } else {
+        Err(Error::RuntimeError(format!(
+            "index {} out of bounds",
+            index
+        )))
+    }
+}
+
+// Returns the integer value of an index, accepting floats with an integral value as Lua does.
+fn to_index(key: &Value) -> Option<Integer> {
+    match *key {
+        Value::Integer(i) => Some(i),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < Integer::MAX as f64 => Some(n as Integer),
+        _ => None,
+    }
+}
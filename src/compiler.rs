@@ -0,0 +1,278 @@
+use std::ffi::CString;
+use std::future::Future;
+use std::os::raw::{c_char, c_int, c_void};
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::{ptr, slice};
+
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::sync::{Condvar, Mutex};
+use crate::util::{pop_error_with, to_string};
+
+/// A pool of background threads which compile Lua source code into bytecode.
+///
+/// Every chunk is parsed in a fresh, throwaway Lua state on one of the worker threads, so loading
+/// many scripts does not block the Lua state that will eventually run them.  The resulting bytecode
+/// can be loaded into any Lua state with [`Context::load_bytecode`].
+///
+/// Compilation only parses the source, no code from the chunk is ever executed on the worker
+/// threads.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Compiler, Lua, Result};
+/// # fn main() -> Result<()> {
+/// let compiler = Compiler::new(2);
+/// let pending = compiler.compile("return 1 + 2");
+///
+/// let bytecode = pending.wait()?;
+/// Lua::new().context(|lua_context| {
+///     // Safe, as the bytecode was compiled from source by the same version of Lua.
+///     let function = unsafe { lua_context.load_bytecode(&bytecode, "sum")? };
+///     assert_eq!(function.call::<_, i32>(())?, 3);
+///     Ok(())
+/// })
+/// # }
+/// ```
+///
+/// [`Context::load_bytecode`]: struct.Context.html#method.load_bytecode
+pub struct Compiler {
+    sender: Mutex<Option<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Compiler {
+    /// Creates a new `Compiler` backed by the given number of worker threads.
+    ///
+    /// At least one worker thread is always created.
+    pub fn new(threads: usize) -> Compiler {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("rlua-compiler-{}", i))
+                    .spawn(move || worker(receiver))
+                    .expect("could not spawn compiler thread")
+            })
+            .collect();
+
+        Compiler {
+            sender: Mutex::new(Some(sender)),
+            workers,
+        }
+    }
+
+    /// Queues the given source to be compiled on a worker thread.
+    pub fn compile<S>(&self, source: &S) -> Compilation
+    where
+        S: ?Sized + AsRef<[u8]>,
+    {
+        self.queue(source.as_ref().to_vec(), None, false)
+    }
+
+    /// Queues the given source to be compiled on a worker thread, using `name` as the chunk name.
+    ///
+    /// If `strip` is true, debug information such as line numbers and local variable names is left
+    /// out of the resulting bytecode.
+    pub fn compile_with_name<S, N>(&self, source: &S, name: &N, strip: bool) -> Compilation
+    where
+        S: ?Sized + AsRef<[u8]>,
+        N: ?Sized + AsRef<[u8]>,
+    {
+        match CString::new(name.as_ref().to_vec()) {
+            Ok(name) => self.queue(source.as_ref().to_vec(), Some(name), strip),
+            Err(e) => Compilation::finished(Err(Error::ToLuaConversionError {
+                from: "&str",
+                to: "string",
+                message: Some(e.to_string()),
+            })),
+        }
+    }
+
+    fn queue(&self, source: Vec<u8>, name: Option<CString>, strip: bool) -> Compilation {
+        let shared = Arc::new(Shared::default());
+        let job = Job {
+            source,
+            name,
+            strip,
+            shared: shared.clone(),
+        };
+
+        let sender = self.sender.lock();
+        if let Err(e) = rlua_expect!(sender.as_ref(), "compiler sender not set").send(job) {
+            e.0.shared.finish(Err(Error::RuntimeError(
+                "compiler worker threads have stopped".to_owned(),
+            )));
+        }
+
+        Compilation(shared)
+    }
+}
+
+impl Drop for Compiler {
+    fn drop(&mut self) {
+        // Dropping the sender makes every worker exit once the queue is drained.
+        self.sender.lock().take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The pending result of a [`Compiler::compile`] call.
+///
+/// The compiled bytecode can either be waited for synchronously with [`wait`], or the
+/// `Compilation` can be awaited as a `Future`.
+///
+/// [`Compiler::compile`]: struct.Compiler.html#method.compile
+/// [`wait`]: #method.wait
+pub struct Compilation(Arc<Shared>);
+
+impl Compilation {
+    /// Blocks the current thread until compilation has finished and returns the bytecode.
+    ///
+    /// Syntax errors in the source are returned as `Error::SyntaxError`.
+    pub fn wait(self) -> Result<Vec<u8>> {
+        let mut state = self.0.state.lock();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.0.cond.wait(state);
+        }
+    }
+
+    /// Returns the result of compilation if it has finished, without blocking.
+    pub fn try_wait(&mut self) -> Option<Result<Vec<u8>>> {
+        self.0.state.lock().result.take()
+    }
+
+    fn finished(result: Result<Vec<u8>>) -> Compilation {
+        let shared = Arc::new(Shared::default());
+        shared.finish(result);
+        Compilation(shared)
+    }
+}
+
+impl Future for Compilation {
+    type Output = Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
+        let mut state = self.0.state.lock();
+        if let Some(result) = state.result.take() {
+            Poll::Ready(result)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+struct Job {
+    source: Vec<u8>,
+    name: Option<CString>,
+    strip: bool,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<SharedState>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct SharedState {
+    result: Option<Result<Vec<u8>>>,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn finish(&self, result: Result<Vec<u8>>) {
+        let mut state = self.state.lock();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.cond.notify_all();
+    }
+}
+
+fn worker(receiver: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = match receiver.lock().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let result = unsafe { compile_chunk(&job.source, job.name.as_ref(), job.strip) };
+        job.shared.finish(result);
+    }
+}
+
+// Loads the given source in a new Lua state and dumps the resulting function.  Only text chunks
+// are accepted, so this can never be used to smuggle arbitrary bytecode through a `Compiler`.
+unsafe fn compile_chunk(source: &[u8], name: Option<&CString>, strip: bool) -> Result<Vec<u8>> {
+    unsafe extern "C" fn writer(
+        _state: *mut ffi::lua_State,
+        p: *const c_void,
+        sz: usize,
+        ud: *mut c_void,
+    ) -> c_int {
+        let output = &mut *(ud as *mut Vec<u8>);
+        if output.try_reserve(sz).is_err() {
+            return 1;
+        }
+        output.extend_from_slice(slice::from_raw_parts(p as *const u8, sz));
+        0
+    }
+
+    let state = ffi::luaL_newstate();
+    if state.is_null() {
+        return Err(Error::MemoryError(
+            "could not create a Lua state for compilation".to_owned(),
+        ));
+    }
+
+    let result = match ffi::luaL_loadbufferx(
+        state,
+        source.as_ptr() as *const c_char,
+        source.len(),
+        name.map(|n| n.as_ptr()).unwrap_or(ptr::null()),
+        cstr!("t"),
+    ) {
+        ffi::LUA_OK => {
+            let mut output = Vec::new();
+            match ffi::lua_dump(
+                state,
+                writer,
+                &mut output as *mut Vec<u8> as *mut c_void,
+                strip as c_int,
+            ) {
+                0 => Ok(output),
+                // The chunk is always a Lua function, so only the writer can fail the dump.
+                _ => Err(Error::MemoryError(
+                    "not enough memory to dump the compiled chunk".to_owned(),
+                )),
+            }
+        }
+        ffi::LUA_ERRMEM => {
+            let message = to_string(state, -1).into_owned();
+            ffi::lua_pop(state, 1);
+            Err(Error::MemoryError(message))
+        }
+        // The compilation state is a plain Lua state without rlua's `ExtraData`.
+        err => Err(pop_error_with(state, err, None)),
+    };
+
+    ffi::lua_close(state);
+    result
+}
@@ -1,29 +1,53 @@
-use std::any::TypeId;
+use std::any::{type_name, Any, TypeId};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::CString;
+use std::future::Future;
+use std::io::{self, Read};
 use std::marker::PhantomData;
 use std::os::raw::{c_char, c_int, c_void};
+use std::pin::Pin;
+use std::string::String as StdString;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
-use std::{mem, ptr};
-
+use std::task::{self, Poll};
+use std::time::{Duration, Instant};
+use std::{mem, panic, ptr, thread};
+
+use crate::bytecode;
+use crate::channel;
+use crate::cmodule;
+use crate::diagnostics::{self, CallStack, ReferencePath};
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::foreign;
 use crate::function::Function;
-use crate::lua::{extra_data, ExtraData, FUNCTION_METATABLE_REGISTRY_KEY};
+use crate::host_api::HostApi;
+use crate::introspect::{self, LoggedCall, RegisteredType};
+use crate::lazy::{self, LazyTable, LazyTableProvider};
+use crate::lua::{extra_data, finalize_userdata, ExtraData, FUNCTION_METATABLE_REGISTRY_KEY};
 use crate::markers::{Invariant, NoUnwindSafe};
+#[cfg(feature = "net")]
+use crate::net::{self, NetPolicy};
+use crate::parallel;
+#[cfg(feature = "process")]
+use crate::process::{self, ProcessPolicy};
+use crate::replay::{self, RecordedCall, Recording};
+use crate::sandbox::{self, LoadPolicy};
 use crate::scope::Scope;
 use crate::string::String;
 use crate::table::Table;
-use crate::thread::Thread;
-use crate::types::{Callback, Integer, LightUserData, LuaRef, Number, RegistryKey};
+use crate::thread::{Thread, ASYNC_PENDING};
+use crate::types::{Callback, Integer, LightUserData, LuaRef, Number, PtrKey, RegistryKey};
 use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
 use crate::util::{
     assert_stack, callback_error, check_stack, get_userdata, get_wrapped_error,
-    init_userdata_metatable, pop_error, protect_lua, protect_lua_closure, push_string,
-    push_userdata, push_wrapped_error, StackGuard,
+    init_userdata_fields, init_userdata_metatable, pop_error, protect_lua, protect_lua_closure,
+    push_string, push_userdata, push_wrapped_error, reserve_stack, StackGuard,
 };
 use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
+#[cfg(feature = "websocket")]
+use crate::websocket;
 
 #[derive(Copy, Clone)]
 pub struct Context<'lua> {
@@ -39,7 +63,11 @@ impl<'lua> Context<'lua> {
     /// similar on the returned builder.  Code is not even parsed until one of these methods is
     /// called.
     ///
+    /// Only Lua source code is accepted, precompiled binary chunks are rejected with a
+    /// `SyntaxError`.  Use [`load_bytecode`] to load those.
+    ///
     /// [`Chunk::exec`]: struct.Chunk.html#method.exec
+    /// [`load_bytecode`]: #method.load_bytecode
     pub fn load<'a, S>(self, source: &'a S) -> Chunk<'lua, 'a>
     where
         S: ?Sized + AsRef<[u8]>,
@@ -52,6 +80,145 @@ impl<'lua> Context<'lua> {
         }
     }
 
+    /// Loads a precompiled binary chunk, such as one produced by [`Function::dump`] or a
+    /// [`Compiler`], as a function.
+    ///
+    /// Loading bytecode skips parsing, which makes it much faster to start large scripts.  `name`
+    /// is only used in error messages about malformed chunks, the chunk itself records the name
+    /// of its source unless debug information was stripped.  Lua source code is rejected with a
+    /// `SyntaxError`, and chunks compiled for another version of Lua, or with other sizes of
+    /// numbers or another byte order, with an [`Error::IncompatibleBytecode`] describing what they
+    /// were compiled for.
+    ///
+    /// # Safety
+    ///
+    /// Lua does not verify bytecode, and maliciously crafted or corrupted bytecode can cause
+    /// undefined behavior both while loading and when running it.  The bytecode must come from a
+    /// trusted source, and must have been produced by the same version of Lua, built with the same
+    /// configuration, as the one rlua is using.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let double: Function = lua_context.load("function(x) return x * 2 end").eval()?;
+    /// let bytecode = double.dump(true)?;
+    ///
+    /// // Safe, as the bytecode was just dumped by this Lua state.
+    /// let loaded = unsafe { lua_context.load_bytecode(&bytecode, "double")? };
+    /// assert_eq!(loaded.call::<_, i64>(21)?, 42);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Function::dump`]: struct.Function.html#method.dump
+    /// [`Compiler`]: struct.Compiler.html
+    /// [`Error::IncompatibleBytecode`]: enum.Error.html#variant.IncompatibleBytecode
+    pub unsafe fn load_bytecode<S, N>(self, bytecode: &S, name: &N) -> Result<Function<'lua>>
+    where
+        S: ?Sized + AsRef<[u8]>,
+        N: ?Sized + AsRef<[u8]>,
+    {
+        let name = chunk_name(name.as_ref())?;
+        bytecode::check_header(bytecode.as_ref())?;
+        self.load_chunk(bytecode.as_ref(), Some(&name), None, cstr!("b"))
+    }
+
+    /// Loads Lua source code from `reader` as a function, without reading it into memory first.
+    ///
+    /// The source is read in pieces while Lua parses it, which keeps memory use down for very
+    /// large scripts and lets scripts streamed over the network be parsed while they arrive.  Like
+    /// [`load`], only source code is accepted.  Errors returned by `reader` stop loading and are
+    /// returned as an `Error::ExternalError`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let source: &[u8] = b"local a, b = ... return a + b";
+    /// let add = lua_context.load_from_reader(source, "=add")?;
+    /// assert_eq!(add.call::<_, i64>((1, 2))?, 3);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`load`]: #method.load
+    pub fn load_from_reader<R, N>(self, mut reader: R, name: &N) -> Result<Function<'lua>>
+    where
+        R: Read,
+        N: ?Sized + AsRef<[u8]>,
+    {
+        struct Source<'a> {
+            reader: &'a mut dyn Read,
+            buffer: Vec<u8>,
+            error: Option<io::Error>,
+            panic: Option<Box<dyn Any + Send>>,
+        }
+
+        unsafe extern "C" fn read_chunk(
+            _state: *mut ffi::lua_State,
+            ud: *mut c_void,
+            size: *mut usize,
+        ) -> *const c_char {
+            let source = &mut *(ud as *mut Source);
+            let Source { reader, buffer, .. } = source;
+            let read = loop {
+                match panic::catch_unwind(panic::AssertUnwindSafe(|| reader.read(buffer))) {
+                    Ok(Err(err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    read => break read,
+                }
+            };
+            *size = match read {
+                Ok(Ok(read)) => read,
+                Ok(Err(err)) => {
+                    source.error = Some(err);
+                    0
+                }
+                Err(panic) => {
+                    source.panic = Some(panic);
+                    0
+                }
+            };
+            source.buffer.as_ptr() as *const c_char
+        }
+
+        let name = chunk_name(name.as_ref())?;
+        let mut source = Source {
+            reader: &mut reader,
+            buffer: vec![0; READER_BUFFER_SIZE],
+            error: None,
+            panic: None,
+        };
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+
+            let status = ffi::lua_load(
+                self.state,
+                read_chunk,
+                &mut source as *mut Source as *mut c_void,
+                name.as_ptr(),
+                cstr!("t"),
+            );
+            if let Some(panic) = source.panic {
+                panic::resume_unwind(panic);
+            }
+            if let Some(err) = source.error {
+                return Err(Error::external(err));
+            }
+            match status {
+                ffi::LUA_OK => Ok(Function(self.pop_ref())),
+                err => Err(pop_error(self.state, err)),
+            }
+        }
+    }
+
     /// Create and return an interned Lua string.  Lua strings can be arbitrary [u8] data including
     /// embedded nulls, so in addition to `&str` and `&String`, you can also pass plain `&[u8]`
     /// here.
@@ -90,9 +257,9 @@ impl<'lua> Context<'lua> {
     {
         unsafe {
             let _sg = StackGuard::new(self.state);
-            // `Lua` instance assumes that on any callback, the Lua stack has at least LUA_MINSTACK
-            // slots available to avoid panics.
-            check_stack(self.state, 5 + ffi::LUA_MINSTACK)?;
+            // `Lua` instance assumes that on any callback, the Lua stack has at least the stack
+            // reserve available to avoid panics.
+            reserve_stack(self.state, 5)?;
 
             unsafe extern "C" fn new_table(state: *mut ffi::lua_State) -> c_int {
                 ffi::lua_newtable(state);
@@ -122,6 +289,56 @@ impl<'lua> Context<'lua> {
         self.create_table_from(cont.into_iter().enumerate().map(|(k, v)| (k + 1, v)))
     }
 
+    /// Creates a table whose entries are looked up from `provider` when they are first read.
+    ///
+    /// This exposes large Rust data sets to scripts without converting them up front.  The table
+    /// starts out empty and its metatable asks the provider for every key read, `#` returns
+    /// [`LazyTableProvider::length`] and `pairs` walks the keys of the provider.  The values found
+    /// are stored in the table if [`LazyTableProvider::cache`] returns true, so later reads of the
+    /// same key do not reach the provider again, until they are dropped with
+    /// [`LazyTable::invalidate`].  Scripts cannot assign to the table.
+    ///
+    /// Raw accesses, including [`Table::raw_get`] and [`Table::pairs`] from Rust and `next` and
+    /// `rawget` in Lua, only see the values cached so far.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Context, LazyTableProvider, Lua, Result, ToLua, Value};
+    /// # fn main() -> Result<()> {
+    /// struct Squares(i64);
+    ///
+    /// impl LazyTableProvider for Squares {
+    ///     fn get<'lua>(&self, lua: Context<'lua>, key: Value<'lua>) -> Result<Value<'lua>> {
+    ///         match key {
+    ///             Value::Integer(i) if i >= 1 && i <= self.0 => (i * i).to_lua(lua),
+    ///             _ => Ok(Value::Nil),
+    ///         }
+    ///     }
+    ///
+    ///     fn length(&self) -> Result<i64> {
+    ///         Ok(self.0)
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// let squares = lua_context.create_lazy_table(Squares(100_000))?;
+    /// lua_context.globals().set("squares", squares)?;
+    /// assert_eq!(lua_context.load("squares[12] + #squares").eval::<i64>()?, 100_144);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`LazyTableProvider::length`]: trait.LazyTableProvider.html#method.length
+    /// [`LazyTableProvider::cache`]: trait.LazyTableProvider.html#method.cache
+    /// [`LazyTable::invalidate`]: struct.LazyTable.html#method.invalidate
+    /// [`Table::raw_get`]: struct.Table.html#method.raw_get
+    /// [`Table::pairs`]: struct.Table.html#method.pairs
+    pub fn create_lazy_table<P: LazyTableProvider>(self, provider: P) -> Result<LazyTable<'lua>> {
+        lazy::create_lazy_table(self, provider)
+    }
+
     /// Wraps a Rust function or closure, creating a callable Lua function handle to it.
     ///
     /// The function's return value is always a `Result`: If the function returns `Err`, the error
@@ -200,6 +417,737 @@ impl<'lua> Context<'lua> {
         })
     }
 
+    /// Wraps a Rust function returning a future, creating a callable Lua function handle to it.
+    ///
+    /// Async functions must be called from within a thread driven by [`Thread::into_async`] or
+    /// [`Function::call_async`].  When called, `func` is given the converted arguments and returns
+    /// a future, and the calling thread yields back to the executor until that future completes.
+    /// The future's result is then converted and returned to the calling Lua code.  Calling an
+    /// async function from anywhere else is an error, as is calling it from Lua code which was
+    /// itself called by a Rust callback, since Lua cannot yield across Rust callbacks.
+    ///
+    /// The future is stored inside Lua while it is pending, so it must be `'static + Send` and
+    /// cannot hold handles to Lua values.  Convert the arguments into Rust types in `func` before
+    /// creating the future.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # use std::future::Future;
+    /// # use std::pin::Pin;
+    /// # use std::task::{Context as TaskContext, Poll, Waker};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let double = lua_context.create_async_function(|_, n: i64| async move { Ok(n * 2) })?;
+    /// lua_context.globals().set("double", double)?;
+    ///
+    /// let script: Function = lua_context.load("function(n) return double(n) + 1 end").eval()?;
+    /// let mut future = script.call_async::<_, i64>(20);
+    ///
+    /// // Any executor can drive the future, this one does not need to wait for anything.
+    /// let mut cx = TaskContext::from_waker(Waker::noop());
+    /// match Pin::new(&mut future).poll(&mut cx) {
+    ///     Poll::Ready(result) => assert_eq!(result?, 41),
+    ///     Poll::Pending => unreachable!(),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Thread::into_async`]: struct.Thread.html#method.into_async
+    /// [`Function::call_async`]: struct.Function.html#method.call_async
+    pub fn create_async_function<A, R, F, FR>(self, func: F) -> Result<Function<'lua>>
+    where
+        A: FromLuaMulti<'lua>,
+        R: 'static + ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> FR,
+        FR: 'static + Send + Future<Output = Result<R>>,
+    {
+        // Polls the pending future in `start`'s userdata, returning `false` if it is still pending,
+        // or `true` followed by its results.  The Lua wrapper yields until the future completes.
+        const ASYNC_WRAPPER: &str = r#"
+            local start, poll, pending, yield = ...
+            local function step(future, ready, ...)
+                if ready then
+                    return ...
+                end
+                yield(pending)
+                return step(future, poll(future))
+            end
+            return function(...)
+                local future = start(...)
+                return step(future, poll(future))
+            end
+        "#;
+
+        unsafe extern "C" fn yield_pending(state: *mut ffi::lua_State) -> c_int {
+            ffi::lua_yield(state, ffi::lua_gettop(state))
+        }
+
+        let start = self.create_function(move |lua, args: A| {
+            lua.create_internal_userdata(AsyncFuture::<R>(Some(Box::pin(func(lua, args)))))
+        })?;
+        let poll = self.create_function(|lua, future: AnyUserData| {
+            let waker =
+                unsafe { (*extra_data(lua.state)).async_waker.clone() }.ok_or_else(|| {
+                    Error::RuntimeError(
+                        "async function called outside of an async thread".to_owned(),
+                    )
+                })?;
+            let mut future = future.borrow_mut::<AsyncFuture<R>>()?;
+            let result = match future.0.as_mut() {
+                Some(pending) => pending
+                    .as_mut()
+                    .poll(&mut task::Context::from_waker(&waker)),
+                None => {
+                    rlua_panic!("async function polled after completion");
+                }
+            };
+            match result {
+                Poll::Pending => Ok(MultiValue::from_vec(vec![Value::Boolean(false)])),
+                Poll::Ready(result) => {
+                    future.0 = None;
+                    let mut results = result?.to_lua_multi(lua)?;
+                    results.push_front(Value::Boolean(true));
+                    Ok(results)
+                }
+            }
+        })?;
+        let pending = LightUserData(&ASYNC_PENDING as *const u8 as *mut c_void);
+        let yield_pending = unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            ffi::lua_pushcfunction(self.state, yield_pending);
+            Function(self.pop_ref())
+        };
+
+        self.load(ASYNC_WRAPPER).set_name("=async function")?.call((
+            start,
+            poll,
+            pending,
+            yield_pending,
+        ))
+    }
+
+    /// Wraps a Rust function, creating a callable Lua function which fails if the Rust function
+    /// takes longer than `timeout` to complete.
+    ///
+    /// Each call runs `func` on a new worker thread while the calling Lua code waits for it.  If
+    /// `func` does not return within `timeout`, the call raises [`Error::CallbackTimeout`] in Lua.
+    /// The worker thread cannot be cancelled, so it keeps running in the background and its result
+    /// is discarded.
+    ///
+    /// Because `func` runs on a different thread, it does not have access to the Lua state, and its
+    /// arguments and return values must be `Send` types that do not borrow from Lua.  If `func`
+    /// panics, the panic is propagated to the calling thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # use std::thread;
+    /// # use std::time::Duration;
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let query = lua_context.create_function_with_timeout(
+    ///     Duration::from_millis(100),
+    ///     |delay: u64| {
+    ///         thread::sleep(Duration::from_millis(delay));
+    ///         Ok(format!("finished after {}ms", delay))
+    ///     },
+    /// )?;
+    /// lua_context.globals().set("query", query)?;
+    ///
+    /// lua_context.load("assert(query(1) == 'finished after 1ms')").exec()?;
+    /// assert!(lua_context.load("query(1000)").exec().is_err());
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Error::CallbackTimeout`]: enum.Error.html#variant.CallbackTimeout
+    pub fn create_function_with_timeout<A, R, F>(
+        self,
+        timeout: Duration,
+        func: F,
+    ) -> Result<Function<'lua>>
+    where
+        A: 'static + Send + FromLuaMulti<'lua>,
+        R: 'static + Send + ToLuaMulti<'lua>,
+        F: 'static + Send + Sync + Fn(A) -> Result<R>,
+    {
+        let func = Arc::new(func);
+        self.create_function(move |_, args: A| {
+            let (sender, receiver) = mpsc::channel();
+            let func = func.clone();
+            let worker = thread::Builder::new()
+                .name("rlua-callback".to_owned())
+                .spawn(move || {
+                    let _ = sender.send(func(args));
+                })
+                .map_err(Error::external)?;
+
+            match receiver.recv_timeout(timeout) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => Err(Error::CallbackTimeout(timeout)),
+                Err(RecvTimeoutError::Disconnected) => match worker.join() {
+                    Err(panic) => panic::resume_unwind(panic),
+                    Ok(()) => {
+                        rlua_panic!("callback worker exited without a result");
+                    }
+                },
+            }
+        })
+    }
+
+    /// Creates a table for exposing host functions to scripts, tagged with the given host API
+    /// version.
+    ///
+    /// See [`HostApi`] for more details.
+    ///
+    /// [`HostApi`]: struct.HostApi.html
+    pub fn create_host_api(self, version: &str) -> Result<HostApi<'lua>> {
+        HostApi::new(self, version)
+    }
+
+    /// Creates a replacement for the standard `load` function which applies the given policy.
+    ///
+    /// Sandboxes often simply remove `load`, which breaks legitimate libraries that compile code at
+    /// runtime.  The returned function takes the same arguments as `load` (and so also works as
+    /// `loadstring`), but:
+    ///
+    /// - only text chunks are accepted, binary chunks are always rejected;
+    /// - the environment of the loaded chunk is always `env`, any environment argument is ignored;
+    /// - chunks larger than `LoadPolicy::max_source_size` are rejected;
+    /// - the size of every chunk is charged against `LoadPolicy::quota`, if set.
+    ///
+    /// Like `load`, a chunk which is rejected or fails to compile results in `nil` plus an error
+    /// message rather than an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{LoadPolicy, LoadQuota, Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let env = lua_context.create_table()?;
+    /// for name in &["assert", "string"] {
+    ///     env.set(*name, lua_context.globals().get::<_, Value>(*name)?)?;
+    /// }
+    ///
+    /// let quota = LoadQuota::new(1024);
+    /// let load = lua_context.create_sandboxed_load(
+    ///     env.clone(),
+    ///     LoadPolicy {
+    ///         max_source_size: Some(256),
+    ///         quota: Some(quota.clone()),
+    ///     },
+    /// )?;
+    /// env.set("load", load.clone())?;
+    /// env.set("loadstring", load)?;
+    ///
+    /// lua_context.load(r#"
+    ///     assert(load("return 1 + 1")() == 2)
+    ///     assert(load("return os")() == nil)
+    ///     local f, err = load(string.dump(function() end))
+    ///     assert(f == nil and err:find("binary chunk"))
+    /// "#).set_environment(env)?.exec()?;
+    ///
+    /// assert!(quota.remaining() < 1024);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn create_sandboxed_load(
+        self,
+        env: Table<'lua>,
+        policy: LoadPolicy,
+    ) -> Result<Function<'lua>> {
+        sandbox::create_sandboxed_load(self, env, policy)
+    }
+
+    /// Creates a networking module for scripts, as a safe replacement for C modules like luasocket.
+    ///
+    /// The module has the following functions, which are [async functions] and so can only be
+    /// called from Lua code driven by [`Function::call_async`] or [`Thread::into_async`]:
+    ///
+    /// - `connect(host, port [, timeout])` opens a TCP connection and returns a socket;
+    /// - `connect_udp(host, port [, timeout])` opens a UDP socket sending to and receiving from the
+    ///   given address.
+    ///
+    /// Sockets have the methods `send(data [, timeout])`, which returns the number of bytes sent,
+    /// `receive(max [, timeout])`, which returns at most `max` bytes (and at most 64KiB) as soon as
+    /// any are available or an empty string at the end of a TCP stream, and `close()`.  Timeouts
+    /// are given in seconds, and the timeout given to `connect` is the default for the socket's
+    /// operations.  Failures, including timeouts, raise errors.
+    ///
+    /// Every connection is checked against `policy` before any name resolution happens.  The
+    /// blocking socket operations run on background threads, so no particular executor is needed.
+    ///
+    /// Requires the `net` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, NetPolicy, Result};
+    /// # use std::time::Duration;
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let net = lua_context.create_net_module(NetPolicy {
+    ///     allow_tcp: true,
+    ///     allowed_hosts: vec!["example.com:80".to_owned()],
+    ///     timeout: Some(Duration::from_secs(10)),
+    ///     ..NetPolicy::default()
+    /// })?;
+    /// lua_context.globals().set("net", net)?;
+    ///
+    /// let fetch: Function = lua_context.load(r#"
+    ///     function()
+    ///         local socket = net.connect("example.com", 80)
+    ///         socket:send("HEAD / HTTP/1.0\r\nHost: example.com\r\n\r\n")
+    ///         local response = socket:receive(1024)
+    ///         socket:close()
+    ///         return response
+    ///     end
+    /// "#).eval()?;
+    ///
+    /// // `fetch.call_async::<_, String>(())` can now be run on any executor.
+    /// # let _ = fetch;
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [async functions]: #method.create_async_function
+    /// [`Function::call_async`]: struct.Function.html#method.call_async
+    /// [`Thread::into_async`]: struct.Thread.html#method.into_async
+    #[cfg(feature = "net")]
+    pub fn create_net_module(self, policy: NetPolicy) -> Result<Table<'lua>> {
+        net::create_net_module(self, policy)
+    }
+
+    /// Creates a WebSocket client module for scripts.
+    ///
+    /// Like the [`net` module], its functions are async functions.  The module has a single
+    /// function, `connect(url [, timeout])`, which opens a connection to a `ws://` URL and returns
+    /// a websocket with the methods:
+    ///
+    /// - `send(text [, timeout])` and `send_binary(data [, timeout])`, which send a text or binary
+    ///   message;
+    /// - `receive([timeout])`, which waits for the next text or binary message and returns it as a
+    ///   string, or returns `nil` once the server closes the connection;
+    /// - `close()`, which closes the connection.
+    ///
+    /// Connections are TCP connections governed by `policy` as for the `net` module, using the host
+    /// and port from the URL.  Operations on a single websocket are performed one at a time, so a
+    /// `send` from one coroutine waits for a pending `receive` in another.
+    ///
+    /// Requires the `websocket` feature.
+    ///
+    /// [`net` module]: #method.create_net_module
+    #[cfg(feature = "websocket")]
+    pub fn create_websocket_module(self, policy: NetPolicy) -> Result<Table<'lua>> {
+        websocket::create_websocket_module(self, policy)
+    }
+
+    /// Creates a module letting scripts run external programs, as an auditable replacement for
+    /// `os.execute` and `io.popen`.
+    ///
+    /// The module has a single function, `run(program, args [, stdin])`, which runs `program` with
+    /// the arguments in the sequence `args`, writing `stdin` to its standard input.  It waits for
+    /// the program to finish and returns its exit code (or `nil` if it was killed by a signal),
+    /// followed by everything it wrote to stdout and stderr.  Programs are run directly, never
+    /// through a shell.
+    ///
+    /// Only the programs listed in `policy` may be run, after their arguments pass the policy's
+    /// validation.  A program which runs longer than the policy's timeout or writes more output
+    /// than its limit is killed, and `run` raises an error.
+    ///
+    /// Requires the `process` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, ProcessPolicy, Result};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let process = lua_context.create_process_module(ProcessPolicy {
+    ///     allowed_programs: vec!["git".to_owned()],
+    ///     validate_args: Some(Arc::new(|_, args| args.first().map(String::as_str) == Some("log"))),
+    ///     max_output_size: Some(1024 * 1024),
+    ///     timeout: Some(Duration::from_secs(5)),
+    /// })?;
+    /// lua_context.globals().set("process", process)?;
+    ///
+    /// lua_context.load(r#"
+    ///     assert(not pcall(process.run, "rm", {"-rf", "/"}))
+    ///     assert(not pcall(process.run, "git", {"push"}))
+    /// "#).exec()?;
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    #[cfg(feature = "process")]
+    pub fn create_process_module(self, policy: ProcessPolicy) -> Result<Table<'lua>> {
+        process::create_process_module(self, policy)
+    }
+
+    /// Creates a module of channels, for coroutines of this state to communicate through.
+    ///
+    /// The module has the following functions:
+    ///
+    /// - `new([capacity])` creates a channel buffering at most `capacity` values, or any number of
+    ///   values if `capacity` is nil;
+    /// - `select(cases [, timeout])` waits until one of `cases` can proceed and performs it.  A
+    ///   case is either a channel to receive from, or a `{channel, value}` table to send `value`
+    ///   on.  The first case which is ready is chosen, and `select` returns its index, followed by
+    ///   the same results as `receive` for a receive case.  If no case is ready after `timeout`
+    ///   seconds, `select` returns nothing, so a timeout of 0 never waits.
+    ///
+    /// Channels have the methods `send(value)`, which waits while the channel is full, `receive()`,
+    /// which waits for a value and returns it followed by `true`, or returns `nil, false` once the
+    /// channel is closed and empty, and `close()`, after which sending is an error.  `#channel` is
+    /// the number of buffered values.
+    ///
+    /// `send`, `receive` and `select` are [async functions]: a coroutine waiting on a channel
+    /// yields to the executor driving it, such as a [`TaskGroup`], which resumes it once the
+    /// channel is ready.  Values are passed as they are, so tables are shared rather than copied,
+    /// and channels cannot be used from other Lua states.  Use a [`Linda`] for that.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::future::Future;
+    /// # use std::pin::Pin;
+    /// # use std::task::{Context as TaskContext, Poll, Waker};
+    /// # use rlua::{Function, Lua, Result, TaskGroup, Value};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let channel = lua_context.create_channel_module()?;
+    /// lua_context.globals().set("channel", channel)?;
+    ///
+    /// let producer: Function = lua_context.load(r#"
+    ///     function(ch)
+    ///         for i = 1, 10 do ch:send(i) end
+    ///         ch:close()
+    ///     end
+    /// "#).eval()?;
+    /// let consumer: Function = lua_context.load(r#"
+    ///     function(ch)
+    ///         local sum = 0
+    ///         while true do
+    ///             local value, ok = ch:receive()
+    ///             if not ok then return sum end
+    ///             sum = sum + value
+    ///         end
+    ///     end
+    /// "#).eval()?;
+    ///
+    /// let ch: Value = lua_context.load("channel.new(2)").eval()?;
+    /// let mut group = TaskGroup::<Option<i64>>::new();
+    /// group.spawn(&consumer, ch.clone());
+    /// group.spawn(&producer, ch);
+    ///
+    /// let mut join = group.join_all();
+    /// let mut cx = TaskContext::from_waker(Waker::noop());
+    /// let results = loop {
+    ///     if let Poll::Ready(results) = Pin::new(&mut join).poll(&mut cx) {
+    ///         break results;
+    ///     }
+    /// };
+    /// assert_eq!(results[0].clone()?, Some(55));
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [async functions]: #method.create_async_function
+    /// [`TaskGroup`]: struct.TaskGroup.html
+    /// [`Linda`]: struct.Linda.html
+    pub fn create_channel_module(self) -> Result<Table<'lua>> {
+        channel::create_channel_module(self)
+    }
+
+    /// Creates a replacement for the standard `os` library which only lets scripts observe the
+    /// environment and the time through the host.
+    ///
+    /// The returned table has the functions `clock`, `date`, `difftime`, `getenv` and `time`, which
+    /// behave like the standard ones, except that environment variables come from the provider set
+    /// with [`Lua::set_env_provider`], and the current time from the clock set with
+    /// [`Lua::set_clock`].  Functions which affect the host, such as `execute`, `exit`, `remove` or
+    /// `setlocale`, are left out.  The standard `os` library does not need to be loaded.
+    ///
+    /// [`Lua::set_env_provider`]: struct.Lua.html#method.set_env_provider
+    /// [`Lua::set_clock`]: struct.Lua.html#method.set_clock
+    pub fn create_sandboxed_os(self) -> Result<Table<'lua>> {
+        sandbox::create_sandboxed_os(self)
+    }
+
+    /// Adds methods to full userdata created outside of rlua, such as by a C library.
+    ///
+    /// The userdata are identified by their existing `metatable`.  For types created with
+    /// `luaL_newmetatable`, it can be found with [`named_registry_value`] using the type name.  The
+    /// functions in `methods` are looked up first when indexing such a userdata, and the metatable's
+    /// previous `__index` is used for every other key, so existing methods keep working.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `metatable` belongs to a Rust [`UserData`] type, which should be
+    /// extended by implementing `UserData::add_methods` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// // The standard io library creates its file handles as "FILE*" userdata.
+    /// let file_metatable: Table = lua_context.named_registry_value("FILE*")?;
+    /// let methods = lua_context.create_table()?;
+    /// methods.set(
+    ///     "is_stdout",
+    ///     lua_context.load("return function(f) return f == io.stdout end").eval::<rlua::Function>()?,
+    /// )?;
+    /// lua_context.extend_foreign_userdata(file_metatable, methods)?;
+    ///
+    /// lua_context.load(r#"
+    ///     assert(io.stdout:is_stdout() and not io.stderr:is_stdout())
+    ///     assert(io.type(io.stdout) == "file" and io.stdout.write)
+    /// "#).exec()?;
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`named_registry_value`]: #method.named_registry_value
+    /// [`UserData`]: trait.UserData.html
+    pub fn extend_foreign_userdata(
+        self,
+        metatable: Table<'lua>,
+        methods: Table<'lua>,
+    ) -> Result<()> {
+        foreign::extend_foreign_userdata(self, metatable, methods)
+    }
+
+    /// Loads a Lua C module from the shared library at `path`, like `require` would.
+    ///
+    /// The library's `luaopen_` function for `name` is called with `name` and `path`, and the
+    /// module it returns is stored in `package.loaded[name]`, so Lua code can then `require` it.
+    /// This needs the `package` library to be loaded.
+    ///
+    /// C modules call the Lua C API directly, so the Lua library they were built against must be
+    /// the one rlua uses.  This generally means linking with a shared Lua 5.3 through the
+    /// `system-lua` feature, or exporting the Lua symbols from an executable built with the
+    /// `builtin-lua` feature.  The userdata such modules create can be recognized with
+    /// [`AnyUserData::foreign_ptr`] and extended with [`extend_foreign_userdata`], and functions
+    /// which report failures as `nil, message` can be called with [`Function::call_checked`].
+    ///
+    /// # Safety
+    ///
+    /// This runs arbitrary native code, which is not bound by any of rlua's safety guarantees.
+    ///
+    /// [`AnyUserData::foreign_ptr`]: struct.AnyUserData.html#method.foreign_ptr
+    /// [`extend_foreign_userdata`]: #method.extend_foreign_userdata
+    /// [`Function::call_checked`]: struct.Function.html#method.call_checked
+    pub unsafe fn load_c_module(self, path: &str, name: &str) -> Result<Value<'lua>> {
+        cmodule::load_c_module(self, path, name)
+    }
+
+    /// Finds a chain of references which keeps the given value alive.
+    ///
+    /// Searches the objects reachable from the globals table and the registry breadth first, and
+    /// returns a shortest path to `value`, preferring paths from the globals table.  Returns `None`
+    /// if `value` cannot be reached from either root (so only the handle passed in and other Rust
+    /// handles keep it alive), or if it is not a collectable value such as a number.
+    ///
+    /// This visits every reachable object in the worst case, so it is meant for debugging rather
+    /// than regular use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let entity: Table = lua_context.load(r#"
+    ///     local listeners = {}
+    ///     function on_event(f) listeners[#listeners + 1] = f end
+    ///
+    ///     local entity = {}
+    ///     on_event(function() return entity end)
+    ///     return entity
+    /// "#).eval()?;
+    ///
+    /// let path = lua_context.reference_path(entity)?.unwrap();
+    /// assert_eq!(
+    ///     path.to_string(),
+    ///     "globals.on_event:upvalue(listeners)[1]:upvalue(entity)"
+    /// );
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn reference_path<V: ToLua<'lua>>(self, value: V) -> Result<Option<ReferencePath>> {
+        diagnostics::reference_path(self, value.to_lua(self)?)
+    }
+
+    /// Renders the values on the Lua stack of the thread this context is running on, as with
+    /// [`Lua::stack_dump`].
+    ///
+    /// Inside a callback this is the stack of the Lua thread which called it.
+    ///
+    /// [`Lua::stack_dump`]: struct.Lua.html#method.stack_dump
+    pub fn stack_dump(self) -> StdString {
+        unsafe { diagnostics::stack_dump(self.state) }
+    }
+
+    /// Returns the active calls of the thread this context is running on, innermost first.
+    ///
+    /// Inside a callback the innermost frame is the callback itself.  This also works inside hook
+    /// functions, where profilers can use [`CallStack::caller`] to attribute calls without being
+    /// confused by tail calls.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let where_am_i = lua_context.create_function(|lua, ()| Ok(lua.call_stack().to_string()))?;
+    /// lua_context.globals().set("where_am_i", where_am_i)?;
+    ///
+    /// let traceback: String = lua_context
+    ///     .load("local function f() return (where_am_i()) end return f()")
+    ///     .set_name("=chunk")?
+    ///     .eval()?;
+    /// assert_eq!(
+    ///     traceback,
+    ///     "stack traceback:\n\t[C]: in function 'where_am_i'\n\t\
+    ///      chunk:1: in function <chunk:1>\n\t(...tail calls...)"
+    /// );
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`CallStack::caller`]: struct.CallStack.html#method.caller
+    pub fn call_stack(self) -> CallStack {
+        unsafe { diagnostics::call_stack(self.state) }
+    }
+
+    /// Creates an [`Error::LuaError`] with `value` as its error object, the Rust equivalent of
+    /// `error(value)`.
+    ///
+    /// Returned from a callback, the error raises `value` itself, so scripts following the common
+    /// convention of raising tables can catch errors from Rust the same way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let fetch = lua_context.create_function(|lua_context, path: String| -> Result<()> {
+    ///     let details = lua_context.create_table()?;
+    ///     details.set("code", 404)?;
+    ///     details.set("path", path)?;
+    ///     Err(lua_context.create_error(details)?)
+    /// })?;
+    /// lua_context.globals().set("fetch", fetch)?;
+    ///
+    /// let code = lua_context
+    ///     .load("local ok, err = pcall(fetch, '/index.html') return err.code")
+    ///     .eval::<i64>()?;
+    /// assert_eq!(code, 404);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Error::LuaError`]: enum.Error.html#variant.LuaError
+    pub fn create_error<T: ToLua<'lua>>(self, value: T) -> Result<Error> {
+        let value = value.to_lua(self)?;
+        let message = match self.coerce_string(value.clone())? {
+            Some(message) => StdString::from_utf8_lossy(message.as_bytes()).into_owned(),
+            None => format!("(error object is a {} value)", value.type_name()),
+        };
+        Ok(Error::LuaError {
+            message,
+            value: Arc::new(self.create_registry_value(value)?),
+        })
+    }
+
+    /// Runs `f` behind a protected call boundary, the Rust equivalent of `xpcall`.
+    ///
+    /// If `f` returns an error, whether its own or one raised by Lua code it called, the error is
+    /// raised as a Lua error and caught again at this boundary, so it is returned as an
+    /// [`Error::CallbackError`] carrying a Lua traceback taken where the error reached the
+    /// boundary.  Panics inside `f` are not caught and propagate normally.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let result = lua_context.protect(|lua_context| {
+    ///     lua_context.load("local x = nil; return x.field").exec()
+    /// });
+    /// match result {
+    ///     Err(Error::CallbackError { traceback, .. }) => assert!(traceback.contains("traceback")),
+    ///     r => panic!("unexpected result {:?}", r),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Error::CallbackError`]: enum.Error.html#variant.CallbackError
+    pub fn protect<F, R>(self, f: F) -> Result<R>
+    where
+        F: FnOnce(Context<'lua>) -> Result<R>,
+    {
+        self.protect_finally(f, |_, _| Ok(()))
+    }
+
+    /// Like [`Context::protect`], but always calls `finally` once `f` has finished, similar to a
+    /// to-be-closed variable in Lua.
+    ///
+    /// `finally` receives the error `f` is about to return, if any.  If both `f` and `finally`
+    /// fail, the error from `f` is returned; if only `finally` fails, its error is returned
+    /// instead of the result of `f`.  `finally` is not called if `f` panics.
+    ///
+    /// [`Context::protect`]: #method.protect
+    pub fn protect_finally<F, G, R>(self, f: F, finally: G) -> Result<R>
+    where
+        F: FnOnce(Context<'lua>) -> Result<R>,
+        G: FnOnce(Context<'lua>, Option<&Error>) -> Result<()>,
+    {
+        let mut f = Some(f);
+        let mut ret = None;
+        let result = self.scope(|scope| {
+            scope
+                .create_function_mut(|_, ()| {
+                    let f = rlua_expect!(f.take(), "protected function called twice");
+                    ret = Some(f(self)?);
+                    Ok(())
+                })?
+                .call::<_, ()>(())
+        });
+
+        let cleanup = finally(self, result.as_ref().err());
+        result?;
+        cleanup?;
+        Ok(rlua_expect!(
+            ret,
+            "protected function did not return a value"
+        ))
+    }
+
     /// Wraps a Lua function into a new thread (or coroutine).
     ///
     /// Equivalent to `coroutine.create`.
@@ -208,33 +1156,316 @@ impl<'lua> Context<'lua> {
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 2);
 
-            let thread_state =
-                protect_lua_closure(self.state, 0, 1, |state| ffi::lua_newthread(state))?;
-            self.push_ref(&func.0);
-            ffi::lua_xmove(self.state, thread_state, 1);
+            let thread_state =
+                protect_lua_closure(self.state, 0, 1, |state| ffi::lua_newthread(state))?;
+            self.push_ref(&func.0);
+            ffi::lua_xmove(self.state, thread_state, 1);
+
+            Ok(Thread(self.pop_ref()))
+        }
+    }
+
+    /// Create a Lua userdata object from a custom userdata type.
+    pub fn create_userdata<T>(self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: 'static + Send + UserData,
+    {
+        unsafe { self.make_userdata(data) }
+    }
+
+    /// Builds the metatable of the userdata type `T` now, rather than when the first userdata of
+    /// this type is created.
+    ///
+    /// Registering types up front moves the cost of calling `UserData::add_methods` and building
+    /// the metatable out of hot paths, and registers types in a deterministic order, which is the
+    /// order [`Lua::registered_types`] lists them in.  Userdata of registered types can be created
+    /// with [`create_registered_userdata`], which checks that the type was registered.
+    ///
+    /// Registering a type more than once has no effect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Vec2(f64, f64);
+    ///
+    /// impl UserData for Vec2 {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method("length", |_, v, ()| Ok((v.0 * v.0 + v.1 * v.1).sqrt()));
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.register_userdata_type::<Vec2>()?;
+    /// let v = lua_context.create_registered_userdata(Vec2(3.0, 4.0))?;
+    /// lua_context.globals().set("v", v)?;
+    /// assert_eq!(lua_context.load("v:length()").eval::<f64>()?, 5.0);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Lua::registered_types`]: struct.Lua.html#method.registered_types
+    /// [`create_registered_userdata`]: #method.create_registered_userdata
+    pub fn register_userdata_type<T: 'static + UserData>(self) -> Result<()> {
+        unsafe {
+            self.userdata_metatable::<T>()?;
+            (*extra_data(self.state))
+                .explicitly_registered
+                .insert(TypeId::of::<T>());
+        }
+        Ok(())
+    }
+
+    /// Creates a userdata like [`create_userdata`], for a type which must have been registered
+    /// with [`register_userdata_type`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::UserDataTypeNotRegistered` if `T` was not registered, even if userdata of
+    /// type `T` were created before.
+    ///
+    /// [`create_userdata`]: #method.create_userdata
+    /// [`register_userdata_type`]: #method.register_userdata_type
+    pub fn create_registered_userdata<T>(self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: 'static + Send + UserData,
+    {
+        unsafe {
+            if !(*extra_data(self.state))
+                .explicitly_registered
+                .contains(&TypeId::of::<T>())
+            {
+                return Err(Error::UserDataTypeNotRegistered(type_name::<T>()));
+            }
+            self.make_userdata(data)
+        }
+    }
+
+    /// Enables looking up userdata of type `T` by pointer with [`try_borrow_userdata_by_ptr`].
+    ///
+    /// Only userdata of type `T` created after this call can be found.  The lookup table holds its
+    /// userdata weakly, so enabling lookups does not keep any userdata alive.
+    ///
+    /// [`try_borrow_userdata_by_ptr`]: #method.try_borrow_userdata_by_ptr
+    pub fn enable_userdata_ptr_lookup<T: 'static + UserData>(self) -> Result<()> {
+        unsafe {
+            if (*extra_data(self.state))
+                .userdata_ptr_lookup
+                .contains_key(&TypeId::of::<T>())
+            {
+                return Ok(());
+            }
+
+            let lookup = self.create_table()?;
+            let metatable = self.create_table()?;
+            metatable.raw_set("__mode", "v")?;
+            lookup.set_metatable(Some(metatable))?;
+
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+            self.push_ref(&lookup.0);
+            let id = protect_lua_closure(self.state, 1, 0, |state| {
+                ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+            })?;
+            (*extra_data(self.state))
+                .userdata_ptr_lookup
+                .insert(TypeId::of::<T>(), id);
+            Ok(())
+        }
+    }
+
+    /// Calls `f` with a borrow of the userdata of type `T` whose [`AnyUserData::to_pointer`] is
+    /// `ptr`.
+    ///
+    /// This lets pointers which were handed to C code as identities be mapped back to the userdata
+    /// they came from.  The pointer is only used as a key and never dereferenced, so it is safe to
+    /// pass any pointer at all.  Lookups must first be enabled for `T` with
+    /// [`enable_userdata_ptr_lookup`].
+    ///
+    /// Returns `Ok(None)` if there is no live userdata of type `T` with this pointer which was
+    /// created while lookups were enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowError` if the userdata is currently mutably borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData};
+    /// # fn main() -> Result<()> {
+    /// struct Handle(u32);
+    /// impl UserData for Handle {}
+    ///
+    /// Lua::new().context(|lua| {
+    ///     lua.enable_userdata_ptr_lookup::<Handle>()?;
+    ///     let handle = lua.create_userdata(Handle(7))?;
+    ///
+    ///     // A C library would be given this pointer, and could later hand it back.
+    ///     let ptr = handle.to_pointer();
+    ///     assert_eq!(lua.try_borrow_userdata_by_ptr(ptr, |h: &Handle| h.0)?, Some(7));
+    ///     assert_eq!(lua.try_borrow_userdata_by_ptr(std::ptr::null(), |h: &Handle| h.0)?, None);
+    ///     Ok(())
+    /// })
+    /// # }
+    /// ```
+    ///
+    /// [`AnyUserData::to_pointer`]: struct.AnyUserData.html#method.to_pointer
+    /// [`enable_userdata_ptr_lookup`]: #method.enable_userdata_ptr_lookup
+    pub fn try_borrow_userdata_by_ptr<T, R, F>(self, ptr: *const c_void, f: F) -> Result<Option<R>>
+    where
+        T: 'static + UserData,
+        F: FnOnce(&T) -> R,
+    {
+        let lookup = match unsafe { self.userdata_ptr_lookup::<T>() } {
+            Some(lookup) => lookup,
+            None => return Ok(None),
+        };
+        match lookup.raw_get(LightUserData(ptr as *mut c_void))? {
+            Value::UserData(ud) => match ud.borrow::<T>() {
+                Ok(data) => Ok(Some(f(&data))),
+                Err(Error::UserDataTypeMismatch) | Err(Error::UserDataDestructed) => Ok(None),
+                Err(err) => Err(err),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a handle to the global environment.
+    pub fn globals(self) -> Table<'lua> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+            ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+            Table(self.pop_ref())
+        }
+    }
+
+    /// Makes the running Rust callback yield `args` instead of returning its results.
+    ///
+    /// This lets Rust functions take part in cooperative scheduling: once the callback returns,
+    /// the coroutine which called it is suspended, and `args` are returned from the
+    /// `coroutine.resume` or [`Thread::resume`] call which resumed it.  The values the callback
+    /// returns are discarded.  When the coroutine is resumed again, the values passed to the
+    /// resume are returned to the Lua code which called the callback.
+    ///
+    /// This fails if the callback cannot yield, because it was not called from Lua code running
+    /// in a coroutine.  If the callback returns an error, the error is raised as usual and nothing
+    /// is yielded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let sleep = lua_context.create_function(|lua, ticks: u32| lua.yield_with(("sleep", ticks)))?;
+    /// lua_context.globals().set("sleep", sleep)?;
+    ///
+    /// let task: Function = lua_context.load(r#"
+    ///     function()
+    ///         local woken_at = sleep(10)
+    ///         return "woken at " .. woken_at
+    ///     end
+    /// "#).eval()?;
+    /// let thread = lua_context.create_thread(task)?;
+    /// assert_eq!(thread.resume::<_, (String, u32)>(())?, ("sleep".to_owned(), 10));
+    /// assert_eq!(thread.resume::<_, String>(12)?, "woken at 12");
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Thread::resume`]: struct.Thread.html#method.resume
+    pub fn yield_with<A: ToLuaMulti<'lua>>(self, args: A) -> Result<()> {
+        unsafe {
+            if ffi::lua_isyieldable(self.state) == 0 {
+                return Err(Error::RuntimeError(
+                    "attempt to yield from a callback which cannot yield".to_owned(),
+                ));
+            }
+        }
+
+        let args = args.to_lua_multi(self)?;
+        let count = args.len() as c_int;
+        let values = self.create_table()?;
+        for (i, value) in args.into_iter().enumerate() {
+            values.raw_set(i as Integer + 1, value)?;
+        }
 
-            Ok(Thread(self.pop_ref()))
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            self.push_ref(&values.0);
+            let id = protect_lua_closure(self.state, 1, 0, |state| {
+                ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+            })?;
+            let extra = extra_data(self.state);
+            if let Some((previous, _)) = (*extra).pending_yield.replace((id, count)) {
+                ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, previous);
+            }
         }
+        Ok(())
     }
 
-    /// Create a Lua userdata object from a custom userdata type.
-    pub fn create_userdata<T>(self, data: T) -> Result<AnyUserData<'lua>>
-    where
-        T: 'static + Send + UserData,
-    {
-        unsafe { self.make_userdata(data) }
+    /// Makes sure the thread this context is running on has room for `n` more values on its
+    /// stack on top of the stack reserve, see [`Lua::check_stack`].
+    ///
+    /// [`Lua::check_stack`]: struct.Lua.html#method.check_stack
+    pub fn check_stack(self, n: usize) -> Result<()> {
+        unsafe { reserve_stack(self.state, n) }
     }
 
-    /// Returns a handle to the global environment.
-    pub fn globals(self) -> Table<'lua> {
+    /// Returns a handle to the thread this context is running on.
+    ///
+    /// Inside a Rust callback, this is the coroutine which called it, or the main thread if the
+    /// callback was not called from a coroutine.
+    pub fn current_thread(self) -> Thread<'lua> {
         unsafe {
             let _sg = StackGuard::new(self.state);
-            assert_stack(self.state, 2);
-            ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
-            Table(self.pop_ref())
+            assert_stack(self.state, 1);
+            ffi::lua_pushthread(self.state);
+            Thread(self.pop_ref())
         }
     }
 
+    /// Returns a copy of the data of type `T` stored in the current thread with
+    /// [`Thread::set_data`], or `None` if there is none.
+    ///
+    /// This makes request scoped context, such as trace ids, available to every Rust callback a
+    /// coroutine calls without passing it through Lua.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// #[derive(Clone)]
+    /// struct TraceId(u64);
+    ///
+    /// let log = lua_context.create_function(|lua, message: String| {
+    ///     let trace = lua.current_thread_data::<TraceId>()?.map_or(0, |id| id.0);
+    ///     Ok(format!("[{}] {}", trace, message))
+    /// })?;
+    /// lua_context.globals().set("log", log)?;
+    ///
+    /// let handler: Function = lua_context.load("function() return log('handled') end").eval()?;
+    /// let thread = lua_context.create_thread(handler)?;
+    /// thread.set_data(TraceId(42))?;
+    /// assert_eq!(thread.resume::<_, String>(())?, "[42] handled");
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Thread::set_data`]: struct.Thread.html#method.set_data
+    pub fn current_thread_data<T: 'static + Clone>(self) -> Result<Option<T>> {
+        self.current_thread().data()
+    }
+
     /// Calls the given function with a `Scope` parameter, giving the function the ability to create
     /// userdata and callbacks from rust types that are !Send or non-'static.
     ///
@@ -262,6 +1493,74 @@ impl<'lua> Context<'lua> {
         f(&Scope::new(unsafe { Context::new(self.state) }))
     }
 
+    /// Returns a future which calls the given async function with a `Scope` parameter, a version of
+    /// [`scope`] whose scope lasts across `.await` points.
+    ///
+    /// Values created through the scope can borrow data, such as request-local state lent to a
+    /// script, and stay valid until the future returned by `f` completes.  Then the scope is
+    /// dropped, which invalidates them as with `scope`.  Dropping the future before it completes
+    /// drops the scope as well.
+    ///
+    /// The returned future must be polled from inside the call to `Lua::context` which created
+    /// this `Context`, like the futures of [`Function::call_async`].
+    ///
+    /// # Safety
+    ///
+    /// Once the future has been polled, it must be either polled to completion or dropped, never
+    /// leaked with `mem::forget` or a reference cycle: the scope is only invalidated when the future
+    /// finishes or is dropped, and until then Lua can keep calling callbacks which borrow data
+    /// that has since gone away.  For the same reason, the output of `f`'s future must not hold
+    /// the `&Scope` reference.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # use std::future::Future;
+    /// # use std::task::{Context as TaskContext, Poll, Waker};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let request_id = String::from("request 1");
+    ///
+    /// let mut future = Box::pin(unsafe {
+    ///     lua_context.scope_async(|scope| {
+    ///         let request_id = &request_id;
+    ///         async move {
+    ///             let id = scope.create_function(move |_, ()| Ok(request_id.clone()))?;
+    ///             lua_context.globals().set("request_id", id)?;
+    ///             // ... await other futures here ...
+    ///             lua_context.load("request_id()").eval::<String>()
+    ///         }
+    ///     })
+    /// });
+    ///
+    /// let mut cx = TaskContext::from_waker(Waker::noop());
+    /// match future.as_mut().poll(&mut cx) {
+    ///     Poll::Ready(result) => assert_eq!(result?, "request 1"),
+    ///     Poll::Pending => unreachable!(),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`scope`]: #method.scope
+    /// [`Function::call_async`]: struct.Function.html#method.call_async
+    pub async unsafe fn scope_async<'scope, F, FR>(self, f: F) -> FR::Output
+    where
+        'lua: 'scope,
+        F: FnOnce(&'scope Scope<'lua, 'scope>) -> FR,
+        FR: Future,
+    {
+        let scope = Scope::new(Context::new(self.state));
+        // The future of `f` is dropped before the scope, and may only keep the reference to the
+        // scope until then, as required by the caller.
+        let scope_ref = &*(&scope as *const Scope<'lua, 'scope>);
+        let output = f(scope_ref).await;
+        drop(scope);
+        output
+    }
+
     /// Attempts to coerce a Lua value into a String in a manner consistent with Lua's internal
     /// behavior.
     ///
@@ -502,20 +1801,183 @@ impl<'lua> Context<'lua> {
     ///
     /// Unlike normal handle values, `RegistryKey`s do not automatically remove themselves on Drop,
     /// but you can call this method to remove any unreachable registry values not manually removed
-    /// by `Lua::remove_registry_value`.
+    /// by `Lua::remove_registry_value`.  This also removes the values of dropped `PtrKey`s.
     pub fn expire_registry_values(self) {
         unsafe {
             let unref_list = mem::replace(
-                &mut *rlua_expect!(
-                    (*extra_data(self.state)).registry_unref_list.lock(),
-                    "unref list poisoned"
-                ),
+                &mut *(*extra_data(self.state)).registry_unref_list.lock(),
                 Some(Vec::new()),
             );
             for id in rlua_expect!(unref_list, "unref list not set") {
                 ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, id);
             }
         }
+        self.expire_ptr_values();
+    }
+
+    /// Creates a key for associating Lua values with `object` by its address, see [`PtrKey`].
+    ///
+    /// The object should not move while the key is in use, for instance by keeping it in a `Box`,
+    /// or another object may end up with the same address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, PtrKey, Result};
+    /// # fn main() -> Result<()> {
+    /// struct Widget {
+    ///     lua_key: Option<PtrKey>,
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// let mut widget = Box::new(Widget { lua_key: None });
+    /// let key = lua_context.create_ptr_key(&*widget);
+    /// lua_context.set_ptr_value(&key, lua_context.create_table()?)?;
+    /// widget.lua_key = Some(key);
+    ///
+    /// let key = widget.lua_key.as_ref().unwrap();
+    /// assert!(lua_context.ptr_value::<Option<rlua::Table>>(key)?.is_some());
+    ///
+    /// // Dropping the widget releases the table.
+    /// drop(widget);
+    /// lua_context.expire_registry_values();
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`PtrKey`]: struct.PtrKey.html
+    pub fn create_ptr_key<T: ?Sized>(self, object: &T) -> PtrKey {
+        // The address may belong to an object whose key was dropped without the value being
+        // expired yet.
+        self.expire_ptr_values();
+        PtrKey {
+            ptr: object as *const T as *const c_void as usize,
+            unref_list: unsafe { (*extra_data(self.state)).ptr_unref_list.clone() },
+        }
+    }
+
+    /// Sets the value associated with the address of a `PtrKey`.  Setting nil removes it.
+    ///
+    /// Returns `Error::MismatchedRegistryKey` if the key was created by an unrelated `Lua`.
+    pub fn set_ptr_value<T: ToLua<'lua>>(self, key: &PtrKey, t: T) -> Result<()> {
+        if !self.owns_ptr_key(key) {
+            return Err(Error::MismatchedRegistryKey);
+        }
+        let t = t.to_lua(self)?;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 3);
+
+            match (*extra_data(self.state)).ptr_values {
+                Some(id) => {
+                    ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                }
+                None if matches!(t, Value::Nil) => return Ok(()),
+                None => {
+                    protect_lua_closure(self.state, 0, 1, |state| ffi::lua_newtable(state))?;
+                    ffi::lua_pushvalue(self.state, -1);
+                    let id = protect_lua_closure(self.state, 1, 0, |state| {
+                        ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                    })?;
+                    (*extra_data(self.state)).ptr_values = Some(id);
+                }
+            }
+
+            self.push_value(t)?;
+            let ptr = key.as_ptr();
+            protect_lua_closure(self.state, 2, 0, |state| {
+                ffi::lua_rawsetp(state, -2, ptr);
+            })
+        }
+    }
+
+    /// Returns the value associated with the address of a `PtrKey`, which is nil if none was set.
+    ///
+    /// Returns `Error::MismatchedRegistryKey` if the key was created by an unrelated `Lua`.
+    pub fn ptr_value<T: FromLua<'lua>>(self, key: &PtrKey) -> Result<T> {
+        if !self.owns_ptr_key(key) {
+            return Err(Error::MismatchedRegistryKey);
+        }
+        let value = unsafe {
+            match (*extra_data(self.state)).ptr_values {
+                Some(id) => {
+                    let _sg = StackGuard::new(self.state);
+                    assert_stack(self.state, 2);
+                    ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                    ffi::lua_rawgetp(self.state, -1, key.as_ptr());
+                    self.pop_value()
+                }
+                None => Nil,
+            }
+        };
+        T::from_lua(value, self)
+    }
+
+    fn owns_ptr_key(self, key: &PtrKey) -> bool {
+        unsafe { Arc::ptr_eq(&key.unref_list, &(*extra_data(self.state)).ptr_unref_list) }
+    }
+
+    // Removes the values of the dropped `PtrKey`s.
+    fn expire_ptr_values(self) {
+        unsafe {
+            let extra = extra_data(self.state);
+            let unref_list = (*extra).ptr_unref_list.lock().replace(Vec::new());
+            let unref_list = rlua_expect!(unref_list, "unref list not set");
+            if let Some(id) = (*extra).ptr_values {
+                let _sg = StackGuard::new(self.state);
+                assert_stack(self.state, 3);
+                ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                for ptr in unref_list {
+                    // Assigning nil to an existing field never allocates, unlike assigning to a
+                    // missing one.
+                    if ffi::lua_rawgetp(self.state, -1, ptr as *const c_void) != ffi::LUA_TNIL {
+                        ffi::lua_pushnil(self.state);
+                        ffi::lua_rawsetp(self.state, -3, ptr as *const c_void);
+                    }
+                    ffi::lua_pop(self.state, 1);
+                }
+            }
+        }
+    }
+
+    /// Maps every value of a data-only table in parallel, using up to `workers` separate Lua states
+    /// on their own threads.
+    ///
+    /// The entries of `table` are split into shards, and each shard is deep copied into the `Lua`
+    /// instance of a worker thread where `func` is called once per value.  The results are copied
+    /// back and merged into a new table with the same keys as the original.
+    ///
+    /// The worker threads and their states belong to this state and are reused by later calls, so
+    /// anything `func` leaves in a worker state, such as globals, may be seen by later calls.  A
+    /// worker state is only replaced after a panic unwound out of it.  If `func` panics, the panic
+    /// is resumed once every shard has finished.
+    ///
+    /// Only plain data can be copied between states: keys, values and results may be nil,
+    /// booleans, numbers, strings, or (non-recursive) tables of those.  Anything else results in a
+    /// `FromLuaConversionError`.  If `func` fails for any value, one of the errors is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let input = lua_context.create_sequence_from(1..=100)?;
+    /// let squares = lua_context.parallel_map(input, 4, |_, value| match value {
+    ///     Value::Integer(i) => Ok(Value::Integer(i * i)),
+    ///     v => Ok(v),
+    /// })?;
+    /// assert_eq!(squares.get::<_, i64>(12)?, 144);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn parallel_map<F>(self, table: Table<'lua>, workers: usize, func: F) -> Result<Table<'lua>>
+    where
+        F: 'static + Send + Sync + for<'a> Fn(Context<'a>, Value<'a>) -> Result<Value<'a>>,
+    {
+        parallel::parallel_map(self, table, workers, func)
     }
 
     // Uses 2 stack spaces, does not call checkstack
@@ -668,6 +2130,15 @@ impl<'lua> Context<'lua> {
     }
 
     pub(crate) unsafe fn userdata_metatable<T: 'static + UserData>(self) -> Result<c_int> {
+        self.userdata_metatable_listed::<T>(true)
+    }
+
+    // Types which are only `listed` are recorded for `Lua::registered_types`, which leaves out the
+    // types rlua uses internally.
+    unsafe fn userdata_metatable_listed<T: 'static + UserData>(
+        self,
+        listed: bool,
+    ) -> Result<c_int> {
         if let Some(table_id) = (*extra_data(self.state))
             .registered_userdata
             .get(&TypeId::of::<T>())
@@ -676,11 +2147,27 @@ impl<'lua> Context<'lua> {
         }
 
         let _sg = StackGuard::new(self.state);
-        assert_stack(self.state, 8);
+        assert_stack(self.state, 10);
 
         let mut methods = StaticUserDataMethods::default();
         T::add_methods(&mut methods);
 
+        let mut info = RegisteredType {
+            type_name: type_name::<T>(),
+            methods: methods
+                .methods
+                .keys()
+                .map(|k| StdString::from_utf8_lossy(k).into_owned())
+                .collect(),
+            meta_methods: methods
+                .meta_methods
+                .keys()
+                .map(|k| StdString::from_utf8_lossy(k.name()).into_owned())
+                .collect(),
+        };
+        info.methods.sort();
+        info.meta_methods.sort();
+
         protect_lua_closure(self.state, 0, 1, |state| {
             ffi::lua_newtable(state);
         })?;
@@ -693,6 +2180,22 @@ impl<'lua> Context<'lua> {
             })?;
         }
 
+        let has_getters = !methods.field_getters.is_empty();
+        let has_setters = !methods.field_setters.is_empty();
+        if has_getters || has_setters {
+            let getters = self.create_callback_table(methods.field_getters)?;
+            let setters = self.create_callback_table(methods.field_setters)?;
+            self.push_ref(&getters.0);
+            self.push_ref(&setters.0);
+            init_userdata_fields(
+                self.state,
+                -3,
+                if has_getters { Some(-2) } else { None },
+                if has_setters { Some(-1) } else { None },
+            )?;
+            ffi::lua_pop(self.state, 2);
+        }
+
         if methods.methods.is_empty() {
             init_userdata_metatable::<RefCell<T>>(self.state, -1, None)?;
         } else {
@@ -711,12 +2214,22 @@ impl<'lua> Context<'lua> {
             ffi::lua_pop(self.state, 1);
         }
 
+        // Replaces the destructor set above with one which can defer dropping the value.
+        push_string(self.state, "__gc")?;
+        ffi::lua_pushcfunction(self.state, finalize_userdata::<T>);
+        protect_lua_closure(self.state, 3, 1, |state| {
+            ffi::lua_rawset(state, -3);
+        })?;
+
         let id = protect_lua_closure(self.state, 1, 0, |state| {
             ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
         })?;
-        (*extra_data(self.state))
-            .registered_userdata
-            .insert(TypeId::of::<T>(), id);
+        let extra = extra_data(self.state);
+        (*extra).registered_userdata.insert(TypeId::of::<T>(), id);
+        (*extra).userdata_type_names.insert(id, type_name::<T>());
+        if listed {
+            (*extra).registered_types.push(info);
+        }
         Ok(id)
     }
 
@@ -733,13 +2246,15 @@ impl<'lua> Context<'lua> {
     // and will reduce the number of hacks required in Context and Scope.
     pub(crate) fn create_callback(self, func: Callback<'lua, 'static>) -> Result<Function<'lua>> {
         unsafe extern "C" fn call_callback(state: *mut ffi::lua_State) -> c_int {
-            callback_error(state, |nargs| {
+            let mut yielding = false;
+            let nresults = callback_error(state, |nargs| {
                 if ffi::lua_type(state, ffi::lua_upvalueindex(1)) == ffi::LUA_TNIL {
                     return Err(Error::CallbackDestructed);
                 }
 
-                if nargs < ffi::LUA_MINSTACK {
-                    check_stack(state, ffi::LUA_MINSTACK - nargs)?;
+                let reserve = (*extra_data(state)).stack_reserve;
+                if nargs < reserve {
+                    check_stack(state, reserve - nargs)?;
                 }
 
                 let context = Context::new(state);
@@ -752,7 +2267,64 @@ impl<'lua> Context<'lua> {
 
                 let func = get_userdata::<Callback>(state, ffi::lua_upvalueindex(1));
 
-                let results = (*func)(context, args)?;
+                // Callbacks called by this one have their own pending yield.
+                let extra = extra_data(state);
+                let outer_yield = (*extra).pending_yield.take();
+                let callback =
+                    ffi::lua_touserdata(state, ffi::lua_upvalueindex(1)) as *const c_void;
+                let logged = (*extra)
+                    .logged_callbacks
+                    .get(&callback)
+                    .map(|name| (name.clone(), introspect::format_values(&args)));
+                let start = (*extra).callback_stats.as_ref().map(|_| Instant::now());
+                let results = (*func)(context, args);
+                if let (Some(start), Some(stats)) = (start, (*extra).callback_stats.as_mut()) {
+                    let totals = stats.entry(callback).or_default();
+                    totals.calls += 1;
+                    totals.time += start.elapsed();
+                }
+                if let Some((function, arguments)) = logged {
+                    if let Some(recording) = (*extra).recording.as_mut() {
+                        recording.push(RecordedCall::new(
+                            function.clone(),
+                            arguments.clone(),
+                            &results,
+                        ));
+                    }
+                    let results = match &results {
+                        Ok(results) => Ok(introspect::format_values(results)),
+                        Err(err) => Err(err.to_string()),
+                    };
+                    let log = &mut (*extra).call_log;
+                    if (*extra).call_log_capacity > 0 {
+                        if log.len() == (*extra).call_log_capacity {
+                            log.pop_front();
+                        }
+                        log.push_back(LoggedCall {
+                            function,
+                            arguments,
+                            results,
+                        });
+                    }
+                }
+                let pending_yield = mem::replace(&mut (*extra).pending_yield, outer_yield);
+
+                if let Some((id, count)) = pending_yield {
+                    if let Err(err) = results.and_then(|_| check_stack(state, count + 1)) {
+                        ffi::luaL_unref(state, ffi::LUA_REGISTRYINDEX, id);
+                        return Err(err);
+                    }
+                    ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                    ffi::luaL_unref(state, ffi::LUA_REGISTRYINDEX, id);
+                    for i in 1..=count {
+                        ffi::lua_rawgeti(state, -i, i as ffi::lua_Integer);
+                    }
+                    ffi::lua_remove(state, -count - 1);
+                    yielding = true;
+                    return Ok(count);
+                }
+
+                let results = results?;
                 let nresults = results.len() as c_int;
 
                 check_stack(state, nresults)?;
@@ -761,7 +2333,15 @@ impl<'lua> Context<'lua> {
                 }
 
                 Ok(nresults)
-            })
+            });
+
+            // Yielding longjmps, so this must happen outside of `callback_error`, where no Rust
+            // values are left to drop.
+            if yielding {
+                ffi::lua_yield(state, nresults)
+            } else {
+                nresults
+            }
         }
 
         unsafe {
@@ -785,15 +2365,130 @@ impl<'lua> Context<'lua> {
         }
     }
 
+    /// Tags a Rust function so that its calls are recorded in [`Lua::call_log`] under `name`, or
+    /// removes the tag if `name` is `None`.
+    ///
+    /// Each call to a tagged function records its arguments, and its results or the error it
+    /// raised, printed as described in [`LoggedCall`].  Only the most recent calls are kept, see
+    /// [`Lua::set_call_log_capacity`].  Tags can be added and removed at any time, which makes it
+    /// possible to look into a misbehaving script without restarting it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RuntimeError` if `function` is not a Rust function.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.context(|lua_context| {
+    ///     let spawn = lua_context.create_function(|_, (kind, _): (String, rlua::Table)| {
+    ///         Ok(format!("{} #1", kind))
+    ///     })?;
+    ///     lua_context.set_call_logging(&spawn, Some("spawn"))?;
+    ///     lua_context.globals().set("spawn", spawn)?;
+    ///     lua_context.load("spawn('orc', { hp = 10 })").exec()
+    /// })?;
+    ///
+    /// let log = lua.call_log();
+    /// assert_eq!(log[0].function, "spawn");
+    /// assert_eq!(log[0].arguments, r#""orc", {hp = 10}"#);
+    /// assert_eq!(log[0].results, Ok(r#""orc #1""#.to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Lua::call_log`]: struct.Lua.html#method.call_log
+    /// [`Lua::set_call_log_capacity`]: struct.Lua.html#method.set_call_log_capacity
+    /// [`LoggedCall`]: struct.LoggedCall.html
+    pub fn set_call_logging(self, function: &Function<'lua>, name: Option<&str>) -> Result<()> {
+        let callback = self.rust_callback_ptr(function).ok_or_else(|| {
+            Error::RuntimeError("call logging is only available for Rust functions".to_owned())
+        })?;
+        let logged = unsafe { &mut (*extra_data(self.state)).logged_callbacks };
+        match name {
+            Some(name) => logged.insert(callback, name.to_owned()),
+            None => logged.remove(&callback),
+        };
+        Ok(())
+    }
+
+    /// Creates stand-ins for the host functions of a [`Recording`], which replay the recorded calls
+    /// so that a script can be run again without its host.
+    ///
+    /// Returns a table holding a function under the name of each recorded function, which the
+    /// caller installs wherever the script expects it.  The functions must be called in the
+    /// recorded order with the same arguments, each call returning the recorded results or raising
+    /// the recorded error.  Any other call raises a `RuntimeError` reporting where the script
+    /// diverged from the recording.
+    ///
+    /// See [`Lua::start_recording`] for an example.
+    ///
+    /// [`Recording`]: struct.Recording.html
+    /// [`Lua::start_recording`]: struct.Lua.html#method.start_recording
+    pub fn create_replay_functions(self, recording: Recording) -> Result<Table<'lua>> {
+        replay::create_replay_functions(self, recording)
+    }
+
+    // Returns true if the given function was created by `create_callback`.
+    pub(crate) fn is_rust_callback(self, function: &Function<'lua>) -> bool {
+        self.rust_callback_ptr(function).is_some()
+    }
+
+    // Returns the address of the callback userdata of a function created by `create_callback`,
+    // which identifies it in the callback statistics.
+    pub(crate) fn rust_callback_ptr(self, function: &Function<'lua>) -> Option<*const c_void> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 4);
+
+            self.push_ref(&function.0);
+            if ffi::lua_iscfunction(self.state, -1) == 0
+                || ffi::lua_getupvalue(self.state, -1, 1).is_null()
+                || ffi::lua_getmetatable(self.state, -1) == 0
+            {
+                return None;
+            }
+
+            ffi::lua_pushlightuserdata(
+                self.state,
+                &FUNCTION_METATABLE_REGISTRY_KEY as *const u8 as *mut c_void,
+            );
+            ffi::lua_rawget(self.state, ffi::LUA_REGISTRYINDEX);
+            if ffi::lua_rawequal(self.state, -1, -2) == 0 {
+                return None;
+            }
+            Some(ffi::lua_touserdata(self.state, -3) as *const c_void)
+        }
+    }
+
     // Does not require Send bounds, which can lead to unsafety.
     pub(crate) unsafe fn make_userdata<T>(self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: 'static + UserData,
+    {
+        self.make_userdata_listed(data, true)
+    }
+
+    // Creates userdata of a type rlua uses internally, which is not listed by
+    // `Lua::registered_types`.
+    pub(crate) fn create_internal_userdata<T>(self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: 'static + UserData,
+    {
+        unsafe { self.make_userdata_listed(data, false) }
+    }
+
+    unsafe fn make_userdata_listed<T>(self, data: T, listed: bool) -> Result<AnyUserData<'lua>>
     where
         T: 'static + UserData,
     {
         let _sg = StackGuard::new(self.state);
         assert_stack(self.state, 4);
 
-        let ud_index = self.userdata_metatable::<T>()?;
+        let ud_index = self.userdata_metatable_listed::<T>(listed)?;
         push_userdata::<RefCell<T>>(self.state, RefCell::new(data))?;
 
         ffi::lua_rawgeti(
@@ -803,7 +2498,34 @@ impl<'lua> Context<'lua> {
         );
         ffi::lua_setmetatable(self.state, -2);
 
-        Ok(AnyUserData(self.pop_ref()))
+        let ud = AnyUserData(self.pop_ref());
+        if let Some(lookup) = self.userdata_ptr_lookup::<T>() {
+            lookup.raw_set(LightUserData(ud.to_pointer() as *mut c_void), ud.clone())?;
+        }
+        Ok(ud)
+    }
+
+    // Creates a table of functions from a map of callbacks.
+    fn create_callback_table(
+        self,
+        callbacks: HashMap<Vec<u8>, Callback<'lua, 'static>>,
+    ) -> Result<Table<'lua>> {
+        let table = self.create_table()?;
+        for (k, f) in callbacks {
+            table.raw_set(self.create_string(&k)?, self.create_callback(f)?)?;
+        }
+        Ok(table)
+    }
+
+    // Returns the pointer lookup table for `T`, if lookups are enabled for it.
+    pub(crate) unsafe fn userdata_ptr_lookup<T: 'static + UserData>(self) -> Option<Table<'lua>> {
+        let id = *(*extra_data(self.state))
+            .userdata_ptr_lookup
+            .get(&TypeId::of::<T>())?;
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 1);
+        ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+        Some(Table(self.pop_ref()))
     }
 
     pub(crate) unsafe fn new(state: *mut ffi::lua_State) -> Context<'lua> {
@@ -819,27 +2541,19 @@ impl<'lua> Context<'lua> {
         source: &[u8],
         name: Option<&CString>,
         env: Option<Value<'lua>>,
+        mode: *const c_char,
     ) -> Result<Function<'lua>> {
         unsafe {
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 1);
-            let source = source.as_ref();
-
-            match if let Some(name) = name {
-                ffi::luaL_loadbuffer(
-                    self.state,
-                    source.as_ptr() as *const c_char,
-                    source.len(),
-                    name.as_ptr() as *const c_char,
-                )
-            } else {
-                ffi::luaL_loadbuffer(
-                    self.state,
-                    source.as_ptr() as *const c_char,
-                    source.len(),
-                    ptr::null(),
-                )
-            } {
+
+            match ffi::luaL_loadbufferx(
+                self.state,
+                source.as_ptr() as *const c_char,
+                source.len(),
+                name.map(|n| n.as_ptr()).unwrap_or(ptr::null()),
+                mode,
+            ) {
                 ffi::LUA_OK => {
                     if let Some(env) = env {
                         self.push_value(env)?;
@@ -853,6 +2567,17 @@ impl<'lua> Context<'lua> {
     }
 }
 
+// How much source `Context::load_from_reader` reads at a time.
+const READER_BUFFER_SIZE: usize = 16 * 1024;
+
+fn chunk_name(name: &[u8]) -> Result<CString> {
+    CString::new(name.to_vec()).map_err(|e| Error::ToLuaConversionError {
+        from: "&str",
+        to: "string",
+        message: Some(e.to_string()),
+    })
+}
+
 /// Returned from [`Context::load`] and is used to finalize loading and executing Lua main chunks.
 ///
 /// [`Context::load`]: struct.Context.html#method.load
@@ -867,13 +2592,7 @@ pub struct Chunk<'lua, 'a> {
 impl<'lua, 'a> Chunk<'lua, 'a> {
     /// Sets the name of this chunk, which results in more informative error traces.
     pub fn set_name<S: ?Sized + AsRef<[u8]>>(mut self, name: &S) -> Result<Chunk<'lua, 'a>> {
-        let name =
-            CString::new(name.as_ref().to_vec()).map_err(|e| Error::ToLuaConversionError {
-                from: "&str",
-                to: "string",
-                message: Some(e.to_string()),
-            })?;
-        self.name = Some(name);
+        self.name = Some(chunk_name(name.as_ref())?);
         Ok(self)
     }
 
@@ -912,10 +2631,12 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
         // actual lua repl does.
         let mut expression_source = b"return ".to_vec();
         expression_source.extend(self.source.as_ref());
-        if let Ok(function) =
-            self.context
-                .load_chunk(&expression_source, self.name.as_ref(), self.env.clone())
-        {
+        if let Ok(function) = self.context.load_chunk(
+            &expression_source,
+            self.name.as_ref(),
+            self.env.clone(),
+            cstr!("t"),
+        ) {
             function.call(())
         } else {
             self.call(())
@@ -934,7 +2655,7 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
     /// This simply compiles the chunk without actually executing it.  
     pub fn into_function(self) -> Result<Function<'lua>> {
         self.context
-            .load_chunk(self.source, self.name.as_ref(), self.env)
+            .load_chunk(self.source, self.name.as_ref(), self.env, cstr!("t"))
     }
 }
 
@@ -956,9 +2677,16 @@ unsafe fn ref_stack_pop(extra: *mut ExtraData) -> c_int {
     }
 }
 
+// The pending future of a call to an async function.
+struct AsyncFuture<R>(Option<Pin<Box<dyn Future<Output = Result<R>> + Send>>>);
+
+impl<R: 'static> UserData for AsyncFuture<R> {}
+
 struct StaticUserDataMethods<'lua, T: 'static + UserData> {
     methods: HashMap<Vec<u8>, Callback<'lua, 'static>>,
     meta_methods: HashMap<MetaMethod, Callback<'lua, 'static>>,
+    field_getters: HashMap<Vec<u8>, Callback<'lua, 'static>>,
+    field_setters: HashMap<Vec<u8>, Callback<'lua, 'static>>,
     _type: PhantomData<T>,
 }
 
@@ -967,6 +2695,8 @@ impl<'lua, T: 'static + UserData> Default for StaticUserDataMethods<'lua, T> {
         StaticUserDataMethods {
             methods: HashMap::new(),
             meta_methods: HashMap::new(),
+            field_getters: HashMap::new(),
+            field_setters: HashMap::new(),
             _type: PhantomData,
         }
     }
@@ -1017,6 +2747,56 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
             .insert(name.as_ref().to_vec(), Self::box_function_mut(function));
     }
 
+    fn add_field_method_get<S, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        M: 'static + Send + Fn(Context<'lua>, &T) -> Result<R>,
+    {
+        self.field_getters.insert(
+            name.as_ref().to_vec(),
+            Self::box_method(move |lua, data, ()| method(lua, data)),
+        );
+    }
+
+    fn add_field_method_set<S, A, M>(&mut self, name: &S, mut method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        M: 'static + Send + FnMut(Context<'lua>, &mut T, A) -> Result<()>,
+    {
+        self.field_setters.insert(
+            name.as_ref().to_vec(),
+            Self::box_method_mut(move |lua, data, value: A| method(lua, data, value)),
+        );
+    }
+
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, AnyUserData<'lua>) -> Result<R>,
+    {
+        self.field_getters.insert(
+            name.as_ref().to_vec(),
+            Self::box_function(move |lua, ud: AnyUserData<'lua>| function(lua, ud)),
+        );
+    }
+
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, mut function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, AnyUserData<'lua>, A) -> Result<()>,
+    {
+        self.field_setters.insert(
+            name.as_ref().to_vec(),
+            Self::box_function_mut(move |lua, (ud, value): (AnyUserData<'lua>, A)| {
+                function(lua, ud, value)
+            }),
+        );
+    }
+
     fn add_meta_method<A, R, M>(&mut self, meta: MetaMethod, method: M)
     where
         A: FromLuaMulti<'lua>,
@@ -1,17 +1,28 @@
-use std::collections::{BTreeMap, HashMap};
-use std::ffi::{CStr, CString};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::hash::{BuildHasher, Hash};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::string::String as StdString;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use num_traits::cast;
+use num_traits::{cast, NumCast};
 
 use crate::context::Context;
 use crate::error::{Error, Result};
 use crate::function::Function;
+use crate::lua::{extra_data, ConversionOptions, DurationFormat, ExtraData};
+use crate::multi::{Strict, TableTuple};
 use crate::string::String;
-use crate::table::Table;
+use crate::table::{Table, TypedTable};
 use crate::thread::Thread;
-use crate::types::{LightUserData, Number};
+use crate::types::{Integer, LightUserData, Number};
 use crate::userdata::{AnyUserData, UserData};
 use crate::value::{FromLua, Nil, ToLua, Value};
 
@@ -64,6 +75,22 @@ impl<'lua> FromLua<'lua> for Table<'lua> {
     }
 }
 
+impl<'lua, K, V> ToLua<'lua> for TypedTable<'lua, K, V> {
+    fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
+        Ok(Value::Table(self.into_table()))
+    }
+}
+
+impl<'lua, K, V> FromLua<'lua> for TypedTable<'lua, K, V>
+where
+    K: Eq + Hash + Clone + ToLua<'lua> + FromLua<'lua>,
+    V: ToLua<'lua> + FromLua<'lua>,
+{
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<TypedTable<'lua, K, V>> {
+        Table::from_lua(value, lua).map(TypedTable::new)
+    }
+}
+
 impl<'lua> ToLua<'lua> for Function<'lua> {
     fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
         Ok(Value::Function(self))
@@ -215,7 +242,7 @@ impl<'lua> FromLua<'lua> for StdString {
     }
 }
 
-impl<'lua, 'a> ToLua<'lua> for &'a str {
+impl<'lua> ToLua<'lua> for &str {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
         Ok(Value::String(lua.create_string(self)?))
     }
@@ -249,12 +276,87 @@ impl<'lua> FromLua<'lua> for CString {
     }
 }
 
-impl<'lua, 'a> ToLua<'lua> for &'a CStr {
+impl<'lua> ToLua<'lua> for &CStr {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
         Ok(Value::String(lua.create_string(self.to_bytes())?))
     }
 }
 
+// Paths and OS strings are arbitrary bytes on Unix, which convert to and from Lua strings as they
+// are.  Elsewhere they are converted through UTF-8, which is lossy for strings that are not valid
+// Unicode, such as Windows paths with unpaired surrogates.
+#[cfg(unix)]
+fn os_str_to_lua<'lua>(lua: Context<'lua>, s: &OsStr) -> Result<Value<'lua>> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(Value::String(lua.create_string(s.as_bytes())?))
+}
+
+#[cfg(not(unix))]
+fn os_str_to_lua<'lua>(lua: Context<'lua>, s: &OsStr) -> Result<Value<'lua>> {
+    Ok(Value::String(lua.create_string(&*s.to_string_lossy())?))
+}
+
+fn os_string_from_lua<'lua>(
+    value: Value<'lua>,
+    lua: Context<'lua>,
+    to: &'static str,
+) -> Result<OsString> {
+    let ty = value.type_name();
+    let string = lua
+        .coerce_string(value)?
+        .ok_or_else(|| Error::FromLuaConversionError {
+            from: ty,
+            to,
+            message: Some("expected string or number".to_string()),
+        })?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(OsString::from_vec(string.as_bytes().to_vec()))
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(OsString::from(string.to_str()?))
+    }
+}
+
+impl<'lua> ToLua<'lua> for OsString {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        os_str_to_lua(lua, &self)
+    }
+}
+
+impl<'lua> FromLua<'lua> for OsString {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        os_string_from_lua(value, lua, "OsString")
+    }
+}
+
+impl<'lua> ToLua<'lua> for &OsStr {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        os_str_to_lua(lua, self)
+    }
+}
+
+impl<'lua> ToLua<'lua> for PathBuf {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        os_str_to_lua(lua, self.as_os_str())
+    }
+}
+
+impl<'lua> FromLua<'lua> for PathBuf {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        os_string_from_lua(value, lua, "PathBuf").map(PathBuf::from)
+    }
+}
+
+impl<'lua> ToLua<'lua> for &Path {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        os_str_to_lua(lua, self.as_os_str())
+    }
+}
+
 macro_rules! lua_convert_int {
     ($x:ty) => {
         impl<'lua> ToLua<'lua> for $x {
@@ -276,6 +378,9 @@ macro_rules! lua_convert_int {
         impl<'lua> FromLua<'lua> for $x {
             fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
                 let ty = value.type_name();
+                if conversion_options(lua).strict_numbers {
+                    return strict_integer(value, stringify!($x));
+                }
                 (if let Some(i) = lua.coerce_integer(value.clone())? {
                     cast(i)
                 } else {
@@ -323,6 +428,19 @@ macro_rules! lua_convert_float {
         impl<'lua> FromLua<'lua> for $x {
             fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
                 let ty = value.type_name();
+                if conversion_options(lua).strict_numbers {
+                    let n = strict_number(value, stringify!($x))?;
+                    let x = n as $x;
+                    return if x as Number == n || n.is_nan() {
+                        Ok(x)
+                    } else {
+                        Err(Error::FromLuaConversionError {
+                            from: ty,
+                            to: stringify!($x),
+                            message: Some("number cannot be represented exactly".to_owned()),
+                        })
+                    };
+                }
                 lua.coerce_number(value)?
                     .ok_or_else(|| Error::FromLuaConversionError {
                         from: ty,
@@ -344,16 +462,230 @@ macro_rules! lua_convert_float {
 lua_convert_float!(f32);
 lua_convert_float!(f64);
 
+// Converts a number to an integer type without coercing strings, truncating or wrapping, for
+// `ConversionOptions::strict_numbers`.
+fn strict_integer<T: NumCast>(value: Value, to: &'static str) -> Result<T> {
+    let error = |message: &str| Error::FromLuaConversionError {
+        from: value.type_name(),
+        to,
+        message: Some(message.to_owned()),
+    };
+    let converted = match value {
+        Value::Integer(i) => cast(i),
+        Value::Number(n) if n.is_finite() && n.fract() != 0.0 => {
+            return Err(error("number has a fractional part"))
+        }
+        Value::Number(n) => cast(n),
+        _ => return Err(error("expected number")),
+    };
+    converted.ok_or_else(|| error("out of range"))
+}
+
+// Converts a number to a float without coercing strings or rounding integers, for
+// `ConversionOptions::strict_numbers`.
+fn strict_number(value: Value, to: &'static str) -> Result<Number> {
+    let error = |message: &str| Error::FromLuaConversionError {
+        from: value.type_name(),
+        to,
+        message: Some(message.to_owned()),
+    };
+    match value {
+        Value::Integer(i) => {
+            let n = i as Number;
+            if cast::<Number, Integer>(n) == Some(i) {
+                Ok(n)
+            } else {
+                Err(error("integer cannot be represented exactly"))
+            }
+        }
+        Value::Number(n) => Ok(n),
+        _ => Err(error("expected number")),
+    }
+}
+
+macro_rules! lua_convert_nonzero {
+    ($x:ty, $int:ty) => {
+        impl<'lua> ToLua<'lua> for $x {
+            fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+                self.get().to_lua(lua)
+            }
+        }
+
+        impl<'lua> FromLua<'lua> for $x {
+            fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+                let ty = value.type_name();
+                <$x>::new(<$int>::from_lua(value, lua)?).ok_or_else(|| {
+                    Error::FromLuaConversionError {
+                        from: ty,
+                        to: stringify!($x),
+                        message: Some("expected a non-zero number".to_owned()),
+                    }
+                })
+            }
+        }
+    };
+}
+
+lua_convert_nonzero!(NonZeroI8, i8);
+lua_convert_nonzero!(NonZeroU8, u8);
+lua_convert_nonzero!(NonZeroI16, i16);
+lua_convert_nonzero!(NonZeroU16, u16);
+lua_convert_nonzero!(NonZeroI32, i32);
+lua_convert_nonzero!(NonZeroU32, u32);
+lua_convert_nonzero!(NonZeroI64, i64);
+lua_convert_nonzero!(NonZeroU64, u64);
+lua_convert_nonzero!(NonZeroI128, i128);
+lua_convert_nonzero!(NonZeroU128, u128);
+lua_convert_nonzero!(NonZeroIsize, isize);
+lua_convert_nonzero!(NonZeroUsize, usize);
+
+// Converts a duration, negated if `negative`, in the format set with `Lua::set_duration_format`.
+fn duration_to_lua<'lua>(
+    lua: Context<'lua>,
+    duration: Duration,
+    negative: bool,
+    from: &'static str,
+) -> Result<Value<'lua>> {
+    let sign = if negative { -1 } else { 1 };
+    match unsafe { (*extra_data(lua.state)).duration_format } {
+        DurationFormat::Seconds => Ok(Value::Number(sign as Number * duration.as_secs_f64())),
+        DurationFormat::Milliseconds => {
+            let millis: Integer =
+                duration
+                    .as_millis()
+                    .try_into()
+                    .map_err(|_| Error::ToLuaConversionError {
+                        from,
+                        to: "integer",
+                        message: Some("out of range".to_owned()),
+                    })?;
+            Ok(Value::Integer(sign * millis))
+        }
+    }
+}
+
+// Reads a duration in the format set with `Lua::set_duration_format`, returning its length and
+// whether it is negative.
+fn duration_from_lua<'lua>(
+    value: Value<'lua>,
+    lua: Context<'lua>,
+    to: &'static str,
+) -> Result<(Duration, bool)> {
+    let ty = value.type_name();
+    let number = lua
+        .coerce_number(value)?
+        .ok_or_else(|| Error::FromLuaConversionError {
+            from: ty,
+            to,
+            message: Some("expected number or string coercible to number".to_string()),
+        })?;
+    let seconds = match unsafe { (*extra_data(lua.state)).duration_format } {
+        DurationFormat::Seconds => number,
+        DurationFormat::Milliseconds => number / 1000.0,
+    };
+    Duration::try_from_secs_f64(seconds.abs())
+        .map(|duration| (duration, seconds < 0.0))
+        .map_err(|_| Error::FromLuaConversionError {
+            from: ty,
+            to,
+            message: Some("number out of range".to_owned()),
+        })
+}
+
+impl<'lua> ToLua<'lua> for Duration {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        duration_to_lua(lua, self, false, "Duration")
+    }
+}
+
+impl<'lua> FromLua<'lua> for Duration {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        let ty = value.type_name();
+        match duration_from_lua(value, lua, "Duration")? {
+            (duration, false) => Ok(duration),
+            (_, true) => Err(Error::FromLuaConversionError {
+                from: ty,
+                to: "Duration",
+                message: Some("negative duration".to_owned()),
+            }),
+        }
+    }
+}
+
+impl<'lua> ToLua<'lua> for SystemTime {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        match self.duration_since(UNIX_EPOCH) {
+            Ok(since) => duration_to_lua(lua, since, false, "SystemTime"),
+            Err(err) => duration_to_lua(lua, err.duration(), true, "SystemTime"),
+        }
+    }
+}
+
+impl<'lua> FromLua<'lua> for SystemTime {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        let ty = value.type_name();
+        let time = match duration_from_lua(value, lua, "SystemTime")? {
+            (since, false) => UNIX_EPOCH.checked_add(since),
+            (before, true) => UNIX_EPOCH.checked_sub(before),
+        };
+        time.ok_or_else(|| Error::FromLuaConversionError {
+            from: ty,
+            to: "SystemTime",
+            message: Some("time out of range".to_owned()),
+        })
+    }
+}
+
+macro_rules! lua_convert_parse {
+    ($x:ty, $what:expr) => {
+        impl<'lua> ToLua<'lua> for $x {
+            fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+                Ok(Value::String(lua.create_string(&self.to_string())?))
+            }
+        }
+
+        impl<'lua> FromLua<'lua> for $x {
+            fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+                let ty = value.type_name();
+                let string =
+                    lua.coerce_string(value)?
+                        .ok_or_else(|| Error::FromLuaConversionError {
+                            from: ty,
+                            to: stringify!($x),
+                            message: Some("expected string".to_string()),
+                        })?;
+                string
+                    .to_str()?
+                    .parse()
+                    .map_err(|_| Error::FromLuaConversionError {
+                        from: ty,
+                        to: stringify!($x),
+                        message: Some(format!("invalid {}", $what)),
+                    })
+            }
+        }
+    };
+}
+
+lua_convert_parse!(IpAddr, "IP address");
+lua_convert_parse!(Ipv4Addr, "IPv4 address");
+lua_convert_parse!(Ipv6Addr, "IPv6 address");
+lua_convert_parse!(SocketAddr, "socket address");
+lua_convert_parse!(SocketAddrV4, "IPv4 socket address");
+lua_convert_parse!(SocketAddrV6, "IPv6 socket address");
+
 impl<'lua, T: ToLua<'lua>> ToLua<'lua> for Vec<T> {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
-        Ok(Value::Table(lua.create_sequence_from(self)?))
+        nested(lua, || Ok(Value::Table(lua.create_sequence_from(self)?)))
     }
 }
 
 impl<'lua, T: FromLua<'lua>> FromLua<'lua> for Vec<T> {
-    fn from_lua(value: Value<'lua>, _: Context<'lua>) -> Result<Self> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
         if let Value::Table(table) = value {
-            table.sequence_values().collect()
+            nested(lua, || {
+                limit_entries(lua, table.sequence_values()).collect()
+            })
         } else {
             Err(Error::FromLuaConversionError {
                 from: value.type_name(),
@@ -364,20 +696,113 @@ impl<'lua, T: FromLua<'lua>> FromLua<'lua> for Vec<T> {
     }
 }
 
+impl<'lua, T: ToLua<'lua>, const N: usize> ToLua<'lua> for [T; N] {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        nested(lua, || Ok(Value::Table(lua.create_sequence_from(self)?)))
+    }
+}
+
+impl<'lua, T: FromLua<'lua>, const N: usize> FromLua<'lua> for [T; N] {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        if let Value::Table(table) = value {
+            let values = nested(lua, || {
+                limit_entries(lua, table.sequence_values().take(N + 1)).collect::<Result<Vec<T>>>()
+            })?;
+            values
+                .try_into()
+                .map_err(|values: Vec<T>| Error::FromLuaConversionError {
+                    from: "table",
+                    to: "array",
+                    message: Some(format!(
+                        "expected a sequence of length {}, got {}",
+                        N,
+                        values.len()
+                    )),
+                })
+        } else {
+            Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "array",
+                message: Some("expected table".to_string()),
+            })
+        }
+    }
+}
+
+macro_rules! lua_convert_table_tuple {
+    ($len:expr; $($name:ident $index:expr),+) => {
+        impl<'lua, $($name: ToLua<'lua>,)+> ToLua<'lua> for TableTuple<($($name,)+)> {
+            #[allow(non_snake_case)]
+            fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+                let TableTuple(($($name,)+)) = self;
+                nested(lua, || {
+                    let table = lua.create_table()?;
+                    $(table.raw_set($index, $name)?;)+
+                    Ok(Value::Table(table))
+                })
+            }
+        }
+
+        impl<'lua, $($name: FromLua<'lua>,)+> FromLua<'lua> for TableTuple<($($name,)+)> {
+            fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+                let table = match value {
+                    Value::Table(table) => table,
+                    value => {
+                        return Err(Error::FromLuaConversionError {
+                            from: value.type_name(),
+                            to: "tuple",
+                            message: Some("expected table".to_string()),
+                        })
+                    }
+                };
+                let len = table.raw_len();
+                if len > $len {
+                    return Err(Error::FromLuaConversionError {
+                        from: "table",
+                        to: "tuple",
+                        message: Some(format!(
+                            "expected a sequence of at most length {}, got {}",
+                            $len, len
+                        )),
+                    });
+                }
+                nested(lua, || Ok(TableTuple(($(table.raw_get::<_, $name>($index)?,)+))))
+            }
+        }
+    };
+}
+
+lua_convert_table_tuple!(1; A 1);
+lua_convert_table_tuple!(2; A 1, B 2);
+lua_convert_table_tuple!(3; A 1, B 2, C 3);
+lua_convert_table_tuple!(4; A 1, B 2, C 3, D 4);
+lua_convert_table_tuple!(5; A 1, B 2, C 3, D 4, E 5);
+lua_convert_table_tuple!(6; A 1, B 2, C 3, D 4, E 5, F 6);
+lua_convert_table_tuple!(7; A 1, B 2, C 3, D 4, E 5, F 6, G 7);
+lua_convert_table_tuple!(8; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8);
+lua_convert_table_tuple!(9; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9);
+lua_convert_table_tuple!(10; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10);
+lua_convert_table_tuple!(11; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10, K 11);
+lua_convert_table_tuple!(12; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10, K 11, L 12);
+lua_convert_table_tuple!(13; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10, K 11, L 12, M 13);
+lua_convert_table_tuple!(14; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10, K 11, L 12, M 13, N 14);
+lua_convert_table_tuple!(15; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10, K 11, L 12, M 13, N 14, O 15);
+lua_convert_table_tuple!(16; A 1, B 2, C 3, D 4, E 5, F 6, G 7, H 8, I 9, J 10, K 11, L 12, M 13, N 14, O 15, P 16);
+
 impl<'lua, K: Eq + Hash + ToLua<'lua>, V: ToLua<'lua>, S: BuildHasher> ToLua<'lua>
     for HashMap<K, V, S>
 {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
-        Ok(Value::Table(lua.create_table_from(self)?))
+        nested(lua, || Ok(Value::Table(lua.create_table_from(self)?)))
     }
 }
 
 impl<'lua, K: Eq + Hash + FromLua<'lua>, V: FromLua<'lua>, S: BuildHasher + Default> FromLua<'lua>
     for HashMap<K, V, S>
 {
-    fn from_lua(value: Value<'lua>, _: Context<'lua>) -> Result<Self> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
         if let Value::Table(table) = value {
-            table.pairs().collect()
+            nested(lua, || limit_entries(lua, table.pairs()).collect())
         } else {
             Err(Error::FromLuaConversionError {
                 from: value.type_name(),
@@ -390,14 +815,14 @@ impl<'lua, K: Eq + Hash + FromLua<'lua>, V: FromLua<'lua>, S: BuildHasher + Defa
 
 impl<'lua, K: Ord + ToLua<'lua>, V: ToLua<'lua>> ToLua<'lua> for BTreeMap<K, V> {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
-        Ok(Value::Table(lua.create_table_from(self)?))
+        nested(lua, || Ok(Value::Table(lua.create_table_from(self)?)))
     }
 }
 
 impl<'lua, K: Ord + FromLua<'lua>, V: FromLua<'lua>> FromLua<'lua> for BTreeMap<K, V> {
-    fn from_lua(value: Value<'lua>, _: Context<'lua>) -> Result<Self> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
         if let Value::Table(table) = value {
-            table.pairs().collect()
+            nested(lua, || limit_entries(lua, table.pairs()).collect())
         } else {
             Err(Error::FromLuaConversionError {
                 from: value.type_name(),
@@ -408,10 +833,80 @@ impl<'lua, K: Ord + FromLua<'lua>, V: FromLua<'lua>> FromLua<'lua> for BTreeMap<
     }
 }
 
+/// Sets are converted to tables with their elements as keys and `true` as values, and are
+/// converted from the keys of a table.
+impl<'lua, T: Eq + Hash + ToLua<'lua>, S: BuildHasher> ToLua<'lua> for HashSet<T, S> {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        nested(lua, || {
+            Ok(Value::Table(
+                lua.create_table_from(self.into_iter().map(|v| (v, true)))?,
+            ))
+        })
+    }
+}
+
+impl<'lua, T: Eq + Hash + FromLua<'lua>, S: BuildHasher + Default> FromLua<'lua> for HashSet<T, S> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        if let Value::Table(table) = value {
+            nested(lua, || {
+                limit_entries(
+                    lua,
+                    table
+                        .pairs::<T, Value>()
+                        .map(|pair| pair.map(|(key, _)| key)),
+                )
+                .collect()
+            })
+        } else {
+            Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "HashSet",
+                message: Some("expected table".to_string()),
+            })
+        }
+    }
+}
+
+/// See the implementation for `HashSet`.
+impl<'lua, T: Ord + ToLua<'lua>> ToLua<'lua> for BTreeSet<T> {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        nested(lua, || {
+            Ok(Value::Table(
+                lua.create_table_from(self.into_iter().map(|v| (v, true)))?,
+            ))
+        })
+    }
+}
+
+impl<'lua, T: Ord + FromLua<'lua>> FromLua<'lua> for BTreeSet<T> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        if let Value::Table(table) = value {
+            nested(lua, || {
+                limit_entries(
+                    lua,
+                    table
+                        .pairs::<T, Value>()
+                        .map(|pair| pair.map(|(key, _)| key)),
+                )
+                .collect()
+            })
+        } else {
+            Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "BTreeSet",
+                message: Some("expected table".to_string()),
+            })
+        }
+    }
+}
+
 impl<'lua, T: ToLua<'lua>> ToLua<'lua> for Option<T> {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
         match self {
             Some(val) => val.to_lua(lua),
+            None if conversion_options(lua).none_as_sentinel => {
+                Ok(Value::LightUserData(LightUserData(ptr::null_mut())))
+            }
             None => Ok(Nil),
         }
     }
@@ -419,9 +914,94 @@ impl<'lua, T: ToLua<'lua>> ToLua<'lua> for Option<T> {
 
 impl<'lua, T: FromLua<'lua>> FromLua<'lua> for Option<T> {
     fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
-        match value {
-            Nil => Ok(None),
-            value => Ok(Some(T::from_lua(value, lua)?)),
+        if conversion_options(lua).none_as_sentinel {
+            match value {
+                Value::LightUserData(ud) if ud.0.is_null() => Ok(None),
+                value => Ok(Some(T::from_lua(value, lua)?)),
+            }
+        } else {
+            match value {
+                Nil => Ok(None),
+                value => Ok(Some(T::from_lua(value, lua)?)),
+            }
+        }
+    }
+
+    fn from_lua_missing(lua: Context<'lua>) -> Result<Self> {
+        if conversion_options(lua).missing_as_none {
+            Ok(None)
+        } else {
+            Err(Error::FromLuaConversionError {
+                from: "no value",
+                to: "Option",
+                message: Some("missing value".to_owned()),
+            })
+        }
+    }
+}
+
+impl<'lua, T: FromLua<'lua>> FromLua<'lua> for Strict<T> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        struct StrictGuard(*mut ExtraData, bool);
+
+        impl Drop for StrictGuard {
+            fn drop(&mut self) {
+                unsafe {
+                    (*self.0).conversion_options.strict_numbers = self.1;
+                }
+            }
+        }
+
+        unsafe {
+            let extra = extra_data(lua.state);
+            let _guard = StrictGuard(extra, (*extra).conversion_options.strict_numbers);
+            (*extra).conversion_options.strict_numbers = true;
+            T::from_lua(value, lua).map(Strict)
+        }
+    }
+}
+
+fn conversion_options(lua: Context) -> ConversionOptions {
+    unsafe { (*extra_data(lua.state)).conversion_options }
+}
+
+// Fails with `Error::TableLimitExceeded` instead of reading more entries than the table size limit
+// allows.
+fn limit_entries<'lua, T, I>(lua: Context<'lua>, entries: I) -> impl Iterator<Item = Result<T>>
+where
+    I: Iterator<Item = Result<T>>,
+{
+    let limit = unsafe { (*extra_data(lua.state)).table_size_limit };
+    entries.enumerate().map(move |(i, entry)| match limit {
+        Some(limit) if i >= limit => Err(Error::TableLimitExceeded { limit }),
+        _ => entry,
+    })
+}
+
+// Runs `f`, which converts a collection, one level deeper.  Fails with
+// `Error::DepthLimitExceeded` when collections are nested more deeply than the conversion depth
+// limit allows, which would otherwise overflow the stack when converting a recursive type from a
+// table that contains itself.
+fn nested<'lua, R>(lua: Context<'lua>, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    struct DepthGuard(*mut ExtraData);
+
+    impl Drop for DepthGuard {
+        fn drop(&mut self) {
+            unsafe {
+                (*self.0).conversion_depth -= 1;
+            }
+        }
+    }
+
+    unsafe {
+        let extra = extra_data(lua.state);
+        if let Some(limit) = (*extra).conversion_depth_limit {
+            if (*extra).conversion_depth >= limit {
+                return Err(Error::DepthLimitExceeded { limit });
+            }
         }
+        (*extra).conversion_depth += 1;
+        let _guard = DepthGuard(extra);
+        f()
     }
 }
@@ -0,0 +1,876 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::slice;
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::ffi;
+use crate::function::Function;
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::thread::Thread;
+use crate::types::Integer;
+use crate::util::{assert_stack, StackGuard};
+use crate::value::{Nil, Value};
+
+/// A snapshot of the global variables of a Lua state, produced by [`Lua::globals_report`].
+///
+/// Comparing reports taken some time apart with [`GlobalsReport::diff`] shows which globals a
+/// long-running script has been adding or growing.
+///
+/// [`Lua::globals_report`]: struct.Lua.html#method.globals_report
+/// [`GlobalsReport::diff`]: #method.diff
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlobalsReport {
+    /// Every global with a string name, along with its type and size.
+    pub globals: BTreeMap<StdString, GlobalInfo>,
+}
+
+/// The type and size of a single global variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlobalInfo {
+    /// The Lua type name of the value, as returned by `type()`.
+    pub type_name: &'static str,
+    /// The length of a string value, or the number of entries of a table value including the
+    /// entries of all tables nested in it.  Zero for other types, and for the globals table itself
+    /// (`_G`).
+    pub size: usize,
+}
+
+/// The number and total size of a group of Lua values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObjectTotals {
+    /// The number of values in the group.
+    pub count: usize,
+    /// The sum of the sizes of the values in the group.  In a [`HeapCensus`] this is the estimated
+    /// memory used by the objects themselves in bytes, in a [`GlobalsReport`] it is the sum of the
+    /// [`GlobalInfo::size`] of each global.
+    ///
+    /// [`HeapCensus`]: struct.HeapCensus.html
+    /// [`GlobalsReport`]: struct.GlobalsReport.html
+    /// [`GlobalInfo::size`]: struct.GlobalInfo.html#structfield.size
+    pub size: usize,
+}
+
+/// The difference between two [`GlobalsReport`]s.
+///
+/// [`GlobalsReport`]: struct.GlobalsReport.html
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlobalsDiff {
+    /// Globals which are only in the newer report.
+    pub added: BTreeMap<StdString, GlobalInfo>,
+    /// Globals which are only in the older report.
+    pub removed: BTreeMap<StdString, GlobalInfo>,
+    /// Globals whose type or size changed, with the old and new info.
+    pub changed: BTreeMap<StdString, (GlobalInfo, GlobalInfo)>,
+}
+
+impl GlobalsReport {
+    /// Returns the number and total size of globals for each Lua type.
+    pub fn by_type(&self) -> BTreeMap<&'static str, ObjectTotals> {
+        let mut totals = BTreeMap::new();
+        for info in self.globals.values() {
+            let total: &mut ObjectTotals = totals.entry(info.type_name).or_default();
+            total.count += 1;
+            total.size += info.size;
+        }
+        totals
+    }
+
+    /// Returns the changes from `previous` to this report.
+    pub fn diff(&self, previous: &GlobalsReport) -> GlobalsDiff {
+        let mut diff = GlobalsDiff::default();
+        for (name, &info) in &self.globals {
+            match previous.globals.get(name) {
+                None => {
+                    diff.added.insert(name.clone(), info);
+                }
+                Some(&old) if old != info => {
+                    diff.changed.insert(name.clone(), (old, info));
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, &info) in &previous.globals {
+            if !self.globals.contains_key(name) {
+                diff.removed.insert(name.clone(), info);
+            }
+        }
+        diff
+    }
+}
+
+impl GlobalsDiff {
+    /// Returns true if the two reports were identical.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for GlobalsReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (type_name, totals) in self.by_type() {
+            writeln!(
+                fmt,
+                "{}: {} globals, size {}",
+                type_name, totals.count, totals.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for GlobalsDiff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (name, info) in &self.added {
+            writeln!(fmt, "+ {} ({}, size {})", name, info.type_name, info.size)?;
+        }
+        for (name, info) in &self.removed {
+            writeln!(fmt, "- {} ({}, size {})", name, info.type_name, info.size)?;
+        }
+        for (name, (old, new)) in &self.changed {
+            writeln!(
+                fmt,
+                "~ {} ({}, size {} -> {}, size {})",
+                name, old.type_name, old.size, new.type_name, new.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts and estimated sizes of every Lua object reachable from the globals table and the
+/// registry, produced by [`Lua::heap_census`].
+///
+/// Lua does not record where objects were allocated, so objects are attributed to the chunk of
+/// the nearest Lua function they were found through: a Lua function is attributed to the chunk it
+/// was defined in, and other objects to the chunk of the function whose upvalues (or the stack of
+/// the coroutine running it) led to them.  Objects only reachable through tables from the globals
+/// table or the registry are attributed to no chunk.
+///
+/// Sizes are estimates for a 64-bit build of Lua 5.3, they do not include memory shared between
+/// objects such as function prototypes.
+///
+/// [`Lua::heap_census`]: struct.Lua.html#method.heap_census
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeapCensus {
+    /// Totals grouped by Lua type name.
+    pub by_type: BTreeMap<&'static str, ObjectTotals>,
+    /// Totals grouped by the short source name of the chunk objects are attributed to.
+    pub by_chunk: BTreeMap<Option<StdString>, ObjectTotals>,
+}
+
+impl HeapCensus {
+    /// Returns the totals over all objects.
+    pub fn total(&self) -> ObjectTotals {
+        let mut total = ObjectTotals::default();
+        for totals in self.by_type.values() {
+            total.count += totals.count;
+            total.size += totals.size;
+        }
+        total
+    }
+}
+
+impl fmt::Display for HeapCensus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        writeln!(fmt, "{} objects, about {} bytes", total.count, total.size)?;
+        writeln!(fmt, "by type:")?;
+        for (type_name, totals) in &self.by_type {
+            writeln!(
+                fmt,
+                "  {}: {} objects, about {} bytes",
+                type_name, totals.count, totals.size
+            )?;
+        }
+        writeln!(fmt, "by chunk:")?;
+        for (chunk, totals) in &self.by_chunk {
+            writeln!(
+                fmt,
+                "  {}: {} objects, about {} bytes",
+                chunk.as_ref().map(|c| c.as_str()).unwrap_or("?"),
+                totals.count,
+                totals.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A chain of references leading from a root of the Lua heap to an object, as returned by
+/// [`Context::reference_path`].
+///
+/// The `Display` implementation formats the path similarly to a Lua expression, such as
+/// `globals.remember:upvalue(cache)[3]`.
+///
+/// [`Context::reference_path`]: struct.Context.html#method.reference_path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReferencePath(pub Vec<PathSegment>);
+
+/// A single reference in a [`ReferencePath`].
+///
+/// [`ReferencePath`]: struct.ReferencePath.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    /// The globals table, the first root searched.
+    Globals,
+    /// The Lua registry, the second root searched.
+    Registry,
+    /// The value stored under a key of a table.  The key is formatted as Lua source, such as
+    /// `name` for string keys which are valid identifiers, `["a b"]` for other strings, or `[1]`.
+    /// Keys which are not strings, numbers or booleans are only described by their type, such as
+    /// `[table]`.
+    Field(StdString),
+    /// A table key itself.
+    Key,
+    /// The metatable of a table or userdata.
+    Metatable,
+    /// The user value of a userdata.
+    UserValue,
+    /// A named upvalue of a function.  C functions have unnamed upvalues.
+    Upvalue(StdString),
+    /// The function of an active call in a coroutine, by stack level with 0 being the innermost
+    /// call.
+    CallFunction(c_int),
+    /// A named local variable of an active call in a coroutine, by stack level and name.
+    Local(c_int, StdString),
+    /// A value in the topmost stack frame of a coroutine, such as the function of a coroutine which
+    /// has not been started yet.
+    Stack(c_int),
+}
+
+impl fmt::Display for ReferencePath {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for segment in &self.0 {
+            match segment {
+                PathSegment::Globals => write!(fmt, "globals")?,
+                PathSegment::Registry => write!(fmt, "registry")?,
+                PathSegment::Field(key) if key.starts_with('[') => write!(fmt, "{}", key)?,
+                PathSegment::Field(key) => write!(fmt, ".{}", key)?,
+                PathSegment::Key => write!(fmt, ":key")?,
+                PathSegment::Metatable => write!(fmt, ":metatable")?,
+                PathSegment::UserValue => write!(fmt, ":uservalue")?,
+                PathSegment::Upvalue(name) => write!(fmt, ":upvalue({})", name)?,
+                PathSegment::CallFunction(level) => write!(fmt, ":call({})", level)?,
+                PathSegment::Local(level, name) => write!(fmt, ":local({}, {})", level, name)?,
+                PathSegment::Stack(index) => write!(fmt, ":stack({})", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The active calls of a Lua thread, innermost first, as returned by [`Context::call_stack`].
+///
+/// A call made with `return f()` replaces the calling function on the stack, so the frame below a
+/// tail call is not the function which made it.  Such frames are marked with
+/// [`CallFrame::is_tail_call`], and [`CallStack::caller`] does not attribute them to the frame
+/// below.  The `Display` implementation formats the stack like `debug.traceback`, with a
+/// `(...tail calls...)` line where callers are missing.
+///
+/// [`Context::call_stack`]: struct.Context.html#method.call_stack
+/// [`CallFrame::is_tail_call`]: struct.CallFrame.html#structfield.is_tail_call
+/// [`CallStack::caller`]: #method.caller
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallStack(pub Vec<CallFrame>);
+
+/// A single active call in a [`CallStack`].
+///
+/// [`CallStack`]: struct.CallStack.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    /// The kind of function, as in the `what` field of `lua_Debug`: `"Lua"` for Lua functions,
+    /// `"main"` for the main part of a chunk and `"C"` for C and Rust functions.
+    pub what: &'static str,
+    /// The short source name of the chunk the function was defined in, `[C]` for C functions.
+    pub source: StdString,
+    /// The line being executed, `None` for C functions.
+    pub current_line: Option<i32>,
+    /// The line where the function was defined, `None` for C functions.
+    pub line_defined: Option<i32>,
+    /// The name the caller used for the function, if it could be determined.  Functions called
+    /// with a tail call have no name.
+    pub name: Option<StdString>,
+    /// True if the function was called with a tail call, which replaced the calling function.
+    pub is_tail_call: bool,
+}
+
+impl CallStack {
+    /// Returns the frame which called the frame at `index`, for attributing calls in profilers and
+    /// call graphs.
+    ///
+    /// Returns `None` for the outermost frame, and for tail calls, as the function which made a
+    /// tail call is no longer on the stack.
+    pub fn caller(&self, index: usize) -> Option<&CallFrame> {
+        match self.0.get(index) {
+            Some(frame) if !frame.is_tail_call => self.0.get(index + 1),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CallStack {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "stack traceback:")?;
+        for frame in &self.0 {
+            write!(fmt, "\n\t{}:", frame.source)?;
+            if let Some(line) = frame.current_line {
+                write!(fmt, "{}:", line)?;
+            }
+            match (&frame.name, frame.what, frame.line_defined) {
+                (Some(name), _, _) => write!(fmt, " in function '{}'", name)?,
+                (None, "main", _) => write!(fmt, " in main chunk")?,
+                (None, _, Some(line)) => write!(fmt, " in function <{}:{}>", frame.source, line)?,
+                (None, _, None) => write!(fmt, " in ?")?,
+            }
+            if frame.is_tail_call {
+                write!(fmt, "\n\t(...tail calls...)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn globals_report(lua: Context) -> Result<GlobalsReport> {
+    let globals = lua.globals();
+    let mut report = GlobalsReport::default();
+    for pair in globals.clone().pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let name = match key {
+            Value::String(name) => StdString::from_utf8_lossy(name.as_bytes()).into_owned(),
+            _ => continue,
+        };
+
+        // Entries of the globals table itself are not counted again through `_G`.
+        let size = match &value {
+            Value::String(s) => s.as_bytes().len(),
+            Value::Table(t) => {
+                let mut visited = HashSet::new();
+                visited.insert(globals.0.to_pointer());
+                table_size(t.clone(), &mut visited)?
+            }
+            _ => 0,
+        };
+        report.globals.insert(
+            name,
+            GlobalInfo {
+                type_name: value.type_name(),
+                size,
+            },
+        );
+    }
+    Ok(report)
+}
+
+// Counts the entries of a table and all tables reachable from it, counting each table once.
+fn table_size(table: Table, visited: &mut HashSet<*const c_void>) -> Result<usize> {
+    if !visited.insert(table.0.to_pointer()) {
+        return Ok(0);
+    }
+
+    let mut size = 0;
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        size += 1;
+        for v in &[key, value] {
+            if let Value::Table(t) = v {
+                size += table_size(t.clone(), visited)?;
+            }
+        }
+    }
+    Ok(size)
+}
+
+pub(crate) fn heap_census(lua: Context) -> Result<HeapCensus> {
+    let mut census = HeapCensus::default();
+    walk_heap(lua, |object| {
+        let size = estimated_size(lua, object.value)?;
+        let totals = census.by_type.entry(object.value.type_name()).or_default();
+        totals.count += 1;
+        totals.size += size;
+        let totals = census
+            .by_chunk
+            .entry(object.chunk.map(|c| c.to_owned()))
+            .or_default();
+        totals.count += 1;
+        totals.size += size;
+        Ok(true)
+    })?;
+    Ok(census)
+}
+
+pub(crate) fn reference_path<'lua>(
+    lua: Context<'lua>,
+    value: Value<'lua>,
+) -> Result<Option<ReferencePath>> {
+    let target = match identity(&value) {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+
+    let mut found = None;
+    walk_heap(lua, |object| {
+        if object.identity == target {
+            found = Some(ReferencePath(object.path.to_vec()));
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    })?;
+    Ok(found)
+}
+
+// Estimates the memory used by a single object itself, based on the object layouts of Lua 5.3 on
+// 64-bit platforms.
+fn estimated_size<'lua>(lua: Context<'lua>, value: &Value<'lua>) -> Result<usize> {
+    Ok(match value {
+        Value::String(s) => 25 + s.as_bytes().len(),
+        Value::Table(t) => {
+            let mut entries = 0;
+            for pair in t.clone().pairs::<Value, Value>() {
+                pair?;
+                entries += 1;
+            }
+            56 + 32 * entries
+        }
+        Value::Function(f) => unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+            lua.push_ref(&f.0);
+            let mut upvalues = 0;
+            while !ffi::lua_getupvalue(lua.state, -1, upvalues + 1).is_null() {
+                ffi::lua_pop(lua.state, 1);
+                upvalues += 1;
+            }
+            32 + 16 * upvalues as usize
+        },
+        Value::UserData(ud) => unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+            lua.push_ref(&ud.0);
+            40 + ffi::lua_rawlen(lua.state, -1)
+        },
+        Value::Thread(t) => unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+            lua.push_ref(&t.0);
+            let thread = ffi::lua_tothread(lua.state, -1);
+            200 + 16 * ffi::lua_gettop(thread) as usize
+        },
+        _ => 0,
+    })
+}
+
+// An object found by `walk_heap`.
+struct HeapObject<'a, 'lua> {
+    value: &'a Value<'lua>,
+    identity: *const c_void,
+    // The references followed from a root to find this object.
+    path: &'a [PathSegment],
+    // The chunk of the nearest Lua function on the path, including the object itself.
+    chunk: Option<&'a str>,
+}
+
+type References<'lua> = Box<dyn Iterator<Item = Result<(PathSegment, Value<'lua>)>> + 'lua>;
+
+// An object waiting to be visited by `walk_heap`.  The object itself is kept in the work list
+// table under `slot`, so that waiting objects do not each hold a reference on the auxiliary stack.
+struct Pending {
+    slot: Integer,
+    identity: *const c_void,
+    path: Vec<PathSegment>,
+    // The chunk of the nearest Lua function on the path, excluding the object itself.
+    chunk: Option<StdString>,
+}
+
+// The objects waiting to be visited by `walk_heap`, in the order they were found.
+struct WorkList<'lua> {
+    objects: Table<'lua>,
+    pending: VecDeque<Pending>,
+    next_slot: Integer,
+    visited: HashSet<*const c_void>,
+    ref_thread: *const c_void,
+}
+
+impl<'lua> WorkList<'lua> {
+    fn new(lua: Context<'lua>) -> Result<WorkList<'lua>> {
+        Ok(WorkList {
+            objects: lua.create_table()?,
+            pending: VecDeque::new(),
+            next_slot: 1,
+            visited: HashSet::new(),
+            ref_thread: unsafe { (*extra_data(lua.state)).ref_thread } as *const c_void,
+        })
+    }
+
+    // Queues an object unless it is not collectable or has already been queued.
+    fn push(
+        &mut self,
+        parent_path: &[PathSegment],
+        parent_chunk: Option<&StdString>,
+        segment: PathSegment,
+        value: Value<'lua>,
+    ) -> Result<()> {
+        let identity = match identity(&value) {
+            Some(identity) => identity,
+            None => return Ok(()),
+        };
+        if identity == self.ref_thread || !self.visited.insert(identity) {
+            return Ok(());
+        }
+
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.objects.raw_set(slot, value)?;
+
+        let mut path = parent_path.to_vec();
+        path.push(segment);
+        self.pending.push_back(Pending {
+            slot,
+            identity,
+            path,
+            chunk: parent_chunk.cloned(),
+        });
+        Ok(())
+    }
+
+    // Takes the object which has been waiting the longest out of the list.
+    fn pop(&mut self) -> Result<Option<(Value<'lua>, Pending)>> {
+        let pending = match self.pending.pop_front() {
+            Some(pending) => pending,
+            None => return Ok(None),
+        };
+        let value = self.objects.raw_get(pending.slot)?;
+        self.objects.raw_set(pending.slot, Nil)?;
+        Ok(Some((value, pending)))
+    }
+}
+
+// Visits every collectable object reachable from the globals table and the registry once, breadth
+// first, until `visit` returns false.  Objects are visited in order of the length of their path,
+// so the path given for each object is a shortest one, preferring the globals table over the
+// registry.
+fn walk_heap<'lua, F>(lua: Context<'lua>, mut visit: F) -> Result<()>
+where
+    F: FnMut(HeapObject<'_, 'lua>) -> Result<bool>,
+{
+    let mut work = WorkList::new(lua)?;
+
+    let registry = unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+        ffi::lua_pushvalue(lua.state, ffi::LUA_REGISTRYINDEX);
+        lua.pop_value()
+    };
+    work.push(&[], None, PathSegment::Globals, Value::Table(lua.globals()))?;
+    work.push(&[], None, PathSegment::Registry, registry)?;
+
+    while let Some((value, pending)) = work.pop()? {
+        let chunk = match &value {
+            Value::Function(f) => function_chunk(lua, f),
+            Value::Thread(t) => thread_chunk(lua, t),
+            _ => None,
+        }
+        .or(pending.chunk);
+
+        let keep_going = visit(HeapObject {
+            value: &value,
+            identity: pending.identity,
+            path: &pending.path,
+            chunk: chunk.as_deref(),
+        })?;
+        if !keep_going {
+            return Ok(());
+        }
+
+        for reference in references(lua, value)? {
+            let (segment, value) = reference?;
+            work.push(&pending.path, chunk.as_ref(), segment, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn references<'lua>(lua: Context<'lua>, value: Value<'lua>) -> Result<References<'lua>> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 3);
+
+        let mut references = Vec::new();
+        match value {
+            Value::Table(t) => {
+                if let Some(metatable) = t.get_metatable() {
+                    references.push(Ok((PathSegment::Metatable, Value::Table(metatable))));
+                }
+                let entries = t.pairs::<Value, Value>().flat_map(|pair| match pair {
+                    Ok((key, value)) => {
+                        let field = PathSegment::Field(key_source(&key));
+                        vec![Ok((PathSegment::Key, key)), Ok((field, value))]
+                    }
+                    Err(err) => vec![Err(err)],
+                });
+                return Ok(Box::new(references.into_iter().chain(entries)));
+            }
+            Value::Function(f) => {
+                lua.push_ref(&f.0);
+                let mut n = 1;
+                loop {
+                    let name = ffi::lua_getupvalue(lua.state, -1, n);
+                    if name.is_null() {
+                        break;
+                    }
+                    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+                    references.push(Ok((PathSegment::Upvalue(name), lua.pop_value())));
+                    n += 1;
+                }
+            }
+            Value::UserData(ud) => {
+                lua.push_ref(&ud.0);
+                if ffi::lua_getmetatable(lua.state, -1) != 0 {
+                    references.push(Ok((PathSegment::Metatable, lua.pop_value())));
+                }
+                ffi::lua_getuservalue(lua.state, -1);
+                references.push(Ok((PathSegment::UserValue, lua.pop_value())));
+            }
+            Value::Thread(t) => {
+                lua.push_ref(&t.0);
+                let thread = ffi::lua_tothread(lua.state, -1);
+                if thread != lua.state && ffi::lua_checkstack(thread, 1) != 0 {
+                    // The function and locals of every active call, then the values in the topmost
+                    // frame, which holds the function of a coroutine that has not started yet.
+                    let mut ar: ffi::lua_Debug = mem::zeroed();
+                    let mut level = 0;
+                    while ffi::lua_getstack(thread, level, &mut ar) != 0 {
+                        ffi::lua_getinfo(thread, cstr!("f"), &mut ar);
+                        ffi::lua_xmove(thread, lua.state, 1);
+                        references.push(Ok((PathSegment::CallFunction(level), lua.pop_value())));
+
+                        let mut n = 1;
+                        loop {
+                            let name = ffi::lua_getlocal(thread, &ar, n);
+                            if name.is_null() {
+                                break;
+                            }
+                            let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+                            ffi::lua_xmove(thread, lua.state, 1);
+                            references.push(Ok((PathSegment::Local(level, name), lua.pop_value())));
+                            n += 1;
+                        }
+                        level += 1;
+                    }
+
+                    for i in 1..=ffi::lua_gettop(thread) {
+                        ffi::lua_pushvalue(thread, i);
+                        ffi::lua_xmove(thread, lua.state, 1);
+                        references.push(Ok((PathSegment::Stack(i), lua.pop_value())));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(Box::new(references.into_iter()))
+    }
+}
+
+// Returns the short source name of the chunk a Lua function was defined in.
+fn function_chunk<'lua>(lua: Context<'lua>, function: &Function<'lua>) -> Option<StdString> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+
+        let mut ar: ffi::lua_Debug = mem::zeroed();
+        lua.push_ref(&function.0);
+        rlua_assert!(
+            ffi::lua_getinfo(lua.state, cstr!(">S"), &mut ar) != 0,
+            "lua_getinfo failed with `>S`"
+        );
+        chunk_name(&ar)
+    }
+}
+
+// Returns the chunk of the outermost Lua function running in a coroutine, which is usually the
+// function the coroutine was created with.
+fn thread_chunk<'lua>(lua: Context<'lua>, thread: &Thread<'lua>) -> Option<StdString> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+
+        lua.push_ref(&thread.0);
+        let thread = ffi::lua_tothread(lua.state, -1);
+        if thread == lua.state {
+            return None;
+        }
+
+        let mut ar: ffi::lua_Debug = mem::zeroed();
+        let mut chunk = None;
+        let mut level = 0;
+        while ffi::lua_getstack(thread, level, &mut ar) != 0 {
+            rlua_assert!(
+                ffi::lua_getinfo(thread, cstr!("S"), &mut ar) != 0,
+                "lua_getinfo failed with `S`"
+            );
+            chunk = chunk_name(&ar).or(chunk);
+            level += 1;
+        }
+        chunk
+    }
+}
+
+unsafe fn chunk_name(ar: &ffi::lua_Debug) -> Option<StdString> {
+    if CStr::from_ptr(ar.what).to_bytes() == b"C" {
+        None
+    } else {
+        Some(
+            CStr::from_ptr(ar.short_src.as_ptr())
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+// Returns a pointer identifying a collectable object.  `lua_topointer` does not work for strings,
+// but their contents are never moved.
+fn identity(value: &Value) -> Option<*const c_void> {
+    match value {
+        Value::String(s) => Some(s.as_bytes().as_ptr() as *const c_void),
+        Value::Table(t) => Some(t.0.to_pointer()),
+        Value::Function(f) => Some(f.0.to_pointer()),
+        Value::UserData(ud) => Some(ud.0.to_pointer()),
+        Value::Thread(t) => Some(t.0.to_pointer()),
+        _ => None,
+    }
+}
+
+fn key_source(key: &Value) -> StdString {
+    match key {
+        Value::String(s) => match s.to_str() {
+            Ok(s) if is_identifier(s) => s.to_owned(),
+            _ => format!("[{:?}]", StdString::from_utf8_lossy(s.as_bytes())),
+        },
+        Value::Integer(i) => format!("[{}]", i),
+        Value::Number(n) => format!("[{}]", n),
+        Value::Boolean(b) => format!("[{}]", b),
+        v => format!("[{}]", v.type_name()),
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+        "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric()) && !KEYWORDS.contains(&s)
+}
+
+// Walks the active calls of `state`.  Does not use any stack space.
+pub(crate) unsafe fn call_stack(state: *mut ffi::lua_State) -> CallStack {
+    let mut frames = Vec::new();
+    let mut ar: ffi::lua_Debug = mem::zeroed();
+    let mut level = 0;
+    while ffi::lua_getstack(state, level, &mut ar) != 0 {
+        rlua_assert!(
+            ffi::lua_getinfo(state, cstr!("Slnt"), &mut ar) != 0,
+            "lua_getinfo failed with `Slnt`"
+        );
+        let what = match CStr::from_ptr(ar.what).to_bytes() {
+            b"main" => "main",
+            b"C" => "C",
+            _ => "Lua",
+        };
+        frames.push(CallFrame {
+            what,
+            source: CStr::from_ptr(ar.short_src.as_ptr())
+                .to_string_lossy()
+                .into_owned(),
+            current_line: if ar.currentline > 0 {
+                Some(ar.currentline)
+            } else {
+                None
+            },
+            line_defined: if what == "C" {
+                None
+            } else {
+                Some(ar.linedefined)
+            },
+            name: if ar.name.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(ar.name).to_string_lossy().into_owned())
+            },
+            is_tail_call: ar.istailcall != 0,
+        });
+        level += 1;
+    }
+    CallStack(frames)
+}
+
+// Renders the values on the stack of `state` from the bottom up, with both their absolute and
+// relative indices.  Does not call metamethods or use any stack space, so this is safe to use with
+// an unbalanced stack.
+pub(crate) unsafe fn stack_dump(state: *mut ffi::lua_State) -> StdString {
+    let extra = extra_data(state);
+    let top = ffi::lua_gettop(state);
+    let mut dump = format!(
+        "stack of thread {:p}: {} values, {} rlua references in use\n",
+        state,
+        top,
+        (*extra).ref_stack_max as usize - (*extra).ref_free.len()
+    );
+    for index in 1..=top {
+        dump.push_str(&format!(
+            "  {:>3} {:>4}  {}\n",
+            index,
+            index - top - 1,
+            describe_stack_value(state, index)
+        ));
+    }
+    dump
+}
+
+unsafe fn describe_stack_value(state: *mut ffi::lua_State, index: c_int) -> StdString {
+    const MAX_STRING: usize = 40;
+
+    match ffi::lua_type(state, index) {
+        ffi::LUA_TNIL => "nil".to_owned(),
+        ffi::LUA_TBOOLEAN => format!("boolean {}", ffi::lua_toboolean(state, index) != 0),
+        ffi::LUA_TNUMBER if ffi::lua_isinteger(state, index) != 0 => {
+            format!(
+                "integer {}",
+                ffi::lua_tointegerx(state, index, ptr::null_mut())
+            )
+        }
+        ffi::LUA_TNUMBER => format!(
+            "number {:?}",
+            ffi::lua_tonumberx(state, index, ptr::null_mut())
+        ),
+        ffi::LUA_TSTRING => {
+            let mut len = 0;
+            let data = ffi::lua_tolstring(state, index, &mut len) as *const u8;
+            let bytes = slice::from_raw_parts(data, len);
+            let shown = StdString::from_utf8_lossy(&bytes[..len.min(MAX_STRING)]);
+            if len > MAX_STRING {
+                format!("string {:?}... ({} bytes)", shown, len)
+            } else {
+                format!("string {:?}", shown)
+            }
+        }
+        ffi::LUA_TTABLE => format!("table {:p}", ffi::lua_topointer(state, index)),
+        ffi::LUA_TFUNCTION => format!("function {:p}", ffi::lua_topointer(state, index)),
+        ffi::LUA_TUSERDATA => format!("userdata {:p}", ffi::lua_topointer(state, index)),
+        ffi::LUA_TLIGHTUSERDATA => format!("lightuserdata {:p}", ffi::lua_topointer(state, index)),
+        ffi::LUA_TTHREAD => format!("thread {:p}", ffi::lua_topointer(state, index)),
+        _ => "<unknown>".to_owned(),
+    }
+}
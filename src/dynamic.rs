@@ -0,0 +1,95 @@
+use std::any::{self, Any};
+use std::fmt;
+
+use crate::userdata::UserData;
+
+/// An opaque Rust value which scripts pass around without looking into it.
+///
+/// Handles which only travel through scripts, from one host function to another, need no methods,
+/// so implementing `UserData` for each of their types is busywork.  `DynUserData` wraps any
+/// `'static + Send` value in a userdata which scripts can store and pass back but do nothing else
+/// with, and host functions get the value back with [`AnyUserData::borrow_dyn`] or the downcasting
+/// methods here.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{AnyUserData, DynUserData, Lua, Result};
+/// # fn main() -> Result<()> {
+/// struct Connection {
+///     peer: String,
+/// }
+///
+/// # Lua::new().context(|lua_context| {
+/// let connect = lua_context.create_function(|_, peer: String| {
+///     Ok(DynUserData::new(Connection { peer }))
+/// })?;
+/// let peer = lua_context.create_function(|_, connection: AnyUserData| {
+///     Ok(connection.borrow_dyn::<Connection>()?.peer.clone())
+/// })?;
+/// lua_context.globals().set("connect", connect)?;
+/// lua_context.globals().set("peer", peer)?;
+///
+/// let peer = lua_context
+///     .load("peer(connect('example.org'))")
+///     .eval::<String>()?;
+/// assert_eq!(peer, "example.org");
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`AnyUserData::borrow_dyn`]: struct.AnyUserData.html#method.borrow_dyn
+pub struct DynUserData {
+    value: Box<dyn Any + Send>,
+    type_name: &'static str,
+}
+
+impl DynUserData {
+    /// Wraps `value`.
+    pub fn new<T: 'static + Send>(value: T) -> DynUserData {
+        DynUserData {
+            value: Box::new(value),
+            type_name: any::type_name::<T>(),
+        }
+    }
+
+    /// Returns the name of the type of the wrapped value, as returned by `std::any::type_name`.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns true if the wrapped value is of type `T`.
+    pub fn is<T: 'static>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    /// Returns a reference to the wrapped value if it is of type `T`.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+
+    /// Returns a mutable reference to the wrapped value if it is of type `T`.
+    pub fn downcast_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.value.downcast_mut()
+    }
+
+    /// Unwraps the value if it is of type `T`, or returns `self` unchanged.
+    pub fn downcast<T: 'static>(self) -> Result<T, DynUserData> {
+        match self.value.downcast() {
+            Ok(value) => Ok(*value),
+            Err(value) => Err(DynUserData {
+                value,
+                type_name: self.type_name,
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for DynUserData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DynUserData").field(&self.type_name).finish()
+    }
+}
+
+impl UserData for DynUserData {}
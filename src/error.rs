@@ -1,8 +1,15 @@
+use std::any::Any;
 use std::error::Error as StdError;
 use std::fmt;
 use std::result::Result as StdResult;
 use std::string::String as StdString;
 use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bytecode::BytecodeHeader;
+use crate::context::Context;
+use crate::types::RegistryKey;
+use crate::value::FromLua;
 
 /// Error type returned by `rlua` methods.
 #[derive(Debug, Clone)]
@@ -22,12 +29,68 @@ pub enum Error {
     /// The Lua VM returns this error when a builtin operation is performed on incompatible types.
     /// Among other things, this includes invoking operators on wrong types (such as calling or
     /// indexing a `nil` value).
+    ///
+    /// Errors raised while Lua code runs under rlua's error handler have a traceback appended to
+    /// their message, which [`Error::traceback`] returns.
+    ///
+    /// [`Error::traceback`]: #method.traceback
     RuntimeError(StdString),
+    /// Lua runtime error whose error object is a table or userdata, such as the one raised by
+    /// `error({code = 404})`.
+    ///
+    /// The error object is kept in the registry, and [`Error::lua_value`] returns it.  Returning
+    /// this error from a callback raises the very same object again, so Lua code catching it with
+    /// `pcall` sees what was originally raised.  Unlike the messages of `RuntimeError`s, error
+    /// objects carry no traceback.
+    ///
+    /// [`Error::lua_value`]: #method.lua_value
+    LuaError {
+        /// A description of the error object.
+        message: StdString,
+        /// The error object.
+        value: Arc<RegistryKey>,
+    },
     /// Lua memory error, aka `LUA_ERRMEM`
     ///
     /// The Lua VM returns this error when the allocator does not return the requested memory, aka
     /// it is an out-of-memory error.
     MemoryError(StdString),
+    /// A string longer than the limit set with [`Lua::set_string_length_limit`] was about to be
+    /// created.
+    ///
+    /// [`Lua::set_string_length_limit`]: struct.Lua.html#method.set_string_length_limit
+    StringLimitExceeded {
+        /// The length of the rejected string.
+        length: usize,
+        /// The string length limit at the time.
+        limit: usize,
+    },
+    /// A table with more entries than the limit set with [`Lua::set_table_size_limit`] was being
+    /// converted into a Rust collection.
+    ///
+    /// [`Lua::set_table_size_limit`]: struct.Lua.html#method.set_table_size_limit
+    TableLimitExceeded {
+        /// The table size limit at the time.
+        limit: usize,
+    },
+    /// Tables nested more deeply than the limit set with [`Lua::set_conversion_depth_limit`] were
+    /// being converted to or from Rust collections.
+    ///
+    /// [`Lua::set_conversion_depth_limit`]: struct.Lua.html#method.set_conversion_depth_limit
+    DepthLimitExceeded {
+        /// The conversion depth limit at the time.
+        limit: usize,
+    },
+    /// A binary chunk passed to [`Context::load_bytecode`] was compiled for another version or
+    /// configuration of Lua.
+    ///
+    /// [`Context::load_bytecode`]: struct.Context.html#method.load_bytecode
+    IncompatibleBytecode {
+        /// What the chunk was compiled for.
+        found: BytecodeHeader,
+        /// What the Lua used by rlua expects.
+        expected: BytecodeHeader,
+    },
     /// Lua garbage collector error, aka `LUA_ERRGCMM`.
     ///
     /// The Lua VM returns this error when there is an error running a `__gc` metamethod.
@@ -40,7 +103,11 @@ pub enum Error {
     /// been destructed.
     ///
     /// This can happen either due to to being destructed in a previous __gc, or due to being
-    /// destructed from exiting a `Lua::scope` call.
+    /// destructed from exiting a `Lua::scope` call.  Using a userdata whose value was taken with
+    /// [`AnyUserData::take`] is a [`UserDataDestructed`] error instead.
+    ///
+    /// [`AnyUserData::take`]: struct.AnyUserData.html#method.take
+    /// [`UserDataDestructed`]: #variant.UserDataDestructed
     CallbackDestructed,
     /// Not enough stack space to place arguments to Lua functions or return values from callbacks.
     ///
@@ -107,11 +174,44 @@ pub enum Error {
     /// [`AnyUserData`]: struct.AnyUserData.html
     /// [`UserData`]: trait.UserData.html
     UserDataBorrowMutError,
+    /// [`Context::create_registered_userdata`] was called for a type which was not registered
+    /// with [`Context::register_userdata_type`].  Contains the name of the type.
+    ///
+    /// [`Context::create_registered_userdata`]: struct.Context.html#method.create_registered_userdata
+    /// [`Context::register_userdata_type`]: struct.Context.html#method.register_userdata_type
+    UserDataTypeNotRegistered(&'static str),
+    /// A userdata was used after its value was dropped by [`AnyUserData::destroy`] or
+    /// [`AnyUserData::take`].
+    ///
+    /// [`AnyUserData::destroy`]: struct.AnyUserData.html#method.destroy
+    /// [`AnyUserData::take`]: struct.AnyUserData.html#method.take
+    UserDataDestructed,
+    /// A task of a [`TaskGroup`] was cancelled before it finished.
+    ///
+    /// [`TaskGroup`]: struct.TaskGroup.html
+    TaskCancelled,
     /// A `RegistryKey` produced from a different Lua state was used.
     MismatchedRegistryKey,
+    /// A function created with [`Context::create_function_with_timeout`] did not finish within
+    /// the contained timeout.
+    ///
+    /// [`Context::create_function_with_timeout`]: struct.Context.html#method.create_function_with_timeout
+    CallbackTimeout(Duration),
     /// A Rust callback returned `Err`, raising the contained `Error` as a Lua error.
+    ///
+    /// Scripts which catch such an error with `pcall` receive a userdata which converts to the
+    /// error message with `tostring`, and has the read-only fields `kind` (see
+    /// [`Error::kind_name`]), `message` and `traceback`.  The traceback is taken where the callback
+    /// raised the error, and is `nil` if tracebacks are disabled with
+    /// [`Lua::set_traceback_enabled`].
+    ///
+    /// [`Error::kind_name`]: #method.kind_name
+    /// [`Lua::set_traceback_enabled`]: struct.Lua.html#method.set_traceback_enabled
     CallbackError {
-        /// Lua call stack backtrace.
+        /// Lua call stack backtrace, empty if tracebacks are disabled with
+        /// [`Lua::set_traceback_enabled`].
+        ///
+        /// [`Lua::set_traceback_enabled`]: struct.Lua.html#method.set_traceback_enabled
         traceback: StdString,
         /// Original error returned by the Rust code.
         cause: Arc<Error>,
@@ -124,8 +224,26 @@ pub enum Error {
     /// error. The Rust code that originally invoked the Lua code then receives a `CallbackError`,
     /// from which the original error (and a stack traceback) can be recovered.
     ExternalError(Arc<dyn StdError + Send + Sync>),
+    /// An error of a category defined outside of `rlua`.
+    ///
+    /// Frameworks built on `rlua` can use this to give their errors a kind which survives being
+    /// raised through Lua.  Like any other error returned from a callback, it reaches the Rust code
+    /// which called into Lua wrapped in a `CallbackError`.  [`Error::custom_kind`] and
+    /// [`Error::downcast_custom`] look through such wrappers.
+    ///
+    /// [`Error::custom_kind`]: #method.custom_kind
+    /// [`Error::downcast_custom`]: #method.downcast_custom
+    Custom {
+        /// The name of the error category.
+        kind: &'static str,
+        /// Arbitrary data describing the error.
+        payload: Arc<dyn Any + Send + Sync>,
+    },
 }
 
+// What `luaL_traceback` puts between an error message and the traceback it appends.
+const TRACEBACK_HEADER: &str = "\nstack traceback:";
+
 /// A specialized `Result` type used by `rlua`'s API.
 pub type Result<T> = StdResult<T, Error>;
 
@@ -134,9 +252,30 @@ impl fmt::Display for Error {
         match *self {
             Error::SyntaxError { ref message, .. } => write!(fmt, "syntax error: {}", message),
             Error::RuntimeError(ref msg) => write!(fmt, "runtime error: {}", msg),
+            Error::LuaError { ref message, .. } => write!(fmt, "runtime error: {}", message),
             Error::MemoryError(ref msg) => {
                 write!(fmt, "memory error: {}", msg)
             }
+            Error::StringLimitExceeded { length, limit } => write!(
+                fmt,
+                "string of length {} exceeds the string length limit of {}",
+                length, limit
+            ),
+            Error::TableLimitExceeded { limit } => write!(
+                fmt,
+                "table has more than the table size limit of {} entries",
+                limit
+            ),
+            Error::DepthLimitExceeded { limit } => write!(
+                fmt,
+                "tables are nested more deeply than the conversion depth limit of {}",
+                limit
+            ),
+            Error::IncompatibleBytecode { found, expected } => write!(
+                fmt,
+                "bytecode compiled for {} cannot be loaded by {}",
+                found, expected
+            ),
             Error::GarbageCollectorError(ref msg) => {
                 write!(fmt, "garbage collector error: {}", msg)
             }
@@ -179,13 +318,29 @@ impl fmt::Display for Error {
             Error::UserDataTypeMismatch => write!(fmt, "userdata is not expected type"),
             Error::UserDataBorrowError => write!(fmt, "userdata already mutably borrowed"),
             Error::UserDataBorrowMutError => write!(fmt, "userdata already borrowed"),
+            Error::UserDataTypeNotRegistered(type_name) => {
+                write!(fmt, "userdata type {} is not registered", type_name)
+            }
+            Error::UserDataDestructed => write!(fmt, "userdata has been destructed"),
+            Error::TaskCancelled => write!(fmt, "task was cancelled"),
             Error::MismatchedRegistryKey => {
                 write!(fmt, "RegistryKey used from different Lua state")
             }
-            Error::CallbackError { ref traceback, .. } => {
-                write!(fmt, "callback error: {}", traceback)
+            Error::CallbackTimeout(timeout) => {
+                write!(fmt, "callback did not finish within {:?}", timeout)
+            }
+            Error::CallbackError {
+                ref traceback,
+                ref cause,
+            } => {
+                if traceback.is_empty() {
+                    write!(fmt, "callback error: {}", cause)
+                } else {
+                    write!(fmt, "callback error: {}", traceback)
+                }
             }
             Error::ExternalError(ref err) => write!(fmt, "external error: {}", err),
+            Error::Custom { kind, .. } => write!(fmt, "{} error", kind),
         }
     }
 }
@@ -204,6 +359,201 @@ impl Error {
     pub fn external<T: Into<Box<dyn StdError + Send + Sync>>>(err: T) -> Error {
         Error::ExternalError(err.into().into())
     }
+
+    /// Creates an `Error::Custom` with the given kind and payload.
+    pub fn custom<T: Any + Send + Sync>(kind: &'static str, payload: T) -> Error {
+        Error::Custom {
+            kind,
+            payload: Arc::new(payload),
+        }
+    }
+
+    /// Returns the kind of this error if it is an `Error::Custom`, or a `CallbackError` caused by
+    /// one.
+    pub fn custom_kind(&self) -> Option<&'static str> {
+        match *self.root_cause() {
+            Error::Custom { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// Returns the payload of this error if it is an `Error::Custom` (or a `CallbackError` caused
+    /// by one) with a payload of type `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// #[derive(Debug, PartialEq)]
+    /// struct NotFound {
+    ///     path: String,
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// let open = lua_context.create_function(|_, path: String| -> Result<()> {
+    ///     Err(Error::custom("not_found", NotFound { path }))
+    /// })?;
+    /// lua_context.globals().set("open", open)?;
+    ///
+    /// let err = lua_context.load(r#"open("config.lua")"#).exec().unwrap_err();
+    /// assert_eq!(err.custom_kind(), Some("not_found"));
+    /// assert_eq!(
+    ///     err.downcast_custom::<NotFound>(),
+    ///     Some(&NotFound { path: "config.lua".to_owned() })
+    /// );
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn downcast_custom<T: Any>(&self) -> Option<&T> {
+        match *self.root_cause() {
+            Error::Custom { ref payload, .. } => payload.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the Lua traceback taken where this error was raised, if any.
+    ///
+    /// This is the `traceback` of a `CallbackError`, or the traceback which rlua's error handler
+    /// appends to the message of a `RuntimeError`.  Errors raised while tracebacks are disabled
+    /// with [`Lua::set_traceback_enabled`] have none.
+    ///
+    /// [`Lua::set_traceback_enabled`]: struct.Lua.html#method.set_traceback_enabled
+    pub fn traceback(&self) -> Option<&str> {
+        match *self {
+            Error::CallbackError { ref traceback, .. } if !traceback.is_empty() => Some(traceback),
+            Error::RuntimeError(ref msg) => {
+                msg.find(TRACEBACK_HEADER).map(|start| &msg[start + 1..])
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the error object of this error if it is an `Error::LuaError`, or a `CallbackError`
+    /// caused by one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let err = lua_context
+    ///     .load("error({code = 404, msg = 'not found'})")
+    ///     .exec()
+    ///     .unwrap_err();
+    /// let value: Table = err.lua_value(lua_context)?.unwrap();
+    /// assert_eq!(value.get::<_, i64>("code")?, 404);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn lua_value<'lua, T: FromLua<'lua>>(&self, lua: Context<'lua>) -> Result<Option<T>> {
+        match *self.root_cause() {
+            Error::LuaError { ref value, .. } => Ok(Some(lua.registry_value(value)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a short name for the kind of this error.
+    ///
+    /// `CallbackError`s, and `ExternalError`s wrapping another `rlua::Error`, report the kind of
+    /// the error which caused them, and `Error::Custom` reports its own `kind`.  This is also the
+    /// `kind` field of errors seen by scripts, see [`Error::CallbackError`].
+    ///
+    /// [`Error::CallbackError`]: #variant.CallbackError
+    pub fn kind_name(&self) -> &'static str {
+        match *self.root_cause() {
+            Error::SyntaxError { .. } => "syntax",
+            Error::RuntimeError(_) => "runtime",
+            Error::LuaError { .. } => "lua_error",
+            Error::MemoryError(_) => "memory",
+            Error::StringLimitExceeded { .. } => "string_limit",
+            Error::TableLimitExceeded { .. } => "table_limit",
+            Error::DepthLimitExceeded { .. } => "depth_limit",
+            Error::IncompatibleBytecode { .. } => "incompatible_bytecode",
+            Error::GarbageCollectorError(_) => "garbage_collector",
+            Error::RecursiveMutCallback => "recursive_mut_callback",
+            Error::CallbackDestructed => "callback_destructed",
+            Error::StackError => "stack",
+            Error::BindError => "bind",
+            Error::ToLuaConversionError { .. } => "to_lua_conversion",
+            Error::FromLuaConversionError { .. } => "from_lua_conversion",
+            Error::CoroutineInactive => "coroutine_inactive",
+            Error::UserDataTypeMismatch => "userdata_type_mismatch",
+            Error::UserDataBorrowError => "userdata_borrow",
+            Error::UserDataBorrowMutError => "userdata_borrow_mut",
+            Error::UserDataTypeNotRegistered(_) => "userdata_type_not_registered",
+            Error::UserDataDestructed => "userdata_destructed",
+            Error::TaskCancelled => "task_cancelled",
+            Error::MismatchedRegistryKey => "mismatched_registry_key",
+            Error::CallbackTimeout(_) => "callback_timeout",
+            Error::CallbackError { .. } => unreachable!(),
+            Error::ExternalError(_) => "external",
+            Error::Custom { kind, .. } => kind,
+        }
+    }
+
+    /// Returns the error which caused this one, looking through `CallbackError`s and through
+    /// `ExternalError`s wrapping another `rlua::Error`.
+    pub fn root_cause(&self) -> &Error {
+        let mut err = self;
+        loop {
+            err = match *err {
+                Error::CallbackError { ref cause, .. } => cause,
+                Error::ExternalError(ref inner) => match inner.downcast_ref::<Error>() {
+                    Some(inner) => inner,
+                    None => return err,
+                },
+                _ => return err,
+            };
+        }
+    }
+
+    /// Returns the first error of type `E` in the chain of causes of this error, starting with the
+    /// error itself.
+    ///
+    /// This recovers the error returned by a callback after it crossed Lua, through any number of
+    /// `CallbackError`s and `ExternalError`s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::fmt;
+    /// # use rlua::{Error, ExternalError, Lua, Result};
+    /// #[derive(Debug)]
+    /// struct Denied;
+    ///
+    /// impl fmt::Display for Denied {
+    ///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    ///         write!(f, "access denied")
+    ///     }
+    /// }
+    ///
+    /// impl std::error::Error for Denied {}
+    ///
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let open = lua_context.create_function(|_, ()| -> Result<()> { Err(Denied.to_lua_err()) })?;
+    /// lua_context.globals().set("open", open)?;
+    ///
+    /// let err = lua_context.load("open()").exec().unwrap_err();
+    /// assert!(err.downcast_ref::<Denied>().is_some());
+    /// assert!(matches!(err.root_cause(), Error::ExternalError(_)));
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        let mut err: &(dyn StdError + 'static) = self;
+        loop {
+            if let Some(err) = err.downcast_ref::<E>() {
+                return Some(err);
+            }
+            err = err.source()?;
+        }
+    }
 }
 
 pub trait ExternalError {
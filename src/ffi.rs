@@ -9,6 +9,7 @@ use std::ptr;
 pub type lua_Integer = c_longlong;
 pub type lua_Number = c_double;
 
+/// A Lua state or thread, as used by the Lua C API.
 pub enum lua_State {}
 pub type lua_Alloc = unsafe extern "C" fn(
     ud: *mut c_void,
@@ -21,6 +22,14 @@ pub type lua_KFunction =
     unsafe extern "C" fn(state: *mut lua_State, status: c_int, ctx: lua_KContext) -> c_int;
 pub type lua_CFunction = unsafe extern "C" fn(state: *mut lua_State) -> c_int;
 pub type lua_Hook = unsafe extern "C" fn(state: *mut lua_State, ar: *mut lua_Debug);
+pub type lua_Reader =
+    unsafe extern "C" fn(state: *mut lua_State, ud: *mut c_void, sz: *mut usize) -> *const c_char;
+pub type lua_Writer = unsafe extern "C" fn(
+    state: *mut lua_State,
+    p: *const c_void,
+    sz: usize,
+    ud: *mut c_void,
+) -> c_int;
 
 #[repr(C)]
 pub struct lua_Debug {
@@ -82,6 +91,12 @@ pub const LUA_GCSETPAUSE: c_int = 6;
 pub const LUA_GCSETSTEPMUL: c_int = 7;
 pub const LUA_GCISRUNNING: c_int = 9;
 
+pub const LUA_HOOKCALL: c_int = 0;
+pub const LUA_HOOKRET: c_int = 1;
+pub const LUA_HOOKLINE: c_int = 2;
+pub const LUA_HOOKCOUNT: c_int = 3;
+pub const LUA_HOOKTAILCALL: c_int = 4;
+
 pub const LUA_MASKCALL: c_int = 1;
 pub const LUA_MASKRET: c_int = 2;
 pub const LUA_MASKLINE: c_int = 4;
@@ -106,6 +121,13 @@ extern "C" {
         ctx: lua_KContext,
         k: Option<lua_KFunction>,
     ) -> c_int;
+    pub fn lua_isyieldable(state: *mut lua_State) -> c_int;
+    pub fn lua_yieldk(
+        state: *mut lua_State,
+        nresults: c_int,
+        ctx: lua_KContext,
+        k: Option<lua_KFunction>,
+    ) -> c_int;
     pub fn lua_resume(state: *mut lua_State, from: *mut lua_State, nargs: c_int) -> c_int;
     pub fn lua_status(state: *mut lua_State) -> c_int;
 
@@ -117,6 +139,7 @@ extern "C" {
     pub fn lua_pushlstring(state: *mut lua_State, s: *const c_char, len: usize) -> *const c_char;
     pub fn lua_pushstring(state: *mut lua_State, s: *const c_char) -> *const c_char;
     pub fn lua_pushlightuserdata(state: *mut lua_State, data: *mut c_void);
+    pub fn lua_pushthread(state: *mut lua_State) -> c_int;
     pub fn lua_pushcclosure(state: *mut lua_State, function: lua_CFunction, n: c_int);
 
     pub fn lua_tointegerx(state: *mut lua_State, index: c_int, isnum: *mut c_int) -> lua_Integer;
@@ -147,6 +170,8 @@ extern "C" {
     pub fn lua_rawget(state: *mut lua_State, index: c_int) -> c_int;
     pub fn lua_rawgeti(state: *mut lua_State, index: c_int, n: lua_Integer) -> c_int;
     pub fn lua_rawseti(state: *mut lua_State, index: c_int, n: lua_Integer);
+    pub fn lua_rawgetp(state: *mut lua_State, index: c_int, p: *const c_void) -> c_int;
+    pub fn lua_rawsetp(state: *mut lua_State, index: c_int, p: *const c_void);
     pub fn lua_getmetatable(state: *mut lua_State, index: c_int) -> c_int;
 
     pub fn lua_createtable(state: *mut lua_State, narr: c_int, nrec: c_int);
@@ -171,6 +196,21 @@ extern "C" {
     pub fn lua_error(state: *mut lua_State) -> !;
     pub fn lua_atpanic(state: *mut lua_State, panic: lua_CFunction) -> lua_CFunction;
     pub fn lua_gc(state: *mut lua_State, what: c_int, data: c_int) -> c_int;
+    pub fn lua_load(
+        state: *mut lua_State,
+        reader: lua_Reader,
+        data: *mut c_void,
+        chunkname: *const c_char,
+        mode: *const c_char,
+    ) -> c_int;
+    pub fn lua_dump(
+        state: *mut lua_State,
+        writer: lua_Writer,
+        data: *mut c_void,
+        strip: c_int,
+    ) -> c_int;
+    pub fn lua_getstack(state: *mut lua_State, level: c_int, ar: *mut lua_Debug) -> c_int;
+    pub fn lua_getlocal(state: *mut lua_State, ar: *const lua_Debug, n: c_int) -> *const c_char;
     pub fn lua_getinfo(state: *mut lua_State, what: *const c_char, ar: *mut lua_Debug) -> c_int;
 
     pub fn lua_sethook(state: *mut lua_State, f: Option<lua_Hook>, mask: c_int, count: c_int);
@@ -318,6 +358,10 @@ pub unsafe fn lua_call(state: *mut lua_State, nargs: c_int, nresults: c_int) {
     lua_callk(state, nargs, nresults, ptr::null_mut(), None)
 }
 
+pub unsafe fn lua_yield(state: *mut lua_State, nresults: c_int) -> c_int {
+    lua_yieldk(state, nresults, ptr::null_mut(), None)
+}
+
 pub unsafe fn lua_pcall(
     state: *mut lua_State,
     nargs: c_int,
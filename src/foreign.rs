@@ -0,0 +1,60 @@
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::util::{assert_stack, StackGuard};
+use crate::value::{MultiValue, Value};
+
+pub(crate) fn extend_foreign_userdata<'lua>(
+    lua: Context<'lua>,
+    metatable: Table<'lua>,
+    methods: Table<'lua>,
+) -> Result<()> {
+    if is_rlua_metatable(lua, &metatable) {
+        return Err(Error::RuntimeError(
+            "cannot extend the metatable of a rust userdata type".to_owned(),
+        ));
+    }
+
+    // Methods added by rlua take priority over the existing `__index`, which is still consulted for
+    // every other key, so extending a metatable more than once layers the method tables.  Both are
+    // bound as upvalues rather than kept in the registry, as the old `__index` is often the metatable
+    // itself and the cycle must stay collectable.
+    let fallback = metatable.raw_get::<_, Value>("__index")?;
+    let index = lua
+        .create_function(
+            |_, (methods, fallback, ud, key): (Table, Value, Value, Value)| {
+                match methods.raw_get(key.clone())? {
+                    Value::Nil => {}
+                    method => return Ok(MultiValue::from_vec(vec![method])),
+                }
+                match fallback {
+                    Value::Table(fallback) => Ok(MultiValue::from_vec(vec![fallback.get(key)?])),
+                    Value::Function(fallback) => fallback.call((ud, key)),
+                    _ => Ok(MultiValue::new()),
+                }
+            },
+        )?
+        .bind((methods, fallback))?;
+    metatable.raw_set("__index", index)
+}
+
+// Whether `metatable` belongs to one of the userdata types rlua has registered, whose layout rlua
+// relies on.
+fn is_rlua_metatable<'lua>(lua: Context<'lua>, metatable: &Table<'lua>) -> bool {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 2);
+        lua.push_ref(&metatable.0);
+        (*extra_data(lua.state))
+            .registered_userdata
+            .values()
+            .any(|&id| {
+                ffi::lua_rawgeti(lua.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                let equal = ffi::lua_rawequal(lua.state, -1, -2) != 0;
+                ffi::lua_pop(lua.state, 1);
+                equal
+            })
+    }
+}
@@ -1,16 +1,25 @@
-use std::os::raw::c_int;
-use std::ptr;
+use std::any::Any;
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::{fmt, mem, ptr, slice};
 
+use crate::cmodule;
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::table::Table;
+use crate::thread::AsyncThread;
 use crate::types::LuaRef;
 use crate::util::{
     assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure, StackGuard,
 };
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
+use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti, Value};
 
 /// Handle to an internal Lua function.
-#[derive(Clone, Debug)]
+///
+/// Cloning a `Function` creates another handle to the same function.
+#[derive(Clone)]
 pub struct Function<'lua>(pub(crate) LuaRef<'lua>);
 
 impl<'lua> Function<'lua> {
@@ -88,6 +97,60 @@ impl<'lua> Function<'lua> {
         R::from_lua_multi(results, lua)
     }
 
+    /// Calls the function like [`call`], turning a `nil, message` result into an error.
+    ///
+    /// Many C modules, and the standard `io` library, report failures by returning `nil` followed
+    /// by an error message rather than raising an error.  If the first result is `nil` and the
+    /// second is not, this returns a `RuntimeError` with the message instead of the results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Function, Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let io: Table = lua_context.globals().get("io")?;
+    /// let open: Function = io.get("open")?;
+    ///
+    /// match open.call_checked::<_, ()>("/nonexistent/file") {
+    ///     Err(Error::RuntimeError(message)) => assert!(message.contains("/nonexistent/file")),
+    ///     r => panic!("unexpected result {:?}", r),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`call`]: #method.call
+    pub fn call_checked<A, R>(&self, args: A) -> Result<R>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let lua = self.0.lua;
+        let results = self.call::<_, MultiValue>(args)?.into_vec();
+        cmodule::check_nil_error(lua, &results)?;
+        R::from_lua_multi(MultiValue::from_vec(results), lua)
+    }
+
+    /// Calls the function inside a new thread, returning a future which resolves to its results.
+    ///
+    /// This is needed to call functions which use async functions created with
+    /// [`Context::create_async_function`].  See [`Thread::into_async`] for how the thread is run.
+    ///
+    /// [`Context::create_async_function`]: struct.Context.html#method.create_async_function
+    /// [`Thread::into_async`]: struct.Thread.html#method.into_async
+    pub fn call_async<A, R>(&self, args: A) -> AsyncThread<'lua, R>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        match self.0.lua.create_thread(self.clone()) {
+            Ok(thread) => thread.into_async(args),
+            Err(err) => AsyncThread::failed(err),
+        }
+    }
+
     /// Returns a function that, when called, calls `self`, passing `args` as the first set of
     /// arguments.
     ///
@@ -161,4 +224,258 @@ impl<'lua> Function<'lua> {
             Ok(Function(lua.pop_ref()))
         }
     }
+
+    /// Dumps the function as a binary chunk, like Lua's `string.dump`.
+    ///
+    /// The chunk can be loaded again with [`Context::load_bytecode`].  If `strip` is true, debug information
+    /// such as line numbers and local variable names is left out.  This fails for functions
+    /// implemented in Rust or C.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let double: Function = lua_context.load("function(x) return x * 2 end").eval()?;
+    /// let bytecode = double.dump(true)?;
+    ///
+    /// let loaded = unsafe { lua_context.load_bytecode(&bytecode, "double")? };
+    /// assert_eq!(loaded.call::<_, i64>(21)?, 42);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Context::load_bytecode`]: struct.Context.html#method.load_bytecode
+    pub fn dump(&self, strip: bool) -> Result<Vec<u8>> {
+        let mut bytecode = Vec::new();
+        self.dump_to(&mut bytecode, strip)?;
+        Ok(bytecode)
+    }
+
+    /// Writes the function as a binary chunk to `writer`, like [`dump`], without collecting the
+    /// whole chunk in memory first.
+    ///
+    /// Errors returned by `writer` stop the dump and are returned as an `Error::ExternalError`.
+    ///
+    /// [`dump`]: #method.dump
+    pub fn dump_to<W: Write>(&self, mut writer: W, strip: bool) -> Result<()> {
+        struct Dump<'a> {
+            writer: &'a mut dyn Write,
+            error: Option<io::Error>,
+            panic: Option<Box<dyn Any + Send>>,
+        }
+
+        unsafe extern "C" fn write_chunk(
+            _state: *mut ffi::lua_State,
+            p: *const c_void,
+            sz: usize,
+            ud: *mut c_void,
+        ) -> c_int {
+            let dump = &mut *(ud as *mut Dump);
+            let bytes = slice::from_raw_parts(p as *const u8, sz);
+            match catch_unwind(AssertUnwindSafe(|| dump.writer.write_all(bytes))) {
+                Ok(Ok(())) => 0,
+                Ok(Err(err)) => {
+                    dump.error = Some(err);
+                    1
+                }
+                Err(panic) => {
+                    dump.panic = Some(panic);
+                    1
+                }
+            }
+        }
+
+        let lua = self.0.lua;
+        let mut dump = Dump {
+            writer: &mut writer,
+            error: None,
+            panic: None,
+        };
+        let status = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+
+            lua.push_ref(&self.0);
+            ffi::lua_dump(
+                lua.state,
+                write_chunk,
+                &mut dump as *mut Dump as *mut c_void,
+                strip as c_int,
+            )
+        };
+
+        if let Some(panic) = dump.panic {
+            resume_unwind(panic);
+        }
+        if let Some(err) = dump.error {
+            return Err(Error::external(err));
+        }
+        if status != 0 {
+            return Err(Error::RuntimeError(
+                "unable to dump a function not implemented in Lua".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns information about where and how the function is defined.
+    ///
+    /// This is useful for tooling such as debuggers and profilers which need to report script
+    /// locations for functions they are handed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let add: Function = lua_context
+    ///     .load("\nreturn function(a, b)\n  return a + b\nend")
+    ///     .set_name("=math_helpers")?
+    ///     .eval()?;
+    /// let info = add.info();
+    /// assert_eq!(info.short_src.as_deref(), Some(&b"math_helpers"[..]));
+    /// assert_eq!(info.line_defined, 2);
+    /// assert_eq!(info.last_line_defined, 4);
+    /// assert_eq!(info.num_params, 2);
+    /// assert!(!info.is_vararg);
+    ///
+    /// let print: Function = lua_context.globals().get("print")?;
+    /// assert_eq!(print.info().name.as_deref(), Some(&b"print"[..]));
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn info(&self) -> FunctionInfo {
+        let lua = self.0.lua;
+        let mut info = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            lua.push_ref(&self.0);
+            rlua_assert!(
+                ffi::lua_getinfo(lua.state, cstr!(">Su"), &mut ar) != 0,
+                "lua_getinfo failed with `>Su`"
+            );
+            FunctionInfo {
+                name: None,
+                source: ptr_to_bytes(ar.source),
+                short_src: ptr_to_bytes(ar.short_src.as_ptr()),
+                line_defined: ar.linedefined as i32,
+                last_line_defined: ar.lastlinedefined as i32,
+                what: ptr_to_bytes(ar.what),
+                num_params: ar.nparams as i32,
+                is_vararg: ar.isvararg != 0,
+            }
+        };
+        // Functions have no name of their own, so like Lua's tracebacks, look for them in the
+        // loaded modules.  The name is only informational, so errors here are ignored.
+        info.name = self.loaded_name().unwrap_or(None);
+        info
+    }
+
+    // Finds the function in `package.loaded`, returning a name like "string.format", or just
+    // "print" for global functions.
+    fn loaded_name(&self) -> Result<Option<Vec<u8>>> {
+        let lua = self.0.lua;
+        let loaded: Table = lua.named_registry_value("_LOADED")?;
+        for pair in loaded.pairs::<Value, Value>() {
+            let (module_name, module) = match pair? {
+                (Value::String(name), Value::Table(module)) => (name, module),
+                _ => continue,
+            };
+            for pair in module.pairs::<Value, Value>() {
+                match pair? {
+                    (Value::String(name), Value::Function(f))
+                        if f.0.to_pointer() == self.0.to_pointer() =>
+                    {
+                        let mut full_name = Vec::new();
+                        if module_name.as_bytes() != b"_G" {
+                            full_name.extend_from_slice(module_name.as_bytes());
+                            full_name.push(b'.');
+                        }
+                        full_name.extend_from_slice(name.as_bytes());
+                        return Ok(Some(full_name));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Information about a function, as returned by [`Function::info`].
+///
+/// The fields correspond to those of the same names in Lua's [`lua_Debug`] structure.
+///
+/// [`Function::info`]: struct.Function.html#method.info
+/// [`lua_Debug`]: https://www.lua.org/manual/5.3/manual.html#lua_Debug
+#[derive(Clone, Debug)]
+pub struct FunctionInfo {
+    /// The name the function can be found under in a loaded module, such as `string.format`, or
+    /// just `print` for global functions.
+    pub name: Option<Vec<u8>>,
+    /// The source the function was loaded from, as given by the chunk name.
+    pub source: Option<Vec<u8>>,
+    /// A printable version of `source`, as used in error messages.
+    pub short_src: Option<Vec<u8>>,
+    /// The line where the function definition starts, or -1 for functions not written in Lua.
+    pub line_defined: i32,
+    /// The line where the function definition ends, or -1 for functions not written in Lua.
+    pub last_line_defined: i32,
+    /// `"Lua"` for Lua functions, `"C"` for functions implemented in Rust or C, or `"main"` for
+    /// the main part of a chunk.
+    pub what: Option<Vec<u8>>,
+    /// The number of fixed parameters, always 0 for functions not written in Lua.
+    pub num_params: i32,
+    /// Whether the function takes variable arguments, always true for functions not written in
+    /// Lua.
+    pub is_vararg: bool,
+}
+
+unsafe fn ptr_to_bytes(input: *const c_char) -> Option<Vec<u8>> {
+    if input.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(input).to_bytes().to_vec())
+    }
+}
+
+impl<'lua> fmt::Debug for Function<'lua> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lua = self.0.lua;
+        let (what, source, line) = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            lua.push_ref(&self.0);
+            rlua_assert!(
+                ffi::lua_getinfo(lua.state, cstr!(">S"), &mut ar) != 0,
+                "lua_getinfo failed with `>S`"
+            );
+            (
+                CStr::from_ptr(ar.what).to_string_lossy(),
+                CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy(),
+                ar.linedefined,
+            )
+        };
+
+        if what == "C" {
+            write!(f, "Function({:p}, C)", self.0.to_pointer())
+        } else {
+            write!(
+                f,
+                "Function({:p}, {}:{})",
+                self.0.to_pointer(),
+                source,
+                line
+            )
+        }
+    }
 }
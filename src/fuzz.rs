@@ -0,0 +1,199 @@
+//! Entry points for fuzzing `rlua`, used by the targets in the `fuzz` directory.
+//!
+//! Every function here accepts arbitrary bytes and must never panic or crash for any input;
+//! errors from Lua are expected and ignored.  Run them with `cargo fuzz run <target>` from the
+//! repository root.
+
+use std::collections::BTreeMap;
+use std::string::String as StdString;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::hook::HookTriggers;
+use crate::lua::{Lua, StdLib};
+use crate::table::Table;
+use crate::testing::pretty_print;
+use crate::value::{FromLua, ToLua, Value};
+
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+const INSTRUCTION_LIMIT: u32 = 1_000_000;
+const MAX_DEPTH: usize = 16;
+
+// Creates a state without the `io`, `os` and `package` libraries, which can only fail with the
+// memory and instruction limits applied.
+fn limited_lua() -> Lua {
+    let lua = Lua::new_with(
+        StdLib::BASE
+            | StdLib::COROUTINE
+            | StdLib::TABLE
+            | StdLib::STRING
+            | StdLib::UTF8
+            | StdLib::MATH,
+    );
+    lua.set_memory_limit(Some(MEMORY_LIMIT));
+    let count = AtomicU32::new(0);
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(1000),
+        move |_, _| {
+            if count.fetch_add(1000, Ordering::Relaxed) >= INSTRUCTION_LIMIT {
+                Err(Error::RuntimeError("instruction limit exceeded".to_owned()))
+            } else {
+                Ok(())
+            }
+        },
+    );
+    lua
+}
+
+/// Loads and runs `data` as a text chunk, with limits on memory and the number of instructions.
+/// Binary chunks are skipped, see `fuzz_bytecode` for those.
+pub fn fuzz_load(data: &[u8]) {
+    if data.starts_with(b"\x1bLua") {
+        return;
+    }
+    limited_lua().context(|lua| {
+        if let Ok(function) = lua
+            .load(data)
+            .set_name("=fuzz")
+            .and_then(|c| c.into_function())
+        {
+            let _ = function.call::<_, Value>(());
+        }
+    });
+}
+
+/// Builds a tree of Lua values described by `data`, then converts it to and from Rust types.
+///
+/// Conversions which succeed are checked to round trip, and the tree is printed with
+/// `testing::pretty_print`.
+pub fn fuzz_convert(data: &[u8]) {
+    limited_lua().context(|lua| {
+        let mut input = Input(data);
+        let value = match input.value(lua, 0) {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+        let _ = pretty_print(&value);
+        round_trip::<i64>(lua, &value);
+        round_trip::<bool>(lua, &value);
+        round_trip::<StdString>(lua, &value);
+        round_trip::<Option<StdString>>(lua, &value);
+        round_trip::<Vec<i64>>(lua, &value);
+        round_trip::<Vec<StdString>>(lua, &value);
+        round_trip::<BTreeMap<StdString, i64>>(lua, &value);
+        round_trip::<BTreeMap<i64, Vec<StdString>>>(lua, &value);
+        let _ = f64::from_lua(value.clone(), lua);
+        let _ = Vec::<Value>::from_lua(value, lua);
+    });
+}
+
+/// Loads `data` as a binary chunk without running it, exercising Lua's bytecode loader.
+///
+/// Lua does not verify bytecode, so malformed bytecode can cause undefined behavior even while
+/// loading.  This is only available with the `fuzz-bytecode` feature, to find such crashes.
+#[cfg(feature = "fuzz-bytecode")]
+pub fn fuzz_bytecode(data: &[u8]) {
+    if !data.starts_with(b"\x1bLua") {
+        return;
+    }
+    limited_lua().context(|lua| {
+        // Unsafe, which is the point: crashes here are bugs in Lua's loader.
+        let _ = unsafe { lua.load_bytecode(data, "=fuzz") };
+    });
+}
+
+fn round_trip<'lua, T>(lua: Context<'lua>, value: &Value<'lua>)
+where
+    T: Clone + PartialEq + std::fmt::Debug + FromLua<'lua> + ToLua<'lua>,
+{
+    if let Ok(converted) = T::from_lua(value.clone(), lua) {
+        let back = converted
+            .clone()
+            .to_lua(lua)
+            .and_then(|v| T::from_lua(v, lua));
+        match back {
+            Ok(back) => assert_eq!(back, converted, "conversion does not round trip"),
+            // The memory limit may be reached while converting back.
+            Err(Error::MemoryError(_)) => {}
+            Err(err) => panic!("converting back failed: {}", err),
+        }
+    }
+}
+
+// Decodes a value tree: each value starts with a tag byte, followed by its contents.
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&first, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(first)
+    }
+
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let (bytes, rest) = self.0.split_at(len.min(self.0.len()));
+        self.0 = rest;
+        bytes
+    }
+
+    fn u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        let bytes = self.bytes(8);
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    fn value<'lua>(&mut self, lua: Context<'lua>, depth: usize) -> Result<Value<'lua>> {
+        let tag = match self.byte() {
+            Some(tag) => tag,
+            None => return Ok(Value::Nil),
+        };
+        Ok(match tag % 8 {
+            0 => Value::Nil,
+            1 => Value::Boolean(tag & 8 != 0),
+            2 => Value::Integer(self.u64() as i64),
+            3 => Value::Number(f64::from_bits(self.u64())),
+            4 => {
+                let len = self.byte().unwrap_or(0) as usize;
+                Value::String(lua.create_string(self.bytes(len))?)
+            }
+            5 if depth < MAX_DEPTH => {
+                let table = lua.create_table()?;
+                for _ in 0..self.byte().unwrap_or(0) % 16 {
+                    let key = self.value(lua, depth + 1)?;
+                    let value = self.value(lua, depth + 1)?;
+                    set(&table, key, value)?;
+                }
+                Value::Table(table)
+            }
+            6 if depth < MAX_DEPTH => {
+                let len = self.byte().unwrap_or(0) % 16;
+                let table = lua.create_table()?;
+                for i in 1..=len {
+                    set(
+                        &table,
+                        Value::Integer(i.into()),
+                        self.value(lua, depth + 1)?,
+                    )?;
+                }
+                Value::Table(table)
+            }
+            7 => {
+                let len = self.byte().unwrap_or(0) as usize;
+                let message = StdString::from_utf8_lossy(self.bytes(len)).into_owned();
+                Value::Error(Error::RuntimeError(message))
+            }
+            _ => Value::Nil,
+        })
+    }
+}
+
+// Sets a table entry, skipping the nil and NaN keys Lua rejects.
+fn set<'lua>(table: &Table<'lua>, key: Value<'lua>, value: Value<'lua>) -> Result<()> {
+    match key {
+        Value::Nil => Ok(()),
+        Value::Number(n) if n.is_nan() => Ok(()),
+        key => table.raw_set(key, value),
+    }
+}
@@ -24,6 +24,24 @@ pub struct Debug<'a> {
 }
 
 impl<'a> Debug<'a> {
+    /// Returns the event which caused the hook to be called.
+    ///
+    /// Note that a call made with `return f()` replaces the calling function on the stack, so it
+    /// is reported as `DebugEvent::TailCall` and no `DebugEvent::Return` event is generated for the
+    /// replaced function.
+    pub fn event(&self) -> DebugEvent {
+        match unsafe { (*self.ar).event } {
+            ffi::LUA_HOOKCALL => DebugEvent::Call,
+            ffi::LUA_HOOKTAILCALL => DebugEvent::TailCall,
+            ffi::LUA_HOOKRET => DebugEvent::Return,
+            ffi::LUA_HOOKLINE => DebugEvent::Line,
+            ffi::LUA_HOOKCOUNT => DebugEvent::Count,
+            event => {
+                rlua_panic!("unknown hook event {}", event);
+            }
+        }
+    }
+
     /// Corresponds to the `n` what mask.
     pub fn names(&self) -> DebugNames<'a> {
         unsafe {
@@ -74,7 +92,7 @@ impl<'a> Debug<'a> {
                 ffi::lua_getinfo(self.state, cstr!("t"), self.ar) != 0,
                 "lua_getinfo failed with `t`"
             );
-            (*self.ar).currentline != 0
+            (*self.ar).istailcall != 0
         }
     }
 
@@ -94,6 +112,23 @@ impl<'a> Debug<'a> {
     }
 }
 
+/// The event which caused a hook function to be called, as returned by [`Debug::event`].
+///
+/// [`Debug::event`]: struct.Debug.html#method.event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DebugEvent {
+    /// A function is being called.
+    Call,
+    /// A function is being called as a tail call, replacing the calling function.
+    TailCall,
+    /// A function is about to return.
+    Return,
+    /// A new line of code is about to be executed.
+    Line,
+    /// The configured number of VM instructions has been executed.
+    Count,
+}
+
 #[derive(Clone, Debug)]
 pub struct DebugNames<'a> {
     pub name: Option<&'a [u8]>,
@@ -135,6 +170,44 @@ pub struct HookTriggers {
 }
 
 impl HookTriggers {
+    /// Returns triggers which never call the hook, to be enabled with the builder methods below.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::HookTriggers;
+    /// let triggers = HookTriggers::new().on_calls().every_nth_instruction(1000);
+    /// assert!(triggers.on_calls && !triggers.on_returns);
+    /// assert_eq!(triggers.every_nth_instruction, Some(1000));
+    /// ```
+    pub fn new() -> HookTriggers {
+        HookTriggers::default()
+    }
+
+    /// Also calls the hook before every function call.
+    pub fn on_calls(mut self) -> HookTriggers {
+        self.on_calls = true;
+        self
+    }
+
+    /// Also calls the hook when Lua returns from a function.
+    pub fn on_returns(mut self) -> HookTriggers {
+        self.on_returns = true;
+        self
+    }
+
+    /// Also calls the hook before executing every new line.
+    pub fn every_line(mut self) -> HookTriggers {
+        self.every_line = true;
+        self
+    }
+
+    /// Also calls the hook every `count` VM instructions.
+    pub fn every_nth_instruction(mut self, count: u32) -> HookTriggers {
+        self.every_nth_instruction = Some(count);
+        self
+    }
+
     // Compute the mask to pass to `lua_sethook`.
     pub(crate) fn mask(&self) -> c_int {
         let mut mask: c_int = 0;
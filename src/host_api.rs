@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_int;
+use std::rc::Rc;
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::function::Function;
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
+
+// The hook set with `Lua::set_deprecation_hook`.
+pub(crate) type DeprecationHook = Rc<RefCell<dyn FnMut(Context, &DeprecationEvent) -> Result<()>>>;
+
+/// Describes why and since when a host function is deprecated.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Deprecation {
+    /// The host API version which deprecated the function.
+    pub since: StdString,
+    /// The name of the function which replaces the deprecated one, if any.
+    pub replacement: Option<StdString>,
+    /// An optional message for script authors.
+    pub message: Option<StdString>,
+}
+
+/// Passed to the hook set with [`Lua::set_deprecation_hook`] whenever a script calls a deprecated
+/// host function.
+///
+/// [`Lua::set_deprecation_hook`]: struct.Lua.html#method.set_deprecation_hook
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeprecationEvent {
+    /// The name the deprecated function was registered under.
+    pub function: StdString,
+    /// The deprecation info given when the function was registered.
+    pub deprecation: Deprecation,
+    /// The location of the Lua code which made the call, as `"source:line"`, or `None` if the
+    /// function was not called from Lua.
+    pub call_site: Option<StdString>,
+}
+
+/// How often a deprecated host function was called from a given call site, as returned by
+/// [`Lua::deprecation_usage`].
+///
+/// [`Lua::deprecation_usage`]: struct.Lua.html#method.deprecation_usage
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeprecationUsage {
+    /// The name the deprecated function was registered under.
+    pub function: StdString,
+    /// The location of the calling Lua code, see `DeprecationEvent::call_site`.
+    pub call_site: Option<StdString>,
+    /// The number of calls.
+    pub count: usize,
+}
+
+/// A table of host functions exposed to scripts, along with the version of the host API.
+///
+/// The table is created by [`Context::create_host_api`] and contains an `api_version` field, which
+/// scripts can use to check which host features are available.  Functions registered as deprecated
+/// with [`HostApi::set_deprecated_function`] or [`HostApi::set_shim`] are listed in the
+/// `deprecated` field, mapping the function name to a table with `since`, `replacement` and
+/// `message` fields.  Every call to one of them is reported to the hook set with
+/// [`Lua::set_deprecation_hook`], and counted in [`Lua::deprecation_usage`].
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Deprecation, Lua, Result};
+/// # use std::sync::{Arc, Mutex};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::new();
+///
+/// let warnings = Arc::new(Mutex::new(Vec::new()));
+/// let hook_warnings = warnings.clone();
+/// lua.set_deprecation_hook(move |_, event| {
+///     hook_warnings.lock().unwrap().push(event.function.clone());
+///     Ok(())
+/// });
+///
+/// lua.context(|lua_context| {
+///     let host = lua_context.create_host_api("2.0.0")?;
+///     host.set_function("spawn", |_, name: String| Ok(format!("spawned {}", name)))?;
+///     host.set_deprecated_function(
+///         "create",
+///         Deprecation {
+///             since: "2.0.0".to_owned(),
+///             message: Some("creating entities by type name is slow".to_owned()),
+///             ..Deprecation::default()
+///         },
+///         |_, name: String| Ok(format!("spawned {}", name)),
+///     )?;
+///     host.set_shim(
+///         "new",
+///         "spawn",
+///         Deprecation {
+///             since: "1.4.0".to_owned(),
+///             ..Deprecation::default()
+///         },
+///     )?;
+///     lua_context.globals().set("host", host.table())?;
+///
+///     lua_context.load(r#"
+///         assert(host.api_version == "2.0.0")
+///         assert(host.deprecated.create.since == "2.0.0")
+///         assert(host.create("goblin") == "spawned goblin")
+///         assert(host.deprecated.new.replacement == "spawn")
+///         assert(host.new("troll") == "spawned troll")
+///     "#).exec()
+/// })?;
+///
+/// assert_eq!(*warnings.lock().unwrap(), vec!["create".to_owned(), "new".to_owned()]);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Context::create_host_api`]: struct.Context.html#method.create_host_api
+/// [`HostApi::set_deprecated_function`]: #method.set_deprecated_function
+/// [`HostApi::set_shim`]: #method.set_shim
+/// [`Lua::set_deprecation_hook`]: struct.Lua.html#method.set_deprecation_hook
+/// [`Lua::deprecation_usage`]: struct.Lua.html#method.deprecation_usage
+#[derive(Clone)]
+pub struct HostApi<'lua> {
+    lua: Context<'lua>,
+    table: Table<'lua>,
+    deprecated: Table<'lua>,
+}
+
+impl<'lua> HostApi<'lua> {
+    pub(crate) fn new(lua: Context<'lua>, version: &str) -> Result<HostApi<'lua>> {
+        let table = lua.create_table()?;
+        let deprecated = lua.create_table()?;
+        table.raw_set("api_version", version)?;
+        table.raw_set("deprecated", deprecated.clone())?;
+        Ok(HostApi {
+            lua,
+            table,
+            deprecated,
+        })
+    }
+
+    /// Returns the host API version this table was created with.
+    pub fn api_version(&self) -> Result<StdString> {
+        self.table.raw_get("api_version")
+    }
+
+    /// Registers a host function under the given name.
+    pub fn set_function<A, R, F>(&self, name: &str, func: F) -> Result<()>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
+    {
+        self.table.raw_set(name, self.lua.create_function(func)?)
+    }
+
+    /// Registers a deprecated host function under the given name.
+    ///
+    /// The function works as normal, but every call to it is first reported to the hook set with
+    /// [`Lua::set_deprecation_hook`].  If the hook returns an error, the call fails with that error
+    /// instead.
+    ///
+    /// [`Lua::set_deprecation_hook`]: struct.Lua.html#method.set_deprecation_hook
+    pub fn set_deprecated_function<A, R, F>(
+        &self,
+        name: &str,
+        deprecation: Deprecation,
+        func: F,
+    ) -> Result<()>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
+    {
+        self.set_deprecation_info(name, &deprecation)?;
+
+        let name = name.to_owned();
+        self.table.raw_set(
+            name.clone(),
+            self.lua.create_function(move |lua, args: A| {
+                report_deprecation(lua, 1, &name, &deprecation)?;
+                func(lua, args)
+            })?,
+        )
+    }
+
+    /// Registers a deprecated name for a host function which has been renamed or moved.
+    ///
+    /// Calling the function `name` reports the call like a function registered with
+    /// [`HostApi::set_deprecated_function`], then forwards the arguments to the function found at
+    /// `target` in this table and returns its results.  `target` may be a path of keys separated by
+    /// `.` for functions moved into nested tables, and is looked up on every call, so the shim
+    /// always forwards to the current implementation.
+    ///
+    /// If the `replacement` field of `deprecation` is `None`, it is set to `target`.
+    ///
+    /// [`HostApi::set_deprecated_function`]: #method.set_deprecated_function
+    pub fn set_shim(&self, name: &str, target: &str, mut deprecation: Deprecation) -> Result<()> {
+        if deprecation.replacement.is_none() {
+            deprecation.replacement = Some(target.to_owned());
+        }
+        self.set_deprecation_info(name, &deprecation)?;
+
+        // The table is bound as an upvalue of the shim rather than kept in the registry, as the
+        // shim is stored in the table itself and the cycle must stay collectable.
+        let function = name.to_owned();
+        let target = target.to_owned();
+        let shim = self
+            .lua
+            .create_function(move |lua, (mut table, args): (Table, MultiValue)| {
+                // The shim is called through the closure created by `bind`, so the calling Lua code
+                // is one more level up the stack.
+                report_deprecation(lua, 2, &function, &deprecation)?;
+                let mut keys = target.split('.').peekable();
+                while let Some(key) = keys.next() {
+                    if keys.peek().is_none() {
+                        let function: Function = table.get(key)?;
+                        return function.call::<_, MultiValue>(args);
+                    }
+                    table = table.get(key)?;
+                }
+                unreachable!()
+            })?
+            .bind(self.table.clone())?;
+        self.table.raw_set(name, shim)
+    }
+
+    /// Returns the table holding the host functions, to be placed where scripts can find it.
+    pub fn table(&self) -> Table<'lua> {
+        self.table.clone()
+    }
+
+    fn set_deprecation_info(&self, name: &str, deprecation: &Deprecation) -> Result<()> {
+        let info = self.lua.create_table()?;
+        info.raw_set("since", deprecation.since.as_str())?;
+        info.raw_set("replacement", deprecation.replacement.as_deref())?;
+        info.raw_set("message", deprecation.message.as_deref())?;
+        self.deprecated.raw_set(name, info)
+    }
+}
+
+// Records a call to a deprecated function and passes it on to the deprecation hook.  Must be called
+// from a Rust callback, with the calling Lua code `level` levels up the stack.
+fn report_deprecation(
+    lua: Context,
+    level: c_int,
+    function: &str,
+    deprecation: &Deprecation,
+) -> Result<()> {
+    let call_site = unsafe { call_site(lua.state, level) };
+    let hook = unsafe {
+        let extra = extra_data(lua.state);
+        *(*extra)
+            .deprecation_usage
+            .entry((function.to_owned(), call_site.clone()))
+            .or_insert(0) += 1;
+        match (*extra).deprecation_hook.clone() {
+            Some(hook) => hook,
+            None => return Ok(()),
+        }
+    };
+
+    let event = DeprecationEvent {
+        function: function.to_owned(),
+        deprecation: deprecation.clone(),
+        call_site,
+    };
+    let mut hook = hook
+        .try_borrow_mut()
+        .map_err(|_| Error::RecursiveMutCallback)?;
+    (*hook)(lua, &event)
+}
+
+unsafe fn call_site(state: *mut ffi::lua_State, level: c_int) -> Option<StdString> {
+    let mut ar: ffi::lua_Debug = mem::zeroed();
+    if ffi::lua_getstack(state, level, &mut ar) == 0 {
+        return None;
+    }
+    rlua_assert!(
+        ffi::lua_getinfo(state, cstr!("Sl"), &mut ar) != 0,
+        "lua_getinfo failed with `Sl`"
+    );
+
+    let source = CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy();
+    Some(if ar.currentline > 0 {
+        format!("{}:{}", source, ar.currentline)
+    } else {
+        source.into_owned()
+    })
+}
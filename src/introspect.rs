@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::result::Result as StdResult;
+use std::string::String as StdString;
+use std::time::Duration;
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::function::Function;
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::value::{MultiValue, Value};
+
+/// Describes a `UserData` type which has been registered with a Lua state.
+///
+/// A type is registered the first time a value of that type is moved into Lua.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisteredType {
+    /// The Rust name of the type, as returned by `std::any::type_name`.
+    pub type_name: &'static str,
+    /// The names of the methods added in `UserData::add_methods`, in sorted order.
+    pub methods: Vec<StdString>,
+    /// The names of the metamethods added in `UserData::add_methods`, in sorted order.
+    pub meta_methods: Vec<StdString>,
+}
+
+/// Describes a Rust function reachable from the globals table of a Lua state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisteredFunction {
+    /// The path to the function from the globals table, with the keys separated by `.`, such as
+    /// `"host.log"`.
+    pub path: StdString,
+}
+
+/// How often a Rust function was called and how long it ran, as returned by
+/// [`Lua::callback_stats`].
+///
+/// [`Lua::callback_stats`]: struct.Lua.html#method.callback_stats
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallbackStats {
+    /// The path to the function from the globals table as in [`RegisteredFunction`], or `None` if
+    /// the function is not reachable from the globals table.
+    ///
+    /// [`RegisteredFunction`]: struct.RegisteredFunction.html
+    pub path: Option<StdString>,
+    /// The number of calls.
+    pub calls: u64,
+    /// The total time spent in the function, including any Lua code and other Rust functions it
+    /// called.
+    pub time: Duration,
+}
+
+/// A call to a Rust function tagged with [`Context::set_call_logging`], as returned by
+/// [`Lua::call_log`].
+///
+/// Arguments and results are printed on a single line, separated by commas.  Nested tables are
+/// printed down to a limited depth and with a limited number of entries, and long strings are
+/// cut short, so that logging stays cheap whatever the function is passed.
+///
+/// [`Context::set_call_logging`]: struct.Context.html#method.set_call_logging
+/// [`Lua::call_log`]: struct.Lua.html#method.call_log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoggedCall {
+    /// The name the function was tagged with.
+    pub function: StdString,
+    /// The arguments of the call.
+    pub arguments: StdString,
+    /// The results of the call, or the error it raised.
+    pub results: StdResult<StdString, StdString>,
+}
+
+// The call counts and times of a Rust callback, kept while callback statistics are enabled.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CallbackTotals {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+// Finds all Rust functions reachable from the globals table through string keys, along with their
+// paths.  Each table is only visited once, so functions in a table reachable by several paths are
+// reported once.
+fn reachable_callbacks(lua: Context) -> Result<Vec<(StdString, Function)>> {
+    fn walk<'lua>(
+        lua: Context<'lua>,
+        table: Table<'lua>,
+        prefix: &str,
+        visited: &mut Vec<*const c_void>,
+        functions: &mut Vec<(StdString, Function<'lua>)>,
+    ) -> Result<()> {
+        visited.push(table.0.to_pointer());
+        for pair in table.pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            let key = match key {
+                Value::String(key) => match key.to_str() {
+                    Ok(key) => key.to_owned(),
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            let path = if prefix.is_empty() {
+                key
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+
+            match value {
+                Value::Function(function) if lua.is_rust_callback(&function) => {
+                    functions.push((path, function));
+                }
+                Value::Table(table) if !visited.contains(&table.0.to_pointer()) => {
+                    walk(lua, table, &path, visited, functions)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    let mut functions = Vec::new();
+    walk(lua, lua.globals(), "", &mut Vec::new(), &mut functions)?;
+    functions.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(functions)
+}
+
+pub(crate) fn registered_functions(lua: Context) -> Result<Vec<RegisteredFunction>> {
+    Ok(reachable_callbacks(lua)?
+        .into_iter()
+        .map(|(path, _)| RegisteredFunction { path })
+        .collect())
+}
+
+// Reports the callback statistics, with the functions which took the most time first.
+pub(crate) fn callback_stats(lua: Context) -> Result<Vec<CallbackStats>> {
+    let totals = match unsafe { &(*extra_data(lua.state)).callback_stats } {
+        Some(totals) => totals.clone(),
+        None => return Ok(Vec::new()),
+    };
+    let mut paths = HashMap::new();
+    for (path, function) in reachable_callbacks(lua)? {
+        if let Some(callback) = lua.rust_callback_ptr(&function) {
+            // Functions are sorted by path, so this keeps the first path to each.
+            paths.entry(callback).or_insert(path);
+        }
+    }
+
+    let mut stats: Vec<CallbackStats> = totals
+        .into_iter()
+        .map(|(callback, totals)| CallbackStats {
+            path: paths.remove(&callback),
+            calls: totals.calls,
+            time: totals.time,
+        })
+        .collect();
+    stats.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.path.cmp(&b.path)));
+    Ok(stats)
+}
+
+// Prints the arguments or results of a logged call.
+pub(crate) fn format_values(values: &MultiValue) -> StdString {
+    let mut out = StdString::new();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        format_value(&mut out, value, 0);
+    }
+    out
+}
+
+fn format_value(out: &mut StdString, value: &Value, depth: usize) {
+    const MAX_DEPTH: usize = 3;
+    const MAX_ENTRIES: usize = 8;
+    const MAX_STRING: usize = 64;
+
+    match value {
+        Value::Nil => out.push_str("nil"),
+        Value::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Integer(i) => out.push_str(&i.to_string()),
+        Value::Number(n) => out.push_str(&format!("{:?}", n)),
+        Value::String(s) => {
+            let bytes = s.as_bytes();
+            let shown = StdString::from_utf8_lossy(&bytes[..bytes.len().min(MAX_STRING)]);
+            out.push_str(&format!("{:?}", shown));
+            if bytes.len() > MAX_STRING {
+                out.push_str(&format!("...({} bytes)", bytes.len()));
+            }
+        }
+        Value::Table(table) => {
+            if depth == MAX_DEPTH {
+                out.push_str("{...}");
+                return;
+            }
+            out.push('{');
+            // The sequence part is usually traversed first, its keys are left out.
+            let mut next_index = 1;
+            for (i, pair) in table.clone().pairs::<Value, Value>().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                if i == MAX_ENTRIES {
+                    out.push_str("...");
+                    break;
+                }
+                let (key, value) = match pair {
+                    Ok(pair) => pair,
+                    Err(_) => break,
+                };
+                match &key {
+                    Value::Integer(index) if *index == next_index => {
+                        next_index += 1;
+                        format_value(out, &value, depth + 1);
+                        continue;
+                    }
+                    Value::String(key) if is_identifier(key.as_bytes()) => {
+                        out.push_str(&StdString::from_utf8_lossy(key.as_bytes()));
+                    }
+                    key => {
+                        out.push('[');
+                        format_value(out, key, depth + 1);
+                        out.push(']');
+                    }
+                }
+                out.push_str(" = ");
+                format_value(out, &value, depth + 1);
+            }
+            out.push('}');
+        }
+        Value::Function(function) => {
+            out.push_str(&format!("function: {:p}", function.0.to_pointer()))
+        }
+        Value::Thread(thread) => out.push_str(&format!("thread: {:p}", thread.0.to_pointer())),
+        Value::UserData(ud) => match ud.type_name() {
+            Some(type_name) => out.push_str(&format!("{}: {:p}", type_name, ud.to_pointer())),
+            None => out.push_str(&format!("userdata: {:p}", ud.to_pointer())),
+        },
+        Value::LightUserData(ud) => out.push_str(&format!("lightuserdata: {:p}", ud.0)),
+        Value::Error(err) => out.push_str(&format!("error: {}", err)),
+    }
+}
+
+fn is_identifier(name: &[u8]) -> bool {
+    match name.first() {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+            name.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+        }
+        _ => false,
+    }
+}
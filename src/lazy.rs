@@ -0,0 +1,340 @@
+use std::mem;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::table::Table;
+use crate::types::Integer;
+use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::value::{FromLua, MultiValue, ToLua, Value};
+
+/// Wraps a function computing a value which is only converted to Lua when a script uses it.
+///
+/// Returning a `Lazy` from a callback gives the script a proxy userdata instead of the value.  The
+/// first time the script indexes, calls or takes the length of the proxy, the function is run and
+/// its result converted, and the proxy forwards these operations to the converted value from then
+/// on.  This saves building large tables which scripts usually only sample a field or two from, or
+/// ignore entirely.
+///
+/// The proxy is not a table itself: `type` returns `"userdata"` for it, and `pairs` and raw
+/// accesses do not see the value.  Indexing and length work on tables, calling works on
+/// functions.  If the function or the conversion fails, using the proxy raises that error, now
+/// and on every later use.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use rlua::{Lazy, Lua, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let inventory = lua_context.create_function(|_, ()| {
+///     Ok(Lazy::new(|| {
+///         (0..10_000)
+///             .map(|i| (format!("item{}", i), i))
+///             .collect::<HashMap<_, _>>()
+///     }))
+/// })?;
+/// lua_context.globals().set("inventory", inventory)?;
+/// assert_eq!(lua_context.load("inventory().item42").eval::<i64>()?, 42);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+pub struct Lazy<F>(F);
+
+impl<F> Lazy<F> {
+    /// Wraps `f`, which is called at most once, when the value is first used.
+    pub fn new(f: F) -> Lazy<F> {
+        Lazy(f)
+    }
+}
+
+impl<'lua, F, R> ToLua<'lua> for Lazy<F>
+where
+    F: 'static + Send + FnOnce() -> R,
+    R: for<'a> ToLua<'a>,
+{
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        let f = self.0;
+        let thunk: Thunk = Box::new(move |lua| f().to_lua(lua));
+        lua.create_internal_userdata(LazyProxy::Pending(thunk))?
+            .to_lua(lua)
+    }
+}
+
+type Thunk = Box<dyn for<'lua> FnOnce(Context<'lua>) -> Result<Value<'lua>> + Send>;
+
+// The userdata standing in for a `Lazy` value, which is kept in its user value once computed.
+enum LazyProxy {
+    Pending(Thunk),
+    Ready,
+    Failed(Error),
+}
+
+impl UserData for LazyProxy {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_function(
+            MetaMethod::Index,
+            |lua, (proxy, key): (AnyUserData, Value)| match force(lua, &proxy)? {
+                Value::Table(table) => table.get::<_, Value>(key),
+                value => Err(unsupported("index", &value)),
+            },
+        );
+        methods.add_meta_function(
+            MetaMethod::Call,
+            |lua, (proxy, args): (AnyUserData, MultiValue)| match force(lua, &proxy)? {
+                Value::Function(function) => function.call::<_, MultiValue>(args),
+                value => Err(unsupported("call", &value)),
+            },
+        );
+        methods.add_meta_function(MetaMethod::Len, |lua, proxy: AnyUserData| {
+            match force(lua, &proxy)? {
+                Value::Table(table) => table.len(),
+                value => Err(unsupported("get length of", &value)),
+            }
+        });
+    }
+}
+
+// Returns the value of a proxy, computing it on first use.
+fn force<'lua>(lua: Context<'lua>, proxy: &AnyUserData<'lua>) -> Result<Value<'lua>> {
+    let thunk = {
+        let mut state = proxy.borrow_mut::<LazyProxy>()?;
+        match &*state {
+            LazyProxy::Ready => return proxy.get_user_value(),
+            LazyProxy::Failed(err) => return Err(err.clone()),
+            LazyProxy::Pending(_) => {}
+        }
+        match mem::replace(&mut *state, LazyProxy::Ready) {
+            LazyProxy::Pending(thunk) => thunk,
+            _ => unreachable!(),
+        }
+    };
+    match thunk(lua).and_then(|value| {
+        proxy.set_user_value(value.clone())?;
+        Ok(value)
+    }) {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            *proxy.borrow_mut::<LazyProxy>()? = LazyProxy::Failed(err.clone());
+            Err(err)
+        }
+    }
+}
+
+fn unsupported(operation: &str, value: &Value) -> Error {
+    Error::RuntimeError(format!(
+        "attempt to {} a lazy {} value",
+        operation,
+        value.type_name()
+    ))
+}
+
+/// Provides the entries of a table created with [`Context::create_lazy_table`].
+///
+/// [`Context::create_lazy_table`]: struct.Context.html#method.create_lazy_table
+pub trait LazyTableProvider: 'static + Send + Sync {
+    /// Returns the value for `key`, or nil if there is no such entry.
+    fn get<'lua>(&self, lua: Context<'lua>, key: Value<'lua>) -> Result<Value<'lua>>;
+
+    /// Returns the key following `key`, or the first key if `key` is nil, like Lua's `next`.  Nil
+    /// ends the iteration.
+    ///
+    /// This is used by `pairs`, which fails with the default implementation.
+    fn next_key<'lua>(&self, _lua: Context<'lua>, _key: Value<'lua>) -> Result<Value<'lua>> {
+        Err(Error::RuntimeError(
+            "attempt to iterate a lazy table without keys".to_owned(),
+        ))
+    }
+
+    /// Returns the result of the `#` operator on the table, 0 by default.
+    fn length(&self) -> Result<Integer> {
+        Ok(0)
+    }
+
+    /// Whether values are stored in the table once looked up, true by default.
+    ///
+    /// Providers whose data changes should return false, so that every read sees the current
+    /// value.
+    fn cache(&self) -> bool {
+        true
+    }
+}
+
+/// A table created with [`Context::create_lazy_table`].
+///
+/// When the data behind the provider changes, the values the table cached are stale.  They can be
+/// dropped with [`invalidate`] or [`invalidate_all`], so scripts holding on to the table read the
+/// fresh values from the provider again, without having to be handed a new table.
+///
+/// A `LazyTable` converts to and from the Lua table, so it can be kept in the registry to
+/// invalidate it later.
+///
+/// # Examples
+///
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # use rlua::{Context, LazyTableProvider, Lua, Result, ToLua, Value};
+/// # fn main() -> Result<()> {
+/// struct Prices(Arc<Mutex<Vec<i64>>>);
+///
+/// impl LazyTableProvider for Prices {
+///     fn get<'lua>(&self, lua: Context<'lua>, key: Value<'lua>) -> Result<Value<'lua>> {
+///         let prices = self.0.lock().unwrap();
+///         match key {
+///             Value::Integer(i) if i >= 1 => prices.get(i as usize - 1).copied().to_lua(lua),
+///             _ => Ok(Value::Nil),
+///         }
+///     }
+/// }
+///
+/// # Lua::new().context(|lua_context| {
+/// let data = Arc::new(Mutex::new(vec![10, 20]));
+/// let prices = lua_context.create_lazy_table(Prices(data.clone()))?;
+/// lua_context.globals().set("prices", prices.clone())?;
+/// assert_eq!(lua_context.load("prices[1]").eval::<i64>()?, 10);
+///
+/// data.lock().unwrap()[0] = 15;
+/// assert_eq!(lua_context.load("prices[1]").eval::<i64>()?, 10);
+/// prices.invalidate(1)?;
+/// assert_eq!(lua_context.load("prices[1]").eval::<i64>()?, 15);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`Context::create_lazy_table`]: struct.Context.html#method.create_lazy_table
+/// [`invalidate`]: #method.invalidate
+/// [`invalidate_all`]: #method.invalidate_all
+#[derive(Clone, Debug)]
+pub struct LazyTable<'lua>(Table<'lua>);
+
+impl<'lua> LazyTable<'lua> {
+    /// Drops the cached value for `key`, so that it is looked up from the provider on the next
+    /// read.
+    pub fn invalidate<K: ToLua<'lua>>(&self, key: K) -> Result<()> {
+        let table = &self.0;
+        let key = key.to_lua(table.0.lua)?;
+        if is_valid_key(&key) {
+            table.raw_set(key, Value::Nil)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Drops all cached values.
+    pub fn invalidate_all(&self) -> Result<()> {
+        self.0.clear()
+    }
+
+    /// Returns the underlying table.
+    pub fn table(&self) -> &Table<'lua> {
+        &self.0
+    }
+
+    /// Returns the underlying table, consuming the `LazyTable`.
+    pub fn into_table(self) -> Table<'lua> {
+        self.0
+    }
+}
+
+impl<'lua> ToLua<'lua> for LazyTable<'lua> {
+    fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
+        Ok(Value::Table(self.0))
+    }
+}
+
+impl<'lua> FromLua<'lua> for LazyTable<'lua> {
+    fn from_lua(value: Value<'lua>, _: Context<'lua>) -> Result<LazyTable<'lua>> {
+        let type_name = value.type_name();
+        if let Value::Table(table) = value {
+            if let Some(metatable) = table.get_metatable() {
+                if metatable.raw_get::<_, bool>(LAZY_TABLE_MARKER)? {
+                    return Ok(LazyTable(table));
+                }
+            }
+        }
+        Err(Error::FromLuaConversionError {
+            from: type_name,
+            to: "LazyTable",
+            message: Some("expected a table created by create_lazy_table".to_owned()),
+        })
+    }
+}
+
+// Marks the metatables of lazy tables, which scripts cannot see.
+const LAZY_TABLE_MARKER: &str = "__rlua_lazy_table";
+
+pub(crate) fn create_lazy_table<'lua, P: LazyTableProvider>(
+    lua: Context<'lua>,
+    provider: P,
+) -> Result<LazyTable<'lua>> {
+    let provider = Arc::new(provider);
+    let metatable = lua.create_table()?;
+
+    let index = provider.clone();
+    metatable.raw_set(
+        "__index",
+        lua.create_function(move |lua, (table, key): (Table, Value)| {
+            lookup(lua, &*index, &table, key)
+        })?,
+    )?;
+    metatable.raw_set(
+        "__newindex",
+        lua.create_function(|_, ()| -> Result<()> {
+            Err(Error::RuntimeError(
+                "attempt to modify a lazy table".to_owned(),
+            ))
+        })?,
+    )?;
+    let len = provider.clone();
+    metatable.raw_set(
+        "__len",
+        lua.create_function(move |_, _: Table| len.length())?,
+    )?;
+    metatable.raw_set(
+        "__pairs",
+        lua.create_function(move |lua, table: Table| {
+            let provider = provider.clone();
+            let next = lua.create_function(move |lua, (table, key): (Table, Value)| {
+                let key = provider.next_key(lua, key)?;
+                if let Value::Nil = key {
+                    return Ok((Value::Nil, Value::Nil));
+                }
+                let value = lookup(lua, &*provider, &table, key.clone())?;
+                Ok((key, value))
+            })?;
+            Ok((next, table, Value::Nil))
+        })?,
+    )?;
+    metatable.raw_set("__metatable", false)?;
+    metatable.raw_set(LAZY_TABLE_MARKER, true)?;
+
+    let table = lua.create_table()?;
+    table.set_metatable(Some(metatable))?;
+    Ok(LazyTable(table))
+}
+
+// Looks up a key of a lazy table, caching the value if the provider wants it.
+fn lookup<'lua, P: LazyTableProvider>(
+    lua: Context<'lua>,
+    provider: &P,
+    table: &Table<'lua>,
+    key: Value<'lua>,
+) -> Result<Value<'lua>> {
+    let value = provider.get(lua, key.clone())?;
+    if is_valid_key(&key) && provider.cache() && !matches!(value, Value::Nil) {
+        table.raw_set(key, value.clone())?;
+    }
+    Ok(value)
+}
+
+// Whether `key` can be stored in a table, which nil and NaN cannot.
+fn is_valid_key(key: &Value) -> bool {
+    match *key {
+        Value::Nil => false,
+        Value::Number(n) => !n.is_nan(),
+        _ => true,
+    }
+}
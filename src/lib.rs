@@ -43,36 +43,101 @@
 #[macro_use]
 mod macros;
 
+mod builder;
+mod bytecode;
+mod channel;
+mod cmodule;
+#[cfg(feature = "collections")]
+mod collections;
+mod compiler;
 mod context;
 mod conversion;
+mod diagnostics;
+mod dynamic;
 mod error;
 mod ffi;
+mod foreign;
 mod function;
 mod hook;
+mod host_api;
+mod introspect;
+mod lazy;
+mod linda;
 mod lua;
 mod markers;
 mod multi;
+#[cfg(feature = "net")]
+mod net;
+mod package;
+mod parallel;
+mod plain;
+#[cfg(feature = "process")]
+mod process;
+mod range;
+mod replay;
+mod sandbox;
 mod scope;
+#[cfg(feature = "serde")]
+mod serde;
+mod slice;
 mod string;
+mod sync;
 mod table;
+mod task;
 mod thread;
 mod types;
 mod userdata;
 mod util;
 mod value;
+mod watchdog;
+#[cfg(feature = "websocket")]
+mod websocket;
 
+pub use crate::builder::LuaBuilder;
+pub use crate::bytecode::{BytecodeHeader, BytecodeLayout, Endianness};
+#[doc(hidden)]
+pub use crate::cmodule::open_module;
+#[cfg(feature = "collections")]
+pub use crate::collections::{SharedMap, SharedVec};
+pub use crate::compiler::{Compilation, Compiler};
 pub use crate::context::{Chunk, Context};
+pub use crate::diagnostics::{
+    CallFrame, CallStack, GlobalInfo, GlobalsDiff, GlobalsReport, HeapCensus, ObjectTotals,
+    PathSegment, ReferencePath,
+};
+pub use crate::dynamic::DynUserData;
 pub use crate::error::{Error, ExternalError, ExternalResult, Result};
-pub use crate::function::Function;
-pub use crate::hook::{Debug, DebugNames, DebugSource, DebugStack, HookTriggers};
-pub use crate::lua::{Lua, StdLib};
-pub use crate::multi::Variadic;
+pub use crate::ffi::lua_State;
+pub use crate::function::{Function, FunctionInfo};
+pub use crate::hook::{Debug, DebugEvent, DebugNames, DebugSource, DebugStack, HookTriggers};
+pub use crate::host_api::{Deprecation, DeprecationEvent, DeprecationUsage, HostApi};
+pub use crate::introspect::{CallbackStats, LoggedCall, RegisteredFunction, RegisteredType};
+pub use crate::lazy::{Lazy, LazyTable, LazyTableProvider};
+pub use crate::linda::Linda;
+pub use crate::lua::{ConversionOptions, DurationFormat, Lua, OomBehavior, StateStatus, StdLib};
+pub use crate::multi::{Strict, TableTuple, Varargs, Variadic};
+#[cfg(feature = "net")]
+pub use crate::net::NetPolicy;
+pub use crate::package::{ModuleGraph, PackagePolicy};
+#[cfg(feature = "process")]
+pub use crate::process::ProcessPolicy;
+pub use crate::range::IntegerRange;
+pub use crate::replay::Recording;
+pub use crate::sandbox::{Clock, EnvProvider, LoadPolicy, LoadQuota};
 pub use crate::scope::Scope;
+#[cfg(feature = "serde")]
+pub use crate::serde::{from_value, to_value};
+pub use crate::slice::{Bytes, Numbers};
 pub use crate::string::String;
-pub use crate::table::{Table, TablePairs, TableSequence};
-pub use crate::thread::{Thread, ThreadStatus};
-pub use crate::types::{Integer, LightUserData, Number, RegistryKey};
-pub use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+pub use crate::table::{NumericElement, Table, TablePairs, TableSequence, TypedTable};
+pub use crate::task::{JoinAll, TaskGroup};
+pub use crate::thread::{AsyncThread, ResumeResult, Thread, ThreadSpan, ThreadStatus};
+pub use crate::types::{Integer, LightUserData, Number, PtrKey, RegistryKey};
+pub use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods, UserDataTrait};
 pub use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
+pub use crate::watchdog::{WatchdogAction, WatchdogConfig, WatchdogEvent};
 
+#[doc(hidden)]
+pub mod fuzz;
 pub mod prelude;
+pub mod testing;
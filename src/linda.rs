@@ -0,0 +1,284 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::plain::PlainValue;
+use crate::string::String;
+use crate::sync::{Condvar, Mutex, MutexGuard};
+use crate::types::Number;
+use crate::userdata::{UserData, UserDataMethods};
+use crate::value::{MultiValue, Value};
+
+/// A thread safe tuple space which can be shared between independent Lua states.
+///
+/// A `Linda` holds a set of FIFO queues identified by string keys.  Values sent to a key are deep
+/// copied out of the sending state, and copied into the receiving state when they are received, so
+/// only plain data (nil, booleans, numbers, strings and tables of those) may be sent.
+///
+/// `Linda` implements `UserData` and `Clone`, so the same `Linda` can be handed to any number of
+/// `Lua` instances, each possibly running on its own thread.  From Lua, the following methods are
+/// available:
+///
+/// - `linda:send(key, value [, timeout])` appends `value` to the queue for `key`, waiting while the
+///   queue is at its limit.  Returns `true`, or `false` if the timeout (in seconds) expired first.
+/// - `linda:receive(key [, timeout])` removes the oldest value in the queue for `key`, waiting
+///   until a value is available.  `key` may also be a sequence of keys, in which case the first
+///   non-empty queue is used.  Returns the key and value, or nothing if the timeout expired.
+/// - `linda:limit(key, n)` limits the queue for `key` to at most `n` values, `nil` removes the
+///   limit.
+/// - `linda:count(key)` returns the number of values currently queued for `key`.
+///
+/// A timeout of `nil`, or one too large to represent, waits forever, blocking the calling OS
+/// thread.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Linda, Lua, Result};
+/// # use std::thread;
+/// # fn main() -> Result<()> {
+/// let linda = Linda::new();
+///
+/// let producer_linda = linda.clone();
+/// let producer = thread::spawn(move || {
+///     Lua::new().context(|lua_context| {
+///         lua_context.globals().set("linda", producer_linda)?;
+///         lua_context.load(r#"
+///             for i = 1, 3 do
+///                 linda:send("jobs", { id = i })
+///             end
+///         "#).exec()
+///     })
+/// });
+///
+/// Lua::new().context(|lua_context| {
+///     lua_context.globals().set("linda", linda)?;
+///     let sum: i64 = lua_context.load(r#"
+///         local sum = 0
+///         for i = 1, 3 do
+///             local key, job = linda:receive("jobs")
+///             sum = sum + job.id
+///         end
+///         return sum
+///     "#).eval()?;
+///     assert_eq!(sum, 6);
+///     Ok(())
+/// })?;
+/// # producer.join().unwrap()
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Linda(Arc<LindaInner>);
+
+#[derive(Default)]
+struct LindaInner {
+    slots: Mutex<HashMap<Vec<u8>, Slot>>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Slot {
+    queue: VecDeque<PlainValue>,
+    limit: Option<usize>,
+}
+
+impl Linda {
+    /// Creates a new, empty `Linda`.
+    pub fn new() -> Linda {
+        Linda::default()
+    }
+
+    /// Sends a value to the queue for the given key.
+    ///
+    /// If the queue is at its limit, waits for up to `timeout` (or forever if `timeout` is `None`)
+    /// for a value to be received.  Returns false if the timeout expired before the value could be
+    /// queued.
+    pub fn send<'lua, K>(
+        &self,
+        key: &K,
+        value: Value<'lua>,
+        timeout: Option<Duration>,
+    ) -> Result<bool>
+    where
+        K: ?Sized + AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let value = PlainValue::from_value(value)?;
+        let deadline = to_deadline(timeout);
+
+        let mut slots = self.lock();
+        loop {
+            let slot = slots.entry(key.to_vec()).or_default();
+            if slot.limit.map(|l| slot.queue.len() < l).unwrap_or(true) {
+                slot.queue.push_back(value);
+                self.0.changed.notify_all();
+                return Ok(true);
+            }
+
+            slots = match self.wait(slots, deadline) {
+                Some(slots) => slots,
+                None => return Ok(false),
+            };
+        }
+    }
+
+    /// Receives the oldest value from the first non-empty queue among the given keys.
+    ///
+    /// Waits for up to `timeout` (or forever if `timeout` is `None`) for a value to be sent.
+    /// Returns the key the value was received from along with the value, or `None` if the timeout
+    /// expired.
+    pub fn receive<'lua, K>(
+        &self,
+        lua: Context<'lua>,
+        keys: &[K],
+        timeout: Option<Duration>,
+    ) -> Result<Option<(String<'lua>, Value<'lua>)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let deadline = to_deadline(timeout);
+
+        let mut slots = self.lock();
+        loop {
+            for key in keys {
+                if let Some(value) = slots
+                    .get_mut(key.as_ref())
+                    .and_then(|slot| slot.queue.pop_front())
+                {
+                    self.0.changed.notify_all();
+                    drop(slots);
+
+                    // The lock is not held while creating Lua values, as that may run finalizers
+                    // which use this linda.  If the conversion fails, the value goes back to the
+                    // front of its queue rather than being lost.
+                    let converted = lua
+                        .create_string(key)
+                        .and_then(|k| Ok((k, value.clone().into_value(lua)?)));
+                    if converted.is_err() {
+                        self.lock()
+                            .entry(key.as_ref().to_vec())
+                            .or_default()
+                            .queue
+                            .push_front(value);
+                        self.0.changed.notify_all();
+                    }
+                    return converted.map(Some);
+                }
+            }
+
+            slots = match self.wait(slots, deadline) {
+                Some(slots) => slots,
+                None => return Ok(None),
+            };
+        }
+    }
+
+    /// Limits the queue for the given key to at most `limit` values.
+    ///
+    /// Values already in the queue are kept, but further sends will wait until the queue is below
+    /// the limit.  A limit of `None` removes any existing limit.
+    pub fn set_limit<K: ?Sized + AsRef<[u8]>>(&self, key: &K, limit: Option<usize>) {
+        let mut slots = self.lock();
+        slots.entry(key.as_ref().to_vec()).or_default().limit = limit;
+        self.0.changed.notify_all();
+    }
+
+    /// Returns the number of values currently queued for the given key.
+    pub fn count<K: ?Sized + AsRef<[u8]>>(&self, key: &K) -> usize {
+        self.lock()
+            .get(key.as_ref())
+            .map(|slot| slot.queue.len())
+            .unwrap_or(0)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Vec<u8>, Slot>> {
+        self.0.slots.lock()
+    }
+
+    // Waits for the linda to change, returns None if the deadline passes first.
+    fn wait<'a>(
+        &self,
+        slots: MutexGuard<'a, HashMap<Vec<u8>, Slot>>,
+        deadline: Option<Instant>,
+    ) -> Option<MutexGuard<'a, HashMap<Vec<u8>, Slot>>> {
+        match deadline {
+            None => Some(self.0.changed.wait(slots)),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    None
+                } else {
+                    Some(self.0.changed.wait_timeout(slots, deadline - now).0)
+                }
+            }
+        }
+    }
+}
+
+impl UserData for Linda {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "send",
+            |_, linda, (key, value, timeout): (String, Value, Option<Number>)| {
+                linda.send(key.as_bytes(), value, to_duration(timeout)?)
+            },
+        );
+
+        methods.add_method(
+            "receive",
+            |lua, linda, (keys, timeout): (Value, Option<Number>)| {
+                let keys = match keys {
+                    Value::Table(t) => t
+                        .sequence_values::<String>()
+                        .map(|k| k.map(|k| k.as_bytes().to_vec()))
+                        .collect::<Result<Vec<_>>>()?,
+                    v => vec![lua
+                        .coerce_string(v)?
+                        .ok_or_else(|| Error::FromLuaConversionError {
+                            from: "value",
+                            to: "linda key",
+                            message: Some("expected a string key or a sequence of keys".to_owned()),
+                        })?
+                        .as_bytes()
+                        .to_vec()],
+                };
+
+                Ok(match linda.receive(lua, &keys, to_duration(timeout)?)? {
+                    Some((key, value)) => MultiValue::from_vec(vec![Value::String(key), value]),
+                    None => MultiValue::new(),
+                })
+            },
+        );
+
+        methods.add_method(
+            "limit",
+            |_, linda, (key, limit): (String, Option<usize>)| {
+                linda.set_limit(key.as_bytes(), limit);
+                Ok(())
+            },
+        );
+
+        methods.add_method("count", |_, linda, key: String| {
+            Ok(linda.count(key.as_bytes()))
+        });
+    }
+}
+
+// Timeouts too large to represent are treated the same as no timeout.
+fn to_deadline(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.and_then(|t| Instant::now().checked_add(t))
+}
+
+fn to_duration(seconds: Option<Number>) -> Result<Option<Duration>> {
+    match seconds {
+        None => Ok(None),
+        Some(s) if s >= 0.0 && s.is_finite() => Ok(Duration::try_from_secs_f64(s).ok()),
+        Some(_) => Err(Error::FromLuaConversionError {
+            from: "number",
+            to: "timeout",
+            message: Some("timeout must be a non-negative number of seconds".to_owned()),
+        }),
+    }
+}
@@ -1,25 +1,42 @@
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::marker::PhantomData;
-use std::os::raw::{c_int, c_void};
+use std::mem;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
 use std::ptr;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::task::Waker;
+use std::thread;
 
 use bitflags::bitflags;
 use libc;
 
+use crate::builder::LuaBuilder;
 use crate::context::Context;
+use crate::diagnostics::{self, GlobalsReport, HeapCensus};
 use crate::error::Result;
 use crate::ffi;
 use crate::hook::{hook_proc, Debug, HookTriggers};
+use crate::host_api::{DeprecationEvent, DeprecationHook, DeprecationUsage};
+use crate::introspect::{
+    self, CallbackStats, CallbackTotals, LoggedCall, RegisteredFunction, RegisteredType,
+};
 use crate::markers::NoRefUnwindSafe;
+use crate::package::{self, ModuleGraph, PackagePolicy};
+use crate::parallel::WorkerPool;
+use crate::replay::{RecordedCall, Recording};
+use crate::sandbox::{Clock, EnvProvider};
+use crate::scope::NonStaticUserData;
+use crate::sync::Mutex;
 use crate::types::Callback;
+use crate::userdata::UserData;
 use crate::util::{
-    assert_stack, init_error_registry, protect_lua_closure, safe_pcall, safe_xpcall,
-    userdata_destructor,
+    assert_stack, callback_error, init_error_registry, protect_lua_closure, reserve_stack,
+    safe_pcall, safe_xpcall, take_userdata, userdata_destructor,
 };
+use crate::watchdog::{DispatchGuard, Watchdog, WatchdogAction, WatchdogConfig, WatchdogEvent};
 
 bitflags! {
     /// Flags describing the set of lua modules to load.
@@ -58,9 +75,126 @@ bitflags! {
     }
 }
 
+/// Whether Lua code may catch memory errors, see [`Lua::set_oom_behavior`].
+///
+/// Running out of memory while running Lua code, whether because the system allocator fails or
+/// because of the limits set with [`Lua::set_memory_limit`] and [`Lua::set_string_length_limit`],
+/// never aborts the process: the error is returned to Rust as [`Error::MemoryError`] or
+/// [`Error::StringLimitExceeded`].  Lua's stack overflow errors, including the "C stack overflow"
+/// error for deeply nested calls between Lua and Rust, are likewise returned as
+/// [`Error::RuntimeError`].  This only decides whether scripts get to catch memory errors on the
+/// way.
+///
+/// Some conditions cannot be turned into errors and still abort the process: allocation failures
+/// in Rust code, including Rust callbacks, and overflowing the native stack through deep
+/// recursion in Rust code itself.
+///
+/// [`Lua::set_oom_behavior`]: struct.Lua.html#method.set_oom_behavior
+/// [`Lua::set_memory_limit`]: struct.Lua.html#method.set_memory_limit
+/// [`Lua::set_string_length_limit`]: struct.Lua.html#method.set_string_length_limit
+/// [`Error::MemoryError`]: enum.Error.html#variant.MemoryError
+/// [`Error::StringLimitExceeded`]: enum.Error.html#variant.StringLimitExceeded
+/// [`Error::RuntimeError`]: enum.Error.html#variant.RuntimeError
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OomBehavior {
+    /// Lua code can catch memory errors with `pcall` and `xpcall` and carry on, as in standard
+    /// Lua.  This is the default.
+    Catchable,
+    /// `pcall` and `xpcall` raise memory errors again instead of returning them, so they always
+    /// reach the Rust code running the script.  This includes memory errors returned by Rust
+    /// callbacks, which are then returned as they are rather than wrapped in an
+    /// `Error::CallbackError`.  Message handlers passed to `xpcall` are not called for them.
+    ///
+    /// `coroutine.resume` and `coroutine.wrap` still report memory errors in the coroutine to the
+    /// script, like any other error.  Lua's string buffers, used by functions such as `string.rep`
+    /// and `table.concat`, report a failure to grow the buffer as a runtime error rather than a
+    /// memory error, so scripts can still catch those.
+    Propagate,
+}
+
+/// How `Duration` and `SystemTime` values are represented in Lua, see
+/// [`Lua::set_duration_format`].
+///
+/// [`Lua::set_duration_format`]: struct.Lua.html#method.set_duration_format
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DurationFormat {
+    /// Durations are numbers of seconds, with a fractional part, like the timeouts of the `net`
+    /// module and the results of `os.clock`.  This is the default.
+    Seconds,
+    /// Durations are integer numbers of milliseconds, truncated towards zero.
+    Milliseconds,
+}
+
+/// How `Option` values and missing arguments are converted, see [`Lua::set_conversion_options`].
+///
+/// [`Lua::set_conversion_options`]: struct.Lua.html#method.set_conversion_options
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConversionOptions {
+    /// Whether `None` converts to a sentinel instead of nil.
+    ///
+    /// The sentinel is a light userdata holding a null pointer, which hosts usually give scripts as
+    /// a global such as `null`.  Only the sentinel converts back to `None`, and nil is converted
+    /// by the inner type, so an `Option<Value>` tells an explicit nil from the absence of a value.
+    /// The default is false, which converts `None` to nil and nil to `None`.
+    pub none_as_sentinel: bool,
+    /// Whether missing trailing arguments, and other values missing from the end of a
+    /// `MultiValue`, convert to `None`.  When false, a missing value is a `FromLuaConversionError`
+    /// even for an `Option`.  The default is true.
+    pub missing_as_none: bool,
+    /// Whether converting to Rust integers and floats rejects strings, as well as numbers which
+    /// would be truncated, rounded or are out of range, instead of coercing them as Lua does.  The
+    /// default is false.  [`Strict`] turns this on for a single conversion.
+    ///
+    /// [`Strict`]: struct.Strict.html
+    pub strict_numbers: bool,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> ConversionOptions {
+        ConversionOptions {
+            none_as_sentinel: false,
+            missing_as_none: true,
+            strict_numbers: false,
+        }
+    }
+}
+
+/// Whether a Lua state can be used normally, as returned by [`Lua::status`].
+///
+/// [`Lua::status`]: struct.Lua.html#method.status
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StateStatus {
+    /// The state is ready to run code.
+    Ok,
+    /// A Rust panic unwound out of [`Lua::context`].
+    ///
+    /// The Lua state itself is still consistent and safe to use, but scripts or Rust code may have
+    /// been stopped halfway through updating their data, so the state may not be in the shape the
+    /// application expects.  Pools will usually want to discard such a state.  The status can be
+    /// reset with [`Lua::clear_panicked`].
+    ///
+    /// [`Lua::context`]: struct.Lua.html#method.context
+    /// [`Lua::clear_panicked`]: struct.Lua.html#method.clear_panicked
+    Panicked,
+    /// The main thread of the state is not running normally, because it raised an error outside
+    /// of any protected call or is a suspended coroutine.
+    ///
+    /// rlua itself never leaves a state like this, but code using the raw state from
+    /// [`Lua::as_raw`] or the host of a state wrapped with [`Lua::init_from_ptr`] can.  No more
+    /// code can be run in the state, and [`Lua::context`] panics, but it can still be dropped
+    /// safely.
+    ///
+    /// [`Lua::as_raw`]: struct.Lua.html#method.as_raw
+    /// [`Lua::init_from_ptr`]: struct.Lua.html#method.init_from_ptr
+    /// [`Lua::context`]: struct.Lua.html#method.context
+    Broken,
+}
+
 /// Top level Lua struct which holds the Lua state itself.
 pub struct Lua {
     main_state: *mut ffi::lua_State,
+    // Whether the state was created by rlua, and so is closed when this is dropped.
+    owned: bool,
     _no_ref_unwind_safe: NoRefUnwindSafe,
 }
 
@@ -75,8 +209,13 @@ impl Drop for Lua {
                     && (*extra).ref_stack_max as usize == (*extra).ref_free.len(),
                 "reference leak detected"
             );
-            *rlua_expect!((*extra).registry_unref_list.lock(), "unref list poisoned") = None;
-            ffi::lua_close(self.main_state);
+            *(*extra).registry_unref_list.lock() = None;
+            *(*extra).ptr_unref_list.lock() = None;
+            if self.owned {
+                ffi::lua_close(self.main_state);
+            } else {
+                *(ffi::lua_getextraspace(self.main_state) as *mut *mut ExtraData) = ptr::null_mut();
+            }
             Box::from_raw(extra);
         }
     }
@@ -88,6 +227,14 @@ impl Lua {
         unsafe { create_lua(StdLib::ALL_NO_DEBUG) }
     }
 
+    /// Returns a [`LuaBuilder`] to create a new Lua state with more options than the other
+    /// constructors offer, such as limits, a hook and preludes.
+    ///
+    /// [`LuaBuilder`]: struct.LuaBuilder.html
+    pub fn builder() -> LuaBuilder {
+        LuaBuilder::new()
+    }
+
     /// Creates a new Lua state and loads the standard library including the `debug` library.
     ///
     /// The debug library is very unsound, it can be used to break the safety guarantees of rlua.
@@ -103,6 +250,27 @@ impl Lua {
     /// the safety guarantees of rlua.  If you really want to load it, use the sister function
     /// [`Lua::unsafe_new_with`].
     ///
+    /// # Examples
+    ///
+    /// Leaving out the `io` and `os` libraries for a sandbox:
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, StdLib, Value};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new_with(StdLib::ALL_NO_DEBUG - StdLib::IO - StdLib::OS);
+    /// lua.context(|lua_context| {
+    ///     for name in &["io", "os"] {
+    ///         match lua_context.globals().get(*name)? {
+    ///             Value::Nil => {}
+    ///             _ => panic!("{} should not be loaded", name),
+    ///         }
+    ///     }
+    ///     lua_context.load("assert(string.upper('ok') == 'OK')").exec()
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
     /// # Panics
     ///
     /// Panics if `lua_mod` contains `StdLib::DEBUG`
@@ -125,6 +293,81 @@ impl Lua {
         create_lua(lua_mod)
     }
 
+    /// Wraps a Lua state created and owned by someone else, such as a host application embedding
+    /// Lua or the state a C module is loaded into.
+    ///
+    /// rlua sets up its internal data in the state, which includes replacing the global `pcall`
+    /// and `xpcall` functions as described in the crate documentation, but does not load any
+    /// standard libraries.  The state is not closed when the returned `Lua` is dropped.  As the
+    /// state keeps using its own allocator, [`Lua::used_memory`] and [`Lua::set_memory_limit`]
+    /// have no effect.
+    ///
+    /// # Safety
+    ///
+    /// - `state` must be a valid state, or a thread of one, created by the same Lua library rlua
+    ///   is linked with.  With the `builtin-lua` feature this means it must have been created
+    ///   through rlua's copy of Lua; with `system-lua` the host must use the same shared library.
+    /// - The "extra space" of the state (`lua_getextraspace`) must not be used by anything else, as
+    ///   rlua keeps a pointer to its internal data there.  Only the main thread, `state` itself,
+    ///   and threads created afterwards get the pointer, so rlua callbacks must not be called from
+    ///   other threads which already existed.
+    /// - The state must not be closed, and must not be used from another OS thread at the same
+    ///   time, while the returned `Lua` exists.
+    /// - No functions or userdata created through the returned `Lua` may be used by the host after
+    ///   it is dropped.  Use [`Lua::into_raw`] instead of dropping it if they must outlive it.
+    ///
+    /// [`Lua::used_memory`]: #method.used_memory
+    /// [`Lua::set_memory_limit`]: #method.set_memory_limit
+    /// [`Lua::into_raw`]: #method.into_raw
+    pub unsafe fn init_from_ptr(state: *mut ffi::lua_State) -> Lua {
+        let lua = init_lua(main_thread(state), new_extra_data(), StdLib::empty(), false);
+        share_extra_data(state);
+        lua
+    }
+
+    /// Returns the main thread of the underlying Lua state, for use with the Lua C API.
+    ///
+    /// The pointer is valid for as long as this `Lua` is.
+    pub fn as_raw(&self) -> *mut ffi::lua_State {
+        self.main_state
+    }
+
+    /// Makes sure the main thread has room for `n` more values on its stack on top of the
+    /// stack reserve, growing the stack if needed.
+    ///
+    /// Use this before pushing values onto the stack returned by [`as_raw`] with the Lua C API.
+    /// Fails with `Error::StackError` if the stack cannot grow that large.
+    ///
+    /// [`as_raw`]: #method.as_raw
+    pub fn check_stack(&self, n: usize) -> Result<()> {
+        unsafe { reserve_stack(self.main_state, n) }
+    }
+
+    /// Sets how many free stack slots Rust callbacks are guaranteed, on top of their arguments.
+    ///
+    /// A callback which cannot get this many slots fails with `Error::StackError` before it runs,
+    /// rather than rlua running out of stack space while it works.  Raise this for callbacks which
+    /// use more of the stack through the C API.  The default is `LUA_MINSTACK`, 20 slots, which is
+    /// also the minimum.
+    pub fn set_stack_reserve(&self, slots: usize) {
+        let slots = slots
+            .max(ffi::LUA_MINSTACK as usize)
+            .min(ffi::LUAI_MAXSTACK as usize);
+        unsafe {
+            (*extra_data(self.main_state)).stack_reserve = slots as c_int;
+        }
+    }
+
+    /// Consumes this `Lua` without closing the underlying state, and returns it.
+    ///
+    /// rlua's internal data is leaked rather than freed, so the values created through this `Lua`
+    /// stay usable from the state.  Closing the state is then up to the caller, with `lua_close`.
+    pub fn into_raw(self) -> *mut ffi::lua_State {
+        let state = self.main_state;
+        mem::forget(self);
+        state
+    }
+
     /// The main entry point of the rlua API.
     ///
     /// In order to create Lua values, load and execute Lua code, or otherwise interact with the Lua
@@ -173,16 +416,72 @@ impl Lua {
     /// # }
     /// ```
     ///
+    /// # Panics
+    ///
+    /// Panics if the state is [`StateStatus::Broken`].
+    ///
     /// [`Context`]: struct.Context.html
     /// [`Context::set_named_registry_value`]: struct.Context.html#method.set_named_registry_value
     /// [`Context::create_registry_value`]: struct.Context.html#method.create_registry_value
+    /// [`StateStatus::Broken`]: enum.StateStatus.html#variant.Broken
     pub fn context<F, R>(&self, f: F) -> R
     where
         F: FnOnce(Context) -> R,
     {
+        assert!(
+            self.status() != StateStatus::Broken,
+            "the main thread of this Lua state is no longer usable"
+        );
+        let _dispatch = unsafe { DispatchGuard::enter(self.main_state) };
+        let _panic = PanicGuard(self.main_state);
         f(unsafe { Context::new(self.main_state) })
     }
 
+    /// Returns whether the state can be used normally.
+    ///
+    /// This lets a pool of states decide whether a state can be reused after something unusual
+    /// happened, such as a panic in a Rust callback.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, StateStatus};
+    /// # use std::panic::{catch_unwind, AssertUnwindSafe};
+    /// let lua = Lua::new();
+    /// assert_eq!(lua.status(), StateStatus::Ok);
+    ///
+    /// let result = catch_unwind(AssertUnwindSafe(|| {
+    ///     lua.context(|_| panic!("interrupted"));
+    /// }));
+    /// assert!(result.is_err());
+    /// assert_eq!(lua.status(), StateStatus::Panicked);
+    ///
+    /// lua.clear_panicked();
+    /// assert_eq!(lua.status(), StateStatus::Ok);
+    /// ```
+    pub fn status(&self) -> StateStatus {
+        unsafe {
+            if ffi::lua_status(self.main_state) != ffi::LUA_OK {
+                StateStatus::Broken
+            } else if (*extra_data(self.main_state)).panicked {
+                StateStatus::Panicked
+            } else {
+                StateStatus::Ok
+            }
+        }
+    }
+
+    /// Resets the status of a state from [`StateStatus::Panicked`] back to [`StateStatus::Ok`],
+    /// once the application has made sure that the state is in a usable shape.
+    ///
+    /// [`StateStatus::Panicked`]: enum.StateStatus.html#variant.Panicked
+    /// [`StateStatus::Ok`]: enum.StateStatus.html#variant.Ok
+    pub fn clear_panicked(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).panicked = false;
+        }
+    }
+
     /// Sets a 'hook' function that will periodically be called as Lua code executes.
     ///
     /// When exactly the hook function is called depends on the contents of the `triggers`
@@ -244,6 +543,471 @@ impl Lua {
         }
     }
 
+    /// Sets a hook function which is called whenever a script calls a deprecated host function.
+    ///
+    /// Deprecated functions are registered with [`HostApi::set_deprecated_function`].  The hook is
+    /// called before the deprecated function runs, and if it returns an error, the call fails with
+    /// that error instead, which can be used to make deprecated functions unavailable in a strict
+    /// mode.  Setting a new hook replaces the previous one.
+    ///
+    /// [`HostApi::set_deprecated_function`]: struct.HostApi.html#method.set_deprecated_function
+    pub fn set_deprecation_hook<F>(&self, callback: F)
+    where
+        F: 'static + Send + FnMut(Context, &DeprecationEvent) -> Result<()>,
+    {
+        unsafe {
+            (*extra_data(self.main_state)).deprecation_hook = Some(Rc::new(RefCell::new(callback)));
+        }
+    }
+
+    /// Removes any hook previously set by `set_deprecation_hook`.
+    pub fn remove_deprecation_hook(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).deprecation_hook = None;
+        }
+    }
+
+    /// Returns how often each deprecated host function has been called from each call site, sorted
+    /// by function name and call site.
+    ///
+    /// Calls are counted whether or not a deprecation hook is set, which makes it possible to
+    /// report which scripts still use deprecated APIs after running them.
+    pub fn deprecation_usage(&self) -> Vec<DeprecationUsage> {
+        unsafe {
+            (*extra_data(self.main_state))
+                .deprecation_usage
+                .iter()
+                .map(|((function, call_site), &count)| DeprecationUsage {
+                    function: function.clone(),
+                    call_site: call_site.clone(),
+                    count,
+                })
+                .collect()
+        }
+    }
+
+    /// Resets the counts returned by `deprecation_usage`.
+    pub fn clear_deprecation_usage(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).deprecation_usage.clear();
+        }
+    }
+
+    /// Enables or disables counting the calls to Rust functions and measuring their run time.
+    ///
+    /// While enabled, every call from Lua to a function created with `Context::create_function`
+    /// and the like is counted and timed, which costs a clock read before and after the call.
+    /// Disabling discards the statistics gathered so far.
+    pub fn set_callback_stats_enabled(&self, enabled: bool) {
+        unsafe {
+            let stats = &mut (*extra_data(self.main_state)).callback_stats;
+            if !enabled {
+                *stats = None;
+            } else if stats.is_none() {
+                *stats = Some(HashMap::new());
+            }
+        }
+    }
+
+    /// Returns how often each Rust function was called and how long it ran since callback
+    /// statistics were enabled with [`set_callback_stats_enabled`], the function which ran the
+    /// longest first.
+    ///
+    /// Functions are named by their path from the globals table, as in [`registered_functions`].
+    /// The functions which are not reachable from it have no path, and those which were garbage
+    /// collected are added up in a single entry without a path.  The report is empty if callback
+    /// statistics are disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_callback_stats_enabled(true);
+    /// lua.context(|lua_context| {
+    ///     let host = lua_context.create_table()?;
+    ///     host.set("log", lua_context.create_function(|_, _: String| Ok(()))?)?;
+    ///     lua_context.globals().set("host", host)?;
+    ///     lua_context.load("for i = 1, 10 do host.log('tick') end").exec()
+    /// })?;
+    ///
+    /// let stats = lua.callback_stats()?;
+    /// assert_eq!(stats[0].path.as_deref(), Some("host.log"));
+    /// assert_eq!(stats[0].calls, 10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`set_callback_stats_enabled`]: #method.set_callback_stats_enabled
+    /// [`registered_functions`]: #method.registered_functions
+    pub fn callback_stats(&self) -> Result<Vec<CallbackStats>> {
+        self.context(introspect::callback_stats)
+    }
+
+    /// Resets the statistics returned by `callback_stats`, keeping them enabled.
+    pub fn clear_callback_stats(&self) {
+        unsafe {
+            if let Some(stats) = (*extra_data(self.main_state)).callback_stats.as_mut() {
+                stats.clear();
+            }
+        }
+    }
+
+    /// Returns the most recent calls to the functions tagged with [`Context::set_call_logging`],
+    /// oldest first.
+    ///
+    /// [`Context::set_call_logging`]: struct.Context.html#method.set_call_logging
+    pub fn call_log(&self) -> Vec<LoggedCall> {
+        unsafe {
+            (*extra_data(self.main_state))
+                .call_log
+                .iter()
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// Removes the calls returned by `call_log`.
+    pub fn clear_call_log(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).call_log.clear();
+        }
+    }
+
+    /// Sets how many calls `call_log` keeps, dropping the oldest ones once it is full.  The default
+    /// is 64.
+    pub fn set_call_log_capacity(&self, capacity: usize) {
+        unsafe {
+            let extra = extra_data(self.main_state);
+            (*extra).call_log_capacity = capacity;
+            while (*extra).call_log.len() > capacity {
+                (*extra).call_log.pop_front();
+            }
+        }
+    }
+
+    /// Starts recording the calls to the functions tagged with [`Context::set_call_logging`],
+    /// discarding any calls recorded before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.context(|lua_context| {
+    ///     let roll = lua_context.create_function(|_, sides: i64| Ok(sides / 2 + 1))?;
+    ///     lua_context.set_call_logging(&roll, Some("roll"))?;
+    ///     lua_context.globals().set("roll", roll)?;
+    ///     Ok(())
+    /// })?;
+    ///
+    /// lua.start_recording();
+    /// lua.context(|lua_context| lua_context.load("roll(6)").exec())?;
+    /// let recording = lua.stop_recording();
+    /// assert_eq!(recording.len(), 1);
+    ///
+    /// // The same script, replayed in a state without the host function.
+    /// Lua::new().context(|lua_context| {
+    ///     let stubs = lua_context.create_replay_functions(recording)?;
+    ///     lua_context.globals().set("roll", stubs.get::<_, rlua::Function>("roll")?)?;
+    ///     assert_eq!(lua_context.load("roll(6)").eval::<i64>()?, 4);
+    ///     Ok(())
+    /// })
+    /// # }
+    /// ```
+    ///
+    /// [`Context::set_call_logging`]: struct.Context.html#method.set_call_logging
+    pub fn start_recording(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).recording = Some(Vec::new());
+        }
+    }
+
+    /// Stops recording and returns the calls recorded since `start_recording`, which is empty if
+    /// recording was not started.
+    pub fn stop_recording(&self) -> Recording {
+        unsafe {
+            Recording::new(
+                (*extra_data(self.main_state))
+                    .recording
+                    .take()
+                    .unwrap_or_default(),
+            )
+        }
+    }
+
+    /// Sets the source of the environment variables returned by the `getenv` function of
+    /// [`Context::create_sandboxed_os`], replacing any previous provider.
+    ///
+    /// Without a provider, scripts see no environment variables at all.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # use std::collections::HashMap;
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let mut env = HashMap::new();
+    /// env.insert("HOME".to_owned(), "/home/sandbox".to_owned());
+    /// lua.set_env_provider(env);
+    ///
+    /// lua.context(|lua_context| {
+    ///     let os = lua_context.create_sandboxed_os()?;
+    ///     lua_context.globals().set("os", os)?;
+    ///     lua_context.load(r#"
+    ///         assert(os.getenv("HOME") == "/home/sandbox")
+    ///         assert(os.getenv("PATH") == nil)
+    ///     "#).exec()
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Context::create_sandboxed_os`]: struct.Context.html#method.create_sandboxed_os
+    pub fn set_env_provider<P: 'static + EnvProvider>(&self, provider: P) {
+        unsafe {
+            (*extra_data(self.main_state)).env_provider = Some(Box::new(provider));
+        }
+    }
+
+    /// Sets the clock used by the `time`, `date` and `clock` functions of
+    /// [`Context::create_sandboxed_os`], replacing any previous clock.
+    ///
+    /// Without a clock, the system time and the processor time of the program are used.  A fixed or
+    /// manually advanced clock makes scripts which depend on the time deterministic, for tests or
+    /// for replaying a recorded run.
+    ///
+    /// [`Context::create_sandboxed_os`]: struct.Context.html#method.create_sandboxed_os
+    pub fn set_clock<C: 'static + Clock>(&self, clock: C) {
+        unsafe {
+            (*extra_data(self.main_state)).clock = Some(Box::new(clock));
+        }
+    }
+
+    /// Installs a watchdog which monitors every call to [`Lua::context`] and can interrupt Lua code
+    /// which runs for too long or allocates too much memory.
+    ///
+    /// A monitor thread is spawned which checks the running time of the current call to
+    /// `Lua::context`, and memory growth is checked every `config.instruction_interval` VM
+    /// instructions.  When a limit in `config` is exceeded, `callback` is called (possibly from the
+    /// monitor thread) with a description of the violation, and decides whether the Lua code is
+    /// interrupted with a runtime error or allowed to continue.  Each limit is only reported once
+    /// per call to `Lua::context`.
+    ///
+    /// Interruption happens through a hook flag, so a blocking Rust or C function called from Lua
+    /// is still reported by the monitor thread, but Lua code is only actually interrupted once
+    /// control returns to the Lua VM.
+    ///
+    /// The watchdog is implemented with a hook function, so this replaces any hook previously set
+    /// with [`Lua::set_hook`], and calling `set_hook` or `remove_hook` disables the interruption of
+    /// Lua code by the watchdog.  Only one watchdog can be installed at a time, installing a
+    /// watchdog replaces the previous one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, WatchdogAction, WatchdogConfig};
+    /// # use std::time::Duration;
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.watchdog(
+    ///     WatchdogConfig {
+    ///         time_limit: Some(Duration::from_millis(50)),
+    ///         ..WatchdogConfig::default()
+    ///     },
+    ///     |_event| WatchdogAction::Interrupt,
+    /// );
+    /// lua.context(|lua_context| {
+    ///     assert!(lua_context.load("while true do end").exec().is_err());
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Lua::context`]: #method.context
+    /// [`Lua::set_hook`]: #method.set_hook
+    pub fn watchdog<F>(&self, config: WatchdogConfig, callback: F)
+    where
+        F: 'static + Send + Sync + Fn(WatchdogEvent) -> WatchdogAction,
+    {
+        unsafe {
+            let extra = extra_data(self.main_state);
+            (*extra).watchdog = None;
+            let watchdog = Arc::new(Watchdog::new(config, Box::new(callback)));
+            (*extra).watchdog = Some(watchdog);
+        }
+
+        self.set_hook(
+            HookTriggers {
+                every_nth_instruction: Some(config.instruction_interval.max(1)),
+                ..Default::default()
+            },
+            |lua, _| match unsafe { (*extra_data(lua.state)).watchdog.clone() } {
+                Some(watchdog) => watchdog.check(lua),
+                None => Ok(()),
+            },
+        );
+    }
+
+    /// Removes a watchdog previously installed with [`Lua::watchdog`], along with its hook.
+    ///
+    /// [`Lua::watchdog`]: #method.watchdog
+    pub fn remove_watchdog(&self) {
+        unsafe {
+            if (*extra_data(self.main_state)).watchdog.take().is_some() {
+                self.remove_hook();
+            }
+        }
+    }
+
+    /// Returns a description of every `UserData` type registered with this Lua state, in the order
+    /// the types were registered.
+    ///
+    /// A `UserData` type is registered the first time a value of that type is moved into Lua, for
+    /// example with [`Context::create_userdata`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Counter(i64);
+    ///
+    /// impl UserData for Counter {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method_mut("increment", |_, counter, ()| {
+    ///             counter.0 += 1;
+    ///             Ok(counter.0)
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// let lua = Lua::new();
+    /// lua.context(|lua_context| lua_context.globals().set("counter", Counter(0)))?;
+    ///
+    /// let types = lua.registered_types();
+    /// assert_eq!(types.len(), 1);
+    /// assert!(types[0].type_name.ends_with("Counter"));
+    /// assert_eq!(types[0].methods, vec!["increment".to_owned()]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Context::create_userdata`]: struct.Context.html#method.create_userdata
+    pub fn registered_types(&self) -> Vec<RegisteredType> {
+        unsafe { (*extra_data(self.main_state)).registered_types.clone() }
+    }
+
+    /// Returns every Rust function reachable from the globals table, sorted by path.
+    ///
+    /// Functions are found by following string keys through nested tables starting at the globals
+    /// table, so functions stored elsewhere (in local variables, upvalues or the registry) are not
+    /// listed.  Userdata methods are listed by [`Lua::registered_types`] instead.
+    ///
+    /// [`Lua::registered_types`]: #method.registered_types
+    pub fn registered_functions(&self) -> Result<Vec<RegisteredFunction>> {
+        self.context(introspect::registered_functions)
+    }
+
+    /// Returns a snapshot of the type and size of every global variable.
+    ///
+    /// Keep the report around and [`diff`] it against a later one to find globals which keep
+    /// growing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let before = lua.globals_report()?;
+    ///
+    /// lua.context(|lua_context| {
+    ///     lua_context.load(r#"
+    ///         cache = {}
+    ///         for i = 1, 100 do
+    ///             cache[i] = { id = i }
+    ///         end
+    ///     "#).exec()
+    /// })?;
+    ///
+    /// let diff = lua.globals_report()?.diff(&before);
+    /// assert_eq!(diff.added["cache"].type_name, "table");
+    /// assert_eq!(diff.added["cache"].size, 200);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`diff`]: struct.GlobalsReport.html#method.diff
+    pub fn globals_report(&self) -> Result<GlobalsReport> {
+        self.context(diagnostics::globals_report)
+    }
+
+    /// Walks every Lua object reachable from the globals table and the registry, and returns their
+    /// counts and estimated sizes grouped by type and by chunk.
+    ///
+    /// This visits the whole object graph, so it can take a while for large states and should not
+    /// be called too often.  See [`HeapCensus`] for how objects are attributed to chunks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.context(|lua_context| {
+    ///     lua_context
+    ///         .load(r#"
+    ///             local cache = {}
+    ///             function remember(value)
+    ///                 cache[#cache + 1] = { value = value }
+    ///             end
+    ///             for i = 1, 1000 do
+    ///                 remember(i)
+    ///             end
+    ///         "#)
+    ///         .set_name("=leaky.lua")?
+    ///         .exec()
+    /// })?;
+    ///
+    /// let census = lua.heap_census()?;
+    /// assert!(census.by_chunk[&Some("leaky.lua".to_owned())].count > 1000);
+    /// println!("{}", census);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`HeapCensus`]: struct.HeapCensus.html
+    pub fn heap_census(&self) -> Result<HeapCensus> {
+        self.context(diagnostics::heap_census)
+    }
+
+    /// Renders the values on the stack of the main thread, for debugging code using the Lua C API
+    /// through [`Lua::as_raw`] and for bug reports.
+    ///
+    /// Each line shows the absolute and relative index of a value, its type and a short
+    /// description of it.  The first line also shows how many rlua handles (`Table`, `Function`
+    /// and so on) are alive, which helps to find leaked handles.  This does not call any
+    /// metamethods.  See [`Context::stack_dump`] for the stack of a running callback.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::Lua;
+    /// let lua = Lua::new();
+    /// let dump = lua.stack_dump();
+    /// assert!(dump.contains(": 0 values, 0 rlua references in use"));
+    /// ```
+    ///
+    /// [`Lua::as_raw`]: #method.as_raw
+    /// [`Context::stack_dump`]: struct.Context.html#method.stack_dump
+    pub fn stack_dump(&self) -> String {
+        unsafe { diagnostics::stack_dump(self.main_state) }
+    }
+
     /// Returns the memory currently used inside this Lua state.
     pub fn used_memory(&self) -> usize {
         unsafe { (*extra_data(self.main_state)).used_memory }
@@ -257,6 +1021,224 @@ impl Lua {
         }
     }
 
+    /// Sets a limit on the length in bytes of any single string created in this Lua state.
+    ///
+    /// Creating a longer string, whether from Lua code such as `string.rep("a", 2^30)` or from
+    /// Rust, fails with [`Error::StringLimitExceeded`] rather than counting against the memory
+    /// limit.  Lua code sees this as a memory error, so it can still be caught with `pcall`.
+    ///
+    /// Functions which build strings in a buffer, like `string.rep` and `table.concat`, may still
+    /// allocate the buffer for the string before the string itself is rejected.
+    ///
+    /// [`Error::StringLimitExceeded`]: enum.Error.html#variant.StringLimitExceeded
+    pub fn set_string_length_limit(&self, limit: Option<usize>) {
+        unsafe {
+            (*extra_data(self.main_state)).string_length_limit = limit;
+        }
+    }
+
+    /// Sets whether Lua code may catch memory errors with `pcall` and `xpcall`.
+    ///
+    /// See [`OomBehavior`] for details.  With [`OomBehavior::Propagate`], a script which runs out
+    /// of memory cannot swallow the error and keep running in a degraded state.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Lua, OomBehavior};
+    /// let lua = Lua::new();
+    /// lua.set_memory_limit(Some(1024 * 1024));
+    /// lua.set_oom_behavior(OomBehavior::Propagate);
+    ///
+    /// lua.context(|lua_context| {
+    ///     let result = lua_context
+    ///         .load(r#"
+    ///             local ok = pcall(function()
+    ///                 local t = {}
+    ///                 for i = 1, 1e6 do t[i] = i end
+    ///             end)
+    ///             return ok
+    ///         "#)
+    ///         .exec();
+    ///     match result {
+    ///         Err(Error::MemoryError(_)) => {}
+    ///         r => panic!("expected a memory error, got {:?}", r),
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`OomBehavior`]: enum.OomBehavior.html
+    /// [`OomBehavior::Propagate`]: enum.OomBehavior.html#variant.Propagate
+    pub fn set_oom_behavior(&self, behavior: OomBehavior) {
+        unsafe {
+            (*extra_data(self.main_state)).oom_behavior = behavior;
+        }
+    }
+
+    /// Returns the behavior set with [`set_oom_behavior`].
+    ///
+    /// [`set_oom_behavior`]: #method.set_oom_behavior
+    pub fn oom_behavior(&self) -> OomBehavior {
+        unsafe { (*extra_data(self.main_state)).oom_behavior }
+    }
+
+    /// Sets a limit on the number of entries of a table that will be converted into a Rust
+    /// collection such as `Vec`, `HashMap` or `BTreeMap`.
+    ///
+    /// Converting a table with more entries fails with [`Error::TableLimitExceeded`] as soon as
+    /// the limit is reached, before the rest of the table is read.
+    ///
+    /// [`Error::TableLimitExceeded`]: enum.Error.html#variant.TableLimitExceeded
+    pub fn set_table_size_limit(&self, limit: Option<usize>) {
+        unsafe {
+            (*extra_data(self.main_state)).table_size_limit = limit;
+        }
+    }
+
+    /// Sets a limit on how deeply nested collections such as `Vec`, `HashMap` or arrays may be
+    /// converted to and from Lua tables.
+    ///
+    /// Converting a table nested more deeply fails with [`Error::DepthLimitExceeded`], which
+    /// protects recursive types converted from self-referencing tables from overflowing the stack.
+    /// The limit also applies to tables copied out of the state as plain data, such as the values
+    /// sent through a [`Linda`] or mapped with [`Context::parallel_map`].  The default limit is
+    /// 128.
+    ///
+    /// [`Error::DepthLimitExceeded`]: enum.Error.html#variant.DepthLimitExceeded
+    /// [`Linda`]: struct.Linda.html
+    /// [`Context::parallel_map`]: struct.Context.html#method.parallel_map
+    pub fn set_conversion_depth_limit(&self, limit: Option<usize>) {
+        unsafe {
+            (*extra_data(self.main_state)).conversion_depth_limit = limit;
+        }
+    }
+
+    /// Sets how `Duration` and `SystemTime` values are converted to and from Lua.
+    ///
+    /// `SystemTime` values are converted as the duration since the Unix epoch, which is negative
+    /// for earlier times.  With `DurationFormat::Seconds`, the default, this matches the result of
+    /// `os.time` up to the fractional part.  Converting from Lua accepts any number, which is read
+    /// in the chosen unit.
+    pub fn set_duration_format(&self, format: DurationFormat) {
+        unsafe {
+            (*extra_data(self.main_state)).duration_format = format;
+        }
+    }
+
+    /// Sets how `Option` values and missing arguments are converted to and from Lua.
+    ///
+    /// By default, `None` and nil convert to each other, which is ambiguous when the inner type
+    /// itself converts from nil, and missing arguments convert to `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::ptr;
+    /// # use rlua::{ConversionOptions, LightUserData, Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_conversion_options(ConversionOptions {
+    ///     none_as_sentinel: true,
+    ///     ..ConversionOptions::default()
+    /// });
+    /// lua.context(|lua_context| {
+    ///     let globals = lua_context.globals();
+    ///     globals.set("null", LightUserData(ptr::null_mut()))?;
+    ///     let set = lua_context.create_function(|_, value: Option<Value>| {
+    ///         Ok(match value {
+    ///             None => "absent",
+    ///             Some(Value::Nil) => "nil",
+    ///             Some(_) => "value",
+    ///         })
+    ///     })?;
+    ///     globals.set("set", set)?;
+    ///     assert_eq!(lua_context.load("set(nil)").eval::<String>()?, "nil");
+    ///     assert_eq!(lua_context.load("set(null)").eval::<String>()?, "absent");
+    ///     assert_eq!(lua_context.load("set()").eval::<String>()?, "absent");
+    ///     Ok(())
+    /// })
+    /// # }
+    /// ```
+    pub fn set_conversion_options(&self, options: ConversionOptions) {
+        unsafe {
+            (*extra_data(self.main_state)).conversion_options = options;
+        }
+    }
+
+    /// Sets whether errors carry a Lua traceback, which is the default.
+    ///
+    /// When enabled, the traceback taken where an error was raised is appended to the message of
+    /// a `RuntimeError`, and kept in the `traceback` field of a `CallbackError`.
+    /// [`Error::traceback`] returns it either way.  Taking tracebacks costs time on every error,
+    /// which may matter to scripts which use errors for control flow.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.context(|lua_context| {
+    ///     let err = lua_context.load("error('oops')").exec().unwrap_err();
+    ///     assert!(err.traceback().unwrap().contains("in main chunk"));
+    /// });
+    ///
+    /// lua.set_traceback_enabled(false);
+    /// lua.context(|lua_context| {
+    ///     let err = lua_context.load("error('oops')").exec().unwrap_err();
+    ///     assert_eq!(err.traceback(), None);
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::traceback`]: enum.Error.html#method.traceback
+    pub fn set_traceback_enabled(&self, enabled: bool) {
+        unsafe {
+            (*extra_data(self.main_state)).traceback_enabled = enabled;
+        }
+    }
+
+    /// Configures how `require` finds modules, see [`PackagePolicy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RuntimeError` if the `package` library is not loaded, or if a path template
+    /// contains `;`, which separates the templates of `package.path`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, PackagePolicy, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_package_policy(PackagePolicy {
+    ///     path: Some(vec!["scripts/?.lua".to_owned(), "scripts/?/init.lua".to_owned()]),
+    ///     c_modules: false,
+    ///     ..PackagePolicy::default()
+    /// })?;
+    /// lua.context(|lua_context| {
+    ///     let path = lua_context.load("package.path").eval::<String>()?;
+    ///     assert_eq!(path, "scripts/?.lua;scripts/?/init.lua");
+    ///     assert!(lua_context.load("package.loadlib == nil").eval::<bool>()?);
+    ///     Ok(())
+    /// })
+    /// # }
+    /// ```
+    ///
+    /// [`PackagePolicy`]: struct.PackagePolicy.html
+    pub fn set_package_policy(&self, policy: PackagePolicy) -> Result<()> {
+        self.context(|lua| package::set_package_policy(lua, &policy))
+    }
+
+    /// Returns the modules loaded with `require` and their dependencies, recorded while
+    /// [`PackagePolicy::track_dependencies`] is set.
+    ///
+    /// [`PackagePolicy::track_dependencies`]: struct.PackagePolicy.html#structfield.track_dependencies
+    pub fn module_graph(&self) -> ModuleGraph {
+        self.context(package::module_graph)
+    }
+
     /// Returns true if the garbage collector is currently running automatically.
     pub fn gc_is_running(&self) -> bool {
         unsafe { ffi::lua_gc(self.main_state, ffi::LUA_GCISRUNNING, 0) != 0 }
@@ -326,6 +1308,66 @@ impl Lua {
     pub fn gc_set_step_multiplier(&self, step_multiplier: c_int) -> c_int {
         unsafe { ffi::lua_gc(self.main_state, ffi::LUA_GCSETSTEPMUL, step_multiplier) }
     }
+
+    /// Sets whether the values of collected userdata of type `T` are dropped later, by
+    /// [`drain_finalizers`], instead of during garbage collection.
+    ///
+    /// Dropping values which own heavyweight resources can take a while, and garbage collection
+    /// steps run whenever scripts allocate.  With deferred finalization, collecting such a userdata
+    /// only moves its value to a queue, and the host drops the queued values on its own schedule,
+    /// such as between frames.  Values still queued when the `Lua` is dropped are dropped with it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData};
+    /// # fn main() -> Result<()> {
+    /// // Frees GPU memory when dropped.
+    /// struct Texture;
+    ///
+    /// impl UserData for Texture {}
+    ///
+    /// let lua = Lua::new();
+    /// lua.set_deferred_finalization::<Texture>(true);
+    /// lua.context(|lua_context| {
+    ///     lua_context.globals().set("texture", Texture)?;
+    ///     lua_context.load("texture = nil").exec()
+    /// })?;
+    /// lua.gc_collect()?;
+    /// assert_eq!(lua.pending_finalizers(), 1);
+    ///
+    /// // Later, at a convenient time.
+    /// assert_eq!(lua.drain_finalizers(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`drain_finalizers`]: #method.drain_finalizers
+    pub fn set_deferred_finalization<T: 'static + Send + UserData>(&self, deferred: bool) {
+        unsafe {
+            let deferred_types = &mut (*extra_data(self.main_state)).deferred_finalization;
+            if deferred {
+                deferred_types.insert(TypeId::of::<T>());
+            } else {
+                deferred_types.remove(&TypeId::of::<T>());
+            }
+        }
+    }
+
+    /// Drops the values of the collected userdata queued by deferred finalization, returning how
+    /// many there were.
+    pub fn drain_finalizers(&self) -> usize {
+        let queued = unsafe { mem::take(&mut (*extra_data(self.main_state)).finalizer_queue) };
+        let count = queued.len();
+        drop(queued);
+        count
+    }
+
+    /// Returns the number of values queued by deferred finalization, which `drain_finalizers`
+    /// drops.
+    pub fn pending_finalizers(&self) -> usize {
+        unsafe { (*extra_data(self.main_state)).finalizer_queue.len() }
+    }
 }
 
 impl Default for Lua {
@@ -334,26 +1376,137 @@ impl Default for Lua {
     }
 }
 
+pub(crate) const DEFAULT_CONVERSION_DEPTH_LIMIT: usize = 128;
+const DEFAULT_CALL_LOG_CAPACITY: usize = 64;
+
 // Data associated with the main lua_State via lua_getextraspace.
 pub(crate) struct ExtraData {
     pub registered_userdata: HashMap<TypeId, c_int>,
+    pub registered_types: Vec<RegisteredType>,
+    // The userdata types registered with `Context::register_userdata_type`.
+    pub explicitly_registered: HashSet<TypeId>,
+    // The userdata types whose collected values are queued in `finalizer_queue` to be dropped by
+    // `Lua::drain_finalizers`.
+    pub deferred_finalization: HashSet<TypeId>,
+    pub finalizer_queue: Vec<Box<dyn Any>>,
+    // The Rust type names of the registered userdata types, keyed by the registry id of their
+    // metatable.
+    pub userdata_type_names: HashMap<c_int, &'static str>,
+    // Registry ids of the weak tables mapping userdata pointers to userdata, for the types which
+    // have pointer lookup enabled.
+    pub userdata_ptr_lookup: HashMap<TypeId, c_int>,
+    // The live userdata created by `Scope::create_nonstatic_userdata`, keyed by their address,
+    // for `AnyUserData::borrow_scoped`.
+    pub nonstatic_userdata: HashMap<*mut c_void, NonStaticUserData>,
+    // Registry id of the weak keyed table mapping threads to their `Thread::set_data` storage.
+    pub thread_storage: Option<c_int>,
+    // The values the running Rust callback yields instead of returning, set by
+    // `Context::yield_with`: the registry id of a table holding them, and their number.
+    pub pending_yield: Option<(c_int, c_int)>,
+    // Set when a panic unwinds out of `Lua::context`, see `StateStatus::Panicked`.
+    pub panicked: bool,
     pub registry_unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
+    // Registry id of the table holding the values of `PtrKey`s, and the addresses of the dropped
+    // keys whose values are still to be removed from it.
+    pub ptr_values: Option<c_int>,
+    pub ptr_unref_list: Arc<Mutex<Option<Vec<usize>>>>,
 
     pub ref_thread: *mut ffi::lua_State,
     pub ref_stack_size: c_int,
     pub ref_stack_max: c_int,
     pub ref_free: Vec<c_int>,
 
-    used_memory: usize,
+    pub used_memory: usize,
     memory_limit: Option<usize>,
+    pub string_length_limit: Option<usize>,
+    // The length of the string most recently rejected by the allocator because of the string
+    // length limit, cleared on any other allocation failure so it only applies to the latest one.
+    pub string_limit_exceeded: Option<usize>,
+    pub table_size_limit: Option<usize>,
+    pub oom_behavior: OomBehavior,
+    pub conversion_depth_limit: Option<usize>,
+    // The free stack slots guaranteed to Rust callbacks and `Context::check_stack`.
+    pub stack_reserve: c_int,
+    pub duration_format: DurationFormat,
+    pub conversion_options: ConversionOptions,
+    pub traceback_enabled: bool,
+    pub module_graph: ModuleGraph,
+    // The modules `require` is being called for, innermost last, and whether each is being loaded
+    // rather than found in `package.loaded`.
+    pub loading_modules: Vec<(String, bool)>,
+    // The number of collection conversions in progress, see `conversion::nested`.
+    pub conversion_depth: usize,
 
     pub hook_callback: Option<Rc<RefCell<FnMut(Context, Debug) -> Result<()>>>>,
+    pub watchdog: Option<Arc<Watchdog>>,
+    pub deprecation_hook: Option<DeprecationHook>,
+    pub deprecation_usage: BTreeMap<(String, Option<String>), usize>,
+    // The call counts and times of the Rust callbacks, keyed by the address of their userdata, or
+    // by null for the callbacks which were collected, if callback statistics are enabled.
+    pub callback_stats: Option<HashMap<*const c_void, CallbackTotals>>,
+    // The names of the Rust callbacks tagged with `Context::set_call_logging`, keyed by the address
+    // of their userdata, and their most recent calls.
+    pub logged_callbacks: HashMap<*const c_void, String>,
+    pub call_log: VecDeque<LoggedCall>,
+    pub call_log_capacity: usize,
+    // The calls to tagged callbacks recorded since `Lua::start_recording`, if recording.
+    pub recording: Option<Vec<RecordedCall>>,
+    // The waker of the task currently driving a thread through `AsyncThread`, if any.
+    pub async_waker: Option<Waker>,
+    pub env_provider: Option<Box<dyn EnvProvider>>,
+    pub clock: Option<Box<dyn Clock>>,
+    // The worker threads of `Context::parallel_map`, created on first use.
+    pub worker_pool: Option<WorkerPool>,
 }
 
 pub(crate) unsafe fn extra_data(state: *mut ffi::lua_State) -> *mut ExtraData {
     *(ffi::lua_getextraspace(state) as *mut *mut ExtraData)
 }
 
+// Destroys a userdata of a 'static type, queueing its value instead of dropping it if the type has
+// deferred finalization.
+pub(crate) unsafe extern "C" fn finalize_userdata<T: 'static>(state: *mut ffi::lua_State) -> c_int {
+    callback_error(state, |_| {
+        let value = take_userdata::<RefCell<T>>(state);
+        let extra = extra_data(state);
+        if !extra.is_null() && (*extra).deferred_finalization.contains(&TypeId::of::<T>()) {
+            (*extra).finalizer_queue.push(Box::new(value));
+        }
+        Ok(0)
+    })
+}
+
+// Destroys a callback, moving its statistics to those of the collected callbacks and removing its
+// call logging tag, so that neither is attributed to a later callback at the same address.
+unsafe extern "C" fn callback_destructor(state: *mut ffi::lua_State) -> c_int {
+    let extra = extra_data(state);
+    if !extra.is_null() {
+        let callback = ffi::lua_touserdata(state, 1) as *const c_void;
+        (*extra).logged_callbacks.remove(&callback);
+        if let Some(stats) = (*extra).callback_stats.as_mut() {
+            if let Some(totals) = stats.remove(&callback) {
+                let collected = stats.entry(ptr::null()).or_default();
+                collected.calls += totals.calls;
+                collected.time += totals.time;
+            }
+        }
+    }
+    userdata_destructor::<Callback>(state)
+}
+
+// Marks the state as panicked if a panic unwinds out of `Lua::context`.
+struct PanicGuard(*mut ffi::lua_State);
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            unsafe {
+                (*extra_data(self.0)).panicked = true;
+            }
+        }
+    }
+}
+
 unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
     unsafe extern "C" fn allocator(
         extra_data: *mut c_void,
@@ -363,8 +1516,17 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
     ) -> *mut c_void {
         let extra_data = extra_data as *mut ExtraData;
 
-        // If the `ptr` argument is null, osize instead encodes the allocated object type, which
-        // we currently ignore.
+        // If the `ptr` argument is null, osize instead encodes the allocated object type.
+        if ptr.is_null() && osize == ffi::LUA_TSTRING as usize {
+            if let Some(limit) = (*extra_data).string_length_limit {
+                let length = nsize.saturating_sub(STRING_OVERHEAD);
+                if length > limit {
+                    (*extra_data).string_limit_exceeded = Some(length);
+                    return ptr::null_mut();
+                }
+            }
+        }
+
         let new_used_memory = if ptr.is_null() {
             (*extra_data).used_memory + nsize
         } else if nsize >= osize {
@@ -377,6 +1539,7 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
             // We only check memory limits when memory is allocated, not freed
             if let Some(memory_limit) = (*extra_data).memory_limit {
                 if new_used_memory > memory_limit {
+                    (*extra_data).string_limit_exceeded = None;
                     return ptr::null_mut();
                 }
             }
@@ -392,14 +1555,34 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
                 // Only commit the new used memory if the allocation was successful.  Probably in
                 // reality, libc::realloc will never fail.
                 (*extra_data).used_memory = new_used_memory;
+            } else {
+                (*extra_data).string_limit_exceeded = None;
             }
             p
         }
     }
 
-    let mut extra = Box::new(ExtraData {
+    let mut extra = new_extra_data();
+    let state = ffi::lua_newstate(allocator, &mut *extra as *mut ExtraData as *mut c_void);
+    init_lua(state, extra, lua_mod_to_load, true)
+}
+
+fn new_extra_data() -> Box<ExtraData> {
+    Box::new(ExtraData {
         registered_userdata: HashMap::new(),
+        deferred_finalization: HashSet::new(),
+        finalizer_queue: Vec::new(),
+        userdata_type_names: HashMap::new(),
+        explicitly_registered: HashSet::new(),
+        registered_types: Vec::new(),
+        userdata_ptr_lookup: HashMap::new(),
+        nonstatic_userdata: HashMap::new(),
+        thread_storage: None,
+        pending_yield: None,
+        panicked: false,
         registry_unref_list: Arc::new(Mutex::new(Some(Vec::new()))),
+        ptr_values: None,
+        ptr_unref_list: Arc::new(Mutex::new(Some(Vec::new()))),
         ref_thread: ptr::null_mut(),
         // We need 1 extra stack space to move values in and out of the ref stack.
         ref_stack_size: ffi::LUA_MINSTACK - 1,
@@ -407,10 +1590,42 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
         ref_free: Vec::new(),
         used_memory: 0,
         memory_limit: None,
+        string_length_limit: None,
+        string_limit_exceeded: None,
+        table_size_limit: None,
+        oom_behavior: OomBehavior::Catchable,
+        conversion_depth_limit: Some(DEFAULT_CONVERSION_DEPTH_LIMIT),
+        stack_reserve: ffi::LUA_MINSTACK,
+        duration_format: DurationFormat::Seconds,
+        conversion_options: ConversionOptions::default(),
+        traceback_enabled: true,
+        module_graph: ModuleGraph::default(),
+        loading_modules: Vec::new(),
+        conversion_depth: 0,
         hook_callback: None,
-    });
+        watchdog: None,
+        deprecation_hook: None,
+        deprecation_usage: BTreeMap::new(),
+        callback_stats: None,
+        logged_callbacks: HashMap::new(),
+        call_log: VecDeque::new(),
+        call_log_capacity: DEFAULT_CALL_LOG_CAPACITY,
+        recording: None,
+        async_waker: None,
+        env_provider: None,
+        clock: None,
+        worker_pool: None,
+    })
+}
 
-    let state = ffi::lua_newstate(allocator, &mut *extra as *mut ExtraData as *mut c_void);
+// Sets up rlua's internal data in `state` and loads the given standard libraries.
+unsafe fn init_lua(
+    state: *mut ffi::lua_State,
+    mut extra: Box<ExtraData>,
+    lua_mod_to_load: StdLib,
+    owned: bool,
+) -> Lua {
+    let top = ffi::lua_gettop(state);
 
     extra.ref_thread = rlua_expect!(
         protect_lua_closure(state, 0, 0, |state| {
@@ -468,7 +1683,7 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
             ffi::lua_newtable(state);
 
             ffi::lua_pushstring(state, cstr!("__gc"));
-            ffi::lua_pushcfunction(state, userdata_destructor::<Callback>);
+            ffi::lua_pushcfunction(state, callback_destructor);
             ffi::lua_rawset(state, -3);
 
             ffi::lua_pushstring(state, cstr!("__metatable"));
@@ -477,6 +1692,12 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
 
             ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
 
+            // Mark the state as set up by this copy of rlua.
+
+            ffi::lua_pushstring(state, STATE_REGISTRY_NAME);
+            ffi::lua_pushlightuserdata(state, &STATE_REGISTRY_KEY as *const u8 as *mut c_void);
+            ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
+
             // Override pcall and xpcall with versions that cannot be used to catch rust panics.
 
             ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
@@ -501,7 +1722,7 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
         "Error during Lua construction",
     );
 
-    rlua_debug_assert!(ffi::lua_gettop(state) == 0, "stack leak during creation");
+    rlua_debug_assert!(ffi::lua_gettop(state) == top, "stack leak during creation");
     assert_stack(state, ffi::LUA_MINSTACK);
 
     // Place pointer to ExtraData in the lua_State "extra space"
@@ -509,8 +1730,47 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
 
     Lua {
         main_state: state,
+        owned,
         _no_ref_unwind_safe: PhantomData,
     }
 }
 
 pub(crate) static FUNCTION_METATABLE_REGISTRY_KEY: u8 = 0;
+
+// Every state set up by rlua has the address of `STATE_REGISTRY_KEY` stored in the registry under
+// this name.  Each copy of rlua linked into a process has its own address, which lets modules built
+// with rlua tell whether a state was set up by the same copy of rlua.
+pub(crate) const STATE_REGISTRY_NAME: *const c_char = cstr!("rlua.state");
+pub(crate) static STATE_REGISTRY_KEY: u8 = 0;
+
+// Returns the main thread of the state `state` is a thread of.
+unsafe fn main_thread(state: *mut ffi::lua_State) -> *mut ffi::lua_State {
+    assert_stack(state, 1);
+    ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_MAINTHREAD);
+    let main_state = ffi::lua_tothread(state, -1);
+    ffi::lua_pop(state, 1);
+    main_state
+}
+
+// Threads copy the extra space of the main thread when they are created, so threads which existed
+// before rlua set up a state need rlua's internal data copied over.
+pub(crate) unsafe fn share_extra_data(state: *mut ffi::lua_State) {
+    *(ffi::lua_getextraspace(state) as *mut *mut ExtraData) = extra_data(main_thread(state));
+}
+
+// The layout of the `TString` header of Lua 5.3 strings, which their contents follow.  Lua pads the
+// header to the maximum alignment, which the fields here already make it a multiple of.
+#[repr(C)]
+struct TStringHeader {
+    next: *mut c_void,
+    tt: u8,
+    marked: u8,
+    extra: u8,
+    shrlen: u8,
+    hash: c_uint,
+    lnglen: usize,
+}
+
+// The size of a Lua 5.3 string object beyond the string contents: the `TString` header and the
+// terminating nul byte.
+const STRING_OVERHEAD: usize = mem::size_of::<TStringHeader>() + 1;
@@ -1,10 +1,12 @@
+use std::fmt;
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
+use std::slice;
 
 use crate::context::Context;
 use crate::error::Result;
-use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
 /// Result is convertible to `MultiValue` following the common Lua idiom of returning the result
 /// on success, or in the case of an error, returning `nil` and an error message.
@@ -34,7 +36,10 @@ impl<'lua, T: ToLua<'lua>> ToLuaMulti<'lua> for T {
 
 impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for T {
     fn from_lua_multi(mut values: MultiValue<'lua>, lua: Context<'lua>) -> Result<Self> {
-        Ok(T::from_lua(values.pop_front().unwrap_or(Nil), lua)?)
+        match values.pop_front() {
+            Some(value) => T::from_lua(value, lua),
+            None => T::from_lua_missing(lua),
+        }
     }
 }
 
@@ -108,6 +113,48 @@ impl<T> IntoIterator for Variadic<T> {
     }
 }
 
+impl<'a, T> IntoIterator for &'a Variadic<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Variadic<T> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
+
+impl<T> From<Vec<T>> for Variadic<T> {
+    fn from(values: Vec<T>) -> Variadic<T> {
+        Variadic(values)
+    }
+}
+
+impl<T> From<Variadic<T>> for Vec<T> {
+    fn from(values: Variadic<T>) -> Vec<T> {
+        values.0
+    }
+}
+
+impl<T> AsRef<[T]> for Variadic<T> {
+    fn as_ref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> AsMut<[T]> for Variadic<T> {
+    fn as_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+}
+
 impl<T> Deref for Variadic<T> {
     type Target = Vec<T>;
 
@@ -138,6 +185,166 @@ impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for Variadic<T> {
     }
 }
 
+/// Captures all the values passed to a Rust callback, converting each one only when asked for.
+///
+/// Unlike [`Variadic`], whose values all have the same type, `Varargs` keeps the values as they
+/// were passed, which suits callbacks taking several optional trailing arguments of different
+/// types.  Used as the last argument of a callback, it holds the remaining arguments, which are
+/// indexed from 0.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result, Varargs};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let pad = lua_context.create_function(|_, (text, rest): (String, Varargs)| {
+///     let width = rest.get::<Option<usize>>(0)?.unwrap_or(8);
+///     let fill = rest.get::<Option<String>>(1)?.unwrap_or_else(|| " ".to_owned());
+///     let padding = fill.repeat(width.saturating_sub(text.len()));
+///     Ok(format!("{}{}", padding, text))
+/// })?;
+/// lua_context.globals().set("pad", pad)?;
+///
+/// assert_eq!(lua_context.load("pad('ab', 4, '.')").eval::<String>()?, "..ab");
+/// assert_eq!(lua_context.load("pad('ab', 3)").eval::<String>()?, " ab");
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`Variadic`]: struct.Variadic.html
+#[derive(Clone)]
+pub struct Varargs<'lua> {
+    lua: Context<'lua>,
+    values: Vec<Value<'lua>>,
+}
+
+impl<'lua> Varargs<'lua> {
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Converts the value at index `i`.
+    ///
+    /// A value past the end converts as a missing argument would, so an `Option` is `None` unless
+    /// [`ConversionOptions::missing_as_none`] is turned off.
+    ///
+    /// [`ConversionOptions::missing_as_none`]: struct.ConversionOptions.html#structfield.missing_as_none
+    pub fn get<T: FromLua<'lua>>(&self, i: usize) -> Result<T> {
+        match self.values.get(i) {
+            Some(value) => T::from_lua(value.clone(), self.lua),
+            None => T::from_lua_missing(self.lua),
+        }
+    }
+
+    /// Returns the value at index `i` without converting it, or `None` past the end.
+    pub fn value(&self, i: usize) -> Option<&Value<'lua>> {
+        self.values.get(i)
+    }
+
+    /// Returns an iterator over the values.
+    pub fn iter(&self) -> slice::Iter<'_, Value<'lua>> {
+        self.values.iter()
+    }
+
+    /// Returns the values in a `MultiValue`, to pass them on to another function.
+    pub fn into_multi_value(self) -> MultiValue<'lua> {
+        MultiValue::from_vec(self.values)
+    }
+}
+
+impl<'lua> fmt::Debug for Varargs<'lua> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Varargs").field(&self.values).finish()
+    }
+}
+
+impl<'lua> ToLuaMulti<'lua> for Varargs<'lua> {
+    fn to_lua_multi(self, _: Context<'lua>) -> Result<MultiValue<'lua>> {
+        Ok(self.into_multi_value())
+    }
+}
+
+impl<'lua> FromLuaMulti<'lua> for Varargs<'lua> {
+    fn from_lua_multi(values: MultiValue<'lua>, lua: Context<'lua>) -> Result<Self> {
+        Ok(Varargs {
+            lua,
+            values: values.into_vec(),
+        })
+    }
+}
+
+/// Wraps a tuple so that it converts to and from a single sequence table.
+///
+/// Tuples themselves convert to multiple values, as the arguments and results of functions do, so
+/// they cannot also be a single [`ToLua`] or [`FromLua`] value.  Wrapped in `TableTuple`, a tuple of
+/// up to 16 elements becomes a table holding its elements at indices `1` to `n`, which lets
+/// fields such as a position round-trip through Lua without custom code.  Fixed-size arrays
+/// already convert this way.
+///
+/// Converting from a table reads the elements from indices `1` to `n`, so trailing elements
+/// converted to `Option` may be missing, but a table with more than `n` elements is an error.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result, TableTuple};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// lua_context
+///     .globals()
+///     .set("position", TableTuple((1.0, 2.0, 0.5)))?;
+/// let TableTuple((name, x)): TableTuple<(String, f32)> = lua_context
+///     .load(r#"{"x", position[1] + position[3]}"#)
+///     .eval()?;
+/// assert_eq!(name, "x");
+/// assert_eq!(x, 1.5);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`ToLua`]: trait.ToLua.html
+/// [`FromLua`]: trait.FromLua.html
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TableTuple<T>(pub T);
+
+/// Wraps a value converted from Lua with strict numeric conversions.
+///
+/// Within the conversion of the wrapped value, integers and floats are converted as with
+/// [`ConversionOptions::strict_numbers`]: strings are not coerced to numbers, and numbers which
+/// have a fractional part, are out of range or lose precision are errors instead of being
+/// truncated or rounded.  This applies to numbers nested in collections as well, such as the
+/// elements of a `Strict<Vec<u32>>`.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result, Strict};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let transfer = lua_context.create_function(|_, Strict(cents): Strict<i64>| Ok(cents))?;
+/// lua_context.globals().set("transfer", transfer)?;
+///
+/// assert_eq!(lua_context.load("transfer(1500)").eval::<i64>()?, 1500);
+/// assert!(lua_context.load("transfer(15.5)").exec().is_err());
+/// assert!(lua_context.load("transfer('1500')").exec().is_err());
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`ConversionOptions::strict_numbers`]: struct.ConversionOptions.html#structfield.strict_numbers
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Strict<T>(pub T);
+
 macro_rules! impl_tuple {
     () => (
         impl<'lua> ToLuaMulti<'lua> for () {
@@ -176,9 +383,12 @@ macro_rules! impl_tuple {
             #[allow(unused_mut)]
             #[allow(non_snake_case)]
             fn from_lua_multi(mut values: MultiValue<'lua>, lua: Context<'lua>) -> Result<Self> {
-                $(let $name = values.pop_front().unwrap_or(Nil);)*
+                $(let $name = match values.pop_front() {
+                    Some(value) => FromLua::from_lua(value, lua)?,
+                    None => FromLua::from_lua_missing(lua)?,
+                };)*
                 let $last = FromLuaMulti::from_lua_multi(values, lua)?;
-                Ok(($(FromLua::from_lua($name, lua)?,)* $last,))
+                Ok(($($name,)* $last,))
             }
         }
     );
@@ -0,0 +1,386 @@
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs, UdpSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::string::String;
+use crate::sync::Mutex;
+use crate::table::Table;
+use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::value::{ToLua, Value};
+
+/// Rules applied to the networking module created with [`Context::create_net_module`].
+///
+/// The default policy allows nothing.
+///
+/// [`Context::create_net_module`]: struct.Context.html#method.create_net_module
+#[derive(Clone, Debug, Default)]
+pub struct NetPolicy {
+    /// Whether scripts may open TCP connections.
+    pub allow_tcp: bool,
+    /// Whether scripts may open UDP sockets.
+    pub allow_udp: bool,
+    /// Hosts scripts may connect to, either as `"host"` to allow every port or `"host:port"`.
+    ///
+    /// Hosts are compared case-insensitively with the name passed by the script, before it is
+    /// resolved.
+    pub allowed_hosts: Vec<std::string::String>,
+    /// The longest any single operation may take.  This is also the timeout used when a script
+    /// does not give one.
+    pub timeout: Option<Duration>,
+}
+
+impl NetPolicy {
+    fn allows(&self, host: &str, port: u16) -> bool {
+        let with_port = format!("{}:{}", host, port);
+        self.allowed_hosts.iter().any(|allowed| {
+            allowed.eq_ignore_ascii_case(host) || allowed.eq_ignore_ascii_case(&with_port)
+        })
+    }
+}
+
+// The timeout for an operation, given the optional timeout in seconds requested by the script.  The
+// requested timeout may only shorten `limit`, timeouts too large to represent leave it unchanged.
+pub(crate) fn limit_timeout(
+    limit: Option<Duration>,
+    requested: Option<f64>,
+) -> Result<Option<Duration>> {
+    let requested = match requested {
+        Some(secs) if secs.is_finite() && secs > 0. => Duration::try_from_secs_f64(secs).ok(),
+        Some(_) => {
+            return Err(Error::RuntimeError(
+                "timeout must be a positive number of seconds".to_owned(),
+            ))
+        }
+        None => None,
+    };
+    Ok(match (requested, limit) {
+        (Some(requested), Some(limit)) => Some(requested.min(limit)),
+        (requested, limit) => requested.or(limit),
+    })
+}
+
+// The largest amount of data a single `receive` will return.
+const MAX_RECEIVE_SIZE: usize = 64 * 1024;
+
+const SOCKET_METHODS_KEY: &str = "rlua.net.socket_methods";
+
+enum Socket {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Socket {
+    fn send(&self, data: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => {
+                stream.set_write_timeout(timeout)?;
+                (&*stream).write_all(data)?;
+                Ok(data.len())
+            }
+            Socket::Udp(socket) => {
+                socket.set_write_timeout(timeout)?;
+                socket.send(data)
+            }
+        }
+    }
+
+    fn receive(&self, max: usize, timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; max.min(MAX_RECEIVE_SIZE)];
+        let len = match self {
+            Socket::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                (&*stream).read(&mut buf)?
+            }
+            Socket::Udp(socket) => {
+                socket.set_read_timeout(timeout)?;
+                socket.recv(&mut buf)?
+            }
+        };
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    fn close(&self) {
+        if let Socket::Tcp(stream) = self {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// A socket handed to scripts.  The underlying socket is shared with the worker threads performing
+// operations on it, and is dropped once it is closed and no operation is using it.
+struct NetSocket {
+    socket: Option<Arc<Socket>>,
+    timeout: Option<Duration>,
+}
+
+impl NetSocket {
+    fn get(&self) -> Result<Arc<Socket>> {
+        self.socket
+            .clone()
+            .ok_or_else(|| Error::RuntimeError("attempt to use a closed socket".to_owned()))
+    }
+}
+
+impl UserData for NetSocket {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // The methods are async functions, which cannot be added here, so they are looked up in a
+        // table created along with the module.
+        methods.add_meta_function(MetaMethod::Index, |lua, (_, key): (AnyUserData, Value)| {
+            lua.named_registry_value::<_, Table>(SOCKET_METHODS_KEY)?
+                .get::<_, Value>(key)
+        });
+    }
+}
+
+pub(crate) fn create_net_module<'lua>(
+    lua: Context<'lua>,
+    policy: NetPolicy,
+) -> Result<Table<'lua>> {
+    let methods = lua.create_table()?;
+    methods.set(
+        "send",
+        lua.create_async_function(
+            |_, (socket, data, timeout): (AnyUserData, String, Option<f64>)| {
+                let prepared = socket.borrow::<NetSocket>().and_then(|socket| {
+                    Ok((socket.get()?, limit_timeout(socket.timeout, timeout)?))
+                });
+                let data = data.as_bytes().to_vec();
+                async move {
+                    let (socket, timeout) = prepared?;
+                    run_blocking(move || {
+                        socket.send(&data, timeout).map_err(|e| io_error("send", e))
+                    })
+                    .await
+                }
+            },
+        )?,
+    )?;
+    methods.set(
+        "receive",
+        lua.create_async_function(
+            |_, (socket, max, timeout): (AnyUserData, usize, Option<f64>)| {
+                let prepared = socket.borrow::<NetSocket>().and_then(|socket| {
+                    Ok((socket.get()?, limit_timeout(socket.timeout, timeout)?))
+                });
+                async move {
+                    let (socket, timeout) = prepared?;
+                    let data = run_blocking(move || {
+                        socket
+                            .receive(max, timeout)
+                            .map_err(|e| io_error("receive", e))
+                    })
+                    .await?;
+                    Ok(BytesResult(data))
+                }
+            },
+        )?,
+    )?;
+    methods.set(
+        "close",
+        lua.create_function(|_, socket: AnyUserData| {
+            if let Some(socket) = socket.borrow_mut::<NetSocket>()?.socket.take() {
+                socket.close();
+            }
+            Ok(())
+        })?,
+    )?;
+    lua.set_named_registry_value(SOCKET_METHODS_KEY, methods)?;
+
+    let policy = Arc::new(policy);
+    let module = lua.create_table()?;
+    let tcp_policy = policy.clone();
+    module.set(
+        "connect",
+        lua.create_async_function(
+            move |_, (host, port, timeout): (std::string::String, u16, Option<f64>)| {
+                let timeout = check_connect(
+                    &tcp_policy,
+                    tcp_policy.allow_tcp,
+                    "TCP",
+                    &host,
+                    port,
+                    timeout,
+                );
+                async move {
+                    let timeout = timeout?;
+                    let stream = run_blocking(move || {
+                        connect_tcp(&host, port, timeout).map_err(|e| io_error("connect", e))
+                    })
+                    .await?;
+                    Ok(NewSocket(Socket::Tcp(stream), timeout))
+                }
+            },
+        )?,
+    )?;
+    let udp_policy = policy;
+    module.set(
+        "connect_udp",
+        lua.create_async_function(
+            move |_, (host, port, timeout): (std::string::String, u16, Option<f64>)| {
+                let timeout = check_connect(
+                    &udp_policy,
+                    udp_policy.allow_udp,
+                    "UDP",
+                    &host,
+                    port,
+                    timeout,
+                );
+                async move {
+                    let timeout = timeout?;
+                    let socket = run_blocking(move || {
+                        connect_udp(&host, port).map_err(|e| io_error("connect", e))
+                    })
+                    .await?;
+                    Ok(NewSocket(Socket::Udp(socket), timeout))
+                }
+            },
+        )?,
+    )?;
+    Ok(module)
+}
+
+pub(crate) fn check_connect(
+    policy: &NetPolicy,
+    allowed: bool,
+    protocol: &str,
+    host: &str,
+    port: u16,
+    timeout: Option<f64>,
+) -> Result<Option<Duration>> {
+    if !allowed {
+        return Err(Error::RuntimeError(format!(
+            "{} connections are not allowed",
+            protocol
+        )));
+    }
+    if !policy.allows(host, port) {
+        return Err(Error::RuntimeError(format!(
+            "connecting to {}:{} is not allowed",
+            host, port
+        )));
+    }
+    limit_timeout(policy.timeout, timeout)
+}
+
+pub(crate) fn connect_tcp(
+    host: &str,
+    port: u16,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in (host, port).to_socket_addrs()? {
+        let result = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no addresses")))
+}
+
+fn connect_udp(host: &str, port: u16) -> io::Result<UdpSocket> {
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no addresses"))?;
+    let socket = if addr.is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0")?
+    } else {
+        UdpSocket::bind("[::]:0")?
+    };
+    socket.connect(addr)?;
+    Ok(socket)
+}
+
+pub(crate) fn io_error(operation: &str, err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            Error::RuntimeError(format!("{} timed out", operation))
+        }
+        _ => Error::RuntimeError(format!("{} failed: {}", operation, err)),
+    }
+}
+
+// Results of async functions have to be `'static`, so these are converted to Lua values only once
+// the operation completes.
+struct NewSocket(Socket, Option<Duration>);
+
+impl<'lua> ToLua<'lua> for NewSocket {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        lua.create_userdata(NetSocket {
+            socket: Some(Arc::new(self.0)),
+            timeout: self.1,
+        })
+        .map(Value::UserData)
+    }
+}
+
+pub(crate) struct BytesResult(pub(crate) Vec<u8>);
+
+impl<'lua> ToLua<'lua> for BytesResult {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        lua.create_string(&self.0).map(Value::String)
+    }
+}
+
+struct Shared<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+// Runs a blocking operation on its own thread, so the Lua thread waiting for it can be suspended
+// without needing an I/O reactor.
+pub(crate) struct Blocking<T>(Arc<Mutex<Shared<T>>>);
+
+pub(crate) fn run_blocking<T, F>(f: F) -> Blocking<T>
+where
+    T: 'static + Send,
+    F: 'static + Send + FnOnce() -> Result<T>,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let worker_shared = shared.clone();
+    let spawned = thread::Builder::new()
+        .name("rlua-net".to_owned())
+        .spawn(move || {
+            let result = f();
+            let mut shared = worker_shared.lock();
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+    if let Err(err) = spawned {
+        shared.lock().result = Some(Err(Error::external(err)));
+    }
+    Blocking(shared)
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Result<T>> {
+        let mut shared = self.0.lock();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::function::Function;
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::value::Value;
+
+// The standard C searchers and `package.loadlib`, kept in the registry once a policy has been
+// applied so that they can be removed from the `package` table and put back later.
+const C_LOADERS_KEY: &str = "rlua.package.c_loaders";
+// The standard `require` and the wrapper which tracks dependencies, kept once tracking has been
+// enabled.
+const REQUIRE_KEY: &str = "rlua.package.require";
+
+// Calls `require` between `enter` and `leave`, raising its errors again unchanged.
+const TRACKING_REQUIRE_SOURCE: &str = r#"
+    local require, enter, leave, pcall, error = ...
+    local function finish(ok, ...)
+        leave(ok)
+        if not ok then
+            error((...), 0)
+        end
+        return ...
+    end
+    return function(name)
+        enter(name)
+        return finish(pcall(require, name))
+    end
+"#;
+
+/// Configuration of how `require` finds modules, applied with [`Lua::set_package_policy`] or
+/// [`LuaBuilder::package_policy`].
+///
+/// The default policy keeps the behavior of the `package` library: the paths are taken from the
+/// `LUA_PATH` and `LUA_CPATH` environment variables or Lua's built-in defaults, and scripts may
+/// load C modules.
+///
+/// [`Lua::set_package_policy`]: struct.Lua.html#method.set_package_policy
+/// [`LuaBuilder::package_policy`]: struct.LuaBuilder.html#method.package_policy
+#[derive(Clone, Debug)]
+pub struct PackagePolicy {
+    /// Templates searched for Lua modules, such as `"scripts/?.lua"`, which replace
+    /// `package.path`.  `None` leaves `package.path` as it is.
+    pub path: Option<Vec<StdString>>,
+    /// Templates searched for C modules, which replace `package.cpath`.  `None` leaves
+    /// `package.cpath` as it is.
+    pub cpath: Option<Vec<StdString>>,
+    /// Whether scripts may load C modules.  When false, `require` does not search for C modules
+    /// and `package.loadlib` is removed.  [`Context::load_c_module`] keeps working, so the host can
+    /// still load the C modules it trusts.
+    ///
+    /// [`Context::load_c_module`]: struct.Context.html#method.load_c_module
+    pub c_modules: bool,
+    /// Whether to record which modules each module loaded with `require`, see
+    /// [`Lua::module_graph`].  This replaces the global `require` with a wrapper, which is put
+    /// back when tracking is turned off again.
+    ///
+    /// [`Lua::module_graph`]: struct.Lua.html#method.module_graph
+    pub track_dependencies: bool,
+}
+
+impl Default for PackagePolicy {
+    fn default() -> PackagePolicy {
+        PackagePolicy {
+            path: None,
+            cpath: None,
+            c_modules: true,
+            track_dependencies: false,
+        }
+    }
+}
+
+pub(crate) fn set_package_policy<'lua>(lua: Context<'lua>, policy: &PackagePolicy) -> Result<()> {
+    let package = match package_table(lua)? {
+        Some(package) => package,
+        None => {
+            return Err(Error::RuntimeError(
+                "package policies require the package library".to_owned(),
+            ))
+        }
+    };
+    if let Some(path) = &policy.path {
+        package.set("path", join_templates(path)?)?;
+    }
+    if let Some(cpath) = &policy.cpath {
+        package.set("cpath", join_templates(cpath)?)?;
+    }
+    set_dependency_tracking(lua, policy.track_dependencies)?;
+
+    let loaders = c_loaders(lua, &package)?;
+    let c_searchers: Vec<Function> = loaders
+        .get::<_, Table>("searchers")?
+        .sequence_values()
+        .collect::<Result<_>>()?;
+    let is_c_searcher = |value: &Value| match value {
+        Value::Function(f) => c_searchers
+            .iter()
+            .any(|c| c.0.to_pointer() == f.0.to_pointer()),
+        _ => false,
+    };
+
+    let searchers: Table = package.get("searchers")?;
+    let current: Vec<Value> = searchers.clone().sequence_values().collect::<Result<_>>()?;
+    let mut updated: Vec<Value> = current
+        .iter()
+        .filter(|&value| !is_c_searcher(value))
+        .cloned()
+        .collect();
+    let has_c_searchers = updated.len() < current.len();
+
+    let loadlib = if policy.c_modules {
+        loaders.get::<_, Value>("loadlib")?
+    } else {
+        Value::Nil
+    };
+    package.set("loadlib", loadlib)?;
+    if policy.c_modules == has_c_searchers {
+        // Searchers which are already there stay in whatever place they were put.
+        return Ok(());
+    }
+    if policy.c_modules {
+        updated.extend(c_searchers.into_iter().map(Value::Function));
+    }
+    for i in updated.len()..current.len() {
+        searchers.raw_set(i + 1, Value::Nil)?;
+    }
+    for (i, searcher) in updated.into_iter().enumerate() {
+        searchers.raw_set(i + 1, searcher)?;
+    }
+    Ok(())
+}
+
+/// The modules loaded with `require` and the modules each of them required, recorded while
+/// [`PackagePolicy::track_dependencies`] is set.
+///
+/// Modules are named as they were passed to `require`.  A module which is loaded again after being
+/// removed from `package.loaded` has its dependencies recorded anew, which keeps the graph correct
+/// across hot reloads.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, PackagePolicy, Result};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::new();
+/// lua.set_package_policy(PackagePolicy {
+///     track_dependencies: true,
+///     ..PackagePolicy::default()
+/// })?;
+/// lua.context(|lua_context| {
+///     lua_context
+///         .load(
+///             r#"
+///                 package.preload.util = function() return {} end
+///                 package.preload.config = function() return {} end
+///                 package.preload.app = function()
+///                     require("util")
+///                     require("config")
+///                     return {}
+///                 end
+///                 require("app")
+///             "#,
+///         )
+///         .exec()
+/// })?;
+///
+/// let graph = lua.module_graph();
+/// assert_eq!(graph.dependencies("app"), vec!["config", "util"]);
+/// assert_eq!(graph.affected_by("util"), vec!["app"]);
+/// assert_eq!(graph.load_order(), vec!["config", "util", "app"]);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`PackagePolicy::track_dependencies`]: struct.PackagePolicy.html#structfield.track_dependencies
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleGraph {
+    dependencies: BTreeMap<StdString, BTreeSet<StdString>>,
+    roots: BTreeSet<StdString>,
+}
+
+impl ModuleGraph {
+    /// Returns every loaded module, sorted by name.
+    pub fn modules(&self) -> Vec<&str> {
+        self.dependencies.keys().map(|m| m.as_str()).collect()
+    }
+
+    /// Returns the modules required from outside of any module, such as by the main chunk of a
+    /// script, sorted by name.
+    pub fn roots(&self) -> Vec<&str> {
+        self.roots.iter().map(|m| m.as_str()).collect()
+    }
+
+    /// Returns the modules which `module` required, sorted by name.
+    pub fn dependencies(&self, module: &str) -> Vec<&str> {
+        self.dependencies
+            .get(module)
+            .map(|dependencies| dependencies.iter().map(|m| m.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the modules which required `module`, sorted by name.
+    pub fn dependents(&self, module: &str) -> Vec<&str> {
+        self.dependencies
+            .iter()
+            .filter(|(_, dependencies)| dependencies.contains(module))
+            .map(|(m, _)| m.as_str())
+            .collect()
+    }
+
+    /// Returns the modules which depend on `module`, directly or not, in the order they should be
+    /// reloaded after it.
+    pub fn affected_by(&self, module: &str) -> Vec<&str> {
+        let mut affected = BTreeSet::new();
+        let mut pending = vec![module];
+        while let Some(m) = pending.pop() {
+            for dependent in self.dependents(m) {
+                if dependent != module && affected.insert(dependent) {
+                    pending.push(dependent);
+                }
+            }
+        }
+        self.load_order()
+            .into_iter()
+            .filter(|m| affected.contains(m))
+            .collect()
+    }
+
+    /// Returns every loaded module after the modules it depends on.  Modules which depend on each
+    /// other are ordered as they were first reached.
+    pub fn load_order(&self) -> Vec<&str> {
+        fn visit<'a>(
+            graph: &'a ModuleGraph,
+            module: &'a str,
+            visited: &mut BTreeSet<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) {
+            if visited.insert(module) {
+                for dependency in graph.dependencies(module) {
+                    visit(graph, dependency, visited, order);
+                }
+                order.push(module);
+            }
+        }
+
+        let mut visited = BTreeSet::new();
+        let mut order = Vec::new();
+        for module in self.dependencies.keys() {
+            visit(self, module, &mut visited, &mut order);
+        }
+        order
+    }
+}
+
+// Installs or removes the `require` wrapper which records dependencies.
+fn set_dependency_tracking<'lua>(lua: Context<'lua>, enabled: bool) -> Result<()> {
+    let globals = lua.globals();
+    let saved = lua.named_registry_value::<_, Option<Table>>(REQUIRE_KEY)?;
+    let saved = match saved {
+        Some(saved) => saved,
+        None if !enabled => return Ok(()),
+        None => {
+            let require: Function = globals.get("require")?;
+            let enter = lua.create_function(|lua, name: StdString| {
+                let loaded: Table = lua.named_registry_value("_LOADED")?;
+                let fresh = matches!(loaded.get::<_, Value>(name.as_str())?, Value::Nil);
+                let extra = unsafe { &mut *extra_data(lua.state) };
+                if fresh {
+                    // Loading the module again records its dependencies anew.
+                    extra
+                        .module_graph
+                        .dependencies
+                        .insert(name.clone(), BTreeSet::new());
+                }
+                extra.loading_modules.push((name, fresh));
+                Ok(())
+            })?;
+            let leave = lua.create_function(|lua, ok: bool| {
+                let extra = unsafe { &mut *extra_data(lua.state) };
+                let (name, fresh) = match extra.loading_modules.pop() {
+                    Some(module) => module,
+                    None => return Ok(()),
+                };
+                let graph = &mut extra.module_graph;
+                if ok {
+                    graph.dependencies.entry(name.clone()).or_default();
+                    match extra.loading_modules.last() {
+                        Some((requirer, _)) => graph
+                            .dependencies
+                            .entry(requirer.clone())
+                            .or_default()
+                            .insert(name),
+                        None => graph.roots.insert(name),
+                    };
+                } else if fresh {
+                    graph.dependencies.remove(&name);
+                }
+                Ok(())
+            })?;
+            let (pcall, error): (Function, Function) =
+                (globals.get("pcall")?, globals.get("error")?);
+            let tracking: Function = lua
+                .load(TRACKING_REQUIRE_SOURCE)
+                .set_name("=require")?
+                .call((require.clone(), enter, leave, pcall, error))?;
+
+            let saved = lua.create_table()?;
+            saved.set("original", require)?;
+            saved.set("tracking", tracking)?;
+            lua.set_named_registry_value(REQUIRE_KEY, saved.clone())?;
+            saved
+        }
+    };
+    let require: Function = saved.get(if enabled { "tracking" } else { "original" })?;
+    globals.set("require", require)
+}
+
+pub(crate) fn module_graph(lua: Context) -> ModuleGraph {
+    unsafe { (*extra_data(lua.state)).module_graph.clone() }
+}
+
+// Returns `package.loadlib`, or the copy kept while a policy disallows C modules.
+pub(crate) fn loadlib<'lua>(lua: Context<'lua>) -> Result<Option<Function<'lua>>> {
+    let package = match package_table(lua)? {
+        Some(package) => package,
+        None => return Ok(None),
+    };
+    match package.get::<_, Value>("loadlib")? {
+        Value::Function(loadlib) => Ok(Some(loadlib)),
+        _ => match lua.named_registry_value::<_, Option<Table>>(C_LOADERS_KEY)? {
+            Some(loaders) => Ok(Some(loaders.get("loadlib")?)),
+            None => Ok(None),
+        },
+    }
+}
+
+fn package_table<'lua>(lua: Context<'lua>) -> Result<Option<Table<'lua>>> {
+    let loaded: Table = lua.named_registry_value("_LOADED")?;
+    match loaded.get::<_, Value>("package")? {
+        Value::Table(package) => Ok(Some(package)),
+        _ => Ok(None),
+    }
+}
+
+// Returns the C searchers and `package.loadlib`, saving them the first time.  These are the last
+// two of the standard searchers, so a policy should be set before scripts change `package.searchers`.
+fn c_loaders<'lua>(lua: Context<'lua>, package: &Table<'lua>) -> Result<Table<'lua>> {
+    if let Some(loaders) = lua.named_registry_value::<_, Option<Table>>(C_LOADERS_KEY)? {
+        return Ok(loaders);
+    }
+    let searchers: Table = package.get("searchers")?;
+    let loaders = lua.create_table()?;
+    loaders.set(
+        "searchers",
+        vec![
+            searchers.get::<_, Function>(3)?,
+            searchers.get::<_, Function>(4)?,
+        ],
+    )?;
+    loaders.set("loadlib", package.get::<_, Function>("loadlib")?)?;
+    lua.set_named_registry_value(C_LOADERS_KEY, loaders.clone())?;
+    Ok(loaders)
+}
+
+// Joins path templates the way `package.path` lists them.
+fn join_templates(templates: &[StdString]) -> Result<StdString> {
+    if let Some(template) = templates.iter().find(|t| t.contains(';')) {
+        return Err(Error::RuntimeError(format!(
+            "package path template '{}' contains ';'",
+            template
+        )));
+    }
+    Ok(templates.join(";"))
+}
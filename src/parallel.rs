@@ -0,0 +1,158 @@
+use std::any::Any;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::lua::{extra_data, Lua, StateStatus};
+use crate::plain::{plain_table, PlainValue};
+use crate::sync::Mutex;
+use crate::table::Table;
+use crate::value::Value;
+
+type Job = Box<dyn FnOnce(&Lua) + Send>;
+
+type ShardResult =
+    ::std::result::Result<Result<Vec<(PlainValue, PlainValue)>>, Box<dyn Any + Send>>;
+
+// The worker threads used by `Context::parallel_map`, each owning a Lua state which is kept for
+// every shard it maps.  The pool belongs to the main state and grows to the largest number of
+// workers requested.
+pub(crate) struct WorkerPool {
+    sender: Option<Sender<Job>>,
+    receiver: Arc<Mutex<Receiver<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new() -> WorkerPool {
+        let (sender, receiver) = channel();
+        WorkerPool {
+            sender: Some(sender),
+            receiver: Arc::new(Mutex::new(receiver)),
+            workers: Vec::new(),
+        }
+    }
+
+    // Spawns worker threads until there are at least `count`, and returns a sender for their jobs.
+    fn reserve(&mut self, count: usize) -> Sender<Job> {
+        while self.workers.len() < count {
+            let receiver = self.receiver.clone();
+            let worker = thread::Builder::new()
+                .name(format!("rlua-parallel-{}", self.workers.len()))
+                .spawn(move || worker(receiver))
+                .expect("could not spawn parallel_map thread");
+            self.workers.push(worker);
+        }
+        rlua_expect!(self.sender.clone(), "worker pool sender not set")
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Dropping the sender makes every worker exit once the queue is drained.
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(receiver: Arc<Mutex<Receiver<Job>>>) {
+    let mut lua = Lua::new();
+    loop {
+        let job = match receiver.lock().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        // Jobs catch their own panics, but a state a panic unwound through is not reused.
+        job(&lua);
+        if lua.status() != StateStatus::Ok {
+            lua = Lua::new();
+        }
+    }
+}
+
+pub(crate) fn parallel_map<'lua, F>(
+    lua: Context<'lua>,
+    table: Table<'lua>,
+    workers: usize,
+    func: F,
+) -> Result<Table<'lua>>
+where
+    F: 'static + Send + Sync + for<'a> Fn(Context<'a>, Value<'a>) -> Result<Value<'a>>,
+{
+    let mut entries = Vec::new();
+    for pair in table.pairs::<Value, Value>() {
+        let (k, v) = pair?;
+        entries.push((PlainValue::from_value(k)?, PlainValue::from_value(v)?));
+    }
+
+    let workers = workers.max(1).min(entries.len().max(1));
+    let shard_size = entries.len().div_ceil(workers);
+
+    let sender = unsafe {
+        let extra = extra_data(lua.state);
+        (*extra)
+            .worker_pool
+            .get_or_insert_with(WorkerPool::new)
+            .reserve(workers)
+    };
+
+    let func = Arc::new(func);
+    let (results_sender, results) = channel::<ShardResult>();
+    let mut shards = 0;
+    while !entries.is_empty() {
+        let rest = entries.split_off(shard_size.min(entries.len()));
+        let shard = std::mem::replace(&mut entries, rest);
+        let func = func.clone();
+        let results_sender = results_sender.clone();
+        let job: Job = Box::new(move |lua: &Lua| {
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                lua.context(|lua| {
+                    let mut results = Vec::with_capacity(shard.len());
+                    for (k, v) in shard {
+                        let v = func(lua, v.into_value(lua)?)?;
+                        results.push((k, PlainValue::from_value(v)?));
+                    }
+                    Ok(results)
+                })
+            }));
+            let _ = results_sender.send(result);
+        });
+        rlua_expect!(sender.send(job).ok(), "parallel_map workers have stopped");
+        shards += 1;
+    }
+
+    // Every shard is waited for before any panic is resumed, so no shard is still running once
+    // this returns.
+    let mut merged = Vec::new();
+    let mut error = None;
+    let mut panic = None;
+    for _ in 0..shards {
+        match rlua_expect!(results.recv().ok(), "parallel_map worker stopped") {
+            Ok(Ok(results)) => merged.extend(results),
+            Ok(Err(err)) => {
+                if error.is_none() {
+                    error = Some(err);
+                }
+            }
+            Err(p) => {
+                if panic.is_none() {
+                    panic = Some(p);
+                }
+            }
+        }
+    }
+
+    if let Some(p) = panic {
+        resume_unwind(p);
+    }
+    if let Some(err) = error {
+        return Err(err);
+    }
+    plain_table(lua, merged)
+}
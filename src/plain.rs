@@ -0,0 +1,96 @@
+use std::os::raw::c_void;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::types::{Integer, Number};
+use crate::value::Value;
+
+// An owned copy of a data-only Lua value, independent of any Lua state.  This is used to move data
+// between separate `Lua` instances, possibly on separate threads.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PlainValue {
+    Nil,
+    Boolean(bool),
+    Integer(Integer),
+    Number(Number),
+    String(Vec<u8>),
+    Table(Vec<(PlainValue, PlainValue)>),
+}
+
+impl PlainValue {
+    // Deep copies the given value.  Fails with a `FromLuaConversionError` for any value that is not
+    // plain data (functions, userdata, threads etc) and for tables containing cycles, and with
+    // `Error::DepthLimitExceeded` for tables nested more deeply than the conversion depth limit of
+    // their state.
+    pub(crate) fn from_value(value: Value) -> Result<PlainValue> {
+        fn copy<'lua>(value: Value<'lua>, visiting: &mut Vec<*const c_void>) -> Result<PlainValue> {
+            Ok(match value {
+                Value::Nil => PlainValue::Nil,
+                Value::Boolean(b) => PlainValue::Boolean(b),
+                Value::Integer(i) => PlainValue::Integer(i),
+                Value::Number(n) => PlainValue::Number(n),
+                Value::String(s) => PlainValue::String(s.as_bytes().to_vec()),
+                Value::Table(t) => {
+                    let limit = unsafe { (*extra_data(t.0.lua.state)).conversion_depth_limit };
+                    if let Some(limit) = limit {
+                        if visiting.len() >= limit {
+                            return Err(Error::DepthLimitExceeded { limit });
+                        }
+                    }
+                    let ptr = t.0.to_pointer();
+                    if visiting.contains(&ptr) {
+                        return Err(Error::FromLuaConversionError {
+                            from: "table",
+                            to: "plain data",
+                            message: Some("recursive table".to_owned()),
+                        });
+                    }
+                    visiting.push(ptr);
+                    let mut entries = Vec::new();
+                    for pair in t.pairs::<Value, Value>() {
+                        let (k, v) = pair?;
+                        entries.push((copy(k, visiting)?, copy(v, visiting)?));
+                    }
+                    visiting.pop();
+                    PlainValue::Table(entries)
+                }
+                v => {
+                    return Err(Error::FromLuaConversionError {
+                        from: v.type_name(),
+                        to: "plain data",
+                        message: Some(
+                            "only nil, booleans, numbers, strings and tables can be copied"
+                                .to_owned(),
+                        ),
+                    })
+                }
+            })
+        }
+
+        copy(value, &mut Vec::new())
+    }
+
+    pub(crate) fn into_value<'lua>(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        Ok(match self {
+            PlainValue::Nil => Value::Nil,
+            PlainValue::Boolean(b) => Value::Boolean(b),
+            PlainValue::Integer(i) => Value::Integer(i),
+            PlainValue::Number(n) => Value::Number(n),
+            PlainValue::String(s) => Value::String(lua.create_string(&s)?),
+            PlainValue::Table(entries) => Value::Table(plain_table(lua, entries)?),
+        })
+    }
+}
+
+pub(crate) fn plain_table<'lua>(
+    lua: Context<'lua>,
+    entries: Vec<(PlainValue, PlainValue)>,
+) -> Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    for (k, v) in entries {
+        table.raw_set(k.into_value(lua)?, v.into_value(lua)?)?;
+    }
+    Ok(table)
+}
@@ -1,14 +1,46 @@
 //! Re-exports most types with an extra `Lua*` prefix to prevent name clashes.
 
 pub use crate::{
-    AnyUserData as LuaAnyUserData, Chunk as LuaChunk, Context as LuaContext, Debug as LuaDebug,
-    DebugNames as LuaDebugNames, DebugSource as LuaDebugSource, DebugStack as LuaDebugStack,
-    Error as LuaError, ExternalError as LuaExternalError, ExternalResult as LuaExternalResult,
-    FromLua, FromLuaMulti, Function as LuaFunction, HookTriggers as LuaHookTriggers,
-    Integer as LuaInteger, LightUserData as LuaLightUserData, Lua, MetaMethod as LuaMetaMethod,
-    MultiValue as LuaMultiValue, Nil as LuaNil, Number as LuaNumber, RegistryKey as LuaRegistryKey,
-    Result as LuaResult, Scope as LuaScope, String as LuaString, Table as LuaTable,
-    TablePairs as LuaTablePairs, TableSequence as LuaTableSequence, Thread as LuaThread,
-    ThreadStatus as LuaThreadStatus, ToLua, ToLuaMulti, UserData as LuaUserData,
-    UserDataMethods as LuaUserDataMethods, Value as LuaValue,
+    AnyUserData as LuaAnyUserData, AsyncThread as LuaAsyncThread,
+    BytecodeHeader as LuaBytecodeHeader, BytecodeLayout as LuaBytecodeLayout, Bytes as LuaBytes,
+    CallFrame as LuaCallFrame, CallStack as LuaCallStack, CallbackStats as LuaCallbackStats,
+    Chunk as LuaChunk, Clock as LuaClock, Compilation as LuaCompilation, Compiler as LuaCompiler,
+    Context as LuaContext, ConversionOptions as LuaConversionOptions, Debug as LuaDebug,
+    DebugEvent as LuaDebugEvent, DebugNames as LuaDebugNames, DebugSource as LuaDebugSource,
+    DebugStack as LuaDebugStack, Deprecation as LuaDeprecation,
+    DeprecationEvent as LuaDeprecationEvent, DeprecationUsage as LuaDeprecationUsage,
+    DurationFormat as LuaDurationFormat, DynUserData as LuaDynUserData,
+    Endianness as LuaEndianness, EnvProvider as LuaEnvProvider, Error as LuaError,
+    ExternalError as LuaExternalError, ExternalResult as LuaExternalResult, FromLua, FromLuaMulti,
+    Function as LuaFunction, FunctionInfo as LuaFunctionInfo, GlobalInfo as LuaGlobalInfo,
+    GlobalsDiff as LuaGlobalsDiff, GlobalsReport as LuaGlobalsReport, HeapCensus as LuaHeapCensus,
+    HookTriggers as LuaHookTriggers, HostApi as LuaHostApi, Integer as LuaInteger,
+    IntegerRange as LuaIntegerRange, JoinAll as LuaJoinAll, Lazy as LuaLazy,
+    LazyTable as LuaLazyTable, LazyTableProvider as LuaLazyTableProvider,
+    LightUserData as LuaLightUserData, Linda as LuaLinda, LoadPolicy as LuaLoadPolicy,
+    LoadQuota as LuaLoadQuota, LoggedCall as LuaLoggedCall, Lua, LuaBuilder,
+    MetaMethod as LuaMetaMethod, ModuleGraph as LuaModuleGraph, MultiValue as LuaMultiValue,
+    Nil as LuaNil, Number as LuaNumber, Numbers as LuaNumbers, NumericElement as LuaNumericElement,
+    ObjectTotals as LuaObjectTotals, OomBehavior as LuaOomBehavior,
+    PackagePolicy as LuaPackagePolicy, PathSegment as LuaPathSegment, PtrKey as LuaPtrKey,
+    Recording as LuaRecording, ReferencePath as LuaReferencePath,
+    RegisteredFunction as LuaRegisteredFunction, RegisteredType as LuaRegisteredType,
+    RegistryKey as LuaRegistryKey, Result as LuaResult, ResumeResult as LuaResumeResult,
+    Scope as LuaScope, StateStatus as LuaStateStatus, Strict as LuaStrict, String as LuaString,
+    Table as LuaTable, TablePairs as LuaTablePairs, TableSequence as LuaTableSequence,
+    TableTuple as LuaTableTuple, TaskGroup as LuaTaskGroup, Thread as LuaThread,
+    ThreadSpan as LuaThreadSpan, ThreadStatus as LuaThreadStatus, ToLua, ToLuaMulti,
+    TypedTable as LuaTypedTable, UserData as LuaUserData, UserDataMethods as LuaUserDataMethods,
+    UserDataTrait as LuaUserDataTrait, Value as LuaValue, Varargs as LuaVarargs,
+    WatchdogAction as LuaWatchdogAction, WatchdogConfig as LuaWatchdogConfig,
+    WatchdogEvent as LuaWatchdogEvent,
 };
+
+#[cfg(feature = "collections")]
+pub use crate::{SharedMap as LuaSharedMap, SharedVec as LuaSharedVec};
+
+#[cfg(feature = "net")]
+pub use crate::NetPolicy as LuaNetPolicy;
+
+#[cfg(feature = "process")]
+pub use crate::ProcessPolicy as LuaProcessPolicy;
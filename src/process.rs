@@ -0,0 +1,200 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::string::String as StdString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::string::String;
+use crate::table::Table;
+use crate::value::{MultiValue, Value};
+
+type ArgValidator = Arc<dyn Fn(&str, &[StdString]) -> bool + Send + Sync>;
+
+/// Rules applied to processes started through the module created with
+/// [`Context::create_process_module`].
+///
+/// The default policy allows nothing.
+///
+/// [`Context::create_process_module`]: struct.Context.html#method.create_process_module
+#[derive(Clone, Default)]
+pub struct ProcessPolicy {
+    /// Programs scripts may run.  The program given by a script must be exactly one of these, so
+    /// listing absolute paths avoids depending on `PATH`.
+    pub allowed_programs: Vec<StdString>,
+    /// Called with the program and its arguments before starting it, the process is only started
+    /// if this returns `true`.
+    pub validate_args: Option<ArgValidator>,
+    /// Maximum number of bytes read from each of the process' stdout and stderr.  The process is
+    /// killed if it writes more.
+    pub max_output_size: Option<usize>,
+    /// The longest a process may run before it is killed.
+    pub timeout: Option<Duration>,
+}
+
+impl fmt::Debug for ProcessPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ProcessPolicy")
+            .field("allowed_programs", &self.allowed_programs)
+            .field("validate_args", &self.validate_args.as_ref().map(|_| ".."))
+            .field("max_output_size", &self.max_output_size)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+pub(crate) fn create_process_module<'lua>(
+    lua: Context<'lua>,
+    policy: ProcessPolicy,
+) -> Result<Table<'lua>> {
+    let module = lua.create_table()?;
+    module.set(
+        "run",
+        lua.create_function(
+            move |lua, (program, args, stdin): (StdString, Option<Vec<StdString>>, Option<String>)| {
+                let args = args.unwrap_or_default();
+                check_policy(&policy, &program, &args)?;
+                let output = run(
+                    &policy,
+                    &program,
+                    &args,
+                    stdin.as_ref().map(|s| s.as_bytes()).unwrap_or_default(),
+                )?;
+                Ok(MultiValue::from_vec(vec![
+                    match output.status.code() {
+                        Some(code) => Value::Integer(code.into()),
+                        None => Value::Nil,
+                    },
+                    Value::String(lua.create_string(&output.stdout)?),
+                    Value::String(lua.create_string(&output.stderr)?),
+                ]))
+            },
+        )?,
+    )?;
+    Ok(module)
+}
+
+fn check_policy(policy: &ProcessPolicy, program: &str, args: &[StdString]) -> Result<()> {
+    if !policy.allowed_programs.iter().any(|p| p == program) {
+        return Err(Error::RuntimeError(format!(
+            "running '{}' is not allowed",
+            program
+        )));
+    }
+    if let Some(validate_args) = &policy.validate_args {
+        if !validate_args(program, args) {
+            return Err(Error::RuntimeError(format!(
+                "arguments to '{}' rejected by policy",
+                program
+            )));
+        }
+    }
+    Ok(())
+}
+
+struct Output {
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+fn run(policy: &ProcessPolicy, program: &str, args: &[StdString], stdin: &[u8]) -> Result<Output> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::RuntimeError(format!("cannot run '{}': {}", program, e)))?;
+
+    let stdin_data = stdin.to_vec();
+    let mut stdin = child.stdin.take();
+    let writer = thread::spawn(move || {
+        if let Some(stdin) = &mut stdin {
+            // The process may exit without reading its input, which is not an error.
+            let _ = stdin.write_all(&stdin_data);
+        }
+    });
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let stdout = read_capped(child.stdout.take(), policy.max_output_size, &exceeded);
+    let stderr = read_capped(child.stderr.take(), policy.max_output_size, &exceeded);
+
+    let status = wait(&mut child, policy.timeout, &exceeded, program);
+    let _ = writer.join();
+    let output = Output {
+        status: status?,
+        stdout: join_reader(stdout),
+        stderr: join_reader(stderr),
+    };
+    if exceeded.load(Ordering::SeqCst) {
+        return Err(Error::RuntimeError(format!(
+            "output of '{}' exceeded the limit",
+            program
+        )));
+    }
+    Ok(output)
+}
+
+// Waits for the process to exit, killing it once the timeout has passed or its output has grown
+// past the limit.
+fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    exceeded: &AtomicBool,
+    program: &str,
+) -> Result<ExitStatus> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) => {}
+            Err(err) => return Err(Error::external(err)),
+        }
+        let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if timed_out || exceeded.load(Ordering::SeqCst) {
+            let _ = child.kill();
+            let status = child.wait().map_err(Error::external)?;
+            if timed_out {
+                return Err(Error::RuntimeError(format!("'{}' timed out", program)));
+            }
+            return Ok(status);
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+type Reader = Option<JoinHandle<Vec<u8>>>;
+
+// Reads a pipe on another thread, setting `exceeded` and stopping if more than `limit` bytes are
+// written to it.
+fn read_capped<R: 'static + Read + Send>(
+    pipe: Option<R>,
+    limit: Option<usize>,
+    exceeded: &Arc<AtomicBool>,
+) -> Reader {
+    let exceeded = exceeded.clone();
+    pipe.map(|pipe| {
+        thread::spawn(move || {
+            let limit = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+            let mut data = Vec::new();
+            let _ = pipe.take(limit).read_to_end(&mut data);
+            if data.len() as u64 == limit {
+                exceeded.store(true, Ordering::SeqCst);
+            }
+            data
+        })
+    })
+}
+
+fn join_reader(reader: Reader) -> Vec<u8> {
+    match reader {
+        Some(reader) => reader
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+        None => Vec::new(),
+    }
+}
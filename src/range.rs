@@ -0,0 +1,174 @@
+use std::ops;
+
+use crate::error::{Error, Result};
+use crate::types::Integer;
+use crate::userdata::{MetaMethod, UserData, UserDataMethods};
+use crate::value::Value;
+
+/// A range of integers which scripts iterate and index without it being turned into a table.
+///
+/// The range goes from `start` to `stop` inclusive in steps of `step`, like the numeric `for` loop
+/// of Lua.  Passed to Lua, it is a userdata which can be iterated directly with `for i in range do`
+/// as well as with `ipairs`, and supports `#range` and `range[i]` for the `i`-th value.  It holds
+/// only its bounds, so it costs the same however many values it has.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Integer, IntegerRange, Lua, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let range = lua_context.create_function(
+///     |_, (start, stop, step): (Integer, Integer, Option<Integer>)| {
+///         IntegerRange::new(start, stop, step.unwrap_or(1))
+///     },
+/// )?;
+/// lua_context.globals().set("range", range)?;
+///
+/// let sum = lua_context
+///     .load(
+///         r#"
+///             local sum = 0
+///             for i in range(1, 1000000, 2) do sum = sum + i end
+///             return sum
+///         "#,
+///     )
+///     .eval::<Integer>()?;
+/// assert_eq!(sum, 250_000_000_000);
+/// assert_eq!(lua_context.load("#range(10, 1, -3)").eval::<Integer>()?, 4);
+/// assert_eq!(lua_context.load("range(10, 1, -3)[2]").eval::<Integer>()?, 7);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IntegerRange {
+    start: Integer,
+    stop: Integer,
+    step: Integer,
+}
+
+impl IntegerRange {
+    /// Creates the range from `start` to `stop` inclusive in steps of `step`, which may be
+    /// negative.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RuntimeError` if `step` is zero.
+    pub fn new(start: Integer, stop: Integer, step: Integer) -> Result<IntegerRange> {
+        if step == 0 {
+            return Err(Error::RuntimeError("range step is zero".to_owned()));
+        }
+        Ok(IntegerRange { start, stop, step })
+    }
+
+    /// Returns the first value of the range.
+    pub fn start(&self) -> Integer {
+        self.start
+    }
+
+    /// Returns the bound of the range, which is its last value if the step reaches it.
+    pub fn stop(&self) -> Integer {
+        self.stop
+    }
+
+    /// Returns the difference between consecutive values of the range.
+    pub fn step(&self) -> Integer {
+        self.step
+    }
+
+    /// Returns the number of values in the range, saturating at `Integer::MAX`.
+    pub fn len(&self) -> Integer {
+        let (start, stop, step) = (
+            i128::from(self.start),
+            i128::from(self.stop),
+            i128::from(self.step),
+        );
+        if (step > 0 && start > stop) || (step < 0 && start < stop) {
+            return 0;
+        }
+        let len = (stop - start) / step + 1;
+        len.min(i128::from(Integer::MAX)) as Integer
+    }
+
+    /// Returns true if the range has no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the `n`-th value of the range, counting from 1 as Lua does.
+    pub fn get(&self, n: Integer) -> Option<Integer> {
+        if n >= 1 && n <= self.len() {
+            // Cannot overflow, the value lies between `start` and `stop`.
+            let value = i128::from(self.start) + i128::from(n - 1) * i128::from(self.step);
+            Some(value as Integer)
+        } else {
+            None
+        }
+    }
+
+    // Returns the value following `value`, if it is still in the range.
+    fn next(&self, value: Integer) -> Option<Integer> {
+        let next = value.checked_add(self.step)?;
+        if (self.step > 0 && next <= self.stop) || (self.step < 0 && next >= self.stop) {
+            Some(next)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<ops::Range<Integer>> for IntegerRange {
+    fn from(range: ops::Range<Integer>) -> IntegerRange {
+        if range.end == Integer::MIN {
+            // An empty range whose bound cannot be made inclusive.
+            IntegerRange {
+                start: 0,
+                stop: -1,
+                step: 1,
+            }
+        } else {
+            IntegerRange {
+                start: range.start,
+                stop: range.end - 1,
+                step: 1,
+            }
+        }
+    }
+}
+
+impl From<ops::RangeInclusive<Integer>> for IntegerRange {
+    fn from(range: ops::RangeInclusive<Integer>) -> IntegerRange {
+        IntegerRange {
+            start: *range.start(),
+            stop: *range.end(),
+            step: 1,
+        }
+    }
+}
+
+impl UserData for IntegerRange {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Makes the range its own iterator function in a generic `for`.
+        methods.add_meta_method(
+            MetaMethod::Call,
+            |_, this, (_, previous): (Value, Option<Integer>)| {
+                Ok(match previous {
+                    None if !this.is_empty() => Some(this.start),
+                    None => None,
+                    Some(previous) => this.next(previous),
+                })
+            },
+        );
+        methods.add_meta_method(MetaMethod::Index, |_, this, key: Value| {
+            Ok(match key {
+                Value::Integer(n) => this.get(n),
+                Value::Number(n) if n.fract() == 0.0 && n.abs() < Integer::MAX as f64 => {
+                    this.get(n as Integer)
+                }
+                _ => None,
+            })
+        });
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.len()));
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::result::Result as StdResult;
+use std::string::String as StdString;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::introspect;
+use crate::plain::PlainValue;
+use crate::sync::Mutex;
+use crate::table::Table;
+use crate::types::{Integer, Number};
+use crate::value::MultiValue;
+
+const MAGIC: &[u8] = b"rlua-recording\x01";
+
+/// The calls to host functions made during a session, recorded with [`Lua::start_recording`] and
+/// replayed with [`Context::create_replay_functions`].
+///
+/// A recording holds the calls to the Rust functions tagged with [`Context::set_call_logging`], in
+/// the order they were made, with their arguments printed as in [`LoggedCall`] and their results
+/// copied out of Lua.  Only plain data (nil, booleans, numbers, strings and tables of those) can be
+/// copied, so a call whose results hold anything else is recorded as failing with an error saying
+/// so.
+///
+/// Recordings can be saved with [`to_bytes`] and loaded again with [`from_bytes`], which makes it
+/// possible to reproduce a bug reported by a user without access to their host.
+///
+/// [`Lua::start_recording`]: struct.Lua.html#method.start_recording
+/// [`Context::create_replay_functions`]: struct.Context.html#method.create_replay_functions
+/// [`Context::set_call_logging`]: struct.Context.html#method.set_call_logging
+/// [`LoggedCall`]: struct.LoggedCall.html
+/// [`to_bytes`]: #method.to_bytes
+/// [`from_bytes`]: #method.from_bytes
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    calls: Vec<RecordedCall>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RecordedCall {
+    function: StdString,
+    arguments: StdString,
+    results: StdResult<Vec<PlainValue>, StdString>,
+}
+
+impl RecordedCall {
+    pub(crate) fn new<'lua>(
+        function: StdString,
+        arguments: StdString,
+        results: &Result<MultiValue<'lua>>,
+    ) -> RecordedCall {
+        let results = match results {
+            Ok(results) => results
+                .iter()
+                .map(|value| PlainValue::from_value(value.clone()))
+                .collect::<Result<Vec<_>>>()
+                .map_err(|err| format!("results cannot be replayed: {}", err)),
+            Err(Error::RuntimeError(message)) => Err(message.clone()),
+            Err(err) => Err(err.to_string()),
+        };
+        RecordedCall {
+            function,
+            arguments,
+            results,
+        }
+    }
+}
+
+impl Recording {
+    pub(crate) fn new(calls: Vec<RecordedCall>) -> Recording {
+        Recording { calls }
+    }
+
+    /// Returns the number of recorded calls.
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Returns true if no calls were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Returns the names of the recorded functions, without duplicates, in the order they were
+    /// first called.
+    pub fn functions(&self) -> Vec<&str> {
+        let mut functions: Vec<&str> = Vec::new();
+        for call in &self.calls {
+            if !functions.contains(&call.function.as_str()) {
+                functions.push(&call.function);
+            }
+        }
+        functions
+    }
+
+    /// Encodes the recording into bytes which `from_bytes` accepts.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        write_len(&mut bytes, self.calls.len());
+        for call in &self.calls {
+            write_bytes(&mut bytes, call.function.as_bytes());
+            write_bytes(&mut bytes, call.arguments.as_bytes());
+            match &call.results {
+                Ok(results) => {
+                    bytes.push(1);
+                    write_len(&mut bytes, results.len());
+                    for value in results {
+                        write_value(&mut bytes, value);
+                    }
+                }
+                Err(message) => {
+                    bytes.push(0);
+                    write_bytes(&mut bytes, message.as_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decodes a recording encoded by `to_bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RuntimeError` if `bytes` is not a valid recording.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Recording> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid());
+        }
+        let mut calls = Vec::new();
+        for _ in 0..reader.len()? {
+            let function = reader.string()?;
+            let arguments = reader.string()?;
+            let results = match reader.byte()? {
+                1 => Ok((0..reader.len()?)
+                    .map(|_| reader.value())
+                    .collect::<Result<_>>()?),
+                0 => Err(reader.string()?),
+                _ => return Err(invalid()),
+            };
+            calls.push(RecordedCall {
+                function,
+                arguments,
+                results,
+            });
+        }
+        if !reader.0.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Recording { calls })
+    }
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_len(bytes, data.len());
+    bytes.extend_from_slice(data);
+}
+
+fn write_value(bytes: &mut Vec<u8>, value: &PlainValue) {
+    match value {
+        PlainValue::Nil => bytes.push(0),
+        PlainValue::Boolean(false) => bytes.push(1),
+        PlainValue::Boolean(true) => bytes.push(2),
+        PlainValue::Integer(i) => {
+            bytes.push(3);
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        PlainValue::Number(n) => {
+            bytes.push(4);
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        PlainValue::String(s) => {
+            bytes.push(5);
+            write_bytes(bytes, s);
+        }
+        PlainValue::Table(entries) => {
+            bytes.push(6);
+            write_len(bytes, entries.len());
+            for (k, v) in entries {
+                write_value(bytes, k);
+                write_value(bytes, v);
+            }
+        }
+    }
+}
+
+fn invalid() -> Error {
+    Error::RuntimeError("invalid recording".to_owned())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.0.len() {
+            return Err(invalid());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn eight(&mut self) -> Result<[u8; 8]> {
+        Ok(self.take(8)?.try_into().unwrap())
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        let len = u64::from_le_bytes(self.eight()?);
+        // Every entry takes at least a byte, which rejects lengths that would overflow.
+        if len > self.0.len() as u64 {
+            return Err(invalid());
+        }
+        Ok(len as usize)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.len()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<StdString> {
+        StdString::from_utf8(self.bytes()?).map_err(|_| invalid())
+    }
+
+    fn value(&mut self) -> Result<PlainValue> {
+        Ok(match self.byte()? {
+            0 => PlainValue::Nil,
+            1 => PlainValue::Boolean(false),
+            2 => PlainValue::Boolean(true),
+            3 => PlainValue::Integer(Integer::from_le_bytes(self.eight()?)),
+            4 => PlainValue::Number(Number::from_le_bytes(self.eight()?)),
+            5 => PlainValue::String(self.bytes()?),
+            6 => PlainValue::Table(
+                (0..self.len()?)
+                    .map(|_| Ok((self.value()?, self.value()?)))
+                    .collect::<Result<_>>()?,
+            ),
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+pub(crate) fn create_replay_functions<'lua>(
+    lua: Context<'lua>,
+    recording: Recording,
+) -> Result<Table<'lua>> {
+    let functions = lua.create_table()?;
+    let names: Vec<StdString> = recording
+        .functions()
+        .into_iter()
+        .map(|name| name.to_owned())
+        .collect();
+    let calls = Arc::new(Mutex::new(VecDeque::from(recording.calls)));
+    for name in names {
+        let calls = calls.clone();
+        let key = name.clone();
+        let stub = lua.create_function(move |lua, args: MultiValue| {
+            let arguments = introspect::format_values(&args);
+            let call = {
+                let mut calls = calls.lock();
+                match calls.front() {
+                    Some(call) if call.function == name && call.arguments == arguments => {}
+                    Some(call) => {
+                        return Err(Error::RuntimeError(format!(
+                            "replay diverged: expected {}({}), got {}({})",
+                            call.function, call.arguments, name, arguments
+                        )))
+                    }
+                    None => {
+                        return Err(Error::RuntimeError(format!(
+                            "replay diverged: no recorded call left for {}({})",
+                            name, arguments
+                        )))
+                    }
+                }
+                calls.pop_front().unwrap()
+            };
+            match call.results {
+                Ok(results) => results
+                    .into_iter()
+                    .map(|value| value.into_value(lua))
+                    .collect::<Result<MultiValue>>(),
+                Err(message) => Err(Error::RuntimeError(message)),
+            }
+        })?;
+        functions.raw_set(key, stub)?;
+    }
+    Ok(functions)
+}
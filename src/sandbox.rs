@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::string::String as StdString;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::function::Function;
+use crate::lua::extra_data;
+use crate::string::String;
+use crate::sync::Mutex;
+use crate::table::Table;
+use crate::util::{assert_stack, StackGuard};
+use crate::value::{MultiValue, Value};
+
+/// Rules applied to code loaded through a function created with [`Context::create_sandboxed_load`].
+///
+/// [`Context::create_sandboxed_load`]: struct.Context.html#method.create_sandboxed_load
+#[derive(Clone, Debug, Default)]
+pub struct LoadPolicy {
+    /// Maximum size in bytes of a single loaded chunk.
+    ///
+    /// Chunks read from a reader function are checked as each piece arrives.  Without a limit they
+    /// are still held to [`LoadPolicy::READER_SOURCE_LIMIT`], as the pieces are collected outside
+    /// of the Lua memory limit.
+    ///
+    /// [`LoadPolicy::READER_SOURCE_LIMIT`]: #associatedconstant.READER_SOURCE_LIMIT
+    pub max_source_size: Option<usize>,
+    /// A budget of source bytes which every loaded chunk is charged against.
+    pub quota: Option<LoadQuota>,
+}
+
+impl LoadPolicy {
+    /// The size limit for chunks read from a reader function when `max_source_size` is `None`.
+    pub const READER_SOURCE_LIMIT: usize = 64 * 1024 * 1024;
+}
+
+/// A shared budget of source bytes that sandboxed `load` functions may compile.
+///
+/// Cloning a `LoadQuota` gives a handle to the same budget, so a single quota can be shared by every
+/// sandbox belonging to one tenant, even across `Lua` instances and threads.
+#[derive(Clone, Debug)]
+pub struct LoadQuota(Arc<Mutex<usize>>);
+
+impl LoadQuota {
+    /// Creates a quota allowing up to `bytes` bytes of source to be loaded.
+    pub fn new(bytes: usize) -> LoadQuota {
+        LoadQuota(Arc::new(Mutex::new(bytes)))
+    }
+
+    /// Returns the number of source bytes which may still be loaded.
+    pub fn remaining(&self) -> usize {
+        *self.0.lock()
+    }
+
+    /// Adds `bytes` to the remaining budget.
+    pub fn refill(&self, bytes: usize) {
+        let mut remaining = self.0.lock();
+        *remaining = remaining.saturating_add(bytes);
+    }
+
+    // Takes `bytes` from the budget, or returns false and leaves it untouched if too few remain.
+    fn charge(&self, bytes: usize) -> bool {
+        let mut remaining = self.0.lock();
+        if *remaining >= bytes {
+            *remaining -= bytes;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub(crate) fn create_sandboxed_load<'lua>(
+    lua: Context<'lua>,
+    env: Table<'lua>,
+    policy: LoadPolicy,
+) -> Result<Function<'lua>> {
+    // The environment is bound as an upvalue rather than kept in the registry, as it usually holds
+    // the `load` function itself and the cycle must stay collectable.
+    lua.create_function(
+        // A fourth argument requesting another environment is ignored.
+        move |lua, (env, chunk, name, mode): (Table, Value, Option<String>, Option<String>)| {
+            Ok(match load(lua, &policy, env, chunk, name, mode)? {
+                Ok(function) => MultiValue::from_vec(vec![Value::Function(function)]),
+                Err(message) => MultiValue::from_vec(vec![
+                    Value::Nil,
+                    Value::String(lua.create_string(&message)?),
+                ]),
+            })
+        },
+    )?
+    .bind(env)
+}
+
+// Follows the conventions of the standard `load`: problems with the loaded code are returned as an
+// error message rather than raised, only errors raised by a reader function are propagated.
+fn load<'lua>(
+    lua: Context<'lua>,
+    policy: &LoadPolicy,
+    env: Table<'lua>,
+    chunk: Value<'lua>,
+    name: Option<String<'lua>>,
+    mode: Option<String<'lua>>,
+) -> Result<::std::result::Result<Function<'lua>, StdString>> {
+    let (source, default_name) = match chunk {
+        Value::String(s) => {
+            let source = s.as_bytes().to_vec();
+            if let Err(message) = check_size(source.len(), policy.max_source_size) {
+                return Ok(Err(message));
+            }
+            (source.clone(), source)
+        }
+        Value::Function(reader) => {
+            let mut source = Vec::new();
+            loop {
+                let piece = match reader.call::<_, Value>(())? {
+                    Value::Nil => break,
+                    Value::String(piece) if piece.as_bytes().is_empty() => break,
+                    Value::String(piece) => piece,
+                    _ => return Ok(Err("reader function must return a string".to_owned())),
+                };
+                // Checked before the piece is kept, so a reader can never grow `source` past the
+                // limits.
+                let size = source.len().saturating_add(piece.as_bytes().len());
+                let limit = policy
+                    .max_source_size
+                    .unwrap_or(LoadPolicy::READER_SOURCE_LIMIT);
+                if let Err(message) = check_size(size, Some(limit)) {
+                    return Ok(Err(message));
+                }
+                if let Some(quota) = &policy.quota {
+                    if size > quota.remaining() {
+                        return Ok(Err("load quota exceeded".to_owned()));
+                    }
+                }
+                source.extend_from_slice(piece.as_bytes());
+            }
+            (source, b"=(load)".to_vec())
+        }
+        v => {
+            return Err(Error::FromLuaConversionError {
+                from: v.type_name(),
+                to: "chunk",
+                message: Some("expected a string or a reader function".to_owned()),
+            })
+        }
+    };
+
+    // Binary chunks bypass the verification done by the parser, so they are never allowed.
+    let mode = match &mode {
+        Some(mode) => mode.to_str()?,
+        None => "bt",
+    };
+    if source.first() == Some(&0x1b) {
+        return Ok(Err(format!(
+            "attempt to load a binary chunk (mode is '{}')",
+            mode
+        )));
+    }
+    if !mode.contains('t') {
+        return Ok(Err(format!(
+            "attempt to load a text chunk (mode is '{}')",
+            mode
+        )));
+    }
+
+    if let Some(quota) = &policy.quota {
+        if !quota.charge(source.len()) {
+            return Ok(Err("load quota exceeded".to_owned()));
+        }
+    }
+
+    let name = match &name {
+        Some(name) => name.as_bytes(),
+        None => &default_name[..],
+    };
+    let chunk = match lua.load(&source).set_name(name) {
+        Ok(chunk) => chunk,
+        Err(err) => return Ok(Err(err.to_string())),
+    };
+    match chunk.set_environment(env)?.into_function() {
+        Ok(function) => Ok(Ok(function)),
+        Err(Error::SyntaxError { message, .. }) => Ok(Err(message)),
+        Err(err) => Err(err),
+    }
+}
+
+fn check_size(size: usize, limit: Option<usize>) -> ::std::result::Result<(), StdString> {
+    match limit {
+        Some(limit) if size > limit => Err(format!(
+            "chunk too large ({} bytes, limit is {})",
+            size, limit
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Supplies the environment variables seen by scripts through the `os.getenv` of
+/// [`Context::create_sandboxed_os`].
+///
+/// Set with [`Lua::set_env_provider`].
+///
+/// [`Context::create_sandboxed_os`]: struct.Context.html#method.create_sandboxed_os
+/// [`Lua::set_env_provider`]: struct.Lua.html#method.set_env_provider
+pub trait EnvProvider: Send {
+    /// Returns the value of the environment variable `name`, if it is set.
+    fn var(&self, name: &str) -> Option<StdString>;
+}
+
+impl EnvProvider for HashMap<StdString, StdString> {
+    fn var(&self, name: &str) -> Option<StdString> {
+        self.get(name).cloned()
+    }
+}
+
+/// Supplies the time seen by scripts through the `os` functions of
+/// [`Context::create_sandboxed_os`].
+///
+/// Set with [`Lua::set_clock`].
+///
+/// [`Context::create_sandboxed_os`]: struct.Context.html#method.create_sandboxed_os
+/// [`Lua::set_clock`]: struct.Lua.html#method.set_clock
+pub trait Clock: Send {
+    /// The current wall clock time, used by `os.time` and `os.date`.
+    fn now(&self) -> SystemTime;
+
+    /// The processor time used by the program, returned by `os.clock`.
+    fn cpu_time(&self) -> Duration;
+}
+
+pub(crate) fn create_sandboxed_os<'lua>(lua: Context<'lua>) -> Result<Table<'lua>> {
+    // The functions of the standard library which do not depend on the environment or the current
+    // time are reused, without making the library itself available.
+    let os: Table = unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+        ffi::lua_pushcfunction(lua.state, ffi::luaopen_os);
+        Function(lua.pop_ref())
+    }
+    .call(())?;
+    let os_time = lua.create_registry_value(os.get::<_, Function>("time")?)?;
+    let os_date = lua.create_registry_value(os.get::<_, Function>("date")?)?;
+    let os_clock = lua.create_registry_value(os.get::<_, Function>("clock")?)?;
+
+    let sandboxed = lua.create_table()?;
+    sandboxed.set("difftime", os.get::<_, Function>("difftime")?)?;
+    sandboxed.set(
+        "getenv",
+        lua.create_function(|lua, name: StdString| {
+            Ok(unsafe { (*extra_data(lua.state)).env_provider.as_ref() }
+                .and_then(|provider| provider.var(&name)))
+        })?,
+    )?;
+    sandboxed.set(
+        "time",
+        lua.create_function(move |lua, date: Option<Table>| match date {
+            // Converting a date to a time does not depend on the current time.
+            Some(date) => lua
+                .registry_value::<Function>(&os_time)?
+                .call::<_, Value>(date),
+            None => Ok(Value::Integer(now(lua))),
+        })?,
+    )?;
+    sandboxed.set(
+        "date",
+        lua.create_function(
+            move |lua, (format, time): (Option<String>, Option<Value>)| {
+                let time = time.unwrap_or_else(|| Value::Integer(now(lua)));
+                lua.registry_value::<Function>(&os_date)?
+                    .call::<_, Value>((format, time))
+            },
+        )?,
+    )?;
+    sandboxed.set(
+        "clock",
+        lua.create_function(move |lua, ()| {
+            match unsafe { (*extra_data(lua.state)).clock.as_ref() } {
+                Some(clock) => Ok(Value::Number(clock.cpu_time().as_secs_f64())),
+                None => lua
+                    .registry_value::<Function>(&os_clock)?
+                    .call::<_, Value>(()),
+            }
+        })?,
+    )?;
+    Ok(sandboxed)
+}
+
+// The current time in seconds since the epoch, from the clock set with `Lua::set_clock`.
+fn now(lua: Context) -> i64 {
+    let now = match unsafe { (*extra_data(lua.state)).clock.as_ref() } {
+        Some(clock) => clock.now(),
+        None => SystemTime::now(),
+    };
+    match now.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+    }
+}
@@ -1,4 +1,4 @@
-use std::any::Any;
+use std::any::{self, Any};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::marker::PhantomData;
@@ -10,14 +10,17 @@ use crate::context::Context;
 use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
+use crate::lua::extra_data;
 use crate::markers::Invariant;
+use crate::string::String;
+use crate::table::Table;
 use crate::types::{Callback, LuaRef};
 use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
 use crate::util::{
-    assert_stack, init_userdata_metatable, protect_lua_closure, push_string, push_userdata,
-    take_userdata, StackGuard,
+    assert_stack, init_userdata_fields, init_userdata_metatable, is_taken_userdata,
+    protect_lua_closure, push_string, push_userdata, take_userdata, StackGuard,
 };
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti, Value};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti, Value};
 
 /// Constructed by the [`Context::scope`] method, allows temporarily passing to Lua userdata that is
 /// !Send, and callbacks that are !Send and not 'static.
@@ -28,6 +31,7 @@ use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti, Value};
 pub struct Scope<'lua, 'scope> {
     lua: Context<'lua>,
     destructors: RefCell<Vec<(LuaRef<'lua>, fn(LuaRef<'lua>) -> Box<Any>)>>,
+    nonstatic_data: RefCell<Vec<Rc<dyn 'scope + Keep>>>,
     _scope_invariant: Invariant<'scope>,
 }
 
@@ -36,6 +40,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         Scope {
             lua,
             destructors: RefCell::new(Vec::new()),
+            nonstatic_data: RefCell::new(Vec::new()),
             _scope_invariant: PhantomData,
         }
     }
@@ -112,8 +117,13 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             let u = self.lua.make_userdata(data)?;
             self.destructors.borrow_mut().push((u.0.clone(), |u| {
                 let state = u.lua.state;
-                assert_stack(state, 1);
+                assert_stack(state, 3);
                 u.lua.push_ref(&u);
+                // The value may already have been taken with `AnyUserData::take`.
+                if is_taken_userdata(state, -1) {
+                    ffi::lua_pop(state, 1);
+                    return Box::new(());
+                }
                 Box::new(take_userdata::<RefCell<T>>(state))
             }));
             Ok(u)
@@ -133,13 +143,17 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
     /// The main limitation that comes from using non-'static userdata is that the produced userdata
     /// will no longer have a `TypeId` associated with it, becuase `TypeId` can only work for
     /// 'static types.  This means that it is impossible, once the userdata is created, to get a
-    /// reference to it back *out* of an `AnyUserData` handle.  This also implies that the
+    /// reference to it back *out* of an `AnyUserData` handle with [`AnyUserData::borrow`].  The
     /// "function" type methods that can be added via [`UserDataMethods`] (the ones that accept
-    /// `AnyUserData` as a first parameter) are vastly less useful.  Also, there is no way to re-use
-    /// a single metatable for multiple non-'static types, so there is a higher cost associated with
-    /// creating the userdata metatable each time a new userdata is created.
+    /// `AnyUserData` as a first parameter) can instead use the unsafe
+    /// [`AnyUserData::borrow_scoped`], which relies on the caller to name the right type.  Also,
+    /// there is no way to re-use a single metatable for multiple non-'static types, so there is a
+    /// higher cost associated with creating the userdata metatable each time a new userdata is
+    /// created.
     ///
     /// [`create_static_userdata`]: #method.create_static_userdata
+    /// [`AnyUserData::borrow`]: struct.AnyUserData.html#method.borrow
+    /// [`AnyUserData::borrow_scoped`]: struct.AnyUserData.html#method.borrow_scoped
     /// [`Context::create_userdata`]: struct.Context.html#method.create_userdata
     /// [`Context::scope`]: struct.Context.html#method.scope
     /// [`UserDataMethods`]: trait.UserDataMethods.html
@@ -157,6 +171,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         fn wrap_method<'scope, 'lua, 'callback: 'scope, T: 'scope>(
             scope: &Scope<'lua, 'scope>,
             data: Rc<RefCell<T>>,
+            ud_ptr: *const c_void,
             method: NonStaticMethod<'callback, T>,
         ) -> Result<Function<'lua>> {
             // On methods that actually receive the userdata, we fake a type check on the passed in
@@ -166,18 +181,9 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             // with a type mismatch, but here without this check would proceed as though you had
             // called the method on the original value (since we otherwise completely ignore the
             // first argument).
-            let check_data = data.clone();
-            let check_ud_type = move |lua: Context<'callback>, value| {
-                if let Some(value) = value {
-                    if let Value::UserData(u) = value {
-                        unsafe {
-                            assert_stack(lua.state, 1);
-                            lua.push_ref(&u.0);
-                            ffi::lua_getuservalue(lua.state, -1);
-                            return ffi::lua_touserdata(lua.state, -1)
-                                == check_data.as_ptr() as *mut c_void;
-                        }
-                    }
+            let check_ud_type = move |_: Context<'callback>, value| {
+                if let Some(Value::UserData(u)) = value {
+                    return u.to_pointer() == ud_ptr;
                 }
 
                 false
@@ -235,11 +241,11 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         unsafe {
             let lua = self.lua;
             let _sg = StackGuard::new(lua.state);
-            assert_stack(lua.state, 6);
+            assert_stack(lua.state, 10);
 
+            // The userdata is identified by its address, leaving its user value to the user.
             push_userdata(lua.state, ())?;
-            ffi::lua_pushlightuserdata(lua.state, data.as_ptr() as *mut c_void);
-            ffi::lua_setuservalue(lua.state, -2);
+            let ud_ptr = ffi::lua_touserdata(lua.state, -1);
 
             protect_lua_closure(lua.state, 0, 1, move |state| {
                 ffi::lua_newtable(state);
@@ -247,13 +253,41 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
 
             for (k, m) in ud_methods.meta_methods {
                 push_string(lua.state, k.name())?;
-                lua.push_value(Value::Function(wrap_method(self, data.clone(), m)?))?;
+                lua.push_value(Value::Function(wrap_method(self, data.clone(), ud_ptr, m)?))?;
 
                 protect_lua_closure(lua.state, 3, 1, |state| {
                     ffi::lua_rawset(state, -3);
                 })?;
             }
 
+            let has_getters = !ud_methods.field_getters.is_empty();
+            let has_setters = !ud_methods.field_setters.is_empty();
+            if has_getters || has_setters {
+                let getters = lua.create_table()?;
+                for (k, m) in ud_methods.field_getters {
+                    getters.raw_set(
+                        lua.create_string(&k)?,
+                        wrap_method(self, data.clone(), ud_ptr, m)?,
+                    )?;
+                }
+                let setters = lua.create_table()?;
+                for (k, m) in ud_methods.field_setters {
+                    setters.raw_set(
+                        lua.create_string(&k)?,
+                        wrap_method(self, data.clone(), ud_ptr, m)?,
+                    )?;
+                }
+                lua.push_ref(&getters.0);
+                lua.push_ref(&setters.0);
+                init_userdata_fields(
+                    lua.state,
+                    -3,
+                    if has_getters { Some(-2) } else { None },
+                    if has_setters { Some(-1) } else { None },
+                )?;
+                ffi::lua_pop(lua.state, 2);
+            }
+
             if ud_methods.methods.is_empty() {
                 init_userdata_metatable::<()>(lua.state, -1, None)?;
             } else {
@@ -262,7 +296,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                 })?;
                 for (k, m) in ud_methods.methods {
                     push_string(lua.state, &k)?;
-                    lua.push_value(Value::Function(wrap_method(self, data.clone(), m)?))?;
+                    lua.push_value(Value::Function(wrap_method(self, data.clone(), ud_ptr, m)?))?;
                     protect_lua_closure(lua.state, 3, 1, |state| {
                         ffi::lua_rawset(state, -3);
                     })?;
@@ -274,10 +308,84 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
 
             ffi::lua_setmetatable(lua.state, -2);
 
-            Ok(AnyUserData(lua.pop_ref()))
+            let ud = lua.pop_ref();
+            (*extra_data(lua.state))
+                .nonstatic_userdata
+                .insert(ud_ptr, NonStaticUserData::new::<T>(&data));
+            self.destructors.borrow_mut().push((ud.clone(), |ud| {
+                // Only unregisters the userdata, its data is dropped with `nonstatic_data`.
+                let state = ud.lua.state;
+                assert_stack(state, 2);
+                ud.lua.push_ref(&ud);
+                (*extra_data(state))
+                    .nonstatic_userdata
+                    .remove(&ffi::lua_touserdata(state, -1));
+                ffi::lua_pushnil(state);
+                ffi::lua_setuservalue(state, -2);
+                ffi::lua_pop(state, 1);
+                Box::new(())
+            }));
+            self.nonstatic_data.borrow_mut().push(data);
+
+            Ok(AnyUserData(ud))
         }
     }
 
+    /// Creates a table which is emptied on scope drop.
+    ///
+    /// This is a version of [`Context::create_table`] for temporary tables, such as arguments
+    /// built for a single call.  When the scope ends, all entries of the table and its metatable
+    /// are removed, even if Lua code kept a reference to it, so the values it held are released
+    /// right away instead of whenever the table itself is collected.  Userdata which were only
+    /// referenced by the table are then dropped by the next garbage collection cycle.
+    ///
+    /// [`Context::create_table`]: struct.Context.html#method.create_table
+    pub fn create_table(&self) -> Result<Table<'lua>> {
+        let table = self.lua.create_table()?;
+        self.destructors
+            .borrow_mut()
+            .push((table.0.clone(), |t| unsafe {
+                let state = t.lua.state;
+                assert_stack(state, 4);
+                t.lua.push_ref(&t);
+
+                // Removing existing entries while traversing a table is allowed and never allocates.
+                ffi::lua_pushnil(state);
+                while ffi::lua_next(state, -2) != 0 {
+                    ffi::lua_pop(state, 1);
+                    ffi::lua_pushvalue(state, -1);
+                    ffi::lua_pushnil(state);
+                    ffi::lua_rawset(state, -4);
+                }
+                ffi::lua_pushnil(state);
+                ffi::lua_setmetatable(state, -2);
+
+                ffi::lua_pop(state, 1);
+                Box::new(())
+            }));
+        Ok(table)
+    }
+
+    /// Creates a string whose reference is held by the scope until it is dropped.
+    ///
+    /// This is a version of [`Context::create_string`] for temporary strings.  Lua strings are
+    /// immutable, so unlike [`create_table`] the string stays usable after the scope ends; the
+    /// scope only releases its own reference, so the string can be collected once the handles
+    /// and Lua references to it are gone.
+    ///
+    /// [`Context::create_string`]: struct.Context.html#method.create_string
+    /// [`create_table`]: #method.create_table
+    pub fn create_string<S>(&self, s: &S) -> Result<String<'lua>>
+    where
+        S: ?Sized + AsRef<[u8]>,
+    {
+        let string = self.lua.create_string(s)?;
+        self.destructors
+            .borrow_mut()
+            .push((string.0.clone(), |_| Box::new(())));
+        Ok(string)
+    }
+
     // Unsafe, because the callback can improperly capture any value with 'callback scope, such as
     // improperly capturing an argument. Since the 'callback lifetime is chosen by the user and the
     // lifetime of the callback itself is 'scope (non-'static), the borrow checker will happily pick
@@ -324,6 +432,49 @@ impl<'lua, 'scope> Drop for Scope<'lua, 'scope> {
             .collect::<Vec<_>>();
 
         drop(to_drop);
+        self.nonstatic_data.get_mut().clear();
+    }
+}
+
+// Implemented by every type, so any value can be kept alive in `Scope::nonstatic_data`.
+trait Keep {}
+
+impl<T: ?Sized> Keep for T {}
+
+// Identifies the type of a userdata created by `Scope::create_nonstatic_userdata`, which has no
+// `TypeId`, and points to its `RefCell`.
+pub(crate) struct NonStaticUserData {
+    type_name: &'static str,
+    size: usize,
+    align: usize,
+    cell: *const c_void,
+}
+
+impl NonStaticUserData {
+    fn new<T>(cell: &RefCell<T>) -> NonStaticUserData {
+        NonStaticUserData {
+            type_name: any::type_name::<T>(),
+            size: mem::size_of::<T>(),
+            align: mem::align_of::<T>(),
+            cell: cell as *const RefCell<T> as *const c_void,
+        }
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    // Returns the `RefCell` if the userdata looks like it was created with type `T`.  Lifetimes
+    // cannot be checked, see `AnyUserData::borrow_scoped`.
+    pub(crate) fn cell<T>(&self) -> Option<*const RefCell<T>> {
+        if self.type_name == any::type_name::<T>()
+            && self.size == mem::size_of::<T>()
+            && self.align == mem::align_of::<T>()
+        {
+            Some(self.cell as *const RefCell<T>)
+        } else {
+            None
+        }
     }
 }
 
@@ -337,6 +488,8 @@ enum NonStaticMethod<'lua, T> {
 struct NonStaticUserDataMethods<'lua, T: UserData> {
     methods: HashMap<Vec<u8>, NonStaticMethod<'lua, T>>,
     meta_methods: HashMap<MetaMethod, NonStaticMethod<'lua, T>>,
+    field_getters: HashMap<Vec<u8>, NonStaticMethod<'lua, T>>,
+    field_setters: HashMap<Vec<u8>, NonStaticMethod<'lua, T>>,
 }
 
 impl<'lua, T: UserData> Default for NonStaticUserDataMethods<'lua, T> {
@@ -344,6 +497,8 @@ impl<'lua, T: UserData> Default for NonStaticUserDataMethods<'lua, T> {
         NonStaticUserDataMethods {
             methods: HashMap::new(),
             meta_methods: HashMap::new(),
+            field_getters: HashMap::new(),
+            field_setters: HashMap::new(),
         }
     }
 }
@@ -409,6 +564,63 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         );
     }
 
+    fn add_field_method_get<S, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        M: 'static + Send + Fn(Context<'lua>, &T) -> Result<R>,
+    {
+        self.field_getters.insert(
+            name.as_ref().to_vec(),
+            NonStaticMethod::Method(Box::new(move |lua, ud, _| {
+                method(lua, ud)?.to_lua_multi(lua)
+            })),
+        );
+    }
+
+    fn add_field_method_set<S, A, M>(&mut self, name: &S, mut method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        M: 'static + Send + FnMut(Context<'lua>, &mut T, A) -> Result<()>,
+    {
+        self.field_setters.insert(
+            name.as_ref().to_vec(),
+            NonStaticMethod::MethodMut(Box::new(move |lua, ud, args| {
+                method(lua, ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+            })),
+        );
+    }
+
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, AnyUserData<'lua>) -> Result<R>,
+    {
+        self.field_getters.insert(
+            name.as_ref().to_vec(),
+            NonStaticMethod::Function(Box::new(move |lua, args| {
+                function(lua, AnyUserData::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+            })),
+        );
+    }
+
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, mut function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, AnyUserData<'lua>, A) -> Result<()>,
+    {
+        self.field_setters.insert(
+            name.as_ref().to_vec(),
+            NonStaticMethod::FunctionMut(Box::new(move |lua, args| {
+                let (ud, value) = <(AnyUserData<'lua>, A)>::from_lua_multi(args, lua)?;
+                function(lua, ud, value)?.to_lua_multi(lua)
+            })),
+        );
+    }
+
     fn add_meta_method<A, R, M>(&mut self, meta: MetaMethod, method: M)
     where
         A: FromLuaMulti<'lua>,
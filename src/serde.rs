@@ -0,0 +1,722 @@
+//! Conversions between Lua values and Rust types implementing `serde`'s traits.
+//!
+//! Only available with the `serde` feature enabled.
+
+use std::fmt;
+use std::string::String as StdString;
+use std::vec::IntoIter;
+
+use ::serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use ::serde::ser::{self, Serialize};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::table::Table;
+use crate::types::Integer;
+use crate::value::Value;
+
+/// Converts any `T: Serialize` into a Lua value.
+///
+/// Structs and maps become tables with string (or other) keys, sequences and tuples become
+/// sequence tables, `None` and `()` become `nil`, and enums use the "externally tagged"
+/// representation: unit variants become the variant name, other variants become a table with the
+/// variant name as its only key.
+///
+/// Note that `None` inside a sequence leaves a hole in the resulting table.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result};
+/// # use serde_derive::Serialize;
+/// # fn main() -> Result<()> {
+/// #[derive(Serialize)]
+/// struct Spawn {
+///     name: String,
+///     position: (f64, f64),
+///     tags: Vec<String>,
+/// }
+///
+/// # Lua::new().context(|lua_context| {
+/// let spawn = Spawn {
+///     name: "goblin".to_owned(),
+///     position: (1.5, -2.0),
+///     tags: vec!["hostile".to_owned()],
+/// };
+/// lua_context.globals().set("spawn", rlua::to_value(lua_context, &spawn)?)?;
+/// lua_context.load(r#"
+///     assert(spawn.name == "goblin")
+///     assert(spawn.position[1] == 1.5)
+///     assert(spawn.tags[1] == "hostile")
+/// "#).exec()
+/// # })
+/// # }
+/// ```
+pub fn to_value<'lua, T: ?Sized + Serialize>(lua: Context<'lua>, value: &T) -> Result<Value<'lua>> {
+    value.serialize(Serializer { lua })
+}
+
+/// Converts a Lua value into any `T: DeserializeOwned`.
+///
+/// This accepts the representation produced by [`to_value`].  Tables are read as sequences or maps
+/// depending on what `T` expects, and floats with no fractional part are accepted for integer
+/// fields.
+///
+/// [`to_value`]: fn.to_value.html
+pub fn from_value<'lua, T: DeserializeOwned>(value: Value<'lua>) -> Result<T> {
+    T::deserialize(Deserializer { value })
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::ToLuaConversionError {
+            from: "Serialize",
+            to: "value",
+            message: Some(msg.to_string()),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::FromLuaConversionError {
+            from: "value",
+            to: "Deserialize",
+            message: Some(msg.to_string()),
+        }
+    }
+}
+
+struct Serializer<'lua> {
+    lua: Context<'lua>,
+}
+
+impl<'lua> Serializer<'lua> {
+    fn tagged(self, variant: &'static str, value: Value<'lua>) -> Result<Value<'lua>> {
+        let table = self.lua.create_table()?;
+        table.raw_set(variant, value)?;
+        Ok(Value::Table(table))
+    }
+}
+
+impl<'lua> ser::Serializer for Serializer<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    type SerializeSeq = SerializeSeq<'lua>;
+    type SerializeTuple = SerializeSeq<'lua>;
+    type SerializeTupleStruct = SerializeSeq<'lua>;
+    type SerializeTupleVariant = SerializeSeq<'lua>;
+    type SerializeMap = SerializeMap<'lua>;
+    type SerializeStruct = SerializeMap<'lua>;
+    type SerializeStructVariant = SerializeMap<'lua>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value<'lua>> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value<'lua>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value<'lua>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value<'lua>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value<'lua>> {
+        Ok(Value::Integer(v as Integer))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value<'lua>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value<'lua>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value<'lua>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value<'lua>> {
+        if v <= i64::MAX as u64 {
+            self.serialize_i64(v as i64)
+        } else {
+            self.serialize_f64(v as f64)
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value<'lua>> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value<'lua>> {
+        Ok(Value::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value<'lua>> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value<'lua>> {
+        Ok(Value::String(self.lua.create_string(v)?))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value<'lua>> {
+        Ok(Value::String(self.lua.create_string(v)?))
+    }
+
+    fn serialize_none(self) -> Result<Value<'lua>> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value<'lua>> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value<'lua>> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value<'lua>> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value<'lua>> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value<'lua>> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value<'lua>> {
+        let value = value.serialize(Serializer { lua: self.lua })?;
+        self.tagged(variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SerializeSeq<'lua>> {
+        Ok(SerializeSeq {
+            lua: self.lua,
+            table: self.lua.create_table()?,
+            len: 0,
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeSeq<'lua>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeSeq<'lua>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeSeq<'lua>> {
+        let mut seq = self.serialize_seq(Some(len))?;
+        seq.variant = Some(variant);
+        Ok(seq)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap<'lua>> {
+        Ok(SerializeMap {
+            lua: self.lua,
+            table: self.lua.create_table()?,
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap<'lua>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeMap<'lua>> {
+        let mut map = self.serialize_map(Some(len))?;
+        map.variant = Some(variant);
+        Ok(map)
+    }
+}
+
+struct SerializeSeq<'lua> {
+    lua: Context<'lua>,
+    table: Table<'lua>,
+    len: Integer,
+    variant: Option<&'static str>,
+}
+
+impl<'lua> SerializeSeq<'lua> {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.len += 1;
+        self.table
+            .raw_set(self.len, value.serialize(Serializer { lua: self.lua })?)
+    }
+
+    fn finish(self) -> Result<Value<'lua>> {
+        match self.variant {
+            Some(variant) => Serializer { lua: self.lua }.tagged(variant, Value::Table(self.table)),
+            None => Ok(Value::Table(self.table)),
+        }
+    }
+}
+
+impl<'lua> ser::SerializeSeq for SerializeSeq<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+impl<'lua> ser::SerializeTuple for SerializeSeq<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+impl<'lua> ser::SerializeTupleStruct for SerializeSeq<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+impl<'lua> ser::SerializeTupleVariant for SerializeSeq<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+struct SerializeMap<'lua> {
+    lua: Context<'lua>,
+    table: Table<'lua>,
+    key: Option<Value<'lua>>,
+    variant: Option<&'static str>,
+}
+
+impl<'lua> SerializeMap<'lua> {
+    fn finish(self) -> Result<Value<'lua>> {
+        match self.variant {
+            Some(variant) => Serializer { lua: self.lua }.tagged(variant, Value::Table(self.table)),
+            None => Ok(Value::Table(self.table)),
+        }
+    }
+}
+
+impl<'lua> ser::SerializeMap for SerializeMap<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(Serializer { lua: self.lua })?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = rlua_expect!(
+            self.key.take(),
+            "serialize_value called before serialize_key"
+        );
+        self.table
+            .raw_set(key, value.serialize(Serializer { lua: self.lua })?)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+impl<'lua> ser::SerializeStruct for SerializeMap<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.table
+            .raw_set(key, value.serialize(Serializer { lua: self.lua })?)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+impl<'lua> ser::SerializeStructVariant for SerializeMap<'lua> {
+    type Ok = Value<'lua>;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.table
+            .raw_set(key, value.serialize(Serializer { lua: self.lua })?)
+    }
+
+    fn end(self) -> Result<Value<'lua>> {
+        self.finish()
+    }
+}
+
+struct Deserializer<'lua> {
+    value: Value<'lua>,
+}
+
+impl<'lua> Deserializer<'lua> {
+    fn unsupported(&self) -> Error {
+        Error::FromLuaConversionError {
+            from: self.value.type_name(),
+            to: "Deserialize",
+            message: Some(
+                "only nil, booleans, numbers, strings and tables are supported".to_owned(),
+            ),
+        }
+    }
+
+    fn integer(&self) -> Option<i64> {
+        match self.value {
+            Value::Integer(i) => Some(i),
+            Value::Number(n) if n.fract() == 0.0 && n >= -(2f64.powi(63)) && n < 2f64.powi(63) => {
+                Some(n as i64)
+            }
+            _ => None,
+        }
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                match self.integer() {
+                    Some(i) => visitor.visit_i64(i),
+                    None => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'lua, 'de> de::Deserializer<'de> for Deserializer<'lua> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Number(n) => visitor.visit_f64(n),
+            Value::String(ref s) => match s.to_str() {
+                Ok(s) => visitor.visit_str(s),
+                Err(_) => visitor.visit_bytes(s.as_bytes()),
+            },
+            Value::Table(ref t) => {
+                let len = t.raw_len();
+                if len > 0 && t.clone().pairs::<Value, Value>().count() as Integer == len {
+                    visit_seq(t.clone(), visitor)
+                } else {
+                    visit_map(t.clone(), visitor)
+                }
+            }
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    deserialize_integer!(
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Table(t) => visit_seq(t, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Table(t) => visit_map(t, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let (variant, value) = match self.value {
+            Value::String(s) => (s.to_str()?.to_owned(), None),
+            Value::Table(t) => {
+                let mut pairs = t.pairs::<StdString, Value>();
+                match (pairs.next(), pairs.next()) {
+                    (Some(pair), None) => {
+                        let (variant, value) = pair?;
+                        (variant, Some(value))
+                    }
+                    _ => {
+                        return Err(de::Error::custom(
+                            "expected a table with a single key naming the enum variant",
+                        ))
+                    }
+                }
+            }
+            _ => return Err(de::Error::custom("expected a string or table for an enum")),
+        };
+        visitor.visit_enum(Enum { variant, value })
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf unit unit_struct identifier
+    }
+}
+
+fn visit_seq<'lua, 'de, V: Visitor<'de>>(table: Table<'lua>, visitor: V) -> Result<V::Value> {
+    let values = table
+        .sequence_values::<Value>()
+        .collect::<Result<Vec<_>>>()?;
+    let len = values.len();
+    let mut seq = Seq {
+        values: values.into_iter(),
+    };
+    let result = visitor.visit_seq(&mut seq)?;
+    if seq.values.len() == 0 {
+        Ok(result)
+    } else {
+        Err(de::Error::invalid_length(
+            len,
+            &"fewer elements in sequence",
+        ))
+    }
+}
+
+fn visit_map<'lua, 'de, V: Visitor<'de>>(table: Table<'lua>, visitor: V) -> Result<V::Value> {
+    let pairs = table.pairs::<Value, Value>().collect::<Result<Vec<_>>>()?;
+    visitor.visit_map(Map {
+        pairs: pairs.into_iter(),
+        value: None,
+    })
+}
+
+struct Seq<'lua> {
+    values: IntoIter<Value<'lua>>,
+}
+
+impl<'lua, 'de> SeqAccess<'de> for Seq<'lua> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.values.next() {
+            Some(value) => seed.deserialize(Deserializer { value }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct Map<'lua> {
+    pairs: IntoIter<(Value<'lua>, Value<'lua>)>,
+    value: Option<Value<'lua>>,
+}
+
+impl<'lua, 'de> MapAccess<'de> for Map<'lua> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.pairs.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer { value: key }).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = rlua_expect!(
+            self.value.take(),
+            "next_value_seed called before next_key_seed"
+        );
+        seed.deserialize(Deserializer { value })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+}
+
+struct Enum<'lua> {
+    variant: StdString,
+    value: Option<Value<'lua>>,
+}
+
+impl<'lua, 'de> EnumAccess<'de> for Enum<'lua> {
+    type Error = Error;
+    type Variant = Variant<'lua>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Variant<'lua>)> {
+        let variant =
+            seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.variant))?;
+        Ok((variant, Variant { value: self.value }))
+    }
+}
+
+struct Variant<'lua> {
+    value: Option<Value<'lua>>,
+}
+
+impl<'lua> Variant<'lua> {
+    fn value(self) -> Result<Deserializer<'lua>> {
+        match self.value {
+            Some(value) => Ok(Deserializer { value }),
+            None => Err(de::Error::custom(
+                "expected a table for a non-unit enum variant",
+            )),
+        }
+    }
+}
+
+impl<'lua, 'de> VariantAccess<'de> for Variant<'lua> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(Value::Nil) => Ok(()),
+            Some(_) => Err(de::Error::custom(
+                "unexpected value for a unit enum variant",
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self.value()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self.value()?, visitor)
+    }
+}
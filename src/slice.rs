@@ -0,0 +1,154 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::string::String;
+use crate::table::NumericElement;
+use crate::value::{FromLua, ToLua, Value};
+
+/// Wraps a Lua string to be used as a byte slice without copying it.
+///
+/// Taking a `Bytes` argument in a callback gives access to the contents of a Lua string as a
+/// `&[u8]` for the duration of the call.  Like [`String`], this also accepts numbers, which are
+/// converted to strings.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Bytes, Lua, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let checksum = lua_context.create_function(|_, data: Bytes| {
+///     Ok(data.iter().map(|&b| b as u32).sum::<u32>())
+/// })?;
+/// lua_context.globals().set("checksum", checksum)?;
+/// assert_eq!(lua_context.load(r#"checksum("\1\2\3")"#).eval::<u32>()?, 6);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`String`]: struct.String.html
+#[derive(Clone, Debug)]
+pub struct Bytes<'lua>(String<'lua>);
+
+impl<'lua> Bytes<'lua> {
+    /// Returns the Lua string backing the bytes.
+    pub fn into_string(self) -> String<'lua> {
+        self.0
+    }
+}
+
+impl<'lua> Deref for Bytes<'lua> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl<'lua> AsRef<[u8]> for Bytes<'lua> {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl<'lua> From<String<'lua>> for Bytes<'lua> {
+    fn from(string: String<'lua>) -> Bytes<'lua> {
+        Bytes(string)
+    }
+}
+
+impl<'lua> ToLua<'lua> for Bytes<'lua> {
+    fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
+        Ok(Value::String(self.0))
+    }
+}
+
+impl<'lua> FromLua<'lua> for Bytes<'lua> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Bytes<'lua>> {
+        String::from_lua(value, lua).map(Bytes)
+    }
+}
+
+/// Wraps a `Vec` of numbers which is converted to and from Lua sequences in bulk.
+///
+/// Converting a `Vec<T>` from a Lua table goes through a `Value` for every element, while
+/// `Numbers<T>` copies the sequence in one pass with [`Table::as_slice_of`], which is much faster
+/// for large arrays such as mesh or audio data.  Converting to Lua likewise uses
+/// [`Table::write_slice`].
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Numbers, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let scale = lua_context.create_function(|_, (mut samples, gain): (Numbers<f32>, f32)| {
+///     for sample in samples.iter_mut() {
+///         *sample *= gain;
+///     }
+///     Ok(samples)
+/// })?;
+/// lua_context.globals().set("scale", scale)?;
+/// assert_eq!(
+///     lua_context.load("scale({ 0.5, -1 }, 2)").eval::<Vec<f32>>()?,
+///     vec![1.0, -2.0]
+/// );
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`Table::as_slice_of`]: struct.Table.html#method.as_slice_of
+/// [`Table::write_slice`]: struct.Table.html#method.write_slice
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Numbers<T>(Vec<T>);
+
+impl<T> Numbers<T> {
+    /// Returns the wrapped `Vec`.
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T> From<Vec<T>> for Numbers<T> {
+    fn from(values: Vec<T>) -> Numbers<T> {
+        Numbers(values)
+    }
+}
+
+impl<T> Deref for Numbers<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Numbers<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'lua, T: NumericElement> ToLua<'lua> for Numbers<T> {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        let table = lua.create_table()?;
+        table.write_slice(&self.0)?;
+        Ok(Value::Table(table))
+    }
+}
+
+impl<'lua, T: NumericElement> FromLua<'lua> for Numbers<T> {
+    fn from_lua(value: Value<'lua>, _: Context<'lua>) -> Result<Numbers<T>> {
+        match value {
+            Value::Table(table) => table.as_slice_of().map(Numbers),
+            value => Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "Numbers",
+                message: Some("expected table".to_string()),
+            }),
+        }
+    }
+}
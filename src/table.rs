@@ -1,14 +1,54 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
 use std::marker::PhantomData;
-use std::os::raw::c_int;
+use std::os::raw::{c_int, c_void};
 
-use crate::error::Result;
+use num_traits::cast;
+
+use crate::error::{Error, Result};
 use crate::ffi;
-use crate::types::{Integer, LuaRef};
+use crate::function::Function;
+use crate::types::{Integer, LightUserData, LuaRef, Number};
 use crate::util::{assert_stack, protect_lua, protect_lua_closure, StackGuard};
 use crate::value::{FromLua, Nil, ToLua, Value};
 
+// The metatable fields which `Table::set_readonly` replaces.
+const READONLY_METAMETHODS: &[&[u8]] = &[b"__index", b"__newindex", b"__len", b"__pairs"];
+
+// The metatable fields which `Table::set_readonly` does not copy, because they only apply to the
+// table holding the contents.  Copying `__gc` would run the finalizer for both tables.
+const CONTENTS_METAFIELDS: &[&[u8]] = &[b"__gc", b"__mode"];
+
+// Creates the metamethods of a read-only table, given the table holding its contents.
+const READONLY_METATABLE_SOURCE: &str = r#"
+    local contents = ...
+    return function()
+        error("attempt to modify a read-only table", 2)
+    end, function()
+        return #contents
+    end, function()
+        return next, contents, nil
+    end
+"#;
+
+// The address of this static identifies the metatable field of read-only tables which holds their
+// contents and previous metatable.
+static READONLY_KEY: u8 = 0;
+
+fn readonly_key() -> LightUserData {
+    LightUserData(&READONLY_KEY as *const u8 as *mut c_void)
+}
+
+// The converted pairs and the errors returned by `Table::get_all`.
+type PartialConversion<'lua, K, V> = (HashMap<K, V>, Vec<(Value<'lua>, Error)>);
+
 /// Handle to an internal Lua table.
-#[derive(Clone, Debug)]
+///
+/// Cloning a `Table` creates another handle to the same table.
+#[derive(Clone)]
 pub struct Table<'lua>(pub(crate) LuaRef<'lua>);
 
 impl<'lua> Table<'lua> {
@@ -174,6 +214,157 @@ impl<'lua> Table<'lua> {
         V::from_lua(value, lua)
     }
 
+    /// Inserts `value` at position `idx` of the sequence, shifting the following elements up,
+    /// without invoking metamethods.
+    ///
+    /// This is the raw version of Lua's `table.insert(t, idx, value)`, and `idx` must be between 1
+    /// and the raw length of the table plus one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let readonly: Table = lua_context.load(r#"
+    ///     setmetatable({ "a", "c" }, { __newindex = function() error("read only") end })
+    /// "#).eval()?;
+    ///
+    /// readonly.raw_insert(2, "b")?;
+    /// readonly.raw_push("d")?;
+    /// readonly.raw_remove(1)?;
+    /// let values = readonly.raw_sequence_values().collect::<Result<Vec<String>>>()?;
+    /// assert_eq!(values, vec!["b", "c", "d"]);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn raw_insert<V: ToLua<'lua>>(&self, idx: Integer, value: V) -> Result<()> {
+        let lua = self.0.lua;
+        let len = self.raw_len();
+        if idx < 1 || idx > len + 1 {
+            return Err(Error::RuntimeError("position out of bounds".to_owned()));
+        }
+        let value = value.to_lua(lua)?;
+
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 6);
+
+            lua.push_ref(&self.0);
+            lua.push_value(value)?;
+            protect_lua_closure(lua.state, 2, 0, |state| {
+                for i in (idx..=len).rev() {
+                    ffi::lua_rawgeti(state, -2, i);
+                    ffi::lua_rawseti(state, -3, i + 1);
+                }
+                ffi::lua_rawseti(state, -2, idx);
+            })
+        }
+    }
+
+    /// Appends `value` to the end of the sequence, without invoking metamethods.
+    pub fn raw_push<V: ToLua<'lua>>(&self, value: V) -> Result<()> {
+        self.raw_insert(self.raw_len() + 1, value)
+    }
+
+    /// Removes `key` from the table without invoking metamethods.
+    ///
+    /// If `key` is an integer position within the sequence, the following elements are shifted
+    /// down, as with Lua's `table.remove`.  Any other key is simply set to nil.
+    pub fn raw_remove<K: ToLua<'lua>>(&self, key: K) -> Result<()> {
+        let lua = self.0.lua;
+        let key = key.to_lua(lua)?;
+        let idx = match key {
+            Value::Integer(idx) => idx,
+            _ => return self.raw_set(key, Nil),
+        };
+        let len = self.raw_len();
+        if idx < 1 || idx > len {
+            return self.raw_set(key, Nil);
+        }
+
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 5);
+
+            lua.push_ref(&self.0);
+            protect_lua_closure(lua.state, 1, 0, |state| {
+                for i in idx..len {
+                    ffi::lua_rawgeti(state, -1, i + 1);
+                    ffi::lua_rawseti(state, -2, i);
+                }
+                ffi::lua_pushnil(state);
+                ffi::lua_rawseti(state, -2, len);
+            })
+        }
+    }
+
+    /// Returns `true` if the table has no entries, without invoking metamethods.
+    pub fn is_empty(&self) -> bool {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 3);
+
+            lua.push_ref(&self.0);
+            ffi::lua_pushnil(lua.state);
+            // Starting a traversal with a nil key cannot fail.
+            ffi::lua_next(lua.state, -2) == 0
+        }
+    }
+
+    /// Removes every entry of the table, without invoking metamethods.
+    ///
+    /// The metatable of the table is kept.
+    pub fn clear(&self) -> Result<()> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 4);
+
+            lua.push_ref(&self.0);
+            protect_lua_closure(lua.state, 1, 0, |state| {
+                ffi::lua_pushnil(state);
+                while ffi::lua_next(state, -2) != 0 {
+                    // Clearing fields during a traversal is allowed by `next`.
+                    ffi::lua_pop(state, 1);
+                    ffi::lua_pushvalue(state, -1);
+                    ffi::lua_pushnil(state);
+                    ffi::lua_rawset(state, -4);
+                }
+            })
+        }
+    }
+
+    /// Creates a new table with the same entries as this one, without invoking metamethods.
+    ///
+    /// The copy is shallow: tables and other reference values in the entries are shared with this
+    /// table, not copied.  The metatable is not copied either.
+    ///
+    /// This is not named `clone`, as cloning a `Table` creates another handle to the same table.
+    pub fn shallow_clone(&self) -> Result<Table<'lua>> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 4);
+
+            lua.push_ref(&self.0);
+            let narr = ffi::lua_rawlen(lua.state, -1).min(c_int::MAX as usize) as c_int;
+            protect_lua_closure(lua.state, 1, 1, |state| {
+                ffi::lua_createtable(state, narr, 0);
+                ffi::lua_pushnil(state);
+                while ffi::lua_next(state, -3) != 0 {
+                    ffi::lua_pushvalue(state, -2);
+                    ffi::lua_pushvalue(state, -2);
+                    ffi::lua_rawset(state, -5);
+                    ffi::lua_pop(state, 1);
+                }
+            })?;
+            Ok(Table(lua.pop_ref()))
+        }
+    }
+
     /// Returns the result of the Lua `#` operator.
     ///
     /// This might invoke the `__len` metamethod. Use the [`raw_len`] method if that is not desired.
@@ -223,7 +414,45 @@ impl<'lua> Table<'lua> {
     ///
     /// If `metatable` is `None`, the metatable is removed (if no metatable is set, this does
     /// nothing).
-    pub fn set_metatable(&self, metatable: Option<Table<'lua>>) {
+    ///
+    /// This fails if `metatable` has a `__gc` field.  Finalizers run at arbitrary points during
+    /// allocation, so setting them is only allowed from Lua code; Rust types which need cleanup
+    /// should be [`UserData`] implementing `Drop` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let defaults = lua_context.create_table()?;
+    /// defaults.set("color", "red")?;
+    /// let metatable = lua_context.create_table()?;
+    /// metatable.set("__index", defaults)?;
+    ///
+    /// let options = lua_context.create_table()?;
+    /// options.set_metatable(Some(metatable))?;
+    /// assert_eq!(options.get::<_, String>("color")?, "red");
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`UserData`]: trait.UserData.html
+    pub fn set_metatable(&self, metatable: Option<Table<'lua>>) -> Result<()> {
+        if let Some(metatable) = &metatable {
+            if !matches!(metatable.raw_get("__gc")?, Value::Nil) {
+                return Err(Error::RuntimeError(
+                    "cannot set a metatable with a __gc field from Rust".to_owned(),
+                ));
+            }
+        }
+        self.set_metatable_unchecked(metatable);
+        Ok(())
+    }
+
+    // Sets the metatable without checking for `__gc`, for metatables which come from Lua.
+    pub(crate) fn set_metatable_unchecked(&self, metatable: Option<Table<'lua>>) {
         let lua = self.0.lua;
         unsafe {
             let _sg = StackGuard::new(lua.state);
@@ -238,6 +467,118 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    /// Makes the table read-only, or writable again.
+    ///
+    /// Lua has no native way to freeze a table, so the contents are moved to a hidden table and
+    /// this table is given a protected metatable which forwards reads and `#`, `pairs` and `ipairs`
+    /// to it and rejects every assignment with an error.  Scripts cannot change or remove the
+    /// metatable, so this protects configuration or API tables from accidental changes by scripts.
+    /// Any metamethods of the previous metatable other than `__index`, `__newindex`, `__len` and
+    /// `__pairs` keep working, and `__index` is still consulted for missing keys.  `__gc` and
+    /// `__mode` stay with the contents.
+    ///
+    /// Making the table writable again moves the contents back and restores the previous
+    /// metatable.
+    ///
+    /// # Note
+    ///
+    /// Raw access bypasses the protection: while the table is read-only the raw methods, `next`
+    /// and the Rust iterators see the (empty) table itself rather than its contents, and scripts
+    /// can still shadow any key with `rawset`.  Do not rely on this to protect tables from
+    /// untrusted scripts which have access to `rawset`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let config: Table = lua_context.load("{ level = 3 }").eval()?;
+    /// config.set_readonly(true)?;
+    /// lua_context.globals().set("config", config.clone())?;
+    ///
+    /// assert_eq!(lua_context.load("config.level").eval::<i64>()?, 3);
+    /// assert!(lua_context.load("config.level = 4").exec().is_err());
+    /// assert!(lua_context.load("setmetatable(config, nil)").exec().is_err());
+    ///
+    /// config.set_readonly(false)?;
+    /// lua_context.load("config.level = 4").exec()?;
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn set_readonly(&self, readonly: bool) -> Result<()> {
+        let lua = self.0.lua;
+        match (readonly, self.readonly_parts()?) {
+            (true, None) => {
+                let previous = self.get_metatable();
+                let contents = self.shallow_clone()?;
+                self.clear()?;
+                contents.set_metatable_unchecked(previous.clone());
+
+                let metatable = lua.create_table()?;
+                if let Some(previous) = &previous {
+                    previous.for_each(|key: Value, value: Value| match &key {
+                        Value::String(name)
+                            if READONLY_METAMETHODS.contains(&name.as_bytes())
+                                || CONTENTS_METAFIELDS.contains(&name.as_bytes()) =>
+                        {
+                            Ok(())
+                        }
+                        _ => metatable.raw_set(key, value),
+                    })?;
+                }
+                let (newindex, len, pairs): (Function, Function, Function) = lua
+                    .load(READONLY_METATABLE_SOURCE)
+                    .set_name("=readonly")?
+                    .call(contents.clone())?;
+                metatable.raw_set("__index", contents.clone())?;
+                metatable.raw_set("__newindex", newindex)?;
+                metatable.raw_set("__len", len)?;
+                metatable.raw_set("__pairs", pairs)?;
+                let protected = match &previous {
+                    Some(previous) => match previous.raw_get::<_, Value>("__metatable")? {
+                        Value::Nil => Value::Table(previous.clone()),
+                        protected => protected,
+                    },
+                    None => Value::Boolean(false),
+                };
+                metatable.raw_set("__metatable", protected)?;
+                let parts = lua.create_table()?;
+                parts.raw_set(1, contents)?;
+                parts.raw_set(2, previous)?;
+                metatable.raw_set(readonly_key(), parts)?;
+                self.set_metatable_unchecked(Some(metatable));
+            }
+            (false, Some((contents, previous))) => {
+                self.set_metatable_unchecked(previous);
+                contents.set_metatable_unchecked(None);
+                contents.for_each(|key: Value, value: Value| self.raw_set(key, value))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the table was made read-only with [`set_readonly`].
+    ///
+    /// [`set_readonly`]: #method.set_readonly
+    pub fn is_readonly(&self) -> bool {
+        matches!(self.readonly_parts(), Ok(Some(_)))
+    }
+
+    // Returns the table holding the contents of a read-only table and its previous metatable.
+    fn readonly_parts(&self) -> Result<Option<(Table<'lua>, Option<Table<'lua>>)>> {
+        let parts = match self.get_metatable() {
+            Some(metatable) => metatable.raw_get::<_, Option<Table>>(readonly_key())?,
+            None => None,
+        };
+        match parts {
+            Some(parts) => Ok(Some((parts.raw_get(1)?, parts.raw_get(2)?))),
+            None => Ok(None),
+        }
+    }
+
     /// Consume this table and return an iterator over the pairs of the table.
     ///
     /// This works like the Lua `pairs` function, but does not invoke the `__pairs` metamethod.
@@ -326,11 +667,475 @@ impl<'lua> Table<'lua> {
         TableSequence {
             table: self.0,
             index: Some(1),
+            raw: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the pairs of the table, without consuming the table.
+    ///
+    /// This is the same as calling [`pairs`] on a clone of this handle.
+    ///
+    /// [`pairs`]: #method.pairs
+    pub fn iter<K: FromLua<'lua>, V: FromLua<'lua>>(&self) -> TablePairs<'lua, K, V> {
+        self.clone().pairs()
+    }
+
+    /// Returns an iterator over the sequence part of the table, without consuming the table.
+    ///
+    /// This is the same as calling [`sequence_values`] on a clone of this handle.
+    ///
+    /// [`sequence_values`]: #method.sequence_values
+    pub fn sequence_iter<V: FromLua<'lua>>(&self) -> TableSequence<'lua, V> {
+        self.clone().sequence_values()
+    }
+
+    /// Returns an iterator over the sequence part of the table, without invoking metamethods and
+    /// without consuming the table.
+    ///
+    /// Unlike [`sequence_values`], this ignores any `__index` metamethod, and reading the values
+    /// cannot fail other than by converting them to `V`.
+    ///
+    /// [`sequence_values`]: #method.sequence_values
+    pub fn raw_sequence_values<V: FromLua<'lua>>(&self) -> TableSequence<'lua, V> {
+        TableSequence {
+            table: self.0.clone(),
+            index: Some(1),
+            raw: true,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Calls `f` with every pair of the table, stopping at the first error.
+    ///
+    /// This visits the same pairs as [`pairs`], but keeps the traversal on the Lua stack instead
+    /// of holding a reference to the current key between steps, which makes it faster for large
+    /// tables.  Errors converting a pair to `K` and `V` and errors returned by `f` end the
+    /// iteration and are returned.
+    ///
+    /// # Note
+    ///
+    /// As with [`pairs`], assigning to fields which are not already present in the table from
+    /// within `f` has undefined results, see the [Lua manual].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let prices: Table = lua_context.load("{ apple = 3, pear = 4 }").eval()?;
+    ///
+    /// let mut total = 0;
+    /// prices.for_each(|_: String, price: i64| {
+    ///     total += price;
+    ///     Ok(())
+    /// })?;
+    /// assert_eq!(total, 7);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`pairs`]: #method.pairs
+    /// [Lua manual]: http://www.lua.org/manual/5.3/manual.html#pdf-next
+    pub fn for_each<K, V, F>(&self, mut f: F) -> Result<()>
+    where
+        K: FromLua<'lua>,
+        V: FromLua<'lua>,
+        F: FnMut(K, V) -> Result<()>,
+    {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 6);
+
+            lua.push_ref(&self.0);
+            ffi::lua_pushnil(lua.state);
+            // The table and the current key stay at the top of the stack between steps.
+            while protect_lua_closure(lua.state, 2, ffi::LUA_MULTRET, |state| {
+                ffi::lua_next(state, -2) != 0
+            })? {
+                ffi::lua_pushvalue(lua.state, -2);
+                let key = lua.pop_value();
+                let value = lua.pop_value();
+                f(K::from_lua(key, lua)?, V::from_lua(value, lua)?)?;
+                assert_stack(lua.state, 6);
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts every pair of the table, collecting the pairs which convert successfully and the
+    /// errors of those which do not.
+    ///
+    /// Unlike converting the whole table to a `HashMap` with `FromLua`, one bad entry does not
+    /// make the whole conversion fail.  Each error is returned along with the key of the pair
+    /// which failed to convert, as a raw `Value` since it might be the key which failed.  The
+    /// order of the errors is the iteration order of the table, which is unspecified.
+    ///
+    /// Returns an error only if iterating the table itself fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table, Value};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let config: Table = lua_context.load(r#"
+    ///     { width = 800, height = 600, depth = "deep" }
+    /// "#).eval()?;
+    ///
+    /// let (values, errors) = config.get_all::<String, u32>()?;
+    /// assert_eq!(values.len(), 2);
+    /// assert_eq!(values["width"], 800);
+    /// assert_eq!(errors.len(), 1);
+    /// match &errors[0].0 {
+    ///     Value::String(key) => assert_eq!(key.to_str()?, "depth"),
+    ///     _ => unreachable!(),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn get_all<K, V>(&self) -> Result<PartialConversion<'lua, K, V>>
+    where
+        K: Eq + Hash + FromLua<'lua>,
+        V: FromLua<'lua>,
+    {
+        let lua = self.0.lua;
+        let mut values = HashMap::new();
+        let mut errors = Vec::new();
+        self.for_each(|key: Value<'lua>, value: Value<'lua>| {
+            let converted =
+                K::from_lua(key.clone(), lua).and_then(|k| Ok((k, V::from_lua(value, lua)?)));
+            match converted {
+                Ok((k, v)) => {
+                    values.insert(k, v);
+                }
+                Err(err) => errors.push((key, err)),
+            }
+            Ok(())
+        })?;
+        Ok((values, errors))
+    }
+
+    /// Copies the sequence part of the table into a new `Vec`, without invoking metamethods.
+    ///
+    /// The elements are converted as by their `FromLua` implementations, but without creating a
+    /// `Value` for each of them, which makes this much faster than [`sequence_values`] for large
+    /// arrays of numbers.  The sequence ends at the raw length of the table, and an error is
+    /// returned if any element within it cannot be converted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let samples: Table = lua_context.load("{ 0.5, 1, -0.25 }").eval()?;
+    /// assert_eq!(samples.as_slice_of::<f32>()?, vec![0.5, 1.0, -0.25]);
+    ///
+    /// samples.write_slice(&[1.5, 2.5])?;
+    /// assert_eq!(samples.as_slice_of::<f64>()?, vec![1.5, 2.5, -0.25]);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`sequence_values`]: #method.sequence_values
+    pub fn as_slice_of<T: NumericElement>(&self) -> Result<Vec<T>> {
+        let mut values = Vec::new();
+        self.read_slice_into(&mut values)?;
+        Ok(values)
+    }
+
+    /// Copies the sequence part of the table into `buf`, replacing its contents.
+    ///
+    /// This behaves like [`as_slice_of`], but reuses the allocation of `buf`.  If an error is
+    /// returned, `buf` holds the elements converted before the failing one.
+    ///
+    /// [`as_slice_of`]: #method.as_slice_of
+    pub fn read_slice_into<T: NumericElement>(&self, buf: &mut Vec<T>) -> Result<()> {
+        let lua = self.0.lua;
+        let len = self.raw_len();
+        buf.clear();
+        buf.reserve(len as usize);
+
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+
+            lua.push_ref(&self.0);
+            for i in 1..=len {
+                ffi::lua_rawgeti(lua.state, -1, i);
+                match T::read(lua.state, -1) {
+                    Some(value) => {
+                        buf.push(value);
+                        ffi::lua_pop(lua.state, 1);
+                    }
+                    None => {
+                        return Err(Error::FromLuaConversionError {
+                            from: lua.pop_value().type_name(),
+                            to: T::TYPE_NAME,
+                            message: Some(format!(
+                                "element {} is not a number or is out of range",
+                                i
+                            )),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the elements of `slice` to the positions `1` through `slice.len()` of the table,
+    /// without invoking metamethods.
+    ///
+    /// Elements of the table after the end of `slice` are left unchanged.
+    pub fn write_slice<T: NumericElement>(&self, slice: &[T]) -> Result<()> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 4);
+
+            lua.push_ref(&self.0);
+            protect_lua_closure(lua.state, 1, 0, |state| {
+                for (i, &value) in slice.iter().enumerate() {
+                    value.push(state);
+                    ffi::lua_rawseti(state, -2, i as Integer + 1);
+                }
+            })
+        }
+    }
+}
+
+impl<'lua> fmt::Debug for Table<'lua> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Table({:p}, len = {})",
+            self.0.to_pointer(),
+            self.raw_len()
+        )
+    }
+}
+
+/// Numeric types which can be copied between Rust slices and Lua sequences in bulk.
+///
+/// See [`Table::as_slice_of`] and [`Table::write_slice`].  This trait is implemented for all the
+/// primitive integer and floating point types.
+///
+/// [`Table::as_slice_of`]: struct.Table.html#method.as_slice_of
+/// [`Table::write_slice`]: struct.Table.html#method.write_slice
+pub trait NumericElement: Copy {
+    #[doc(hidden)]
+    const TYPE_NAME: &'static str;
+
+    // Converts the value at `index` like the `FromLua` implementation of the type.
+    #[doc(hidden)]
+    unsafe fn read(state: *mut ffi::lua_State, index: c_int) -> Option<Self>;
+
+    // Pushes the value like the `ToLua` implementation of the type.
+    #[doc(hidden)]
+    unsafe fn push(self, state: *mut ffi::lua_State);
+}
+
+macro_rules! numeric_element_int {
+    ($x:ty) => {
+        impl NumericElement for $x {
+            const TYPE_NAME: &'static str = stringify!($x);
+
+            unsafe fn read(state: *mut ffi::lua_State, index: c_int) -> Option<Self> {
+                let mut isint = 0;
+                let i = ffi::lua_tointegerx(state, index, &mut isint);
+                if isint != 0 {
+                    cast(i)
+                } else {
+                    let mut isnum = 0;
+                    let n = ffi::lua_tonumberx(state, index, &mut isnum);
+                    if isnum != 0 {
+                        cast(n)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            unsafe fn push(self, state: *mut ffi::lua_State) {
+                match cast(self) {
+                    Some(i) => ffi::lua_pushinteger(state, i),
+                    None => ffi::lua_pushnumber(state, self as Number),
+                }
+            }
+        }
+    };
+}
+
+numeric_element_int!(i8);
+numeric_element_int!(u8);
+numeric_element_int!(i16);
+numeric_element_int!(u16);
+numeric_element_int!(i32);
+numeric_element_int!(u32);
+numeric_element_int!(i64);
+numeric_element_int!(u64);
+numeric_element_int!(i128);
+numeric_element_int!(u128);
+numeric_element_int!(isize);
+numeric_element_int!(usize);
+
+macro_rules! numeric_element_float {
+    ($x:ty) => {
+        impl NumericElement for $x {
+            const TYPE_NAME: &'static str = stringify!($x);
+
+            unsafe fn read(state: *mut ffi::lua_State, index: c_int) -> Option<Self> {
+                let mut isnum = 0;
+                let n = ffi::lua_tonumberx(state, index, &mut isnum);
+                if isnum != 0 {
+                    cast(n)
+                } else {
+                    None
+                }
+            }
+
+            unsafe fn push(self, state: *mut ffi::lua_State) {
+                ffi::lua_pushnumber(state, self as Number);
+            }
+        }
+    };
+}
+
+numeric_element_float!(f32);
+numeric_element_float!(f64);
+
+/// A view of a table whose keys and values have the Rust types `K` and `V`.
+///
+/// `TypedTable` converts keys and values automatically, like a map.  It also caches the Lua values
+/// of the keys it is given, so repeatedly accessing the same field by name does not create a Lua
+/// string every time.  As every key used is kept in the cache, it is meant for tables with a
+/// fixed set of keys, such as configuration tables.
+///
+/// Like the methods of `Table`, `get` and `set` may invoke metamethods.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result, TypedTable};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let stats: TypedTable<String, i64> = lua_context.load("{ hp = 10, mp = 5 }").eval()?;
+/// stats.set("hp".to_owned(), stats.get("hp")? - 3)?;
+/// assert_eq!(stats.get("hp")?, 7);
+/// assert!(stats.get("speed").is_err());
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+pub struct TypedTable<'lua, K, V> {
+    table: Table<'lua>,
+    keys: RefCell<HashMap<K, Value<'lua>>>,
+    _phantom: PhantomData<V>,
+}
+
+impl<'lua, K, V> TypedTable<'lua, K, V>
+where
+    K: Eq + Hash + Clone + ToLua<'lua> + FromLua<'lua>,
+    V: ToLua<'lua> + FromLua<'lua>,
+{
+    /// Creates a typed view of `table`.
+    pub fn new(table: Table<'lua>) -> TypedTable<'lua, K, V> {
+        TypedTable {
+            table,
+            keys: RefCell::new(HashMap::new()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Gets the value for `key` converted to `V`.
+    ///
+    /// As with `HashMap::get`, the key may be any borrowed form of `K`, such as `&str` for
+    /// `String` keys.
+    pub fn get<Q>(&self, key: &Q) -> Result<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash + ToOwned<Owned = K>,
+    {
+        self.table.get(self.key(key)?)
+    }
+
+    /// Sets the value for `key`.
+    pub fn set(&self, key: K, value: V) -> Result<()> {
+        let key = self.key(&key)?;
+        self.table.set(key, value)
+    }
+
+    /// Checks whether the table contains a non-nil value for `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash + ToOwned<Owned = K>,
+    {
+        self.table.contains_key(self.key(key)?)
+    }
+
+    /// Returns an iterator over the pairs of the table converted to `K` and `V`, as with
+    /// [`Table::pairs`].
+    ///
+    /// [`Table::pairs`]: struct.Table.html#method.pairs
+    pub fn iter(&self) -> TablePairs<'lua, K, V> {
+        self.table.iter()
+    }
+
+    // Returns the Lua value of `key`, converting it and adding it to the cache the first time.
+    fn key<Q>(&self, key: &Q) -> Result<Value<'lua>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash + ToOwned<Owned = K>,
+    {
+        if let Some(value) = self.keys.borrow().get(key) {
+            return Ok(value.clone());
+        }
+        let key = key.to_owned();
+        let value = key.clone().to_lua(self.table.0.lua)?;
+        self.keys.borrow_mut().insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+impl<'lua, K, V> TypedTable<'lua, K, V> {
+    /// Returns the underlying table.
+    pub fn table(&self) -> &Table<'lua> {
+        &self.table
+    }
+
+    /// Returns the underlying table, consuming the view.
+    pub fn into_table(self) -> Table<'lua> {
+        self.table
+    }
+}
+
+impl<'lua, K, V> Clone for TypedTable<'lua, K, V>
+where
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        TypedTable {
+            table: self.table.clone(),
+            keys: self.keys.clone(),
             _phantom: PhantomData,
         }
     }
 }
 
+impl<'lua, K, V> fmt::Debug for TypedTable<'lua, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("TypedTable").field(&self.table).finish()
+    }
+}
+
 /// An iterator over the pairs of a Lua table.
 ///
 /// This struct is created by the [`Table::pairs`] method.
@@ -395,12 +1200,15 @@ where
 
 /// An iterator over the sequence part of a Lua table.
 ///
-/// This struct is created by the [`Table::sequence_values`] method.
+/// This struct is created by the [`Table::sequence_values`] and [`Table::raw_sequence_values`]
+/// methods.
 ///
 /// [`Table::sequence_values`]: struct.Table.html#method.sequence_values
+/// [`Table::raw_sequence_values`]: struct.Table.html#method.raw_sequence_values
 pub struct TableSequence<'lua, V> {
     table: LuaRef<'lua>,
     index: Option<Integer>,
+    raw: bool,
     _phantom: PhantomData<V>,
 }
 
@@ -419,8 +1227,12 @@ where
                 assert_stack(lua.state, 5);
 
                 lua.push_ref(&self.table);
-                match protect_lua_closure(lua.state, 1, 1, |state| ffi::lua_geti(state, -1, index))
-                {
+                let res = if self.raw {
+                    Ok(ffi::lua_rawgeti(lua.state, -1, index))
+                } else {
+                    protect_lua_closure(lua.state, 1, 1, |state| ffi::lua_geti(state, -1, index))
+                };
+                match res {
                     Ok(ffi::LUA_TNIL) => None,
                     Ok(_) => {
                         let value = lua.pop_value();
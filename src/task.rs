@@ -0,0 +1,206 @@
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use crate::error::{Error, Result};
+use crate::function::Function;
+use crate::thread::{AsyncThread, Thread};
+use crate::value::{FromLuaMulti, ToLuaMulti};
+
+/// Runs a group of Lua functions or threads as async tasks which are joined together.
+///
+/// Tasks are spawned with [`spawn`] or [`spawn_thread`] and run concurrently, as with
+/// [`Function::call_async`], when the future returned by [`join_all`] is polled.  That future
+/// resolves to the results of all tasks, in the order they were spawned.
+///
+/// By default, the first task to fail cancels all tasks which are still running, whose results
+/// are then `Error::TaskCancelled`.  [`set_cancel_on_error`] lets the remaining tasks finish
+/// instead.
+///
+/// The group owns its tasks: dropping the group or the `join_all` future cancels every task that
+/// has not finished.  Cancelled tasks are never resumed again, and the futures of any async
+/// functions they were waiting on are dropped when Lua collects their threads.
+///
+/// # Examples
+///
+/// ```
+/// # use std::future::Future;
+/// # use std::pin::Pin;
+/// # use std::task::{Context as TaskContext, Poll, Waker};
+/// # use rlua::{Function, Lua, Result, TaskGroup};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let square: Function = lua_context.load("function(n) return n * n end").eval()?;
+///
+/// let mut group = TaskGroup::new();
+/// for n in 1..=3 {
+///     group.spawn(&square, n);
+/// }
+/// let mut join = group.join_all();
+///
+/// let mut cx = TaskContext::from_waker(Waker::noop());
+/// let results = match Pin::new(&mut join).poll(&mut cx) {
+///     Poll::Ready(results) => results,
+///     Poll::Pending => unreachable!(),
+/// };
+/// let squares = results.into_iter().collect::<Result<Vec<i64>>>()?;
+/// assert_eq!(squares, vec![1, 4, 9]);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`spawn`]: #method.spawn
+/// [`spawn_thread`]: #method.spawn_thread
+/// [`join_all`]: #method.join_all
+/// [`set_cancel_on_error`]: #method.set_cancel_on_error
+/// [`Function::call_async`]: struct.Function.html#method.call_async
+pub struct TaskGroup<'lua, R> {
+    tasks: Vec<Task<'lua, R>>,
+    cancel_on_error: bool,
+}
+
+enum Task<'lua, R> {
+    Running(AsyncThread<'lua, R>),
+    Finished(Result<R>),
+    Joined,
+}
+
+impl<'lua, R: FromLuaMulti<'lua>> TaskGroup<'lua, R> {
+    /// Creates an empty group which cancels its tasks on the first error.
+    pub fn new() -> TaskGroup<'lua, R> {
+        TaskGroup {
+            tasks: Vec::new(),
+            cancel_on_error: true,
+        }
+    }
+
+    /// Sets whether the first task to fail cancels the other tasks, which is the default.
+    pub fn set_cancel_on_error(&mut self, cancel_on_error: bool) {
+        self.cancel_on_error = cancel_on_error;
+    }
+
+    /// Adds a task calling `function` with `args` in a new thread.
+    pub fn spawn<A: ToLuaMulti<'lua>>(&mut self, function: &Function<'lua>, args: A) {
+        self.tasks.push(Task::Running(function.call_async(args)));
+    }
+
+    /// Adds a task resuming `thread` with `args` until it finishes, see [`Thread::into_async`].
+    ///
+    /// [`Thread::into_async`]: struct.Thread.html#method.into_async
+    pub fn spawn_thread<A: ToLuaMulti<'lua>>(&mut self, thread: Thread<'lua>, args: A) {
+        self.tasks.push(Task::Running(thread.into_async(args)));
+    }
+
+    /// Returns the number of tasks in the group.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns true if no tasks were spawned.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Cancels all tasks which have not finished yet.
+    pub fn cancel(&mut self) {
+        for task in &mut self.tasks {
+            if let Task::Running(_) = task {
+                *task = Task::Finished(Err(Error::TaskCancelled));
+            }
+        }
+    }
+
+    /// Returns a future which runs all tasks and resolves to their results, in the order they were
+    /// spawned.
+    pub fn join_all(self) -> JoinAll<'lua, R> {
+        JoinAll { group: self }
+    }
+
+    // Polls the running tasks, returning true once all of them finished.
+    fn poll_tasks(&mut self, cx: &mut task::Context) -> bool {
+        let mut finished = true;
+        for i in 0..self.tasks.len() {
+            let result = match &mut self.tasks[i] {
+                Task::Running(thread) => match Pin::new(thread).poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => {
+                        finished = false;
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+            let failed = result.is_err();
+            self.tasks[i] = Task::Finished(result);
+            if failed && self.cancel_on_error {
+                self.cancel();
+                return true;
+            }
+        }
+        finished
+    }
+}
+
+impl<'lua, R: FromLuaMulti<'lua>> Default for TaskGroup<'lua, R> {
+    fn default() -> TaskGroup<'lua, R> {
+        TaskGroup::new()
+    }
+}
+
+impl<'lua, R> fmt::Debug for TaskGroup<'lua, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let running = self
+            .tasks
+            .iter()
+            .filter(|task| matches!(task, Task::Running(_)))
+            .count();
+        f.debug_struct("TaskGroup")
+            .field("tasks", &self.tasks.len())
+            .field("running", &running)
+            .field("cancel_on_error", &self.cancel_on_error)
+            .finish()
+    }
+}
+
+/// The future returned by [`TaskGroup::join_all`].
+///
+/// [`TaskGroup::join_all`]: struct.TaskGroup.html#method.join_all
+#[must_use = "futures do nothing unless polled"]
+pub struct JoinAll<'lua, R> {
+    group: TaskGroup<'lua, R>,
+}
+
+impl<'lua, R> fmt::Debug for JoinAll<'lua, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("JoinAll").field(&self.group).finish()
+    }
+}
+
+// The results are never pinned, only moved out once all tasks finished.
+impl<'lua, R> Unpin for JoinAll<'lua, R> {}
+
+impl<'lua, R: FromLuaMulti<'lua>> Future for JoinAll<'lua, R> {
+    type Output = Vec<Result<R>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Vec<Result<R>>> {
+        let group = &mut self.get_mut().group;
+        if !group.poll_tasks(cx) {
+            return Poll::Pending;
+        }
+        Poll::Ready(
+            group
+                .tasks
+                .iter_mut()
+                .map(|task| match mem::replace(task, Task::Joined) {
+                    Task::Finished(result) => result,
+                    _ => {
+                        rlua_panic!("task group polled after completion");
+                    }
+                })
+                .collect(),
+        )
+    }
+}
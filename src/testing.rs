@@ -0,0 +1,662 @@
+//! Utilities for testing Lua bindings and script libraries from Rust tests.
+//!
+//! [`assert_lua_eq!`] checks the value of a Lua expression, [`Fixture`] builds `Lua` states with the
+//! same libraries, globals and scripts for every test, and [`run_script_tests`] runs the test
+//! functions of every `*_test.lua` file in a directory.  [`assert_snapshot`] compares a Lua value
+//! against a snapshot file printed by [`pretty_print`].  With the `proptest` feature,
+//! [`check_round_trip`] checks that values survive conversion to Lua and back.
+//!
+//! [`assert_lua_eq!`]: ../macro.assert_lua_eq.html
+//! [`Fixture`]: struct.Fixture.html
+//! [`run_script_tests`]: fn.run_script_tests.html
+//! [`assert_snapshot`]: fn.assert_snapshot.html
+//! [`pretty_print`]: fn.pretty_print.html
+//! [`check_round_trip`]: fn.check_round_trip.html
+
+use std::cmp::Ordering;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+use std::string::String as StdString;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::lua::{Lua, StdLib};
+use crate::table::Table;
+#[cfg(feature = "proptest")]
+use crate::value::ToLua;
+use crate::value::{FromLua, Value};
+
+/// Asserts that a Lua expression evaluates to the given Rust value.
+///
+/// The expression is evaluated in the given `Context` and converted to the type of the expected
+/// value, which must implement `FromLua`, `PartialEq` and `Debug`.  The assertion fails if the
+/// expression raises an error or cannot be converted.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{assert_lua_eq, Lua};
+/// Lua::new().context(|lua_context| {
+///     assert_lua_eq!(lua_context, "1 + 2", 3);
+///     assert_lua_eq!(lua_context, "('x'):rep(3)", "xxx".to_owned());
+///     assert_lua_eq!(lua_context, "nil", None::<i64>, "optional values work too");
+/// });
+/// ```
+#[macro_export]
+macro_rules! assert_lua_eq {
+    ($lua:expr, $expr:expr, $expected:expr $(,)?) => {{
+        let expected = $expected;
+        let actual = $crate::testing::eval_like(&expected, $lua, $expr);
+        assert_eq!(actual, expected, "Lua expression `{}`", $expr);
+    }};
+
+    ($lua:expr, $expr:expr, $expected:expr, $($arg:tt)+) => {{
+        let expected = $expected;
+        let actual = $crate::testing::eval_like(&expected, $lua, $expr);
+        assert_eq!(actual, expected, $($arg)+);
+    }};
+}
+
+#[doc(hidden)]
+pub fn eval_like<'lua, T: FromLua<'lua>>(_expected: &T, lua: Context<'lua>, expr: &str) -> T {
+    match lua.load(&format!("return {}", expr)).eval() {
+        Ok(value) => value,
+        Err(err) => panic!("Lua expression `{}` failed: {}", expr, err),
+    }
+}
+
+type Setup = Arc<dyn Fn(Context) -> Result<()> + Send + Sync>;
+
+/// A recipe for `Lua` states prepared the same way for every test.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::testing::Fixture;
+/// # use rlua::{assert_lua_eq, Result, StdLib};
+/// # fn main() -> Result<()> {
+/// let fixture = Fixture::new()
+///     .libs(StdLib::BASE | StdLib::STRING)
+///     .setup(|lua_context| lua_context.globals().set("VERSION", 2))
+///     .script("util", "function double(x) return x * VERSION end");
+///
+/// fixture.build()?.context(|lua_context| {
+///     assert_lua_eq!(lua_context, "double(21)", 42);
+///     assert_lua_eq!(lua_context, "os", None::<i64>);
+/// });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Fixture {
+    libs: StdLib,
+    steps: Vec<Step>,
+}
+
+#[derive(Clone)]
+enum Step {
+    Setup(Setup),
+    Script(StdString, StdString),
+}
+
+impl Fixture {
+    /// Creates a fixture for states with the standard libraries loaded by `Lua::new`.
+    pub fn new() -> Fixture {
+        Fixture {
+            libs: StdLib::ALL_NO_DEBUG,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Sets the standard libraries to load.
+    ///
+    /// # Panics
+    ///
+    /// Building the state panics if `libs` contains `StdLib::DEBUG`, as with `Lua::new_with`.
+    pub fn libs(mut self, libs: StdLib) -> Fixture {
+        self.libs = libs;
+        self
+    }
+
+    /// Adds a function which prepares each state, such as by registering bindings.
+    pub fn setup<F>(mut self, setup: F) -> Fixture
+    where
+        F: 'static + Send + Sync + Fn(Context) -> Result<()>,
+    {
+        self.steps.push(Step::Setup(Arc::new(setup)));
+        self
+    }
+
+    /// Adds a script to run in each state, named `name` in error messages and tracebacks.
+    pub fn script(mut self, name: &str, source: &str) -> Fixture {
+        self.steps
+            .push(Step::Script(name.to_owned(), source.to_owned()));
+        self
+    }
+
+    /// Creates a new state, running the setup functions and scripts in the order they were added.
+    pub fn build(&self) -> Result<Lua> {
+        let lua = Lua::new_with(self.libs);
+        lua.context(|lua| {
+            for step in &self.steps {
+                match step {
+                    Step::Setup(setup) => setup(lua)?,
+                    Step::Script(name, source) => {
+                        lua.load(source).set_name(&format!("@{}", name))?.exec()?
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        Ok(lua)
+    }
+}
+
+impl Default for Fixture {
+    fn default() -> Fixture {
+        Fixture::new()
+    }
+}
+
+impl fmt::Debug for Fixture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Fixture")
+            .field("libs", &self.libs)
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+/// The outcome of one test function run by [`run_script_tests`].
+///
+/// [`run_script_tests`]: fn.run_script_tests.html
+#[derive(Clone, Debug)]
+pub struct ScriptTest {
+    /// The `*_test.lua` file defining the test.
+    pub file: PathBuf,
+    /// The name of the test function, or `None` if the file itself failed to load or run.
+    pub name: Option<StdString>,
+    /// The error raised by the test, if it failed.
+    pub error: Option<Error>,
+}
+
+impl ScriptTest {
+    /// Whether the test passed.
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl fmt::Display for ScriptTest {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.file.display())?;
+        if let Some(name) = &self.name {
+            write!(fmt, "::{}", name)?;
+        }
+        match &self.error {
+            None => write!(fmt, " ... ok"),
+            Some(err) => write!(fmt, " ... FAILED\n{}", err),
+        }
+    }
+}
+
+/// Runs the Lua tests in every `*_test.lua` file in `dir` and its subdirectories.
+///
+/// Each file is run in a new state built from `fixture`, and then every global function it defined
+/// whose name starts with `test_` is called, in order of name.  A test fails if it raises an error.
+/// Files are visited in order of path, and the results are returned in the order tests were run.
+///
+/// # Errors
+///
+/// Returns an error if `dir` cannot be read or a fixture cannot be built.  Failing tests are
+/// reported in the results instead.
+pub fn run_script_tests<P: AsRef<Path>>(dir: P, fixture: &Fixture) -> Result<Vec<ScriptTest>> {
+    let mut files = Vec::new();
+    find_test_files(dir.as_ref(), &mut files).map_err(Error::external)?;
+    files.sort();
+
+    let mut results = Vec::new();
+    for file in files {
+        let source = fs::read(&file).map_err(Error::external)?;
+        fixture.build()?.context(|lua| {
+            let run = lua
+                .load(&source)
+                .set_name(&format!("@{}", file.display()))
+                .and_then(|chunk| chunk.exec());
+            if let Err(err) = run {
+                results.push(ScriptTest {
+                    file: file.clone(),
+                    name: None,
+                    error: Some(err),
+                });
+                return Ok(());
+            }
+
+            let mut tests = Vec::new();
+            for pair in lua.globals().pairs::<Value, Value>() {
+                if let (Value::String(name), Value::Function(test)) = pair? {
+                    match name.to_str() {
+                        Ok(name) if name.starts_with("test_") => {
+                            tests.push((name.to_owned(), test))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            tests.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, test) in tests {
+                results.push(ScriptTest {
+                    file: file.clone(),
+                    name: Some(name),
+                    error: test.call::<_, ()>(()).err(),
+                });
+            }
+            Ok(())
+        })?;
+    }
+    Ok(results)
+}
+
+/// Runs [`run_script_tests`], printing each result like the standard test harness, and panics if
+/// any test failed or no tests were found.
+///
+/// Calling this from a `#[test]` function makes the Lua tests part of `cargo test`.
+///
+/// [`run_script_tests`]: fn.run_script_tests.html
+pub fn assert_script_tests<P: AsRef<Path>>(dir: P, fixture: &Fixture) {
+    let dir = dir.as_ref();
+    let results = match run_script_tests(dir, fixture) {
+        Ok(results) => results,
+        Err(err) => panic!("cannot run Lua tests in {}: {}", dir.display(), err),
+    };
+    assert!(
+        !results.is_empty(),
+        "no Lua tests found in {}",
+        dir.display()
+    );
+    for result in &results {
+        println!("{}", result);
+    }
+    let failed = results.iter().filter(|r| !r.passed()).count();
+    assert!(
+        failed == 0,
+        "{} of {} Lua tests failed",
+        failed,
+        results.len()
+    );
+}
+
+fn find_test_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_test_files(&path, files)?;
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with("_test.lua"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Prints a Lua value in a deterministic, Lua-like format suitable for snapshots.
+///
+/// Tables are printed one entry per line with their keys sorted: booleans, then numbers, then
+/// strings, then everything else.  Floats are printed with the shortest representation which reads
+/// back as the same number, and always contain a `.` or exponent to distinguish them from integers.
+/// Functions, threads and userdata are printed as just their type, so the output does not depend on
+/// addresses.  Metatables are ignored, and a table containing itself prints `<cycle>` instead of
+/// recursing.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::testing::pretty_print;
+/// # use rlua::{Lua, Result, Value};
+/// # fn main() -> Result<()> {
+/// Lua::new().context(|lua_context| {
+///     let value: Value = lua_context.load("{ 10, 0.5, name = 'x', [true] = {} }").eval()?;
+///     assert_eq!(
+///         pretty_print(&value)?,
+///         "{\n  [true] = {},\n  [1] = 10,\n  [2] = 0.5,\n  name = \"x\",\n}"
+///     );
+///     Ok(())
+/// })
+/// # }
+/// ```
+pub fn pretty_print(value: &Value) -> Result<StdString> {
+    let mut out = Vec::new();
+    pretty_print_to(&mut out, value)?;
+    // The printer escapes everything which is not valid UTF-8.
+    Ok(rlua_expect!(
+        StdString::from_utf8(out),
+        "pretty printed value is not UTF-8"
+    ))
+}
+
+/// Prints a Lua value like [`pretty_print`], writing the output to `writer` as it goes instead of
+/// returning it as a string.
+///
+/// Only the keys of the tables being printed are held in memory, so this can print very large
+/// values directly to a file.  Errors returned by `writer` stop printing and are returned as an
+/// `Error::ExternalError`.
+///
+/// [`pretty_print`]: fn.pretty_print.html
+pub fn pretty_print_to<W: Write>(writer: W, value: &Value) -> Result<()> {
+    let mut printer = Printer {
+        out: writer,
+        tables: Vec::new(),
+    };
+    printer.value(value, 0)
+}
+
+/// Asserts that a Lua value matches the snapshot stored in the file at `path`.
+///
+/// The value is printed with [`pretty_print`] and compared with the contents of the file, and on a
+/// mismatch the panic message contains a line diff.  If the file does not exist, or the
+/// `RLUA_UPDATE_SNAPSHOTS` environment variable is set, the file is written instead, so snapshots
+/// can be created and updated by running the tests.  Relative paths are relative to the current
+/// directory, which `cargo test` sets to the package root.
+///
+/// [`pretty_print`]: fn.pretty_print.html
+pub fn assert_snapshot<P: AsRef<Path>>(path: P, value: &Value) {
+    let path = path.as_ref();
+    let actual = match pretty_print(value) {
+        Ok(actual) => actual + "\n",
+        Err(err) => panic!("cannot print snapshot {}: {}", path.display(), err),
+    };
+    let update = env::var_os("RLUA_UPDATE_SNAPSHOTS").is_some_and(|v| !v.is_empty());
+    if !update {
+        match fs::read_to_string(path) {
+            Ok(expected) if expected == actual => return,
+            Ok(expected) => panic!(
+                "snapshot {} does not match (- expected, + actual):\n{}",
+                path.display(),
+                diff_lines(&expected, &actual)
+            ),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => panic!("cannot read snapshot {}: {}", path.display(), err),
+        }
+    }
+    let written = match path.parent() {
+        Some(dir) => fs::create_dir_all(dir).and_then(|()| fs::write(path, &actual)),
+        None => fs::write(path, &actual),
+    };
+    if let Err(err) = written {
+        panic!("cannot write snapshot {}: {}", path.display(), err);
+    }
+}
+
+/// Checks that values generated by a `proptest` strategy convert to Lua and back unchanged.
+///
+/// Every generated value is converted with `ToLua` and then back with `FromLua`, and the check
+/// fails if either conversion fails or the result differs from the original.  This catches
+/// `ToLua` and `FromLua` implementations which do not agree with each other.  Failing values are
+/// shrunk, and the panic message shows the smallest failing value along with the Lua value it
+/// converted to, printed with [`pretty_print`].
+///
+/// Requires the `proptest` feature.
+///
+/// # Examples
+///
+/// ```
+/// # use proptest::prelude::*;
+/// # use rlua::testing::check_round_trip;
+/// check_round_trip(proptest::collection::vec(any::<i64>(), 0..10));
+/// check_round_trip(proptest::collection::hash_map("[a-z]{1,8}", any::<bool>(), 0..10));
+/// ```
+///
+/// # Panics
+///
+/// Panics with a description of the smallest failing value if the check fails.
+///
+/// [`pretty_print`]: fn.pretty_print.html
+#[cfg(feature = "proptest")]
+pub fn check_round_trip<S>(strategy: S)
+where
+    S: proptest::strategy::Strategy,
+    S::Value: Clone + PartialEq + fmt::Debug + for<'lua> ToLua<'lua> + for<'lua> FromLua<'lua>,
+{
+    use proptest::test_runner::{TestCaseError, TestError, TestRunner};
+
+    let lua = Lua::new();
+    let mut runner = TestRunner::default();
+    let result = runner.run(&strategy, |original| {
+        lua.context(|lua| {
+            let value = original
+                .clone()
+                .to_lua(lua)
+                .map_err(|err| TestCaseError::fail(format!("converting to Lua failed: {}", err)))?;
+            let printed = pretty_print(&value).unwrap_or_else(|err| format!("<{}>", err));
+            match S::Value::from_lua(value, lua) {
+                Ok(back) if back == original => Ok(()),
+                Ok(back) => Err(TestCaseError::fail(format!(
+                    "converted to Lua value {} and back to {:?}",
+                    printed, back
+                ))),
+                Err(err) => Err(TestCaseError::fail(format!(
+                    "converted to Lua value {} which failed to convert back: {}",
+                    printed, err
+                ))),
+            }
+        })
+    });
+    match result {
+        Ok(()) => {}
+        Err(TestError::Fail(reason, value)) => {
+            panic!("{:?} does not round trip: {}", value, reason)
+        }
+        Err(err) => panic!("{}", err),
+    }
+}
+
+struct Printer<W> {
+    out: W,
+    // Tables currently being printed, to detect cycles.
+    tables: Vec<*const c_void>,
+}
+
+impl<W: Write> Printer<W> {
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.out.write_all(text.as_bytes()).map_err(Error::external)
+    }
+
+    fn value(&mut self, value: &Value, indent: usize) -> Result<()> {
+        match value {
+            Value::Nil => self.write("nil"),
+            Value::Boolean(b) => self.write(if *b { "true" } else { "false" }),
+            Value::Integer(i) => self.write(&i.to_string()),
+            Value::Number(n) => self.write(&format_number(*n)),
+            Value::String(s) => self.string(s.as_bytes()),
+            Value::Table(t) => self.table(t, indent),
+            Value::Function(_) => self.write("<function>"),
+            Value::Thread(_) => self.write("<thread>"),
+            Value::UserData(_) => self.write("<userdata>"),
+            Value::LightUserData(_) => self.write("<lightuserdata>"),
+            Value::Error(err) => {
+                self.write("<error ")?;
+                self.string(err.to_string().as_bytes())?;
+                self.write(">")
+            }
+        }
+    }
+
+    fn table(&mut self, table: &Table, indent: usize) -> Result<()> {
+        let ptr = table.0.to_pointer();
+        if self.tables.contains(&ptr) {
+            return self.write("<cycle>");
+        }
+
+        let mut entries = Vec::new();
+        for pair in table.clone().pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            let mut key_printer = Printer {
+                out: Vec::new(),
+                tables: self.tables.clone(),
+            };
+            key_printer.tables.push(ptr);
+            key_printer.key(&key, indent + 1)?;
+            entries.push((key, key_printer.out, value));
+        }
+        if entries.is_empty() {
+            return self.write("{}");
+        }
+        entries.sort_by(|a, b| compare_keys(&a.0, &b.0).then_with(|| a.1.cmp(&b.1)));
+
+        self.tables.push(ptr);
+        self.write("{\n")?;
+        for (_, key, value) in entries {
+            self.indent(indent + 1)?;
+            self.out.write_all(&key).map_err(Error::external)?;
+            self.write(" = ")?;
+            self.value(&value, indent + 1)?;
+            self.write(",\n")?;
+        }
+        self.indent(indent)?;
+        self.write("}")?;
+        self.tables.pop();
+        Ok(())
+    }
+
+    fn key(&mut self, key: &Value, indent: usize) -> Result<()> {
+        if let Value::String(s) = key {
+            if let Ok(name) = s.to_str() {
+                if is_identifier(name) {
+                    return self.write(name);
+                }
+            }
+        }
+        self.write("[")?;
+        self.value(key, indent)?;
+        self.write("]")
+    }
+
+    // Quotes a string, escaping everything except printable ASCII and valid UTF-8.
+    fn string(&mut self, bytes: &[u8]) -> Result<()> {
+        let utf8 = std::str::from_utf8(bytes).ok();
+        let text = match utf8 {
+            Some(text) => text.chars().collect::<Vec<_>>(),
+            None => bytes.iter().map(|&b| b as char).collect(),
+        };
+        let mut quoted = StdString::with_capacity(text.len() + 2);
+        quoted.push('"');
+        for c in text {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_ascii_control() || (utf8.is_none() && !c.is_ascii()) => {
+                    quoted.push_str(&format!("\\{:03}", c as u32))
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        self.write(&quoted)
+    }
+
+    fn indent(&mut self, indent: usize) -> Result<()> {
+        for _ in 0..indent {
+            self.write("  ")?;
+        }
+        Ok(())
+    }
+}
+
+fn format_number(n: f64) -> StdString {
+    if n.is_nan() {
+        "nan".to_owned()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        format!("{:?}", n)
+    }
+}
+
+// Orders table keys by kind, then by value for booleans, numbers and strings.  Keys of other kinds
+// compare equal here and are ordered by how they print.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Boolean(_) => 0,
+            Value::Integer(_) | Value::Number(_) => 1,
+            Value::String(_) => 2,
+            _ => 3,
+        }
+    }
+
+    fn number(value: &Value) -> f64 {
+        match *value {
+            Value::Integer(i) => i as f64,
+            Value::Number(n) => n,
+            _ => 0.0,
+        }
+    }
+
+    match (a, b) {
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::String(a), Value::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        _ if rank(a) == 1 && rank(b) == 1 => {
+            number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal)
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+        "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name)
+}
+
+// A line diff of two texts, from the longest common subsequence of their lines.
+fn diff_lines(expected: &str, actual: &str) -> StdString {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+    let (n, m) = (expected.len(), actual.len());
+    let mut common = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = StdString::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            diff.push_str(&format!(" {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+            diff.push_str(&format!("-{}\n", expected[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", actual[j]));
+            j += 1;
+        }
+    }
+    diff
+}
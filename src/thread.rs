@@ -1,12 +1,22 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
 use std::os::raw::c_int;
+use std::pin::Pin;
+use std::task::{self, Poll};
 
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::lua::extra_data;
+use crate::table::Table;
 use crate::types::LuaRef;
+use crate::userdata::{AnyUserData, UserData};
 use crate::util::{
     assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure, StackGuard,
 };
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
+use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti, Value};
 
 /// Status of a Lua thread (or coroutine).
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -23,8 +33,21 @@ pub enum ThreadStatus {
     Error,
 }
 
+/// The outcome of resuming a thread with [`Thread::resume_result`].
+///
+/// [`Thread::resume_result`]: struct.Thread.html#method.resume_result
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResumeResult<Y, R> {
+    /// The thread yielded these values, and can be resumed again.
+    Yielded(Y),
+    /// The thread returned these values from its main function, and is finished.
+    Returned(R),
+}
+
 /// Handle to an internal Lua thread (or coroutine).
-#[derive(Clone, Debug)]
+///
+/// Cloning a `Thread` creates another handle to the same thread.
+#[derive(Clone)]
 pub struct Thread<'lua>(pub(crate) LuaRef<'lua>);
 
 impl<'lua> Thread<'lua> {
@@ -75,8 +98,67 @@ impl<'lua> Thread<'lua> {
         R: FromLuaMulti<'lua>,
     {
         let lua = self.0.lua;
-        let args = args.to_lua_multi(lua)?;
-        let results = unsafe {
+        let (results, _) = self.resume_multi(args.to_lua_multi(lua)?)?;
+        R::from_lua_multi(results, lua)
+    }
+
+    /// Resumes execution of this thread like [`resume`], but tells whether the thread yielded or
+    /// returned.
+    ///
+    /// Values yielded by the thread are converted to `Y`, values returned from its main function
+    /// to `R`.  This is useful for schedulers which drive many coroutines and need to know when
+    /// one of them is done, as a thread which returned cannot be told apart from a thread which
+    /// yielded by its values alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, ResumeResult, Result, Thread};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let thread: Thread = lua_context.load(r#"
+    ///     coroutine.create(function(n)
+    ///         for i = 1, n do
+    ///             coroutine.yield(i)
+    ///         end
+    ///         return "done"
+    ///     end)
+    /// "#).eval()?;
+    ///
+    /// let mut yielded = Vec::new();
+    /// loop {
+    ///     match thread.resume_result::<_, i64, String>(2)? {
+    ///         ResumeResult::Yielded(i) => yielded.push(i),
+    ///         ResumeResult::Returned(message) => {
+    ///             assert_eq!(message, "done");
+    ///             break;
+    ///         }
+    ///     }
+    /// }
+    /// assert_eq!(yielded, vec![1, 2]);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`resume`]: #method.resume
+    pub fn resume_result<A, Y, R>(&self, args: A) -> Result<ResumeResult<Y, R>>
+    where
+        A: ToLuaMulti<'lua>,
+        Y: FromLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let lua = self.0.lua;
+        match self.resume_multi(args.to_lua_multi(lua)?)? {
+            (results, true) => Ok(ResumeResult::Yielded(Y::from_lua_multi(results, lua)?)),
+            (results, false) => Ok(ResumeResult::Returned(R::from_lua_multi(results, lua)?)),
+        }
+    }
+
+    // Resumes the thread, returning its results and whether it yielded them.
+    fn resume_multi(&self, args: MultiValue<'lua>) -> Result<(MultiValue<'lua>, bool)> {
+        let lua = self.0.lua;
+        unsafe {
             let _sg = StackGuard::new(lua.state);
             assert_stack(lua.state, 3);
 
@@ -94,31 +176,55 @@ impl<'lua> Thread<'lua> {
             check_stack(lua.state, nargs)?;
             check_stack(thread_state, nargs + 1)?;
 
-            for arg in args {
-                lua.push_value(arg)?;
-            }
-            ffi::lua_xmove(lua.state, thread_state, nargs);
-
-            let ret = ffi::lua_resume(thread_state, lua.state, nargs);
-            if ret != ffi::LUA_OK && ret != ffi::LUA_YIELD {
-                protect_lua_closure(lua.state, 0, 0, |_| {
-                    error_traceback(thread_state);
-                    0
-                })?;
-                return Err(pop_error(thread_state, ret));
-            }
+            // Threads which failed cannot run again, so there is nothing to enter.  Spans are
+            // entered before the arguments are moved so that failing to enter them leaves the
+            // thread untouched, and exited only once the results or error are off its stack.
+            let entered = if status == ffi::LUA_OK || status == ffi::LUA_YIELD {
+                self.enter_spans()?
+            } else {
+                0
+            };
+            let resumed = self.resume_entered(thread_state, args);
+            let exited = self.exit_spans(entered);
+            let resumed = resumed?;
+            exited?;
+            Ok(resumed)
+        }
+    }
 
-            let nresults = ffi::lua_gettop(thread_state);
-            let mut results = MultiValue::new();
-            ffi::lua_xmove(thread_state, lua.state, nresults);
+    // Moves `args` to `thread_state` and resumes it, taking its results or error off its stack.
+    unsafe fn resume_entered(
+        &self,
+        thread_state: *mut ffi::lua_State,
+        args: MultiValue<'lua>,
+    ) -> Result<(MultiValue<'lua>, bool)> {
+        let lua = self.0.lua;
+        let _sg = StackGuard::new(lua.state);
 
-            assert_stack(lua.state, 2);
-            for _ in 0..nresults {
-                results.push_front(lua.pop_value());
-            }
-            results
-        };
-        R::from_lua_multi(results, lua)
+        let nargs = args.len() as c_int;
+        for arg in args {
+            lua.push_value(arg)?;
+        }
+        ffi::lua_xmove(lua.state, thread_state, nargs);
+
+        let ret = ffi::lua_resume(thread_state, lua.state, nargs);
+        if ret != ffi::LUA_OK && ret != ffi::LUA_YIELD {
+            protect_lua_closure(lua.state, 0, 0, |_| {
+                error_traceback(thread_state);
+                0
+            })?;
+            return Err(pop_error(thread_state, ret));
+        }
+
+        let nresults = ffi::lua_gettop(thread_state);
+        let mut results = MultiValue::new();
+        ffi::lua_xmove(thread_state, lua.state, nresults);
+
+        assert_stack(lua.state, 2);
+        for _ in 0..nresults {
+            results.push_front(lua.pop_value());
+        }
+        Ok((results, ret == ffi::LUA_YIELD))
     }
 
     /// Gets the status of the thread.
@@ -142,4 +248,344 @@ impl<'lua> Thread<'lua> {
             }
         }
     }
+
+    /// Converts this thread into a future which resumes it until it finishes.
+    ///
+    /// `args` are passed to the thread on its first resume.  This is how async functions created
+    /// with [`Context::create_async_function`] are run: whenever one of them is waiting on its
+    /// future, the thread yields and the returned future is pending until it is woken.  Values
+    /// yielded by the script itself with `coroutine.yield` are discarded, the future wakes itself
+    /// and the thread is resumed with no arguments on the next poll, so scripts can yield to the
+    /// executor.
+    ///
+    /// The future resolves to the values the thread returns.
+    ///
+    /// [`Context::create_async_function`]: struct.Context.html#method.create_async_function
+    pub fn into_async<A, R>(self, args: A) -> AsyncThread<'lua, R>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let args = args.to_lua_multi(self.0.lua);
+        AsyncThread {
+            thread: Some(self),
+            args: Some(args),
+            _return: PhantomData,
+        }
+    }
+
+    /// Stores `data` in this thread, returning the previously stored value of the same type.
+    ///
+    /// Every thread can store one value of each type, which Rust callbacks running on the thread
+    /// can read with [`Context::current_thread_data`].  The data is dropped when the thread is
+    /// garbage collected.
+    ///
+    /// [`Context::current_thread_data`]: struct.Context.html#method.current_thread_data
+    pub fn set_data<T: 'static + Send>(&self, data: T) -> Result<Option<T>> {
+        let storage = match self.storage(true)? {
+            Some(storage) => storage,
+            None => {
+                rlua_panic!("thread storage was not created");
+            }
+        };
+        let previous = storage
+            .borrow_mut::<ThreadStorage>()?
+            .data
+            .insert(TypeId::of::<T>(), Box::new(data));
+        Ok(previous.map(downcast))
+    }
+
+    /// Returns a copy of the data of type `T` stored with [`set_data`], or `None` if there is
+    /// none.
+    ///
+    /// [`set_data`]: #method.set_data
+    pub fn data<T: 'static + Clone>(&self) -> Result<Option<T>> {
+        Ok(match self.storage(false)? {
+            Some(storage) => storage
+                .borrow::<ThreadStorage>()?
+                .data
+                .get(&TypeId::of::<T>())
+                .and_then(|data| data.downcast_ref::<T>())
+                .cloned(),
+            None => None,
+        })
+    }
+
+    /// Removes and returns the data of type `T` stored with [`set_data`].
+    ///
+    /// [`set_data`]: #method.set_data
+    pub fn remove_data<T: 'static>(&self) -> Result<Option<T>> {
+        Ok(match self.storage(false)? {
+            Some(storage) => storage
+                .borrow_mut::<ThreadStorage>()?
+                .data
+                .remove(&TypeId::of::<T>())
+                .map(downcast),
+            None => None,
+        })
+    }
+
+    /// Adds a span which is entered whenever the thread is resumed from Rust, see [`ThreadSpan`].
+    ///
+    /// Spans are entered in the order they were added, and exited in reverse order.
+    ///
+    /// [`ThreadSpan`]: trait.ThreadSpan.html
+    pub fn add_span<S: ThreadSpan>(&self, span: S) -> Result<()> {
+        let storage = match self.storage(true)? {
+            Some(storage) => storage,
+            None => {
+                rlua_panic!("thread storage was not created");
+            }
+        };
+        storage
+            .borrow_mut::<ThreadStorage>()?
+            .spans
+            .push(Box::new(span));
+        Ok(())
+    }
+
+    /// Removes all spans added with [`add_span`].
+    ///
+    /// [`add_span`]: #method.add_span
+    pub fn clear_spans(&self) -> Result<()> {
+        if let Some(storage) = self.storage(false)? {
+            storage.borrow_mut::<ThreadStorage>()?.spans.clear();
+        }
+        Ok(())
+    }
+
+    // Enters the spans of this thread, returning the number of spans entered.
+    fn enter_spans(&self) -> Result<usize> {
+        Ok(match self.storage(false)? {
+            Some(storage) => {
+                let mut storage = storage.borrow_mut::<ThreadStorage>()?;
+                for span in &mut storage.spans {
+                    span.enter();
+                }
+                storage.spans.len()
+            }
+            None => 0,
+        })
+    }
+
+    // Exits the first `entered` spans of this thread, which may have been added to while running.
+    fn exit_spans(&self, entered: usize) -> Result<()> {
+        if entered > 0 {
+            if let Some(storage) = self.storage(false)? {
+                let mut storage = storage.borrow_mut::<ThreadStorage>()?;
+                let entered = entered.min(storage.spans.len());
+                for span in storage.spans[..entered].iter_mut().rev() {
+                    span.exit();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the userdata holding the data stored in this thread, creating it if `create` is set.
+    fn storage(&self, create: bool) -> Result<Option<AnyUserData<'lua>>> {
+        let lua = self.0.lua;
+        let table = unsafe {
+            match (*extra_data(lua.state)).thread_storage {
+                Some(id) => {
+                    let _sg = StackGuard::new(lua.state);
+                    assert_stack(lua.state, 1);
+                    ffi::lua_rawgeti(lua.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                    Table(lua.pop_ref())
+                }
+                None if !create => return Ok(None),
+                None => {
+                    let table = lua.create_table()?;
+                    let metatable = lua.create_table()?;
+                    metatable.raw_set("__mode", "k")?;
+                    table.set_metatable(Some(metatable))?;
+
+                    let _sg = StackGuard::new(lua.state);
+                    assert_stack(lua.state, 1);
+                    lua.push_ref(&table.0);
+                    let id = protect_lua_closure(lua.state, 1, 0, |state| {
+                        ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                    })?;
+                    (*extra_data(lua.state)).thread_storage = Some(id);
+                    table
+                }
+            }
+        };
+
+        match table.raw_get::<_, Option<AnyUserData>>(self.clone())? {
+            Some(storage) => Ok(Some(storage)),
+            None if create => {
+                let storage = lua.create_internal_userdata(ThreadStorage::default())?;
+                table.raw_set(self.clone(), storage.clone())?;
+                Ok(Some(storage))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Context which follows a thread across yields, such as a tracing span.
+///
+/// Spans are added to a thread with [`Thread::add_span`].  Whenever the thread is resumed from
+/// Rust, with [`Thread::resume`] or by polling an [`AsyncThread`], its spans are entered before
+/// any of its code runs, and exited again when it yields, returns or fails.  This way, context
+/// such as the current span of a tracing library or a thread local trace id is only active while
+/// the thread is actually running, and is seen by every Rust callback the thread calls.
+///
+/// Coroutines resumed from Lua with `coroutine.resume` or `coroutine.wrap` do not enter their
+/// spans.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Function, Lua, Result, ThreadSpan};
+/// # use std::cell::Cell;
+/// # fn main() -> Result<()> {
+/// thread_local! {
+///     static TRACE_ID: Cell<Option<u64>> = Cell::new(None);
+/// }
+///
+/// struct Trace {
+///     id: u64,
+///     outer: Option<u64>,
+/// }
+///
+/// impl ThreadSpan for Trace {
+///     fn enter(&mut self) {
+///         self.outer = TRACE_ID.with(|current| current.replace(Some(self.id)));
+///     }
+///
+///     fn exit(&mut self) {
+///         TRACE_ID.with(|current| current.set(self.outer));
+///     }
+/// }
+///
+/// Lua::new().context(|lua_context| {
+///     let trace_id = lua_context.create_function(|_, ()| Ok(TRACE_ID.with(|id| id.get())))?;
+///     lua_context.globals().set("trace_id", trace_id)?;
+///
+///     let handler: Function = lua_context.load(r#"
+///         function()
+///             coroutine.yield(trace_id())
+///             return trace_id()
+///         end
+///     "#).eval()?;
+///     let thread = lua_context.create_thread(handler)?;
+///     thread.add_span(Trace { id: 7, outer: None })?;
+///
+///     assert_eq!(thread.resume::<_, Option<u64>>(())?, Some(7));
+///     assert_eq!(TRACE_ID.with(|id| id.get()), None);
+///     assert_eq!(thread.resume::<_, Option<u64>>(())?, Some(7));
+///     Ok(())
+/// })
+/// # }
+/// ```
+///
+/// [`Thread::add_span`]: struct.Thread.html#method.add_span
+/// [`Thread::resume`]: struct.Thread.html#method.resume
+/// [`AsyncThread`]: struct.AsyncThread.html
+pub trait ThreadSpan: 'static + Send {
+    /// Called right before the thread runs.
+    fn enter(&mut self);
+
+    /// Called when the thread stops running, undoing `enter`.
+    fn exit(&mut self);
+}
+
+// What is stored in a thread with `Thread::set_data` and `Thread::add_span`.
+#[derive(Default)]
+struct ThreadStorage {
+    data: HashMap<TypeId, Box<dyn Any + Send>>,
+    spans: Vec<Box<dyn ThreadSpan>>,
+}
+
+impl UserData for ThreadStorage {}
+
+fn downcast<T: 'static>(data: Box<dyn Any + Send>) -> T {
+    match data.downcast() {
+        Ok(data) => *data,
+        Err(_) => {
+            rlua_panic!("thread data stored under the wrong type");
+        }
+    }
+}
+
+/// A future driving a Lua thread, created by [`Thread::into_async`] or [`Function::call_async`].
+///
+/// [`Thread::into_async`]: struct.Thread.html#method.into_async
+/// [`Function::call_async`]: struct.Function.html#method.call_async
+#[must_use = "futures do nothing unless polled"]
+pub struct AsyncThread<'lua, R> {
+    // Only `None` if creating the thread failed, in which case `args` holds the error.
+    thread: Option<Thread<'lua>>,
+    // The arguments for the first resume, or the error from converting them.
+    args: Option<Result<MultiValue<'lua>>>,
+    _return: PhantomData<fn() -> R>,
+}
+
+impl<'lua, R> AsyncThread<'lua, R> {
+    pub(crate) fn failed(err: Error) -> AsyncThread<'lua, R> {
+        AsyncThread {
+            thread: None,
+            args: Some(Err(err)),
+            _return: PhantomData,
+        }
+    }
+}
+
+impl<'lua, R: FromLuaMulti<'lua>> Future for AsyncThread<'lua, R> {
+    type Output = Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Result<R>> {
+        let this = self.get_mut();
+        let args = match this.args.take() {
+            Some(args) => args?,
+            None => MultiValue::new(),
+        };
+        let thread = rlua_expect!(this.thread.as_ref(), "async thread missing");
+        let lua = thread.0.lua;
+
+        let results = unsafe {
+            let extra = extra_data(lua.state);
+            let outer = (*extra).async_waker.replace(cx.waker().clone());
+            let results = thread.resume::<_, MultiValue>(args);
+            (*extra).async_waker = outer;
+            results?
+        };
+
+        if thread.status() == ThreadStatus::Resumable {
+            if !is_pending(&results) {
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        } else {
+            Poll::Ready(R::from_lua_multi(results, lua))
+        }
+    }
+}
+
+impl<'lua, R> fmt::Debug for AsyncThread<'lua, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.thread {
+            Some(thread) => write!(f, "AsyncThread({:?})", thread),
+            None => write!(f, "AsyncThread(<failed>)"),
+        }
+    }
+}
+
+// The address of this is yielded by async functions while their future is pending.
+pub(crate) static ASYNC_PENDING: u8 = 0;
+
+fn is_pending(values: &MultiValue) -> bool {
+    let mut values = values.iter();
+    match (values.next(), values.next()) {
+        (Some(Value::LightUserData(ud)), None) => ud.0 as *const u8 == &ASYNC_PENDING,
+        _ => false,
+    }
+}
+
+impl<'lua> fmt::Debug for Thread<'lua> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Thread({:p}, {:?})", self.0.to_pointer(), self.status())
+    }
 }
@@ -1,10 +1,12 @@
 use std::os::raw::{c_int, c_void};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::{fmt, mem, ptr};
 
 use crate::context::Context;
 use crate::error::Result;
 use crate::ffi;
+use crate::lua::extra_data;
+use crate::sync::Mutex;
 use crate::value::MultiValue;
 
 /// Type of Lua integer numbers.
@@ -55,7 +57,7 @@ impl fmt::Debug for RegistryKey {
 
 impl Drop for RegistryKey {
     fn drop(&mut self) {
-        if let Some(list) = rlua_expect!(self.unref_list.lock(), "unref_list poisoned").as_mut() {
+        if let Some(list) = self.unref_list.lock().as_mut() {
             list.push(self.registry_id);
         }
     }
@@ -73,11 +75,66 @@ impl RegistryKey {
     }
 }
 
+/// A key associating Lua values with a Rust object by its address.
+///
+/// This is a safe version of storing values with `lua_rawsetp` under the address of the object
+/// they belong to.  Keys are created with [`Context::create_ptr_key`], and the value is accessed
+/// with [`Context::set_ptr_value`] and [`Context::ptr_value`].  The values are kept in a table of
+/// rlua's own rather than in the registry, so they cannot clash with pointer keys set by C code.
+///
+/// The key is meant to be owned by the object it identifies, so that the value is released when
+/// the object is dropped: the value of a dropped key is removed by the next call to
+/// [`Context::expire_registry_values`], or as soon as a new key is created for any address, which
+/// keeps an object reusing the address from seeing a stale value.  Objects which share an address,
+/// such as a struct and its first field, share the value, so there should be only one key per
+/// address.
+///
+/// Like [`RegistryKey`], this is `Send + Sync + 'static`.
+///
+/// [`Context::create_ptr_key`]: struct.Context.html#method.create_ptr_key
+/// [`Context::set_ptr_value`]: struct.Context.html#method.set_ptr_value
+/// [`Context::ptr_value`]: struct.Context.html#method.ptr_value
+/// [`Context::expire_registry_values`]: struct.Context.html#method.expire_registry_values
+/// [`RegistryKey`]: struct.RegistryKey.html
+pub struct PtrKey {
+    pub(crate) ptr: usize,
+    pub(crate) unref_list: Arc<Mutex<Option<Vec<usize>>>>,
+}
+
+impl PtrKey {
+    /// Returns the address this key was created for.
+    pub fn as_ptr(&self) -> *const c_void {
+        self.ptr as *const c_void
+    }
+}
+
+impl fmt::Debug for PtrKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PtrKey({:p})", self.as_ptr())
+    }
+}
+
+impl Drop for PtrKey {
+    fn drop(&mut self) {
+        if let Some(list) = self.unref_list.lock().as_mut() {
+            list.push(self.ptr);
+        }
+    }
+}
+
 pub(crate) struct LuaRef<'lua> {
     pub(crate) lua: Context<'lua>,
     pub(crate) index: c_int,
 }
 
+impl<'lua> LuaRef<'lua> {
+    // Returns the address of the referenced object, only useful as a unique identity for tables,
+    // functions, userdata and threads.
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        unsafe { ffi::lua_topointer((*extra_data(self.lua.state)).ref_thread, self.index) }
+    }
+}
+
 impl<'lua> fmt::Debug for LuaRef<'lua> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Ref({})", self.index)
@@ -1,16 +1,25 @@
 use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::sync::Arc;
 
 use crate::context::Context;
+use crate::dynamic::DynUserData;
 use crate::error::{Error, Result};
 use crate::ffi;
-use crate::types::LuaRef;
-use crate::util::{assert_stack, get_userdata, StackGuard};
-use crate::value::{FromLua, FromLuaMulti, ToLua, ToLuaMulti};
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::types::{LightUserData, LuaRef};
+use crate::util::{assert_stack, get_userdata, is_taken_userdata, take_userdata_value, StackGuard};
+use crate::value::{FromLua, FromLuaMulti, Nil, ToLua, ToLuaMulti, Value};
 
 /// Kinds of metamethods that can be overridden.
 ///
-/// Currently, this mechanism does not allow overriding the `__gc` metamethod, since there is
-/// generally no need to do so: [`UserData`] implementors can instead just implement `Drop`.
+/// These are all the metamethods of Lua 5.3 whose value is a function.  Currently, this mechanism
+/// does not allow overriding the `__gc` metamethod, since there is generally no need to do so:
+/// [`UserData`] implementors can instead just implement `Drop`.  `__close` only exists in later
+/// versions of Lua, and `__name`, `__mode` and `__metatable` are not functions.
 ///
 /// [`UserData`]: trait.UserData.html
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -63,6 +72,16 @@ pub enum MetaMethod {
     ///
     /// This is not an operator, but will be called by methods such as `tostring` and `print`.
     ToString,
+    /// The `__pairs` metamethod.
+    ///
+    /// This is not an operator, but will be called by `pairs` to iterate over the object.
+    Pairs,
+    /// The `__ipairs` metamethod.
+    ///
+    /// This is deprecated in Lua 5.3 and only called by `ipairs` when Lua is built with
+    /// `LUA_COMPAT_IPAIRS`, which the bundled Lua is not.  Otherwise `ipairs` uses the `__index`
+    /// metamethod, so an object supporting integer indices can be iterated without it.
+    IPairs,
 }
 
 impl MetaMethod {
@@ -91,6 +110,8 @@ impl MetaMethod {
             MetaMethod::NewIndex => b"__newindex",
             MetaMethod::Call => b"__call",
             MetaMethod::ToString => b"__tostring",
+            MetaMethod::Pairs => b"__pairs",
+            MetaMethod::IPairs => b"__ipairs",
         }
     }
 }
@@ -152,6 +173,55 @@ pub trait UserDataMethods<'lua, T: UserData> {
         R: ToLuaMulti<'lua>,
         F: 'static + Send + FnMut(Context<'lua>, A) -> Result<R>;
 
+    /// Add a field getter which accepts a `&T` as its parameter, so that `userdata.name` returns the
+    /// result of `method`.
+    ///
+    /// Fields are implemented by overriding the `__index` and `__newindex` metamethods.  Regular
+    /// methods take priority over fields with the same name, and if `add_meta_method` is used to set
+    /// the `__index` metamethod, it will be used as a fall-back if no method or field is found.
+    fn add_field_method_get<S, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        M: 'static + Send + Fn(Context<'lua>, &T) -> Result<R>;
+
+    /// Add a field setter which accepts a `&mut T` and the assigned value as its parameters, so
+    /// that `userdata.name = value` calls `method`.
+    ///
+    /// Assigning to a name which has no setter is an error, unless the `__newindex` metamethod is
+    /// set with `add_meta_method`, which is then used as a fall-back.  Refer to
+    /// [`add_field_method_get`] for more information about the implementation.
+    ///
+    /// [`add_field_method_get`]: #method.add_field_method_get
+    fn add_field_method_set<S, A, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        M: 'static + Send + FnMut(Context<'lua>, &mut T, A) -> Result<()>;
+
+    /// Add a field getter as a function which accepts the userdata as an `AnyUserData`.
+    ///
+    /// Prefer to use [`add_field_method_get`] as it is easier to use.
+    ///
+    /// [`add_field_method_get`]: #method.add_field_method_get
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, AnyUserData<'lua>) -> Result<R>;
+
+    /// Add a field setter as a function which accepts the userdata as an `AnyUserData` and the
+    /// assigned value.
+    ///
+    /// Prefer to use [`add_field_method_set`] as it is easier to use.
+    ///
+    /// [`add_field_method_set`]: #method.add_field_method_set
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, AnyUserData<'lua>, A) -> Result<()>;
+
     /// Add a metamethod which accepts a `&T` as the first parameter.
     ///
     /// # Note
@@ -201,6 +271,278 @@ pub trait UserDataMethods<'lua, T: UserData> {
         A: FromLuaMulti<'lua>,
         R: ToLuaMulti<'lua>,
         F: 'static + Send + FnMut(Context<'lua>, A) -> Result<R>;
+
+    /// Makes the userdata callable like a function, passing `method` a `&T` and the call
+    /// arguments.
+    ///
+    /// This is the `__call` metamethod added with [`add_meta_method`].  Lua passes the called
+    /// userdata as the first argument of `__call`, so `method` is only given the arguments which
+    /// follow it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Multiplier(i64);
+    ///
+    /// impl UserData for Multiplier {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_call(|_, this, n: i64| Ok(this.0 * n));
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.globals().set("triple", Multiplier(3))?;
+    /// assert_eq!(lua_context.load("triple(5)").eval::<i64>()?, 15);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`add_meta_method`]: #method.add_meta_method
+    fn add_call<A, R, M>(&mut self, method: M)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + Fn(Context<'lua>, &T, A) -> Result<R>,
+    {
+        self.add_meta_method(MetaMethod::Call, method)
+    }
+
+    /// Makes the userdata callable like a function, passing `method` a `&mut T` and the call
+    /// arguments.
+    ///
+    /// Refer to [`add_call`] for more information.
+    ///
+    /// [`add_call`]: #method.add_call
+    fn add_call_mut<A, R, M>(&mut self, method: M)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + FnMut(Context<'lua>, &mut T, A) -> Result<R>,
+    {
+        self.add_meta_method_mut(MetaMethod::Call, method)
+    }
+
+    /// Adds the methods, fields and metamethods of the userdata type `B`, so that `T` inherits
+    /// them.
+    ///
+    /// The inherited methods are called with the `T` converted to a `B` through `AsRef` or
+    /// `AsMut`, typically by returning a field holding the base.  Anything added with the same name
+    /// after calling this replaces what was inherited, so a type can both extend and override its
+    /// base.  Bases can have bases of their own.
+    ///
+    /// Functions of `B` which take the userdata as an `AnyUserData`, rather than as `&B`, are
+    /// inherited as they are and so are passed the `T` userdata.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Entity {
+    ///     name: String,
+    /// }
+    ///
+    /// impl UserData for Entity {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method("name", |_, this, ()| Ok(this.name.clone()));
+    ///         methods.add_method("describe", |_, this, ()| Ok(format!("entity {}", this.name)));
+    ///     }
+    /// }
+    ///
+    /// struct Player {
+    ///     entity: Entity,
+    ///     score: i64,
+    /// }
+    ///
+    /// impl AsRef<Entity> for Player {
+    ///     fn as_ref(&self) -> &Entity {
+    ///         &self.entity
+    ///     }
+    /// }
+    ///
+    /// impl AsMut<Entity> for Player {
+    ///     fn as_mut(&mut self) -> &mut Entity {
+    ///         &mut self.entity
+    ///     }
+    /// }
+    ///
+    /// impl UserData for Player {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_base::<Entity>();
+    ///         methods.add_method("describe", |_, this, ()| {
+    ///             Ok(format!("player {} ({})", this.entity.name, this.score))
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// let player = Player {
+    ///     entity: Entity { name: "alice".to_owned() },
+    ///     score: 3,
+    /// };
+    /// lua_context.globals().set("player", player)?;
+    /// lua_context
+    ///     .load(r#"assert(player:name() == "alice" and player:describe() == "player alice (3)")"#)
+    ///     .exec()
+    /// # })
+    /// # }
+    /// ```
+    fn add_base<B>(&mut self)
+    where
+        Self: Sized,
+        B: UserData,
+        T: AsRef<B> + AsMut<B>,
+    {
+        B::add_methods(&mut BaseMethods {
+            methods: self,
+            _types: PhantomData,
+        });
+    }
+}
+
+// Adds the methods of a base userdata type `B` to the methods of `T`, see
+// `UserDataMethods::add_base`.
+struct BaseMethods<'a, M, T, B> {
+    methods: &'a mut M,
+    _types: PhantomData<fn(&T) -> &B>,
+}
+
+impl<'a, 'lua, M, T, B> UserDataMethods<'lua, B> for BaseMethods<'a, M, T, B>
+where
+    M: UserDataMethods<'lua, T>,
+    T: UserData + AsRef<B> + AsMut<B>,
+    B: UserData,
+{
+    fn add_method<S, A, R, F>(&mut self, name: &S, method: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, &B, A) -> Result<R>,
+    {
+        self.methods
+            .add_method(name, move |lua, this: &T, args: A| {
+                method(lua, this.as_ref(), args)
+            });
+    }
+
+    fn add_method_mut<S, A, R, F>(&mut self, name: &S, mut method: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, &mut B, A) -> Result<R>,
+    {
+        self.methods
+            .add_method_mut(name, move |lua, this: &mut T, args: A| {
+                method(lua, this.as_mut(), args)
+            });
+    }
+
+    fn add_function<S, A, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
+    {
+        self.methods.add_function(name, function);
+    }
+
+    fn add_function_mut<S, A, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, A) -> Result<R>,
+    {
+        self.methods.add_function_mut(name, function);
+    }
+
+    fn add_field_method_get<S, R, F>(&mut self, name: &S, method: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, &B) -> Result<R>,
+    {
+        self.methods
+            .add_field_method_get(name, move |lua, this: &T| method(lua, this.as_ref()));
+    }
+
+    fn add_field_method_set<S, A, F>(&mut self, name: &S, mut method: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, &mut B, A) -> Result<()>,
+    {
+        self.methods
+            .add_field_method_set(name, move |lua, this: &mut T, value: A| {
+                method(lua, this.as_mut(), value)
+            });
+    }
+
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, AnyUserData<'lua>) -> Result<R>,
+    {
+        self.methods.add_field_function_get(name, function);
+    }
+
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, AnyUserData<'lua>, A) -> Result<()>,
+    {
+        self.methods.add_field_function_set(name, function);
+    }
+
+    fn add_meta_method<A, R, F>(&mut self, meta: MetaMethod, method: F)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, &B, A) -> Result<R>,
+    {
+        self.methods
+            .add_meta_method(meta, move |lua, this: &T, args: A| {
+                method(lua, this.as_ref(), args)
+            });
+    }
+
+    fn add_meta_method_mut<A, R, F>(&mut self, meta: MetaMethod, mut method: F)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, &mut B, A) -> Result<R>,
+    {
+        self.methods
+            .add_meta_method_mut(meta, move |lua, this: &mut T, args: A| {
+                method(lua, this.as_mut(), args)
+            });
+    }
+
+    fn add_meta_function<A, R, F>(&mut self, meta: MetaMethod, function: F)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
+    {
+        self.methods.add_meta_function(meta, function);
+    }
+
+    fn add_meta_function_mut<A, R, F>(&mut self, meta: MetaMethod, function: F)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + FnMut(Context<'lua>, A) -> Result<R>,
+    {
+        self.methods.add_meta_function_mut(meta, function);
+    }
 }
 
 /// Trait for custom userdata types.
@@ -250,6 +592,12 @@ pub trait UserDataMethods<'lua, T: UserData> {
 ///         methods.add_meta_method(MetaMethod::Add, |_, this, value: i32| {
 ///             Ok(this.0 + value)
 ///         });
+///
+///         methods.add_field_method_get("value", |_, this| Ok(this.0));
+///         methods.add_field_method_set("value", |_, this, value: i32| {
+///             this.0 = value;
+///             Ok(())
+///         });
 ///     }
 /// }
 ///
@@ -260,6 +608,8 @@ pub trait UserDataMethods<'lua, T: UserData> {
 ///     myobject:add(7)
 ///     assert(myobject:get() == 130)
 ///     assert(myobject + 10 == 140)
+///     myobject.value = myobject.value + 1
+///     assert(myobject:get() == 131)
 /// "#).exec()?;
 /// # Ok(())
 /// # })
@@ -274,13 +624,78 @@ pub trait UserData: Sized {
     fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {}
 }
 
+/// Trait for trait objects which can be used as userdata through `Box<dyn Trait>` and
+/// `Arc<dyn Trait>`.
+///
+/// Implementing this for `dyn Trait` makes boxed and shared trait objects userdata whose methods
+/// dispatch dynamically to the type implementing the trait, so a plugin interface defined as a
+/// trait can be exposed to scripts without a userdata wrapper for each implementation.  The
+/// `#[user_data_trait]` attribute of `rlua-derive` implements this from the trait methods marked
+/// with `#[lua(method)]`.
+///
+/// Only shared access is possible through an `Arc`, so its methods cannot take `&mut self`.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result};
+/// use rlua_derive::user_data_trait;
+///
+/// #[user_data_trait]
+/// trait Plugin {
+///     #[lua(method)]
+///     fn name(&self) -> String;
+/// }
+///
+/// struct Greeter;
+///
+/// impl Plugin for Greeter {
+///     fn name(&self) -> String {
+///         "greeter".to_owned()
+///     }
+/// }
+///
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let plugin: Box<dyn Plugin + Send> = Box::new(Greeter);
+/// lua_context.globals().set("plugin", plugin)?;
+/// assert_eq!(lua_context.load("plugin:name()").eval::<String>()?, "greeter");
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+pub trait UserDataTrait {
+    /// Adds the methods of `Box<Self>` userdata.
+    fn add_boxed_methods<'lua, M: UserDataMethods<'lua, Box<Self>>>(_methods: &mut M) {}
+
+    /// Adds the methods of `Arc<Self>` userdata.
+    fn add_shared_methods<'lua, M: UserDataMethods<'lua, Arc<Self>>>(_methods: &mut M) {}
+}
+
+impl<T: ?Sized + UserDataTrait> UserData for Box<T> {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        T::add_boxed_methods(methods);
+    }
+}
+
+impl<T: ?Sized + UserDataTrait> UserData for Arc<T> {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        T::add_shared_methods(methods);
+    }
+}
+
 /// Handle to an internal Lua userdata for any type that implements [`UserData`].
 ///
 /// Similar to `std::any::Any`, this provides an interface for dynamic type checking via the [`is`]
-/// and [`borrow`] methods.
+/// and [`borrow`] methods, and [`type_name`] tells which type a userdata has.
 ///
 /// Internally, instances are stored in a `RefCell`, to best match the mutable semantics of the Lua
-/// language.
+/// language.  Borrows are counted, so any number of shared borrows of the same userdata (from
+/// [`borrow`] or from methods added with `UserDataMethods::add_method`) may be alive at once, even
+/// across nested Lua → Rust → Lua calls.  A `UserDataBorrowError` or `UserDataBorrowMutError` is
+/// only returned when a mutable borrow would overlap with any other borrow.
+///
+/// Cloning an `AnyUserData` creates another handle to the same userdata.
 ///
 /// # Note
 ///
@@ -290,7 +705,8 @@ pub trait UserData: Sized {
 /// [`UserData`]: trait.UserData.html
 /// [`is`]: #method.is
 /// [`borrow`]: #method.borrow
-#[derive(Clone, Debug)]
+/// [`type_name`]: #method.type_name
+#[derive(Clone)]
 pub struct AnyUserData<'lua>(pub(crate) LuaRef<'lua>);
 
 impl<'lua> AnyUserData<'lua> {
@@ -298,11 +714,65 @@ impl<'lua> AnyUserData<'lua> {
     pub fn is<T: 'static + UserData>(&self) -> bool {
         match self.inspect(|_: &RefCell<T>| Ok(())) {
             Ok(()) => true,
-            Err(Error::UserDataTypeMismatch) => false,
+            Err(Error::UserDataTypeMismatch) | Err(Error::UserDataDestructed) => false,
             Err(_) => unreachable!(),
         }
     }
 
+    /// Returns the Rust type name of this userdata, as returned by `std::any::type_name`.
+    ///
+    /// This works for all userdata created by rlua, including those created in a [`Scope`], and
+    /// returns `None` for userdata created by C code.  Use [`is`] to check for a specific type.
+    ///
+    /// [`Scope`]: struct.Scope.html
+    /// [`is`]: #method.is
+    pub fn type_name(&self) -> Option<&'static str> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 3);
+            lua.push_ref(&self.0);
+            let extra = extra_data(lua.state);
+            if ffi::lua_getmetatable(lua.state, -1) != 0 {
+                for (&id, &type_name) in &(*extra).userdata_type_names {
+                    ffi::lua_rawgeti(lua.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                    let equal = ffi::lua_rawequal(lua.state, -1, -2) != 0;
+                    ffi::lua_pop(lua.state, 1);
+                    if equal {
+                        return Some(type_name);
+                    }
+                }
+                ffi::lua_pop(lua.state, 1);
+            }
+            let key = ffi::lua_touserdata(lua.state, -1);
+            (*extra)
+                .nonstatic_userdata
+                .get(&key)
+                .map(|userdata| userdata.type_name())
+        }
+    }
+
+    /// Returns a copy of the metatable of this userdata, if it has one.
+    ///
+    /// The metatables of rlua types are protected from modification, so this returns a new table
+    /// with the same entries, like [`Table::shallow_clone`], to inspect the metamethods and methods
+    /// of a userdata.
+    ///
+    /// [`Table::shallow_clone`]: struct.Table.html#method.shallow_clone
+    pub fn metatable(&self) -> Result<Option<Table<'lua>>> {
+        let lua = self.0.lua;
+        let metatable = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+            lua.push_ref(&self.0);
+            if ffi::lua_getmetatable(lua.state, -1) == 0 {
+                return Ok(None);
+            }
+            Table(lua.pop_ref())
+        };
+        metatable.shallow_clone().map(Some)
+    }
+
     /// Borrow this userdata immutably if it is of type `T`.
     ///
     /// # Errors
@@ -327,11 +797,226 @@ impl<'lua> AnyUserData<'lua> {
         })
     }
 
+    /// Moves the value out of this userdata if it is of type `T`, invalidating the userdata.
+    ///
+    /// Afterwards, any use of the userdata from Lua raises a `UserDataDestructed` error, and
+    /// borrowing it from Rust fails with one.  The memory of the userdata itself is still reclaimed
+    /// by the garbage collector.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowMutError` if the userdata is borrowed, such as by a method running
+    /// on it.  Returns a `UserDataTypeMismatch` if the userdata is not of type `T`, and a
+    /// `UserDataDestructed` if its value was already taken.
+    pub fn take<T: 'static + UserData>(&self) -> Result<T> {
+        self.inspect(|cell: &RefCell<T>| {
+            cell.try_borrow_mut()
+                .map(|_| ())
+                .map_err(|_| Error::UserDataBorrowMutError)
+        })?;
+        let lua = self.0.lua;
+        unsafe {
+            // A taken userdata can no longer be found by `Context::try_borrow_userdata_by_ptr`.
+            if let Some(lookup) = lua.userdata_ptr_lookup::<T>() {
+                lookup.raw_set(LightUserData(self.to_pointer() as *mut c_void), Nil)?;
+            }
+
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+            lua.push_ref(&self.0);
+            Ok(take_userdata_value::<RefCell<T>>(lua.state).into_inner())
+        }
+    }
+
+    /// Drops the value of this userdata right away if it is of type `T`, instead of whenever the
+    /// garbage collector finalizes it.
+    ///
+    /// This releases the resources owned by the value deterministically, such as files or
+    /// connections, even while scripts still hold references to it.  See [`take`] for how the
+    /// userdata behaves afterwards, and the errors returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct File(String);
+    ///
+    /// impl UserData for File {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method("name", |_, file, ()| Ok(file.0.clone()));
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// let file = lua_context.create_userdata(File("log.txt".to_owned()))?;
+    /// lua_context.globals().set("file", file.clone())?;
+    /// file.destroy::<File>()?;
+    ///
+    /// match lua_context.load("file:name()").exec() {
+    ///     Err(Error::CallbackError { cause, .. }) => match *cause {
+    ///         Error::UserDataDestructed => {}
+    ///         ref err => panic!("expected UserDataDestructed, got {:?}", err),
+    ///     },
+    ///     r => panic!("expected CallbackError, got {:?}", r),
+    /// }
+    /// assert!(file.borrow::<File>().is_err());
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`take`]: #method.take
+    pub fn destroy<T: 'static + UserData>(&self) -> Result<()> {
+        self.take::<T>().map(drop)
+    }
+
+    /// Borrows the value wrapped in a [`DynUserData`] if it is of type `T`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowError` if the userdata is already mutably borrowed. Returns a
+    /// `UserDataTypeMismatch` if the userdata is not a `DynUserData` wrapping a `T`.
+    ///
+    /// [`DynUserData`]: struct.DynUserData.html
+    pub fn borrow_dyn<T: 'static>(&self) -> Result<Ref<'_, T>> {
+        Ref::filter_map(self.borrow::<DynUserData>()?, |dynamic| {
+            dynamic.downcast_ref::<T>()
+        })
+        .map_err(|_| Error::UserDataTypeMismatch)
+    }
+
+    /// Borrows the value wrapped in a [`DynUserData`] mutably if it is of type `T`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowMutError` if the userdata is already borrowed. Returns a
+    /// `UserDataTypeMismatch` if the userdata is not a `DynUserData` wrapping a `T`.
+    ///
+    /// [`DynUserData`]: struct.DynUserData.html
+    pub fn borrow_dyn_mut<T: 'static>(&self) -> Result<RefMut<'_, T>> {
+        RefMut::filter_map(self.borrow_mut::<DynUserData>()?, |dynamic| {
+            dynamic.downcast_mut::<T>()
+        })
+        .map_err(|_| Error::UserDataTypeMismatch)
+    }
+
+    /// Borrows a userdata created with [`Scope::create_nonstatic_userdata`] immutably if it is of
+    /// type `T`, and calls `f` with it.
+    ///
+    /// Non-'static userdata have no `TypeId`, so unlike [`borrow`] this looks the userdata up in a
+    /// registry of the live non-'static userdata, which records the name, size and alignment of
+    /// their types.  This is how "function" type methods of such userdata, which receive it as an
+    /// `AnyUserData`, get at its value.  The borrow ends when `f` returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowError` if the userdata is mutably borrowed.  Returns a
+    /// `UserDataTypeMismatch` if it was not created by `create_nonstatic_userdata` with type `T`,
+    /// or its scope has ended.
+    ///
+    /// # Safety
+    ///
+    /// Lifetimes are not part of the recorded type, so `T` must be the type the userdata was
+    /// created with down to its lifetime parameters.  Borrowing a `Foo<'a>` as a `Foo<'static>`
+    /// would let `f` keep references past the end of the scope.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{AnyUserData, Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Counter<'a>(&'a i64);
+    ///
+    /// impl<'a> UserData for Counter<'a> {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_function("get", |_, ud: AnyUserData| unsafe {
+    ///             ud.borrow_scoped::<Counter, _>(|counter| *counter.0)
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// let count = 42;
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.scope(|scope| {
+    ///     let counter = scope.create_nonstatic_userdata(Counter(&count))?;
+    ///     lua_context.globals().set("counter", counter)?;
+    ///     assert_eq!(lua_context.load("counter.get(counter)").eval::<i64>()?, 42);
+    ///     Ok(())
+    /// })
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Scope::create_nonstatic_userdata`]: struct.Scope.html#method.create_nonstatic_userdata
+    /// [`borrow`]: #method.borrow
+    pub unsafe fn borrow_scoped<T, R>(&self, f: impl FnOnce(&T) -> R) -> Result<R> {
+        let lua = self.0.lua;
+        let cell = {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+            lua.push_ref(&self.0);
+            let key = ffi::lua_touserdata(lua.state, -1);
+            match (*extra_data(lua.state)).nonstatic_userdata.get(&key) {
+                Some(userdata) => userdata.cell::<T>(),
+                None => None,
+            }
+        };
+        let cell = &*cell.ok_or(Error::UserDataTypeMismatch)?;
+        let data = cell.try_borrow().map_err(|_| Error::UserDataBorrowError)?;
+        Ok(f(&data))
+    }
+
+    /// Returns the address of this userdata's block of memory.
+    ///
+    /// The pointer stays the same for the lifetime of the userdata, so it may be handed to C code as
+    /// an identity and mapped back to the userdata with [`Context::try_borrow_userdata_by_ptr`].  It
+    /// must not be dereferenced.
+    ///
+    /// [`Context::try_borrow_userdata_by_ptr`]: struct.Context.html#method.try_borrow_userdata_by_ptr
+    pub fn to_pointer(&self) -> *const c_void {
+        self.0.to_pointer()
+    }
+
+    /// Returns the address of this userdata's block of memory if it is a userdata created by C code
+    /// with the metatable registered under `type_name`.
+    ///
+    /// This is the same check as `luaL_testudata`, for recognizing userdata created by C modules
+    /// with `luaL_newmetatable` / `luaL_setmetatable`.  The returned pointer can be passed back to
+    /// the C library owning the type.
+    pub fn foreign_ptr<S: ?Sized + AsRef<[u8]>>(
+        &self,
+        type_name: &S,
+    ) -> Result<Option<*mut c_void>> {
+        let lua = self.0.lua;
+        let metatable = match lua.named_registry_value::<_, Value>(type_name)? {
+            Value::Table(metatable) => metatable,
+            _ => return Ok(None),
+        };
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 3);
+            lua.push_ref(&self.0);
+            if ffi::lua_getmetatable(lua.state, -1) == 0 {
+                return Ok(None);
+            }
+            lua.push_ref(&metatable.0);
+            if ffi::lua_rawequal(lua.state, -1, -2) == 0 {
+                return Ok(None);
+            }
+            Ok(Some(ffi::lua_touserdata(lua.state, -3)))
+        }
+    }
+
     /// Sets an associated value to this `AnyUserData`.
     ///
-    /// The value may be any Lua value whatsoever, and can be retrieved with [`get_user_value`].
+    /// The value may be any Lua value whatsoever, such as a table of per-instance data or
+    /// callbacks, and can be retrieved with [`get_user_value`].  It is kept alive by the userdata
+    /// and collected along with it, so it may refer back to the userdata without leaking.  This
+    /// works for every userdata, including those created in a [`Scope`].
     ///
     /// [`get_user_value`]: #method.get_user_value
+    /// [`Scope`]: struct.Scope.html
     pub fn set_user_value<V: ToLua<'lua>>(&self, v: V) -> Result<()> {
         let lua = self.0.lua;
         let v = v.to_lua(lua)?;
@@ -372,7 +1057,9 @@ impl<'lua> AnyUserData<'lua> {
 
             lua.push_ref(&self.0);
 
-            if ffi::lua_getmetatable(lua.state, -1) == 0 {
+            if is_taken_userdata(lua.state, -1) {
+                Err(Error::UserDataDestructed)
+            } else if ffi::lua_getmetatable(lua.state, -1) == 0 {
                 Err(Error::UserDataTypeMismatch)
             } else {
                 ffi::lua_rawgeti(
@@ -390,3 +1077,9 @@ impl<'lua> AnyUserData<'lua> {
         }
     }
 }
+
+impl<'lua> fmt::Debug for AnyUserData<'lua> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AnyUserData({:p})", self.0.to_pointer())
+    }
+}
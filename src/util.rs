@@ -1,5 +1,6 @@
 use std::any::Any;
 use std::borrow::Cow;
+use std::ffi::CStr;
 use std::fmt::Write;
 use std::os::raw::{c_char, c_int, c_void};
 use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
@@ -8,6 +9,8 @@ use std::{mem, ptr, slice};
 
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::lua::{extra_data, ExtraData, OomBehavior};
+use crate::types::RegistryKey;
 
 // Checks that Lua has enough free stack space for future stack operations.  On failure, this will
 // panic with an internal error message.
@@ -30,6 +33,16 @@ pub unsafe fn check_stack(state: *mut ffi::lua_State, amount: c_int) -> Result<(
     }
 }
 
+// Checks that Lua has room for `amount` values on top of the stack reserve configured with
+// `Lua::set_stack_reserve`, returning `Error::StackError` on failure.
+pub unsafe fn reserve_stack(state: *mut ffi::lua_State, amount: usize) -> Result<()> {
+    let reserve = (*extra_data(state)).stack_reserve as usize;
+    if amount > ffi::LUAI_MAXSTACK as usize {
+        return Err(Error::StackError);
+    }
+    check_stack(state, (amount + reserve) as c_int)
+}
+
 pub struct StackGuard {
     state: *mut ffi::lua_State,
     top: c_int,
@@ -167,6 +180,17 @@ where
 //   3) Otherwise, interprets the error as the appropriate lua error.
 // Uses 2 stack spaces, does not call lua_checkstack.
 pub unsafe fn pop_error(state: *mut ffi::lua_State, err_code: c_int) -> Error {
+    pop_error_with(state, err_code, Some(extra_data(state)))
+}
+
+// Like `pop_error`, but takes the `ExtraData` of the state explicitly, so that it can also be used
+// on plain Lua states that were not created by rlua.  Without `ExtraData`, a memory error is
+// always returned as `Error::MemoryError` and error objects are returned as their string form.
+pub unsafe fn pop_error_with(
+    state: *mut ffi::lua_State,
+    err_code: c_int,
+    extra: Option<*mut ExtraData>,
+) -> Error {
     rlua_debug_assert!(
         err_code != ffi::LUA_OK && err_code != ffi::LUA_YIELD,
         "pop_error called with non-error return code"
@@ -182,6 +206,8 @@ pub unsafe fn pop_error(state: *mut ffi::lua_State, err_code: c_int) -> Error {
         } else {
             rlua_panic!("error during panic handling, panic was resumed twice")
         }
+    } else if err_code == ffi::LUA_ERRRUN && extra.is_some() && is_error_object(state, -1) {
+        pop_error_object(state)
     } else {
         let err_string = to_string(state, -1).into_owned();
         ffi::lua_pop(state, 1);
@@ -203,13 +229,67 @@ pub unsafe fn pop_error(state: *mut ffi::lua_State, err_code: c_int) -> Error {
                 // between that and "ordinary" runtime errors, we handle them the same way.
                 Error::RuntimeError(err_string)
             }
-            ffi::LUA_ERRMEM => Error::MemoryError(err_string),
+            ffi::LUA_ERRMEM => {
+                // The exceeded length is taken so that it is not reported again for a later
+                // memory error.
+                let limit = extra.and_then(|extra| {
+                    match (
+                        (*extra).string_limit_exceeded.take(),
+                        (*extra).string_length_limit,
+                    ) {
+                        (Some(length), Some(limit)) => Some((length, limit)),
+                        _ => None,
+                    }
+                });
+                match limit {
+                    Some((length, limit)) => Error::StringLimitExceeded { length, limit },
+                    None => Error::MemoryError(err_string),
+                }
+            }
             ffi::LUA_ERRGCMM => Error::GarbageCollectorError(err_string),
             _ => rlua_panic!("unrecognized lua error code"),
         }
     }
 }
 
+// Returns true if the value at `index` is a table or userdata raised as an error by Lua code, as
+// opposed to an error or panic raised by rlua.
+unsafe fn is_error_object(state: *mut ffi::lua_State, index: c_int) -> bool {
+    match ffi::lua_type(state, index) {
+        ffi::LUA_TTABLE => true,
+        ffi::LUA_TUSERDATA => {
+            get_wrapped_error(state, index).is_null() && !is_wrapped_panic(state, index)
+        }
+        _ => false,
+    }
+}
+
+// Pops the table or userdata at the top of the stack into an `Error::LuaError`.
+pub unsafe fn pop_error_object(state: *mut ffi::lua_State) -> Error {
+    let type_name = if ffi::lua_type(state, -1) == ffi::LUA_TTABLE {
+        "table"
+    } else {
+        "userdata"
+    };
+    let message = format!("(error object is a {} value)", type_name);
+    if ffi::lua_checkstack(state, 3) == 0 {
+        ffi::lua_pop(state, 1);
+        return Error::RuntimeError(message);
+    }
+    match protect_lua_closure(state, 1, 0, |state| {
+        ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+    }) {
+        Ok(registry_id) => Error::LuaError {
+            message,
+            value: Arc::new(RegistryKey {
+                registry_id,
+                unref_list: (*extra_data(state)).registry_unref_list.clone(),
+            }),
+        },
+        Err(err) => err,
+    }
+}
+
 // Internally uses 4 stack spaces, does not call checkstack
 pub unsafe fn push_string<S: ?Sized + AsRef<[u8]>>(
     state: *mut ffi::lua_State,
@@ -251,6 +331,17 @@ pub unsafe fn take_userdata<T>(state: *mut ffi::lua_State) -> T {
     ptr::read(ud)
 }
 
+// Like `take_userdata`, for values taken by `AnyUserData::take`: the userdata raises
+// `UserDataDestructed` rather than `CallbackDestructed` when used afterwards.
+pub unsafe fn take_userdata_value<T>(state: *mut ffi::lua_State) -> T {
+    get_taken_userdata_metatable(state);
+    ffi::lua_setmetatable(state, -2);
+    let ud = ffi::lua_touserdata(state, -1) as *mut T;
+    rlua_debug_assert!(!ud.is_null(), "userdata pointer is null");
+    ffi::lua_pop(state, 1);
+    ptr::read(ud)
+}
+
 // Populates the given table with the appropriate members to be a userdata metatable for the given
 // type.  This function takes the given table at the `metatable` index, and adds an appropriate __gc
 // member to it for the given type and a __metatable entry to protect the table from script access.
@@ -326,6 +417,101 @@ pub unsafe fn init_userdata_metatable<T>(
     Ok(())
 }
 
+// Sets up field access on the userdata metatable at the `metatable` index, and must be called
+// before `init_userdata_metatable`.  Given a `getters` table index, the __index metamethod is
+// replaced with one that calls the getter stored under the accessed key with the userdata, falling
+// back to the previous __index function, if any.  Given a `setters` table index, __newindex is
+// replaced the same way, calling the setter with the userdata and assigned value and raising an
+// error for unknown keys if there was no previous __newindex function.  Internally uses 6 stack
+// spaces and does not call checkstack.
+pub unsafe fn init_userdata_fields(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    getters: Option<c_int>,
+    setters: Option<c_int>,
+) -> Result<()> {
+    unsafe extern "C" fn field_index_impl(state: *mut ffi::lua_State) -> c_int {
+        ffi::luaL_checkstack(state, 2, ptr::null());
+        ffi::lua_settop(state, 2);
+
+        ffi::lua_pushvalue(state, 2);
+        if ffi::lua_rawget(state, ffi::lua_upvalueindex(1)) != ffi::LUA_TNIL {
+            ffi::lua_pushvalue(state, 1);
+            ffi::lua_call(state, 1, 1);
+        } else if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
+            ffi::lua_pop(state, 1);
+            ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+            ffi::lua_insert(state, 1);
+            ffi::lua_call(state, 2, 1);
+        }
+        1
+    }
+
+    unsafe extern "C" fn field_newindex_impl(state: *mut ffi::lua_State) -> c_int {
+        ffi::luaL_checkstack(state, 2, ptr::null());
+        ffi::lua_settop(state, 3);
+
+        ffi::lua_pushvalue(state, 2);
+        if ffi::lua_rawget(state, ffi::lua_upvalueindex(1)) != ffi::LUA_TNIL {
+            ffi::lua_insert(state, 1);
+            ffi::lua_remove(state, 3);
+            ffi::lua_call(state, 2, 0);
+        } else if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
+            ffi::lua_pop(state, 1);
+            ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+            ffi::lua_insert(state, 1);
+            ffi::lua_call(state, 3, 0);
+        } else {
+            let key = if ffi::lua_type(state, 2) == ffi::LUA_TSTRING {
+                CStr::from_ptr(ffi::lua_tostring(state, 2))
+                    .to_string_lossy()
+                    .into_owned()
+            } else {
+                "?".to_owned()
+            };
+            callback_error(state, |_| {
+                Err::<(), _>(Error::RuntimeError(format!(
+                    "cannot set field '{}' of userdata",
+                    key
+                )))
+            })
+        }
+        0
+    }
+
+    let metatable = ffi::lua_absindex(state, metatable);
+    for &(field_table, meta, imp) in &[
+        (getters, "__index", field_index_impl as ffi::lua_CFunction),
+        (
+            setters,
+            "__newindex",
+            field_newindex_impl as ffi::lua_CFunction,
+        ),
+    ] {
+        let field_table = match field_table {
+            Some(field_table) => ffi::lua_absindex(state, field_table),
+            None => continue,
+        };
+
+        ffi::lua_pushvalue(state, metatable);
+        push_string(state, meta)?;
+        ffi::lua_pushvalue(state, field_table);
+        ffi::lua_pushvalue(state, -2);
+        let previous_type = ffi::lua_rawget(state, metatable);
+        if previous_type != ffi::LUA_TNIL && previous_type != ffi::LUA_TFUNCTION {
+            rlua_panic!("improper {} type {}", meta, previous_type);
+        }
+        protect_lua_closure(state, 2, 1, |state| {
+            ffi::lua_pushcclosure(state, imp, 2);
+        })?;
+        protect_lua_closure(state, 3, 0, |state| {
+            ffi::lua_rawset(state, -3);
+        })?;
+    }
+
+    Ok(())
+}
+
 pub unsafe extern "C" fn userdata_destructor<T>(state: *mut ffi::lua_State) -> c_int {
     callback_error(state, |_| {
         take_userdata::<T>(state);
@@ -375,10 +561,32 @@ where
         }
         Ok(Err(err)) => {
             ffi::lua_settop(state, 1);
-            ptr::write(ud as *mut WrappedError, WrappedError(err));
-            get_error_metatable(state);
-            ffi::lua_setmetatable(state, -2);
-            ffi::lua_error(state)
+            match err {
+                Error::LuaError { message, value }
+                    if Arc::ptr_eq(
+                        &value.unref_list,
+                        &(*extra_data(state)).registry_unref_list,
+                    ) =>
+                {
+                    // Error objects are raised again as they are rather than wrapped.  Nothing may
+                    // be left to drop once `lua_error` jumps out.
+                    ffi::lua_rawgeti(
+                        state,
+                        ffi::LUA_REGISTRYINDEX,
+                        value.registry_id as ffi::lua_Integer,
+                    );
+                    drop(message);
+                    drop(value);
+                    ffi::lua_error(state)
+                }
+                err => {
+                    let traceback = raise_traceback(state, &err);
+                    ptr::write(ud as *mut WrappedError, WrappedError(err, traceback));
+                    get_error_metatable(state);
+                    ffi::lua_setmetatable(state, -2);
+                    ffi::lua_error(state)
+                }
+            }
         }
         Err(p) => {
             ffi::lua_settop(state, 1);
@@ -390,6 +598,32 @@ where
     }
 }
 
+// Takes the traceback of a callback which is about to raise `err`, so that scripts catching the
+// error with `pcall` can see where it was raised.  Never raises a Lua error, returns an empty string
+// if tracebacks are disabled or there is not enough stack space or memory.
+unsafe fn raise_traceback(state: *mut ffi::lua_State, err: &Error) -> String {
+    if !(*extra_data(state)).traceback_enabled
+        || ffi::lua_checkstack(state, LUA_TRACEBACK_STACK + 3) == 0
+    {
+        return String::new();
+    }
+    if let Error::MemoryError(_) = err.root_cause() {
+        return String::new();
+    }
+
+    // Level 0 is the protected call itself, level 1 the callback.
+    match protect_lua_closure(state, 0, 1, |state| {
+        ffi::luaL_traceback(state, state, ptr::null(), 1)
+    }) {
+        Ok(()) => {
+            let traceback = to_string(state, -1).into_owned();
+            ffi::lua_pop(state, 1);
+            traceback
+        }
+        Err(_) => String::new(),
+    }
+}
+
 // Takes an error at the top of the stack, and if it is a WrappedError, converts it to an
 // Error::CallbackError with a traceback, if it is some lua type, prints the error along with a
 // traceback, and if it is a WrappedPanic, does not modify it.  This function does its best to avoid
@@ -397,17 +631,19 @@ where
 // shadow rust errors under certain memory conditions.  This function ensures that such behavior
 // will *never* occur with a rust panic, however.
 pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
-    // I believe luaL_traceback requires this much free stack to not error.
-    const LUA_TRACEBACK_STACK: c_int = 11;
-
     if ffi::lua_checkstack(state, 2) == 0 {
         // If we don't have enough stack space to even check the error type, do nothing so we don't
         // risk shadowing a rust panic.
+    } else if is_propagated_memory_error(state, ffi::LUA_ERRRUN, -1) {
+        // Memory errors which Lua code may not catch reach Rust unchanged, like memory errors
+        // raised by Lua itself.
     } else if let Some(error) = get_wrapped_error(state, -1).as_ref() {
         // lua_newuserdata and luaL_traceback may error, but nothing that implements Drop should be
         // on the rust stack at this time.
         let ud = ffi::lua_newuserdata(state, mem::size_of::<WrappedError>()) as *mut WrappedError;
-        let traceback = if ffi::lua_checkstack(state, LUA_TRACEBACK_STACK) != 0 {
+        let traceback = if !(*extra_data(state)).traceback_enabled {
+            String::new()
+        } else if ffi::lua_checkstack(state, LUA_TRACEBACK_STACK) != 0 {
             ffi::luaL_traceback(state, state, ptr::null(), 0);
 
             let traceback = to_string(state, -1).into_owned();
@@ -422,14 +658,20 @@ pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
 
         ptr::write(
             ud,
-            WrappedError(Error::CallbackError {
-                traceback,
-                cause: Arc::new(error),
-            }),
+            WrappedError(
+                Error::CallbackError {
+                    traceback,
+                    cause: Arc::new(error),
+                },
+                String::new(),
+            ),
         );
         get_error_metatable(state);
         ffi::lua_setmetatable(state, -2);
-    } else if !is_wrapped_panic(state, -1) {
+    } else if !is_wrapped_panic(state, -1)
+        && !is_error_object(state, -1)
+        && (*extra_data(state)).traceback_enabled
+    {
         if ffi::lua_checkstack(state, LUA_TRACEBACK_STACK) != 0 {
             let s = ffi::luaL_tolstring(state, -1, ptr::null_mut());
             ffi::luaL_traceback(state, state, s, 0);
@@ -447,17 +689,22 @@ pub unsafe extern "C" fn safe_pcall(state: *mut ffi::lua_State) -> c_int {
     if top == 0 {
         ffi::lua_pushstring(state, cstr!("not enough arguments to pcall"));
         ffi::lua_error(state);
-    } else if ffi::lua_pcall(state, top - 1, ffi::LUA_MULTRET, 0) != ffi::LUA_OK {
+    } else {
+        let status = ffi::lua_pcall(state, top - 1, ffi::LUA_MULTRET, 0);
+        if status == ffi::LUA_OK {
+            ffi::lua_pushboolean(state, 1);
+            ffi::lua_insert(state, 1);
+            return ffi::lua_gettop(state);
+        }
         if is_wrapped_panic(state, -1) {
             ffi::lua_error(state);
         }
+        if is_propagated_memory_error(state, status, -1) {
+            raise_memory_error(state, status);
+        }
         ffi::lua_pushboolean(state, 0);
         ffi::lua_insert(state, -2);
         2
-    } else {
-        ffi::lua_pushboolean(state, 1);
-        ffi::lua_insert(state, 1);
-        ffi::lua_gettop(state)
     }
 }
 
@@ -466,7 +713,7 @@ pub unsafe extern "C" fn safe_xpcall(state: *mut ffi::lua_State) -> c_int {
     unsafe extern "C" fn xpcall_msgh(state: *mut ffi::lua_State) -> c_int {
         ffi::luaL_checkstack(state, 2, ptr::null());
 
-        if is_wrapped_panic(state, -1) {
+        if is_wrapped_panic(state, -1) || is_propagated_memory_error(state, ffi::LUA_ERRRUN, -1) {
             1
         } else {
             ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
@@ -494,6 +741,9 @@ pub unsafe extern "C" fn safe_xpcall(state: *mut ffi::lua_State) -> c_int {
         if is_wrapped_panic(state, -1) {
             ffi::lua_error(state);
         }
+        if is_propagated_memory_error(state, res, -1) {
+            raise_memory_error(state, res);
+        }
         ffi::lua_pushboolean(state, 0);
         ffi::lua_insert(state, -2);
         2
@@ -504,13 +754,50 @@ pub unsafe extern "C" fn safe_xpcall(state: *mut ffi::lua_State) -> c_int {
     }
 }
 
+// Checks whether the error at the given index, caught by `pcall` or `xpcall` with the given
+// status, is a memory error which `OomBehavior::Propagate` forbids Lua code from catching.  Uses 2
+// stack spaces and does not call lua_checkstack.
+unsafe fn is_propagated_memory_error(
+    state: *mut ffi::lua_State,
+    status: c_int,
+    index: c_int,
+) -> bool {
+    if (*extra_data(state)).oom_behavior != OomBehavior::Propagate {
+        return false;
+    }
+    if status == ffi::LUA_ERRMEM {
+        return true;
+    }
+    matches!(
+        get_wrapped_error(state, index)
+            .as_ref()
+            .map(Error::root_cause),
+        Some(Error::MemoryError(_)) | Some(Error::StringLimitExceeded { .. })
+    )
+}
+
+// Raises the memory error on top of the stack again.  `lua_error` always raises a runtime error,
+// so a plain memory error is first wrapped, which keeps it a memory error once it reaches Rust.
+unsafe fn raise_memory_error(state: *mut ffi::lua_State, status: c_int) -> ! {
+    if status == ffi::LUA_ERRMEM {
+        let err = pop_error(state, status);
+        if push_wrapped_error(state, err).is_err() {
+            ffi::lua_pushstring(state, cstr!("not enough memory"));
+        }
+    }
+    ffi::lua_error(state)
+}
+
+// I believe luaL_traceback requires this much free stack to not error.
+const LUA_TRACEBACK_STACK: c_int = 11;
+
 // Pushes a WrappedError to the top of the stack.  Uses two stack spaces and does not call
 // lua_checkstack.
 pub unsafe fn push_wrapped_error(state: *mut ffi::lua_State, err: Error) -> Result<()> {
     let ud = protect_lua_closure(state, 0, 1, move |state| {
         ffi::lua_newuserdata(state, mem::size_of::<WrappedError>()) as *mut WrappedError
     })?;
-    ptr::write(ud, WrappedError(err));
+    ptr::write(ud, WrappedError(err, String::new()));
     get_error_metatable(state);
     ffi::lua_setmetatable(state, -2);
     Ok(())
@@ -533,7 +820,7 @@ pub unsafe fn get_wrapped_error(state: *mut ffi::lua_State, index: c_int) -> *co
     ffi::lua_pop(state, 2);
 
     if res {
-        &(*get_userdata::<WrappedError>(state, -1)).0
+        &(*get_userdata::<WrappedError>(state, index)).0
     } else {
         ptr::null()
     }
@@ -578,6 +865,35 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) {
         1
     }
 
+    // Exposes the `kind`, `message` and `traceback` fields of an error to scripts.
+    unsafe extern "C" fn error_index(state: *mut ffi::lua_State) -> c_int {
+        callback_error(state, |_| {
+            check_stack(state, 2)?;
+            // The first stack slot is taken by `callback_error`.
+            if get_wrapped_error(state, 2).is_null() {
+                return Err(Error::UserDataTypeMismatch);
+            }
+            let WrappedError(error, raised_traceback) = &*get_userdata::<WrappedError>(state, 2);
+            let key = if ffi::lua_type(state, 3) == ffi::LUA_TSTRING {
+                CStr::from_ptr(ffi::lua_tostring(state, 3)).to_bytes()
+            } else {
+                &[]
+            };
+            match (key, error) {
+                (b"kind", _) => push_string(state, error.kind_name())?,
+                (b"message", _) => push_string(state, &error.root_cause().to_string())?,
+                (b"traceback", Error::CallbackError { traceback, .. }) if !traceback.is_empty() => {
+                    push_string(state, traceback)?
+                }
+                (b"traceback", _) if !raised_traceback.is_empty() => {
+                    push_string(state, raised_traceback)?
+                }
+                _ => ffi::lua_pushnil(state),
+            }
+            Ok(1)
+        })
+    }
+
     ffi::lua_pushlightuserdata(
         state,
         &ERROR_METATABLE_REGISTRY_KEY as *const u8 as *mut c_void,
@@ -592,6 +908,10 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) {
     ffi::lua_pushcfunction(state, error_tostring);
     ffi::lua_rawset(state, -3);
 
+    ffi::lua_pushstring(state, cstr!("__index"));
+    ffi::lua_pushcfunction(state, error_index);
+    ffi::lua_rawset(state, -3);
+
     ffi::lua_pushstring(state, cstr!("__metatable"));
     ffi::lua_pushboolean(state, 0);
     ffi::lua_rawset(state, -3);
@@ -622,16 +942,58 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) {
         ffi::luaL_checkstack(state, 2, ptr::null());
         let ud = ffi::lua_newuserdata(state, mem::size_of::<WrappedError>()) as *mut WrappedError;
 
-        ptr::write(ud, WrappedError(Error::CallbackDestructed));
+        ptr::write(ud, WrappedError(Error::CallbackDestructed, String::new()));
         get_error_metatable(state);
         ffi::lua_setmetatable(state, -2);
         ffi::lua_error(state)
     }
 
-    ffi::lua_pushlightuserdata(
-        state,
-        &DESTRUCTED_USERDATA_METATABLE as *const u8 as *mut c_void,
-    );
+    // Userdata whose value was taken with `AnyUserData::take` get a metatable of their own, which
+    // raises `UserDataDestructed` instead.
+    unsafe extern "C" fn taken_error(state: *mut ffi::lua_State) -> c_int {
+        ffi::luaL_checkstack(state, 2, ptr::null());
+        let ud = ffi::lua_newuserdata(state, mem::size_of::<WrappedError>()) as *mut WrappedError;
+
+        ptr::write(ud, WrappedError(Error::UserDataDestructed, String::new()));
+        get_error_metatable(state);
+        ffi::lua_setmetatable(state, -2);
+        ffi::lua_error(state)
+    }
+
+    for &(key, error) in &[
+        (
+            &DESTRUCTED_USERDATA_METATABLE,
+            destructed_error as ffi::lua_CFunction,
+        ),
+        (&TAKEN_USERDATA_METATABLE, taken_error),
+    ] {
+        init_destructed_userdata_metatable(state, key, error);
+    }
+
+    // Create error print buffer
+
+    ffi::lua_pushlightuserdata(state, &ERROR_PRINT_BUFFER_KEY as *const u8 as *mut c_void);
+
+    let ud = ffi::lua_newuserdata(state, mem::size_of::<String>()) as *mut String;
+    ptr::write(ud, String::new());
+
+    ffi::lua_newtable(state);
+    ffi::lua_pushstring(state, cstr!("__gc"));
+    ffi::lua_pushcfunction(state, userdata_destructor::<String>);
+    ffi::lua_rawset(state, -3);
+    ffi::lua_setmetatable(state, -2);
+
+    ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
+}
+
+// Stores a metatable for userdata whose value was dropped in the registry under `key`, with every
+// metamethod calling `error`.
+unsafe fn init_destructed_userdata_metatable(
+    state: *mut ffi::lua_State,
+    key: &'static u8,
+    error: ffi::lua_CFunction,
+) {
+    ffi::lua_pushlightuserdata(state, key as *const u8 as *mut c_void);
     ffi::lua_newtable(state);
 
     for &method in &[
@@ -662,34 +1024,21 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) {
         cstr!("__ipairs"),
     ] {
         ffi::lua_pushstring(state, method);
-        ffi::lua_pushcfunction(state, destructed_error);
+        ffi::lua_pushcfunction(state, error);
         ffi::lua_rawset(state, -3);
     }
 
     ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
-
-    // Create error print buffer
-
-    ffi::lua_pushlightuserdata(state, &ERROR_PRINT_BUFFER_KEY as *const u8 as *mut c_void);
-
-    let ud = ffi::lua_newuserdata(state, mem::size_of::<String>()) as *mut String;
-    ptr::write(ud, String::new());
-
-    ffi::lua_newtable(state);
-    ffi::lua_pushstring(state, cstr!("__gc"));
-    ffi::lua_pushcfunction(state, userdata_destructor::<String>);
-    ffi::lua_rawset(state, -3);
-    ffi::lua_setmetatable(state, -2);
-
-    ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
 }
 
-struct WrappedError(pub Error);
+// A Rust error raised as a Lua error, along with the traceback taken when a callback raised it.  The
+// traceback is empty for errors raised in any other way.
+struct WrappedError(pub Error, pub String);
 struct WrappedPanic(pub Option<Box<Any + Send>>);
 
 // Converts the given lua value to a string in a reasonable format without causing a Lua error or
 // panicking.
-unsafe fn to_string<'a>(state: *mut ffi::lua_State, index: c_int) -> Cow<'a, str> {
+pub unsafe fn to_string<'a>(state: *mut ffi::lua_State, index: c_int) -> Cow<'a, str> {
     match ffi::lua_type(state, index) {
         ffi::LUA_TNONE => "<none>".into(),
         ffi::LUA_TNIL => "<nil>".into(),
@@ -761,7 +1110,25 @@ unsafe fn get_destructed_userdata_metatable(state: *mut ffi::lua_State) {
     ffi::lua_rawget(state, ffi::LUA_REGISTRYINDEX);
 }
 
+unsafe fn get_taken_userdata_metatable(state: *mut ffi::lua_State) {
+    ffi::lua_pushlightuserdata(state, &TAKEN_USERDATA_METATABLE as *const u8 as *mut c_void);
+    ffi::lua_rawget(state, ffi::LUA_REGISTRYINDEX);
+}
+
+// Returns true if the value at the given index is a userdata whose value was already taken by
+// `take_userdata_value`.  Uses 2 stack spaces, does not call checkstack.
+pub unsafe fn is_taken_userdata(state: *mut ffi::lua_State, index: c_int) -> bool {
+    if ffi::lua_getmetatable(state, index) == 0 {
+        return false;
+    }
+    get_taken_userdata_metatable(state);
+    let destructed = ffi::lua_rawequal(state, -1, -2) != 0;
+    ffi::lua_pop(state, 2);
+    destructed
+}
+
 static ERROR_METATABLE_REGISTRY_KEY: u8 = 0;
 static PANIC_METATABLE_REGISTRY_KEY: u8 = 0;
 static DESTRUCTED_USERDATA_METATABLE: u8 = 0;
+static TAKEN_USERDATA_METATABLE: u8 = 0;
 static ERROR_PRINT_BUFFER_KEY: u8 = 0;
@@ -73,6 +73,13 @@ pub trait ToLua<'lua> {
 pub trait FromLua<'lua>: Sized {
     /// Performs the conversion.
     fn from_lua(lua_value: Value<'lua>, lua: Context<'lua>) -> Result<Self>;
+
+    // Converts a value missing from the end of a `MultiValue`, such as an argument which was not
+    // passed.  Only `Option` tells this apart from nil.
+    #[doc(hidden)]
+    fn from_lua_missing(lua: Context<'lua>) -> Result<Self> {
+        Self::from_lua(Nil, lua)
+    }
 }
 
 /// Multiple Lua values used for both argument passing and also for multiple return values.
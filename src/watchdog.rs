@@ -0,0 +1,229 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::lua::extra_data;
+use crate::sync::{Condvar, Mutex, MutexGuard};
+
+/// Limits enforced by a watchdog installed with [`Lua::watchdog`].
+///
+/// A "dispatch" is a single outermost call to [`Lua::context`].  Limits are measured from the start
+/// of each dispatch.
+///
+/// [`Lua::watchdog`]: struct.Lua.html#method.watchdog
+/// [`Lua::context`]: struct.Lua.html#method.context
+#[derive(Clone, Copy, Debug)]
+pub struct WatchdogConfig {
+    /// Maximum wall-clock time a single dispatch may take.
+    pub time_limit: Option<Duration>,
+    /// Maximum amount of memory a single dispatch may allocate beyond what was in use when it
+    /// started.
+    pub memory_limit: Option<usize>,
+    /// How many VM instructions are executed between checks of the interrupt flag and memory
+    /// usage.
+    pub instruction_interval: u32,
+    /// How often the monitor thread checks the running time of the current dispatch.
+    pub poll_interval: Duration,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> WatchdogConfig {
+        WatchdogConfig {
+            time_limit: None,
+            memory_limit: None,
+            instruction_interval: 1000,
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+}
+
+/// A limit violation detected by a watchdog.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchdogEvent {
+    /// The current dispatch has been running for longer than `WatchdogConfig::time_limit`.
+    TimeLimit { elapsed: Duration },
+    /// The current dispatch has allocated more than `WatchdogConfig::memory_limit` bytes.
+    MemoryLimit { growth: usize },
+}
+
+/// Decides what happens after a [`WatchdogEvent`].
+///
+/// [`WatchdogEvent`]: enum.WatchdogEvent.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WatchdogAction {
+    /// Interrupt the running Lua code with an error.
+    Interrupt,
+    /// Let the Lua code continue.  The watchdog will not report this dispatch again.
+    Continue,
+}
+
+pub(crate) type WatchdogCallback = dyn Fn(WatchdogEvent) -> WatchdogAction + Send + Sync;
+
+// Watchdog state owned by the `Lua` instance.  Dropping this stops the monitor thread.
+pub(crate) struct Watchdog {
+    shared: Arc<Shared>,
+    monitor: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    config: WatchdogConfig,
+    callback: Box<WatchdogCallback>,
+    dispatch: Mutex<Dispatch>,
+    stop: Condvar,
+    interrupt: AtomicBool,
+}
+
+#[derive(Default)]
+struct Dispatch {
+    depth: usize,
+    started: Option<Instant>,
+    start_memory: usize,
+    reported: bool,
+    stopped: bool,
+}
+
+impl Watchdog {
+    pub(crate) fn new(config: WatchdogConfig, callback: Box<WatchdogCallback>) -> Watchdog {
+        let shared = Arc::new(Shared {
+            config,
+            callback,
+            dispatch: Mutex::new(Dispatch::default()),
+            stop: Condvar::new(),
+            interrupt: AtomicBool::new(false),
+        });
+
+        let monitor = if config.time_limit.is_some() {
+            let shared = shared.clone();
+            Some(
+                thread::Builder::new()
+                    .name("rlua-watchdog".to_owned())
+                    .spawn(move || monitor(&shared))
+                    .expect("could not spawn watchdog thread"),
+            )
+        } else {
+            None
+        };
+
+        Watchdog { shared, monitor }
+    }
+
+    // Called from the hook installed by `Lua::watchdog`.
+    pub(crate) fn check(&self, lua: Context) -> Result<()> {
+        let shared = &self.shared;
+
+        if let Some(limit) = shared.config.memory_limit {
+            let used = unsafe { (*extra_data(lua.state)).used_memory };
+            let mut dispatch = shared.lock();
+            let growth = used.saturating_sub(dispatch.start_memory);
+            if dispatch.started.is_some() && !dispatch.reported && growth > limit {
+                dispatch.reported = true;
+                drop(dispatch);
+                if (shared.callback)(WatchdogEvent::MemoryLimit { growth })
+                    == WatchdogAction::Interrupt
+                {
+                    shared.interrupt.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+
+        if shared.interrupt.swap(false, Ordering::SeqCst) {
+            Err(Error::RuntimeError(
+                "script interrupted by watchdog".to_owned(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Marks the start of a call to `Lua::context`, only outermost calls count as a dispatch.
+    fn enter(&self, state: *mut ffi::lua_State) {
+        let mut dispatch = self.shared.lock();
+        dispatch.depth += 1;
+        if dispatch.depth == 1 {
+            dispatch.started = Some(Instant::now());
+            dispatch.start_memory = unsafe { (*extra_data(state)).used_memory };
+            dispatch.reported = false;
+            self.shared.interrupt.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn exit(&self) {
+        let mut dispatch = self.shared.lock();
+        dispatch.depth -= 1;
+        if dispatch.depth == 0 {
+            dispatch.started = None;
+        }
+    }
+}
+
+// Tracks a call to `Lua::context` for the watchdog installed on the state, if any.
+pub(crate) struct DispatchGuard(Option<Arc<Watchdog>>);
+
+impl DispatchGuard {
+    pub(crate) unsafe fn enter(state: *mut ffi::lua_State) -> DispatchGuard {
+        let watchdog = (*extra_data(state)).watchdog.clone();
+        if let Some(watchdog) = &watchdog {
+            watchdog.enter(state);
+        }
+        DispatchGuard(watchdog)
+    }
+}
+
+impl Drop for DispatchGuard {
+    fn drop(&mut self) {
+        if let Some(watchdog) = &self.0 {
+            watchdog.exit();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+        self.shared.stop.notify_all();
+        if let Some(monitor) = self.monitor.take() {
+            let _ = monitor.join();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Dispatch> {
+        self.dispatch.lock()
+    }
+}
+
+fn monitor(shared: &Shared) {
+    let time_limit = rlua_expect!(shared.config.time_limit, "no watchdog time limit");
+
+    let mut dispatch = shared.lock();
+    loop {
+        if dispatch.stopped {
+            return;
+        }
+
+        if let Some(started) = dispatch.started {
+            let elapsed = started.elapsed();
+            if !dispatch.reported && elapsed > time_limit {
+                dispatch.reported = true;
+                drop(dispatch);
+                if (shared.callback)(WatchdogEvent::TimeLimit { elapsed })
+                    == WatchdogAction::Interrupt
+                {
+                    shared.interrupt.store(true, Ordering::SeqCst);
+                }
+                dispatch = shared.lock();
+                continue;
+            }
+        }
+
+        dispatch = shared
+            .stop
+            .wait_timeout(dispatch, shared.config.poll_interval)
+            .0;
+    }
+}
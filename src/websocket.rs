@@ -0,0 +1,218 @@
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tungstenite::http::Uri;
+use tungstenite::{Message, WebSocket};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::function::Function;
+use crate::net::{
+    check_connect, connect_tcp, io_error, limit_timeout, run_blocking, BytesResult, NetPolicy,
+};
+use crate::string::String;
+use crate::sync::Mutex;
+use crate::table::Table;
+use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::value::{ToLua, Value};
+
+const WEBSOCKET_METHODS_KEY: &str = "rlua.websocket.methods";
+
+// Operations on one connection are serialized, as reading and writing both need the whole
+// `WebSocket`.
+type Connection = Arc<Mutex<WebSocket<TcpStream>>>;
+
+struct LuaWebSocket {
+    connection: Option<Connection>,
+    timeout: Option<Duration>,
+}
+
+impl LuaWebSocket {
+    fn get(&self) -> Result<Connection> {
+        self.connection
+            .clone()
+            .ok_or_else(|| Error::RuntimeError("attempt to use a closed websocket".to_owned()))
+    }
+}
+
+impl UserData for LuaWebSocket {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // As for `net` sockets, the async methods live in a table created along with the module.
+        methods.add_meta_function(MetaMethod::Index, |lua, (_, key): (AnyUserData, Value)| {
+            lua.named_registry_value::<_, Table>(WEBSOCKET_METHODS_KEY)?
+                .get::<_, Value>(key)
+        });
+    }
+}
+
+pub(crate) fn create_websocket_module<'lua>(
+    lua: Context<'lua>,
+    policy: NetPolicy,
+) -> Result<Table<'lua>> {
+    let methods = lua.create_table()?;
+    methods.set("send", create_send(lua, false)?)?;
+    methods.set("send_binary", create_send(lua, true)?)?;
+    methods.set(
+        "receive",
+        lua.create_async_function(|_, (ws, timeout): (AnyUserData, Option<f64>)| {
+            let prepared = ws
+                .borrow::<LuaWebSocket>()
+                .and_then(|ws| Ok((ws.get()?, limit_timeout(ws.timeout, timeout)?)));
+            async move {
+                let (connection, timeout) = prepared?;
+                let message = run_blocking(move || {
+                    let mut ws = connection.lock();
+                    ws.get_ref()
+                        .set_read_timeout(timeout)
+                        .map_err(|e| io_error("receive", e))?;
+                    loop {
+                        match ws.read() {
+                            Ok(Message::Text(text)) => return Ok(Some(text.into_bytes())),
+                            Ok(Message::Binary(data)) => return Ok(Some(data)),
+                            Ok(_) => {}
+                            Err(tungstenite::Error::ConnectionClosed)
+                            | Err(tungstenite::Error::AlreadyClosed) => return Ok(None),
+                            Err(err) => return Err(websocket_error("receive", err)),
+                        }
+                    }
+                })
+                .await?;
+                Ok(message.map(BytesResult))
+            }
+        })?,
+    )?;
+    methods.set(
+        "close",
+        lua.create_async_function(|_, ws: AnyUserData| {
+            let connection = ws
+                .borrow_mut::<LuaWebSocket>()
+                .map(|mut ws| ws.connection.take());
+            async move {
+                if let Some(connection) = connection? {
+                    run_blocking(move || {
+                        let mut ws = connection.lock();
+                        let closed = match ws.close(None) {
+                            Ok(()) => ws.flush(),
+                            Err(err) => Err(err),
+                        };
+                        match closed {
+                            Ok(())
+                            | Err(tungstenite::Error::ConnectionClosed)
+                            | Err(tungstenite::Error::AlreadyClosed) => Ok(()),
+                            Err(err) => Err(websocket_error("close", err)),
+                        }
+                    })
+                    .await?;
+                }
+                Ok(())
+            }
+        })?,
+    )?;
+    lua.set_named_registry_value(WEBSOCKET_METHODS_KEY, methods)?;
+
+    let module = lua.create_table()?;
+    module.set(
+        "connect",
+        lua.create_async_function(
+            move |_, (url, timeout): (std::string::String, Option<f64>)| {
+                let prepared = parse_url(&url).and_then(|(host, port)| {
+                    let timeout =
+                        check_connect(&policy, policy.allow_tcp, "TCP", &host, port, timeout)?;
+                    Ok((host, port, timeout))
+                });
+                async move {
+                    let (host, port, timeout) = prepared?;
+                    let connection = run_blocking(move || {
+                        let stream = connect_tcp(&host, port, timeout)
+                            .map_err(|e| io_error("connect", e))?;
+                        stream
+                            .set_read_timeout(timeout)
+                            .and_then(|()| stream.set_write_timeout(timeout))
+                            .map_err(|e| io_error("connect", e))?;
+                        match tungstenite::client(url.as_str(), stream) {
+                            Ok((ws, _)) => Ok(ws),
+                            Err(tungstenite::HandshakeError::Failure(err)) => {
+                                Err(websocket_error("connect", err))
+                            }
+                            Err(tungstenite::HandshakeError::Interrupted(_)) => {
+                                Err(Error::RuntimeError("connect timed out".to_owned()))
+                            }
+                        }
+                    })
+                    .await?;
+                    Ok(NewWebSocket(connection, timeout))
+                }
+            },
+        )?,
+    )?;
+    Ok(module)
+}
+
+fn create_send<'lua>(lua: Context<'lua>, binary: bool) -> Result<Function<'lua>> {
+    lua.create_async_function(
+        move |_, (ws, data, timeout): (AnyUserData, String, Option<f64>)| {
+            let prepared = ws.borrow::<LuaWebSocket>().and_then(|ws| {
+                let message = if binary {
+                    Message::Binary(data.as_bytes().to_vec())
+                } else {
+                    Message::Text(data.to_str()?.to_owned())
+                };
+                Ok((ws.get()?, message, limit_timeout(ws.timeout, timeout)?))
+            });
+            async move {
+                let (connection, message, timeout) = prepared?;
+                run_blocking(move || {
+                    let mut ws = connection.lock();
+                    ws.get_ref()
+                        .set_write_timeout(timeout)
+                        .map_err(|e| io_error("send", e))?;
+                    ws.send(message).map_err(|e| websocket_error("send", e))
+                })
+                .await
+            }
+        },
+    )
+}
+
+// Returns the host and port to connect to for a `ws://` URL.
+fn parse_url(url: &str) -> Result<(std::string::String, u16)> {
+    let invalid = || Error::RuntimeError(format!("invalid websocket url '{}'", url));
+    let uri: Uri = url.parse().map_err(|_| invalid())?;
+    match uri.scheme_str() {
+        Some("ws") => {}
+        Some(scheme) => {
+            return Err(Error::RuntimeError(format!(
+                "unsupported websocket url scheme '{}'",
+                scheme
+            )))
+        }
+        None => return Err(invalid()),
+    }
+    let host = uri.host().ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok((host.to_owned(), uri.port_u16().unwrap_or(80)))
+}
+
+fn websocket_error(operation: &str, err: tungstenite::Error) -> Error {
+    match err {
+        tungstenite::Error::Io(err) => io_error(operation, err),
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io_error(operation, io::ErrorKind::NotConnected.into())
+        }
+        err => Error::RuntimeError(format!("{} failed: {}", operation, err)),
+    }
+}
+
+struct NewWebSocket(WebSocket<TcpStream>, Option<Duration>);
+
+impl<'lua> ToLua<'lua> for NewWebSocket {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        lua.create_userdata(LuaWebSocket {
+            connection: Some(Arc::new(Mutex::new(self.0))),
+            timeout: self.1,
+        })
+        .map(Value::UserData)
+    }
+}
@@ -0,0 +1,219 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::thread;
+
+use rlua::{Error, Function, Lua, TaskGroup, Thread};
+
+mod common;
+
+use common::{block_on, block_on_counting};
+
+// A future which is pending `remaining` times, waking itself from another thread each time.
+struct Delay {
+    remaining: usize,
+    value: i64,
+}
+
+impl Future for Delay {
+    type Output = rlua::Result<i64>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<rlua::Result<i64>> {
+        if self.remaining == 0 {
+            return Poll::Ready(Ok(self.value));
+        }
+        self.remaining -= 1;
+        let waker = cx.waker().clone();
+        thread::spawn(move || waker.wake());
+        Poll::Pending
+    }
+}
+
+#[test]
+fn test_async_function() {
+    Lua::new().context(|lua| {
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let delay = lua
+            .create_async_function(move |_, (value, remaining): (i64, usize)| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Delay { remaining, value }
+            })
+            .unwrap();
+        lua.globals().set("delay", delay).unwrap();
+
+        let script: Function = lua
+            .load(
+                r#"
+                    function(n)
+                        local a = delay(n, 2)
+                        local b = delay(a + 1, 0)
+                        return a + b, "done"
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let (result, polls) = block_on_counting(script.call_async::<_, (i64, String)>(20));
+        assert_eq!(result.unwrap(), (41, "done".to_owned()));
+        assert_eq!(polls, 3);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+
+        // Plain coroutine yields go back to the executor and resume straight away.
+        let thread: Thread = lua
+            .load(
+                r#"
+                    coroutine.create(function(n)
+                        coroutine.yield("ignored")
+                        return delay(n, 1) * 2
+                    end)
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let (result, polls) = block_on_counting(thread.into_async::<_, i64>(5));
+        assert_eq!(result.unwrap(), 10);
+        assert_eq!(polls, 3);
+    });
+}
+
+#[test]
+fn test_async_function_errors() {
+    Lua::new().context(|lua| {
+        let fail = lua
+            .create_async_function(|_, ()| async {
+                Err::<(), _>(Error::RuntimeError("failed".to_owned()))
+            })
+            .unwrap();
+        lua.globals().set("fail", fail.clone()).unwrap();
+
+        let script: Function = lua
+            .load(
+                r#"
+                    function()
+                        local ok, err = pcall(fail)
+                        return ok, tostring(err)
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let result = block_on(script.call_async::<_, (bool, String)>(()));
+        let (ok, err) = result.unwrap();
+        assert!(!ok);
+        assert!(err.contains("failed"));
+
+        // Without an async thread driving it, an async function cannot wait.
+        match fail.call::<_, ()>(()) {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::RuntimeError(ref msg) => assert!(msg.contains("outside of an async thread")),
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_scope_async() {
+    Lua::new().context(|lua| {
+        let mut log = Vec::new();
+        let future = unsafe {
+            lua.scope_async(|scope| {
+                let log = &mut log;
+                async move {
+                    let push = scope
+                        .create_function_mut(move |_, entry: String| {
+                            log.push(entry);
+                            Ok(())
+                        })
+                        .unwrap();
+                    lua.globals().set("push", push).unwrap();
+                    lua.load(r#"push("before")"#).exec().unwrap();
+                    let value = Delay {
+                        remaining: 2,
+                        value: 1,
+                    }
+                    .await
+                    .unwrap();
+                    lua.load(r#"push("after")"#).exec().unwrap();
+                    value
+                }
+            })
+        };
+
+        let (value, polls) = block_on_counting(Box::pin(future));
+        assert_eq!(value, 1);
+        assert_eq!(polls, 3);
+        assert_eq!(log, vec!["before".to_owned(), "after".to_owned()]);
+
+        match lua.load(r#"push("late")"#).exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::CallbackDestructed => {}
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_task_group() {
+    Lua::new().context(|lua| {
+        let delay = lua
+            .create_async_function(|_, (value, remaining): (i64, usize)| Delay { remaining, value })
+            .unwrap();
+        lua.globals().set("delay", delay).unwrap();
+        let task: Function = lua
+            .load(
+                r#"
+                    function(value, remaining)
+                        local result = delay(value, remaining)
+                        if result < 0 then error("negative") end
+                        return result
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let mut group = TaskGroup::new();
+        group.spawn(&task, (1, 3));
+        group.spawn(&task, (2, 0));
+        group.spawn_thread(lua.create_thread(task.clone()).unwrap(), (3, 1));
+        assert_eq!(group.len(), 3);
+        let results = block_on(group.join_all());
+        let results = results.into_iter().collect::<rlua::Result<Vec<i64>>>();
+        assert_eq!(results.unwrap(), vec![1, 2, 3]);
+
+        let mut group = TaskGroup::<i64>::new();
+        group.spawn(&task, (1, 5));
+        group.spawn(&task, (-1, 1));
+        group.spawn(&task, (3, 0));
+        let results = block_on(group.join_all());
+        assert!(matches!(results[0], Err(Error::TaskCancelled)));
+        assert!(matches!(
+            results[1],
+            Err(Error::CallbackError { .. }) | Err(Error::RuntimeError(_))
+        ));
+        assert_eq!(*results[2].as_ref().unwrap(), 3);
+
+        let mut group = TaskGroup::<i64>::new();
+        group.set_cancel_on_error(false);
+        group.spawn(&task, (1, 5));
+        group.spawn(&task, (-1, 1));
+        let results = block_on(group.join_all());
+        assert_eq!(*results[0].as_ref().unwrap(), 1);
+        assert!(results[1].is_err());
+
+        let mut group = TaskGroup::<i64>::new();
+        group.spawn(&task, (1, 5));
+        group.cancel();
+        let (results, polls) = block_on_counting(group.join_all());
+        assert!(matches!(results[0], Err(Error::TaskCancelled)));
+        assert_eq!(polls, 1);
+    });
+}
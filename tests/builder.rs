@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rlua::{Error, HookTriggers, Lua, LuaBuilder, OomBehavior, Result, StdLib};
+
+#[test]
+fn test_builder_preludes() {
+    let builder = LuaBuilder::new()
+        .prelude("log[#log + 1] = 'second'")
+        .prelude_with_priority(10, "log[#log + 1] = 'last'")
+        .prelude_with_priority(-10, "log = {}")
+        .prelude("log[#log + 1] = 'third'");
+
+    for _ in 0..3 {
+        let lua = builder.build().unwrap();
+        lua.context(|lua| {
+            let log: Vec<String> = lua.globals().get("log").unwrap();
+            assert_eq!(log, vec!["second", "third", "last"]);
+        });
+    }
+}
+
+#[test]
+fn test_builder_prelude_environment() {
+    let builder = LuaBuilder::new().prelude("answer = 42");
+    let lua = builder.build().unwrap();
+    lua.context(|lua| {
+        lua.globals().set("answer", rlua::Nil).unwrap();
+        let env = lua.create_table().unwrap();
+        builder.run_preludes(lua, env.clone()).unwrap();
+        assert_eq!(env.get::<_, i64>("answer").unwrap(), 42);
+        assert!(!lua.globals().contains_key("answer").unwrap());
+    });
+}
+
+#[test]
+fn test_builder_errors() {
+    let result = LuaBuilder::new()
+        .std_lib(StdLib::BASE)
+        .prelude("string.format('%d', 1)")
+        .build();
+    match result {
+        Err(Error::RuntimeError(msg)) => assert!(msg.starts_with("prelude #1:1:"), "{}", msg),
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("prelude should have failed"),
+    }
+
+    let result: Result<_> = LuaBuilder::new()
+        .memory_limit(Some(1024 * 1024))
+        .prelude("local t = {} for i = 1, 1e6 do t[i] = i end")
+        .build();
+    match result {
+        Err(Error::MemoryError(_)) => {}
+        _ => panic!("prelude should have run out of memory"),
+    }
+
+    // A limit below what the standard libraries need is rejected up front.
+    match LuaBuilder::new().memory_limit(Some(1024)).build() {
+        Err(Error::MemoryError(msg)) => {
+            assert!(msg.contains("memory limit of 1024 bytes"), "{}", msg)
+        }
+        _ => panic!("memory limit should have been rejected"),
+    }
+}
+
+#[test]
+fn test_builder_options() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let hook_calls = calls.clone();
+    let builder = Lua::builder()
+        .std_lib(StdLib::BASE | StdLib::STRING)
+        .memory_limit(Some(1024 * 1024))
+        .string_length_limit(Some(100))
+        .table_size_limit(Some(3))
+        .conversion_depth_limit(Some(1))
+        .oom_behavior(OomBehavior::Propagate)
+        .hook(HookTriggers::new().on_calls(), move |_, _| {
+            hook_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .prelude("function noop() end");
+
+    for _ in 0..2 {
+        let lua = builder.build().unwrap();
+        assert_eq!(lua.oom_behavior(), OomBehavior::Propagate);
+        lua.context(|lua| {
+            assert!(!lua.globals().contains_key("table").unwrap());
+            match lua.load("pcall(string.rep, 'x', 200)").exec() {
+                Err(Error::StringLimitExceeded { .. }) => {}
+                r => panic!("expected StringLimitExceeded, got {:?}", r),
+            }
+            match lua.load("{1, 2, 3, 4}").eval::<Vec<i64>>() {
+                Err(Error::TableLimitExceeded { .. }) => {}
+                r => panic!("expected TableLimitExceeded, got {:?}", r),
+            }
+            match lua.load("{{1}}").eval::<Vec<Vec<i64>>>() {
+                Err(Error::DepthLimitExceeded { .. }) => {}
+                r => panic!("expected DepthLimitExceeded, got {:?}", r),
+            }
+        });
+    }
+    assert!(calls.load(Ordering::SeqCst) > 0);
+}
@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use rlua::{Error, Function, Lua, TaskGroup, Value};
+
+mod common;
+
+use common::block_on;
+
+#[test]
+fn test_channel_send_receive() {
+    Lua::new().context(|lua| {
+        let channel = lua.create_channel_module().unwrap();
+        lua.globals().set("channel", channel).unwrap();
+
+        let producer: Function = lua
+            .load(
+                r#"
+                    function(ch, log)
+                        for i = 1, 4 do
+                            ch:send({ n = i })
+                            table.insert(log, "sent " .. i)
+                        end
+                        ch:close()
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let consumer: Function = lua
+            .load(
+                r#"
+                    function(ch, log)
+                        local sum = 0
+                        while true do
+                            local value, ok = ch:receive()
+                            if not ok then break end
+                            table.insert(log, "received " .. value.n)
+                            sum = sum + value.n
+                        end
+                        assert(select('#', ch:receive()) == 2)
+                        return sum
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let ch: Value = lua.load("channel.new(1)").eval().unwrap();
+        let log = lua.create_table().unwrap();
+        let mut group = TaskGroup::<Option<i64>>::new();
+        group.spawn(&producer, (ch.clone(), log.clone()));
+        group.spawn(&consumer, (ch.clone(), log.clone()));
+        let results = block_on(group.join_all());
+        assert_eq!(results[0].clone().unwrap(), None);
+        assert_eq!(results[1].clone().unwrap(), Some(10));
+
+        // With a capacity of 1, the producer is never more than one value ahead.
+        let log = log
+            .sequence_values::<String>()
+            .collect::<rlua::Result<Vec<_>>>()
+            .unwrap();
+        for (i, entry) in log.iter().enumerate() {
+            if let Some(n) = entry.strip_prefix("sent ") {
+                let n: usize = n.parse().unwrap();
+                let received = log[..i]
+                    .iter()
+                    .filter(|e| e.starts_with("received"))
+                    .count();
+                assert!(n <= received + 2, "{:?}", log);
+            }
+        }
+
+        // The same table comes out of the channel.
+        lua.load(
+            r#"
+                unbounded = channel.new()
+                shared = {}
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let roundtrip: Function = lua
+            .load(
+                r#"
+                    function()
+                        for i = 1, 100 do unbounded:send(i) end
+                        assert(#unbounded == 100)
+                        unbounded:send(shared)
+                        for i = 1, 100 do assert(unbounded:receive() == i) end
+                        return unbounded:receive() == shared
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(block_on(roundtrip.call_async::<_, bool>(())).unwrap());
+
+        match block_on(
+            lua.load("function() local ch = channel.new() ch:close() ch:send(1) end")
+                .eval::<Function>()
+                .unwrap()
+                .call_async::<_, ()>(()),
+        ) {
+            Err(Error::CallbackError { .. }) | Err(Error::RuntimeError(_)) => {}
+            r => panic!("sending on a closed channel succeeded: {:?}", r),
+        }
+        assert!(lua.load("channel.new(0)").exec().is_err());
+    });
+}
+
+#[test]
+fn test_channel_select() {
+    Lua::new().context(|lua| {
+        let channel = lua.create_channel_module().unwrap();
+        lua.globals().set("channel", channel).unwrap();
+        lua.load(
+            r#"
+                a, b = channel.new(), channel.new(1)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let script: Function = lua
+            .load(
+                r#"
+                    function()
+                        local results = {}
+                        -- Nothing is ready and a timeout of 0 does not wait.
+                        assert(select('#', channel.select({a, b}, 0)) == 0)
+                        -- Sending on `b` is ready, receiving from `a` is not.
+                        results[1] = channel.select({a, {b, "x"}})
+                        -- Now `b` is full, so only receiving from it is ready.
+                        local i, value, ok = channel.select({{b, "y"}, b})
+                        results[2] = i .. value .. tostring(ok)
+                        a:close()
+                        local i, value, ok = channel.select({a}, 10)
+                        results[3] = i .. tostring(value) .. tostring(ok)
+                        return results[1], results[2], results[3]
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let results = block_on(script.call_async::<_, (i64, String, String)>(()));
+        assert_eq!(
+            results.unwrap(),
+            (2, "2xtrue".to_owned(), "1nilfalse".to_owned())
+        );
+
+        let timeout: Function = lua
+            .load("function() return select('#', channel.select({channel.new()}, 0.05)) end")
+            .eval()
+            .unwrap();
+        let start = Instant::now();
+        assert_eq!(block_on(timeout.call_async::<_, usize>(())).unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Timeouts too large to represent wait forever rather than failing.
+        let huge: Function = lua
+            .load(
+                r#"
+                    function()
+                        local ch = channel.new(1)
+                        ch:send(7)
+                        local i, value = channel.select({ch}, 1e300)
+                        return value
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(block_on(huge.call_async::<_, i64>(())).unwrap(), 7);
+
+        assert!(lua.load("channel.select({1})").exec().is_err());
+    });
+}
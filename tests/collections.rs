@@ -0,0 +1,77 @@
+#![cfg(feature = "collections")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rlua::{Error, Lua, SharedMap, SharedVec};
+
+#[test]
+fn test_shared_vec() {
+    Lua::new().context(|lua| {
+        let vec = SharedVec::new(vec![10, 20, 30]);
+        lua.globals().set("vec", vec.clone()).unwrap();
+        lua.load(
+            r#"
+                assert(#vec == 3 and vec[1] == 10 and vec[3] == 30 and vec[4] == nil)
+                assert(vec[0] == nil and vec.x == nil and vec[2.0] == 20)
+                vec[2] = 21
+                vec[4] = 40
+                vec:push(50)
+                assert(vec:pop() == 50)
+                vec:insert(1, 5)
+                assert(vec:remove(2) == 10)
+
+                local sum = 0
+                for i, v in ipairs(vec) do sum = sum + i * v end
+                for i, v in pairs(vec) do sum = sum - i * v end
+                assert(sum == 0)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(*vec.lock(), vec![5, 21, 30, 40]);
+
+        vec.lock().push(60);
+        assert_eq!(lua.load("#vec").eval::<usize>().unwrap(), 5);
+        for code in &["vec[7] = 1", "vec[0] = 1", "vec:remove(6)", "vec[1] = 'x'"] {
+            match lua.load(code).exec() {
+                Err(Error::CallbackError { .. }) => {}
+                r => panic!("unexpected result for {}: {:?}", code, r),
+            }
+        }
+        lua.load("vec:clear()").exec().unwrap();
+        assert!(vec.lock().is_empty());
+    });
+}
+
+#[test]
+fn test_shared_map() {
+    let shared = Arc::new(Mutex::new(HashMap::new()));
+    shared.lock().unwrap().insert("a".to_owned(), 1);
+
+    Lua::new().context(|lua| {
+        let map = SharedMap::from(shared.clone());
+        lua.globals().set("map", map).unwrap();
+        lua.load(
+            r#"
+                assert(map.a == 1 and map.b == nil and map[1] == nil and #map == 1)
+                map.b = 2
+                map.c = 3
+                map.a = nil
+
+                local keys = {}
+                for k, v in pairs(map) do
+                    keys[#keys + 1] = k .. v
+                    if k == "b" then map.c = nil else map.b = nil end
+                end
+                assert(#keys == 1)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+
+    let map = shared.lock().unwrap();
+    assert_eq!(map.len(), 1);
+    assert!(!map.contains_key("a"));
+}
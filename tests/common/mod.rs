@@ -0,0 +1,36 @@
+// Helpers for the integration tests which drive futures to completion.
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Wake, Waker};
+use std::thread;
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Polls `future` to completion on the current thread.
+pub fn block_on<F: Future + Unpin>(future: F) -> F::Output {
+    block_on_counting(future).0
+}
+
+// Polls `future` to completion on the current thread, returning the result and the number of times
+// it was polled.
+pub fn block_on_counting<F: Future + Unpin>(mut future: F) -> (F::Output, usize) {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = TaskContext::from_waker(&waker);
+    let mut polls = 0;
+    loop {
+        polls += 1;
+        if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+            return (output, polls);
+        }
+        thread::park();
+    }
+}
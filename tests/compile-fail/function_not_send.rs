@@ -0,0 +1,15 @@
+extern crate rlua;
+
+use std::thread;
+
+use rlua::{Function, Lua};
+
+// Like every other handle, a `Function` cannot be moved to another thread.
+fn main() {
+    Lua::new().context(|lua| {
+        let function: Function = lua.globals().get("print").unwrap();
+        thread::spawn(move || drop(function));
+        //~^ error: cannot be sent between threads safely
+        //~| error: cannot be sent between threads safely
+    });
+}
@@ -0,0 +1,16 @@
+extern crate rlua;
+
+use std::thread;
+
+use rlua::{Lua, Table};
+
+// Handles borrow the `Lua` state they came from, which is not thread safe, so they can never be
+// moved to another thread.
+fn main() {
+    Lua::new().context(|lua| {
+        let table: Table = lua.create_table().unwrap();
+        thread::spawn(move || drop(table));
+        //~^ error: cannot be sent between threads safely
+        //~| error: cannot be sent between threads safely
+    });
+}
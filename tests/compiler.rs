@@ -0,0 +1,34 @@
+use rlua::{Compiler, Error, Lua};
+
+#[test]
+fn test_compile() {
+    let compiler = Compiler::new(2);
+    let first = compiler.compile("return 1 + 2");
+    let second = compiler.compile_with_name("local x = ... return x * 2", "double", true);
+
+    let first = first.wait().unwrap();
+    let second = second.wait().unwrap();
+
+    Lua::new().context(|lua| {
+        let first = unsafe { lua.load_bytecode(&first, "first").unwrap() };
+        let second = unsafe { lua.load_bytecode(&second, "second").unwrap() };
+        assert_eq!(first.call::<_, i32>(()).unwrap(), 3);
+        assert_eq!(second.call::<_, i32>(21).unwrap(), 42);
+    });
+}
+
+#[test]
+fn test_compile_error() {
+    let compiler = Compiler::new(1);
+    match compiler.compile("this is not lua").wait() {
+        Err(Error::SyntaxError { .. }) => {}
+        r => panic!("expected SyntaxError, got {:?}", r),
+    }
+
+    // Bytecode is never accepted as input
+    let bytecode = compiler.compile("return 1").wait().unwrap();
+    match compiler.compile(&bytecode).wait() {
+        Err(Error::SyntaxError { .. }) => {}
+        r => panic!("expected SyntaxError, got {:?}", r),
+    }
+}
@@ -0,0 +1,227 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use rlua::{AnyUserData, Context, Error, Lua, Result};
+use rlua_derive::{user_data_methods, user_data_trait, UserData};
+
+#[derive(UserData)]
+#[lua(methods)]
+struct Player {
+    #[lua(getter)]
+    name: String,
+    #[lua(getter, setter, name = "hp")]
+    health: i64,
+    secret: i64,
+}
+
+#[user_data_methods]
+impl Player {
+    #[lua(method)]
+    fn heal(&mut self, amount: i64) {
+        self.health += amount;
+    }
+
+    #[lua(method)]
+    fn is_alive(&self) -> bool {
+        self.health > 0
+    }
+
+    #[lua(method)]
+    fn describe<'lua>(&self, lua: Context<'lua>, prefix: String) -> Result<rlua::String<'lua>> {
+        lua.create_string(&format!("{}{} ({})", prefix, self.name, self.health))
+    }
+
+    #[lua(method, name = "new")]
+    fn create(name: String) -> Player {
+        Player {
+            name,
+            health: 1,
+            secret: 0,
+        }
+    }
+
+    #[lua(method)]
+    fn fail(&self) -> Result<()> {
+        Err(Error::RuntimeError("failed".to_owned()))
+    }
+
+    #[allow(unused)]
+    fn not_exposed(&self) -> i64 {
+        self.secret
+    }
+}
+
+#[test]
+fn test_derive_userdata() {
+    Lua::new().context(|lua| {
+        let player = lua
+            .create_userdata(Player {
+                name: "bob".to_owned(),
+                health: 10,
+                secret: 7,
+            })
+            .unwrap();
+        lua.globals().set("player", player.clone()).unwrap();
+
+        lua.load(
+            r#"
+                assert(player.name == "bob")
+                assert(player.hp == 10)
+                assert(player.secret == nil and player.not_exposed == nil)
+                player:heal(5)
+                assert(player:is_alive())
+                player.hp = -1
+                assert(not player:is_alive())
+                assert(player:describe("player ") == "player bob (-1)")
+
+                local other = player.new("alice")
+                assert(other.name == "alice" and other.hp == 1)
+
+                assert(not pcall(function() player.name = "eve" end))
+                assert(not pcall(function() player.hp = "lots" end))
+                local ok, err = pcall(player.fail, player)
+                assert(not ok and tostring(err):find("failed"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let player = player.borrow::<Player>().unwrap();
+        assert_eq!(player.health, -1);
+        assert_eq!(player.secret, 7);
+    });
+}
+
+#[derive(UserData)]
+#[lua(methods)]
+struct Counter<'a> {
+    count: &'a Cell<i64>,
+    #[lua(getter, setter)]
+    step: i64,
+}
+
+#[user_data_methods]
+impl<'a> Counter<'a> {
+    #[lua(method)]
+    fn inc(&self) -> i64 {
+        self.count.set(self.count.get() + self.step);
+        self.count.get()
+    }
+}
+
+#[test]
+fn test_derive_nonstatic_userdata() {
+    let count = Cell::new(0);
+    Lua::new().context(|lua| {
+        lua.scope(|scope| {
+            let counter = scope
+                .create_nonstatic_userdata(Counter {
+                    count: &count,
+                    step: 1,
+                })
+                .unwrap();
+            lua.globals().set("counter", counter).unwrap();
+            lua.load(
+                r#"
+                    counter:inc()
+                    counter.step = 10
+                    assert(counter:inc() == 11)
+                "#,
+            )
+            .exec()
+            .unwrap();
+        });
+    });
+    assert_eq!(count.get(), 11);
+}
+
+#[derive(UserData)]
+struct Marker;
+
+#[test]
+fn test_derive_plain_userdata() {
+    Lua::new().context(|lua| {
+        let marker: AnyUserData = lua.create_userdata(Marker).unwrap();
+        assert!(marker.is::<Marker>());
+        lua.globals().set("marker", marker).unwrap();
+        lua.load("assert(type(marker) == 'userdata')")
+            .exec()
+            .unwrap();
+    });
+}
+
+#[user_data_trait]
+trait Plugin {
+    #[lua(method)]
+    fn name(&self) -> String;
+
+    #[lua(method, name = "run")]
+    fn execute<'lua>(&mut self, lua: Context<'lua>, input: i64) -> Result<rlua::Value<'lua>>;
+
+    #[allow(unused)]
+    fn not_exposed(&self) {}
+}
+
+struct Doubler {
+    runs: i64,
+}
+
+impl Plugin for Doubler {
+    fn name(&self) -> String {
+        "doubler".to_owned()
+    }
+
+    fn execute<'lua>(&mut self, lua: Context<'lua>, input: i64) -> Result<rlua::Value<'lua>> {
+        self.runs += 1;
+        rlua::ToLua::to_lua(input * 2 + self.runs * 100, lua)
+    }
+}
+
+struct Shouter;
+
+impl Plugin for Shouter {
+    fn name(&self) -> String {
+        "shouter".to_owned()
+    }
+
+    fn execute<'lua>(&mut self, lua: Context<'lua>, input: i64) -> Result<rlua::Value<'lua>> {
+        rlua::ToLua::to_lua(format!("{}!", input), lua)
+    }
+}
+
+#[test]
+fn test_derive_trait_userdata() {
+    Lua::new().context(|lua| {
+        let plugins: Vec<Box<dyn Plugin + Send>> =
+            vec![Box::new(Doubler { runs: 0 }), Box::new(Shouter)];
+        lua.globals().set("plugins", plugins).unwrap();
+        let shared: Arc<dyn Plugin + Send + Sync> = Arc::new(Shouter);
+        lua.globals().set("shared", shared).unwrap();
+
+        lua.load(
+            r#"
+                assert(plugins[1]:name() == "doubler" and plugins[2]:name() == "shouter")
+                assert(plugins[1]:run(5) == 110)
+                assert(plugins[1]:run(5) == 210)
+                assert(plugins[2]:run(5) == "5!")
+                assert(plugins[1].not_exposed == nil)
+
+                assert(shared:name() == "shouter")
+                assert(shared.run == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let plugin = lua
+            .globals()
+            .get::<_, Vec<AnyUserData>>("plugins")
+            .unwrap()
+            .remove(0);
+        assert!(plugin.is::<Box<dyn Plugin + Send>>());
+        assert_eq!(
+            plugin.borrow::<Box<dyn Plugin + Send>>().unwrap().name(),
+            "doubler"
+        );
+    });
+}
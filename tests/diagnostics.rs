@@ -0,0 +1,181 @@
+use rlua::{GlobalInfo, Lua, ObjectTotals, PathSegment, ReferencePath, Table, Value};
+
+#[test]
+fn test_globals_report() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                name = "hello"
+                config = { a = 1, nested = { b = 2, c = 3 } }
+                config.self = config
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+
+    let first = lua.globals_report().unwrap();
+    assert_eq!(
+        first.globals["name"],
+        GlobalInfo {
+            type_name: "string",
+            size: 5
+        }
+    );
+    assert_eq!(first.globals["config"].size, 5);
+    assert_eq!(first.globals["_G"].size, 0);
+    assert_eq!(
+        first.by_type()["string"],
+        ObjectTotals {
+            count: 2,
+            size: 5 + "Lua 5.3".len()
+        }
+    );
+    assert!(first.diff(&first).is_empty());
+
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                name = nil
+                config.nested.d = 4
+                leak = {}
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+
+    let diff = lua.globals_report().unwrap().diff(&first);
+    assert_eq!(diff.added.keys().collect::<Vec<_>>(), vec!["leak"]);
+    assert_eq!(diff.removed.keys().collect::<Vec<_>>(), vec!["name"]);
+    assert_eq!(diff.changed["config"].0.size, 5);
+    assert_eq!(diff.changed["config"].1.size, 6);
+    assert_eq!(
+        diff.to_string(),
+        "+ leak (table, size 0)\n- name (string, size 5)\n~ config (table, size 5 -> table, size 6)\n"
+    );
+}
+
+#[test]
+fn test_heap_census() {
+    let lua = Lua::new();
+    let before = lua.heap_census().unwrap();
+    assert!(before.by_type["table"].count > 0);
+    assert!(before.by_type["function"].count > 0);
+    assert!(before.by_chunk.keys().all(|k| k.is_none()));
+
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                local blobs = {}
+                function add_blob()
+                    blobs[#blobs + 1] = string.rep("x", 1000) .. #blobs
+                end
+                for i = 1, 10 do
+                    add_blob()
+                end
+
+                worker = coroutine.create(function()
+                    local held = { "held by a suspended coroutine" }
+                    coroutine.yield()
+                end)
+                coroutine.resume(worker)
+            "#,
+        )
+        .set_name("=blobs.lua")
+        .unwrap()
+        .exec()
+        .unwrap();
+    });
+
+    let after = lua.heap_census().unwrap();
+    let blobs = after.by_chunk[&Some("blobs.lua".to_owned())];
+    // The two functions, the upvalue table and its ten strings, the coroutine, its local table and
+    // its string.
+    assert!(blobs.count >= 15, "{:?}", blobs);
+    assert!(blobs.size > 10 * 1000);
+    assert_eq!(
+        after.by_type["thread"].count,
+        before.by_type["thread"].count + 1
+    );
+    assert_eq!(
+        after.total().count,
+        after.by_chunk.values().map(|t| t.count).sum()
+    );
+    assert!(after.to_string().contains("blobs.lua: "));
+}
+
+#[test]
+fn test_reference_path() {
+    Lua::new().context(|lua| {
+        let (keyed, weird, held, orphan): (Table, Table, Table, Table) = lua
+            .load(
+                r#"
+                    local keyed, weird = {}, {}
+                    registry_like = { [keyed] = true, ["not an identifier"] = { weird } }
+                    worker = coroutine.create(function()
+                        local held_local = {}
+                        coroutine.yield(held_local)
+                    end)
+                    local _, held = coroutine.resume(worker)
+                    return keyed, weird, held, {}
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(
+            lua.reference_path(keyed).unwrap(),
+            Some(ReferencePath(vec![
+                PathSegment::Globals,
+                PathSegment::Field("registry_like".to_owned()),
+                PathSegment::Key,
+            ]))
+        );
+        assert_eq!(
+            lua.reference_path(weird).unwrap().unwrap().to_string(),
+            "globals.registry_like[\"not an identifier\"][1]"
+        );
+
+        let path = lua.reference_path(held).unwrap().unwrap();
+        assert_eq!(path.0[1], PathSegment::Field("worker".to_owned()));
+        match path.0.last() {
+            Some(PathSegment::Local(_, name)) => assert_eq!(name, "held_local"),
+            p => panic!("unexpected path {:?}", p),
+        }
+
+        assert_eq!(lua.reference_path(orphan).unwrap(), None);
+        assert_eq!(lua.reference_path(Value::Integer(1)).unwrap(), None);
+
+        let loaded = lua.reference_path(lua.globals().get::<_, Table>("string").unwrap());
+        assert_eq!(loaded.unwrap().unwrap().to_string(), "globals.string");
+
+        // Objects only reachable from the registry
+        let key = lua
+            .create_registry_value(lua.create_table().unwrap())
+            .unwrap();
+        let table: Table = lua.registry_value(&key).unwrap();
+        assert_eq!(
+            lua.reference_path(table).unwrap().unwrap().0[0],
+            PathSegment::Registry
+        );
+    });
+}
+
+#[test]
+fn test_walk_large_heap() {
+    // More objects than fit on the auxiliary stack rlua keeps its references on.
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.load("big = {} for i = 1, 1100000 do big[i] = {} end")
+            .exec()
+            .unwrap();
+        let last: Table = lua.load("big[#big]").eval().unwrap();
+        assert_eq!(
+            lua.reference_path(last).unwrap().unwrap().to_string(),
+            "globals.big[1100000]"
+        );
+    });
+    assert!(lua.heap_census().unwrap().by_type["table"].count > 1100000);
+}
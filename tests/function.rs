@@ -1,4 +1,9 @@
-use rlua::{Function, Lua, String};
+use std::io::{self, Write};
+use std::string::String as StdString;
+use std::thread;
+use std::time::Duration;
+
+use rlua::{BytecodeHeader, Error, Function, Lua, Recording, Result, String, Value};
 
 #[test]
 fn test_function() {
@@ -72,3 +77,495 @@ fn test_rust_function() {
         assert_eq!(lua_function.call::<_, String>(()).unwrap(), "hello");
     });
 }
+
+#[test]
+fn test_function_with_timeout() {
+    Lua::new().context(|lua| {
+        let sleep = lua
+            .create_function_with_timeout(Duration::from_millis(200), |ms: u64| {
+                thread::sleep(Duration::from_millis(ms));
+                Ok(ms * 2)
+            })
+            .unwrap();
+
+        assert_eq!(sleep.call::<_, u64>(1).unwrap(), 2);
+        match sleep.call::<_, u64>(2000) {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::CallbackTimeout(timeout) => {
+                    assert_eq!(timeout, Duration::from_millis(200))
+                }
+                ref e => panic!("wrong error kind {:?}", e),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_dump() {
+    struct FailingWriter(usize);
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 < buf.len() {
+                return Err(io::Error::other("disk full"));
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let counter: Function = lua
+            .load(
+                r#"
+                local count = 0
+                return function(step)
+                    count = count + (step or 1)
+                    return count
+                end
+            "#,
+            )
+            .set_name("counter")
+            .unwrap()
+            .eval()
+            .unwrap();
+
+        let full = counter.dump(false).unwrap();
+        let stripped = counter.dump(true).unwrap();
+        assert!(full.starts_with(b"\x1bLua"));
+        assert!(stripped.len() < full.len());
+
+        let mut streamed = Vec::new();
+        counter.dump_to(&mut streamed, false).unwrap();
+        assert_eq!(streamed, full);
+
+        let loaded = unsafe { lua.load_bytecode(&full, "counter").unwrap() };
+        // Upvalues are not dumped, `count` is nil in the loaded function.
+        assert!(loaded.call::<_, i64>(2).is_err());
+
+        match counter.dump_to(FailingWriter(16), false) {
+            Err(Error::ExternalError(err)) => assert_eq!(err.to_string(), "disk full"),
+            r => panic!("expected ExternalError, got {:?}", r),
+        }
+
+        let print: Function = lua.globals().get("print").unwrap();
+        assert!(print.dump(false).is_err());
+        let callback = lua.create_function(|_, ()| Ok(())).unwrap();
+        assert!(callback.dump(true).is_err());
+    });
+}
+
+#[test]
+fn test_function_info() {
+    Lua::new().context(|lua| {
+        lua.load(
+            r#"
+                function greet(name, ...)
+                    return "hello " .. name
+                end
+            "#,
+        )
+        .set_name("=greeter")
+        .unwrap()
+        .exec()
+        .unwrap();
+
+        let greet: Function = lua.globals().get("greet").unwrap();
+        let info = greet.info();
+        assert_eq!(info.name.as_deref(), Some(&b"greet"[..]));
+        assert_eq!(info.source.as_deref(), Some(&b"=greeter"[..]));
+        assert_eq!(info.short_src.as_deref(), Some(&b"greeter"[..]));
+        assert_eq!(info.what.as_deref(), Some(&b"Lua"[..]));
+        assert_eq!(info.line_defined, 2);
+        assert_eq!(info.last_line_defined, 4);
+        assert_eq!(info.num_params, 1);
+        assert!(info.is_vararg);
+
+        let format: Function = lua.load("string.format").eval().unwrap();
+        let info = format.info();
+        assert_eq!(info.name.as_deref(), Some(&b"string.format"[..]));
+        assert_eq!(info.what.as_deref(), Some(&b"C"[..]));
+        assert_eq!(info.line_defined, -1);
+
+        let callback = lua.create_function(|_, ()| Ok(())).unwrap();
+        let info = callback.info();
+        assert_eq!(info.name, None);
+        assert_eq!(info.what.as_deref(), Some(&b"C"[..]));
+    });
+}
+
+#[test]
+fn test_load_bytecode() {
+    Lua::new().context(|lua| {
+        let add: Function = lua.load("function(a, b) return a + b end").eval().unwrap();
+        let bytecode = add.dump(false).unwrap();
+
+        // Text chunks only accept source code, and binary chunks only bytecode.
+        match lua.load(&bytecode).into_function() {
+            Err(Error::SyntaxError { message, .. }) => {
+                assert!(
+                    message.contains("attempt to load a binary chunk"),
+                    "{}",
+                    message
+                )
+            }
+            r => panic!("expected SyntaxError, got {:?}", r),
+        }
+        match unsafe { lua.load_bytecode("return 1", "source") } {
+            Err(Error::SyntaxError { message, .. }) => {
+                assert!(
+                    message.contains("attempt to load a text chunk"),
+                    "{}",
+                    message
+                )
+            }
+            r => panic!("expected SyntaxError, got {:?}", r),
+        }
+
+        let loaded = unsafe { lua.load_bytecode(&bytecode, "add").unwrap() };
+        assert_eq!(loaded.call::<_, i64>((1, 2)).unwrap(), 3);
+    });
+}
+
+#[test]
+fn test_incompatible_bytecode() {
+    Lua::new().context(|lua| {
+        let add: Function = lua.load("function(a, b) return a + b end").eval().unwrap();
+        let bytecode = add.dump(true).unwrap();
+        let native = BytecodeHeader::native();
+        assert_eq!(BytecodeHeader::read(&bytecode), Some(native));
+
+        let check =
+            |patched: &[u8], describe: &str| match unsafe { lua.load_bytecode(patched, "patched") }
+            {
+                Err(err @ Error::IncompatibleBytecode { .. }) => {
+                    assert_eq!(err.kind_name(), "incompatible_bytecode");
+                    assert!(err.to_string().contains(describe), "{}", err);
+                    match err {
+                        Error::IncompatibleBytecode { found, expected } => {
+                            assert_eq!(expected, native);
+                            assert_eq!(Some(found), BytecodeHeader::read(patched));
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                r => panic!("expected IncompatibleBytecode, got {:?}", r),
+            };
+
+        // A Lua 5.1 chunk, whose header is laid out differently.
+        let mut patched = bytecode.clone();
+        patched[4] = 0x51;
+        check(&patched, "compiled for Lua 5.1 cannot");
+
+        let mut patched = bytecode.clone();
+        patched[5] = 1;
+        check(&patched, "(format 1)");
+
+        // Integer sizes and byte order come after the signature, version, format and check bytes.
+        let mut patched = bytecode.clone();
+        patched[15] = 4;
+        check(&patched, "4-byte integers");
+
+        let mut patched = bytecode.clone();
+        patched[17..25].reverse();
+        patched[25..33].reverse();
+        let found = BytecodeHeader::read(&patched).unwrap();
+        let endianness = found.layout.unwrap().endianness.unwrap();
+        assert_ne!(Some(endianness), native.layout.unwrap().endianness);
+        check(&patched, "-endian cannot");
+
+        let mut patched = bytecode.clone();
+        patched[25..33].copy_from_slice(&1.5f64.to_le_bytes());
+        check(&patched, "unknown number format");
+
+        // Corrupted headers which still claim to be for this Lua are rejected by Lua itself.
+        let mut patched = bytecode.clone();
+        patched[6] = 0;
+        match unsafe { lua.load_bytecode(&patched, "patched") } {
+            Err(Error::SyntaxError { message, .. }) => {
+                assert!(message.contains("corrupted"), "{}", message)
+            }
+            r => panic!("expected SyntaxError, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_load_from_reader() {
+    // Hands out the source one byte at a time, then fails if `fail` is set.
+    struct Trickle<'a> {
+        source: &'a [u8],
+        fail: bool,
+    }
+
+    impl<'a> io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.source.split_first() {
+                Some((&first, rest)) => {
+                    buf[0] = first;
+                    self.source = rest;
+                    Ok(1)
+                }
+                None if self.fail => Err(io::Error::other("connection reset")),
+                None => Ok(0),
+            }
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let mut source = "local t = {}\n".to_owned();
+        for i in 0..10_000 {
+            source.push_str(&format!("t[{}] = {}\n", i, i));
+        }
+        source.push_str("return t[9999]");
+        let function = lua.load_from_reader(source.as_bytes(), "=big").unwrap();
+        assert_eq!(function.call::<_, i64>(()).unwrap(), 9999);
+
+        let reader = Trickle {
+            source: b"return 1 +",
+            fail: false,
+        };
+        match lua.load_from_reader(reader, "=trickle") {
+            Err(Error::SyntaxError {
+                incomplete_input, ..
+            }) => assert!(incomplete_input),
+            r => panic!("expected SyntaxError, got {:?}", r),
+        }
+
+        let reader = Trickle {
+            source: b"return 1",
+            fail: true,
+        };
+        match lua.load_from_reader(reader, "=trickle") {
+            Err(Error::ExternalError(err)) => assert_eq!(err.to_string(), "connection reset"),
+            r => panic!("expected ExternalError, got {:?}", r),
+        }
+
+        let bytecode = function.dump(true).unwrap();
+        assert!(lua.load_from_reader(&bytecode[..], "=binary").is_err());
+    });
+}
+
+#[test]
+fn test_callback_stats() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let globals = lua.globals();
+        let host = lua.create_table().unwrap();
+        host.set(
+            "sleep",
+            lua.create_function(|_, ()| {
+                thread::sleep(Duration::from_millis(20));
+                Ok(())
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        host.set(
+            "add",
+            lua.create_function(|_, (a, b): (i64, i64)| Ok(a + b))
+                .unwrap(),
+        )
+        .unwrap();
+        globals.set("host", host).unwrap();
+        globals
+            .set("hidden", lua.create_function(|_, ()| Ok(())).unwrap())
+            .unwrap();
+    });
+
+    let script = r#"
+        host.sleep()
+        for i = 1, 5 do host.add(i, i) end
+        local temporary = hidden
+        hidden = nil
+        temporary()
+    "#;
+
+    // Nothing is recorded until statistics are enabled.
+    lua.context(|lua| lua.load(script).exec()).unwrap();
+    assert!(lua.callback_stats().unwrap().is_empty());
+    lua.context(|lua| {
+        lua.globals()
+            .set("hidden", lua.create_function(|_, ()| Ok(()))?)
+    })
+    .unwrap();
+
+    lua.set_callback_stats_enabled(true);
+    lua.context(|lua| lua.load(script).exec()).unwrap();
+    let stats = lua.callback_stats().unwrap();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats[0].path.as_deref(), Some("host.sleep"));
+    assert_eq!(stats[0].calls, 1);
+    assert!(stats[0].time >= Duration::from_millis(20));
+    let add = stats
+        .iter()
+        .find(|s| s.path.as_deref() == Some("host.add"))
+        .unwrap();
+    assert_eq!(add.calls, 5);
+    let hidden = stats.iter().find(|s| s.path.is_none()).unwrap();
+    assert_eq!(hidden.calls, 1);
+
+    // Collected functions are added up without a path.
+    lua.gc_collect().unwrap();
+    let stats = lua.callback_stats().unwrap();
+    assert_eq!(stats.iter().filter(|s| s.path.is_none()).count(), 1);
+    assert_eq!(stats.iter().map(|s| s.calls).sum::<u64>(), 7);
+
+    lua.clear_callback_stats();
+    assert!(lua.callback_stats().unwrap().is_empty());
+    lua.context(|lua| lua.load("host.add(1, 2)").exec())
+        .unwrap();
+    assert_eq!(lua.callback_stats().unwrap()[0].calls, 1);
+
+    lua.set_callback_stats_enabled(false);
+    assert!(lua.callback_stats().unwrap().is_empty());
+}
+
+#[test]
+fn test_call_logging() {
+    let lua = Lua::new();
+    lua.set_call_log_capacity(3);
+    lua.context(|lua| {
+        let globals = lua.globals();
+        let check = lua
+            .create_function(|_, (n, _): (i64, Value)| {
+                if n < 0 {
+                    Err(Error::RuntimeError("negative".to_owned()))
+                } else {
+                    Ok((n, "ok"))
+                }
+            })
+            .unwrap();
+        lua.set_call_logging(&check, Some("check")).unwrap();
+        globals.set("check", check).unwrap();
+        globals
+            .set("untagged", lua.create_function(|_, ()| Ok(())).unwrap())
+            .unwrap();
+
+        let chunk: Function = lua.load("function() end").eval().unwrap();
+        assert!(lua.set_call_logging(&chunk, Some("chunk")).is_err());
+
+        lua.load(
+            r#"
+                untagged()
+                check(1, { a = { b = { c = { d = 1 } } } })
+                pcall(check, -1, string.rep("x", 100))
+                check(2, { 1, 2, 3, 4, 5, 6, 7, 8, 9 })
+                check(3, 0.5)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+
+    let log = lua.call_log();
+    assert_eq!(log.len(), 3);
+    assert_eq!(log[0].function, "check");
+    assert_eq!(
+        log[0].arguments,
+        format!("-1, \"{}\"...(100 bytes)", "x".repeat(64))
+    );
+    assert!(log[0].results.as_ref().unwrap_err().contains("negative"));
+    assert_eq!(log[1].arguments, "2, {1, 2, 3, 4, 5, 6, 7, 8, ...}");
+    assert_eq!(log[2].arguments, "3, 0.5");
+    assert_eq!(log[2].results, Ok("3, \"ok\"".to_owned()));
+
+    lua.clear_call_log();
+    lua.context(|lua| {
+        let check: Function = lua.globals().get("check").unwrap();
+        lua.load("check(4, { a = { b = { c = { d = 1 } } } })")
+            .exec()
+            .unwrap();
+        lua.set_call_logging(&check, None).unwrap();
+        lua.load("check(5)").exec().unwrap();
+    });
+    let log = lua.call_log();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].arguments, "4, {a = {b = {c = {...}}}}");
+}
+
+#[test]
+fn test_record_replay() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let globals = lua.globals();
+        let next_id = lua.create_function(|_, ()| Ok(7)).unwrap();
+        lua.set_call_logging(&next_id, Some("next_id")).unwrap();
+        globals.set("next_id", next_id).unwrap();
+        let lookup = lua
+            .create_function(|lua, name: StdString| {
+                if name == "missing" {
+                    return Err(Error::RuntimeError("no such item".to_owned()));
+                }
+                let item = lua.create_table()?;
+                item.set("name", name)?;
+                item.set("weight", 2.5)?;
+                Ok((item, true))
+            })
+            .unwrap();
+        lua.set_call_logging(&lookup, Some("lookup")).unwrap();
+        globals.set("lookup", lookup).unwrap();
+        let handle = lua
+            .create_function(|lua, ()| lua.create_function(|_, ()| Ok(())))
+            .unwrap();
+        lua.set_call_logging(&handle, Some("handle")).unwrap();
+        globals.set("handle", handle).unwrap();
+    });
+
+    let script = r#"
+        local id = next_id()
+        local item, found = lookup("sword")
+        local ok, err = pcall(lookup, "missing")
+        return string.format("%d %s %g %s %s", id, item.name, item.weight, tostring(found),
+            tostring(err):match("no such item") or "?")
+    "#;
+
+    lua.context(|lua| lua.load("next_id()").exec()).unwrap();
+    lua.start_recording();
+    let expected: StdString = lua.context(|lua| lua.load(script).eval()).unwrap();
+    let recording = lua.stop_recording();
+    assert_eq!(expected, "7 sword 2.5 true no such item");
+    assert_eq!(recording.len(), 3);
+    assert_eq!(recording.functions(), vec!["next_id", "lookup"]);
+
+    let recording = Recording::from_bytes(&recording.to_bytes()).unwrap();
+    assert!(Recording::from_bytes(b"not a recording").is_err());
+
+    let callback_cause = |err: Error| match err {
+        Error::CallbackError { cause, .. } => cause.to_string(),
+        err => panic!("expected CallbackError, got {:?}", err),
+    };
+    let replay = |recording: Recording, script: &str| -> Result<StdString> {
+        Lua::new().context(|lua| {
+            let stubs = lua.create_replay_functions(recording)?;
+            for pair in stubs.pairs::<StdString, Function>() {
+                let (name, stub) = pair?;
+                lua.globals().set(name, stub)?;
+            }
+            lua.load(script).eval()
+        })
+    };
+    assert_eq!(replay(recording.clone(), script).unwrap(), expected);
+
+    let diverged = replay(recording.clone(), "lookup('shield')").unwrap_err();
+    assert!(callback_cause(diverged)
+        .contains("replay diverged: expected next_id(), got lookup(\"shield\")"));
+    let exhausted = replay(
+        recording,
+        &format!("{} next_id()", script.replace("return", "local _ =")),
+    )
+    .unwrap_err();
+    assert!(callback_cause(exhausted).contains("no recorded call left for next_id()"));
+
+    lua.start_recording();
+    lua.context(|lua| lua.load("handle()").exec()).unwrap();
+    let recording = lua.stop_recording();
+    let err = replay(recording, "return tostring(handle())").unwrap_err();
+    assert!(callback_cause(err).contains("results cannot be replayed"));
+    assert!(lua.stop_recording().is_empty());
+}
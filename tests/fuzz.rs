@@ -0,0 +1,64 @@
+use rlua::fuzz::{fuzz_convert, fuzz_load};
+
+// Deterministic pseudo-random inputs, so the smoke tests below cover more than the hand written
+// cases without depending on a fuzzer.
+fn random_inputs(count: usize) -> Vec<Vec<u8>> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..count)
+        .map(|i| {
+            (0..i % 64)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_fuzz_load() {
+    fuzz_load(b"return 1 + 1");
+    fuzz_load(b"while true do end");
+    fuzz_load(b"local t = {} while true do t[#t + 1] = ('x'):rep(1024) end");
+    fuzz_load(b"local function f() return f() + 1 end f()");
+    fuzz_load(b"error(setmetatable({}, { __tostring = error }))");
+    fuzz_load(b"\x1bLua garbage");
+    fuzz_load(b"\xff\x00 not lua");
+    for input in random_inputs(200) {
+        fuzz_load(&input);
+    }
+}
+
+#[test]
+fn test_fuzz_convert() {
+    fuzz_convert(b"");
+    // A sequence of an integer, a string and a nested table.
+    fuzz_convert(
+        b"\x06\x03\x02\x01\0\0\0\0\0\0\0\x04\x02hi\x05\x01\x04\x01k\x02\x07\0\0\0\0\0\0\0",
+    );
+    // Deeply nested tables.
+    fuzz_convert(&[5, 1].repeat(64));
+    for input in random_inputs(500) {
+        fuzz_convert(&input);
+    }
+}
+
+#[cfg(feature = "fuzz-bytecode")]
+#[test]
+fn test_fuzz_bytecode() {
+    use rlua::Lua;
+
+    let bytecode = Lua::new().context(|lua| {
+        lua.load("string.dump(function(x) return x * 2 end)")
+            .eval::<rlua::String>()
+            .unwrap()
+            .as_bytes()
+            .to_vec()
+    });
+    rlua::fuzz::fuzz_bytecode(&bytecode);
+    rlua::fuzz::fuzz_bytecode(&bytecode[..bytecode.len() / 2]);
+    rlua::fuzz::fuzz_bytecode(b"return 1");
+}
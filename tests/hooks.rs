@@ -2,8 +2,12 @@ use std::cell::RefCell;
 use std::ops::Deref;
 use std::str;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use rlua::{Error, HookTriggers, Lua, Value};
+use rlua::{
+    DebugEvent, Error, Function, HookTriggers, Lua, Value, WatchdogAction, WatchdogConfig,
+    WatchdogEvent,
+};
 
 #[test]
 fn line_counts() {
@@ -106,6 +110,40 @@ fn error_within_hook() {
     }
 }
 
+#[test]
+fn instruction_limit_through_function_call() {
+    let lua = Lua::new();
+    let mut remaining = 100;
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(10),
+        move |_lua, debug| {
+            assert_eq!(debug.event(), DebugEvent::Count);
+            remaining -= 1;
+            if remaining == 0 {
+                Err(Error::RuntimeError("instruction limit reached".to_string()))
+            } else {
+                Ok(())
+            }
+        },
+    );
+
+    lua.context(|lua| {
+        let spin: Function = lua.load("function() while true do end end").eval().unwrap();
+        match spin.call::<_, ()>(()) {
+            Err(Error::CallbackError { cause, .. }) => match cause.deref() {
+                Error::RuntimeError(s) => assert_eq!(s, "instruction limit reached"),
+                _ => panic!("wrong callback error kind caught"),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+
+    lua.remove_hook();
+    lua.context(|lua| {
+        lua.load("for i = 1, 10000 do end").exec().unwrap();
+    });
+}
+
 #[test]
 fn limit_execution_instructions() {
     let lua = Lua::new();
@@ -230,3 +268,211 @@ fn hook_swap_within_hook() {
         });
     });
 }
+
+#[test]
+fn watchdog() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let watchdog_events = events.clone();
+
+    let lua = Lua::new();
+    lua.watchdog(
+        WatchdogConfig {
+            time_limit: Some(Duration::from_millis(50)),
+            memory_limit: Some(1024 * 1024),
+            ..WatchdogConfig::default()
+        },
+        move |event| {
+            watchdog_events.lock().unwrap().push(event);
+            WatchdogAction::Interrupt
+        },
+    );
+
+    lua.context(|lua| match lua.load("while true do end").exec() {
+        Err(Error::CallbackError { cause, .. }) => match cause.deref() {
+            Error::RuntimeError(s) => assert_eq!(s, "script interrupted by watchdog"),
+            _ => panic!("wrong callback error kind caught"),
+        },
+        r => panic!("wrong result {:?}", r),
+    });
+    match events.lock().unwrap().pop() {
+        Some(WatchdogEvent::TimeLimit { elapsed }) => assert!(elapsed >= Duration::from_millis(50)),
+        e => panic!("wrong event {:?}", e),
+    }
+
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                local t = {}
+                for i = 1, 10000000 do
+                    t[i] = tostring(i)
+                end
+            "#,
+        )
+        .exec()
+        .unwrap_err();
+    });
+    match events.lock().unwrap().pop() {
+        Some(WatchdogEvent::MemoryLimit { growth }) => assert!(growth > 1024 * 1024),
+        e => panic!("wrong event {:?}", e),
+    }
+
+    // Each dispatch is measured separately, and code within the limits is unaffected.
+    lua.context(|lua| lua.load("local x = 1 + 1").exec())
+        .unwrap();
+
+    lua.remove_watchdog();
+    lua.context(|lua| {
+        lua.load("local t = {} for i = 1, 1000000 do t[i] = i end")
+            .exec()
+    })
+    .unwrap();
+    assert!(events.lock().unwrap().is_empty());
+}
+
+#[test]
+fn tail_calls() {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let hook_output = output.clone();
+
+    let lua = Lua::new();
+    lua.set_hook(
+        HookTriggers {
+            on_calls: true,
+            on_returns: true,
+            ..Default::default()
+        },
+        move |_lua, debug| {
+            let source = debug.source();
+            if source.what == Some(b"Lua") {
+                hook_output.lock().unwrap().push((
+                    debug.event(),
+                    debug.is_tail_call(),
+                    source.line_defined,
+                ));
+            }
+            Ok(())
+        },
+    );
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                local function inner()
+                    return 1
+                end
+                local function outer()
+                    return inner()
+                end
+                outer()
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+    lua.remove_hook();
+
+    let output = output.lock().unwrap();
+    assert_eq!(
+        *output,
+        vec![
+            (DebugEvent::Call, false, 5),
+            (DebugEvent::TailCall, true, 2),
+            (DebugEvent::Return, true, 2),
+        ]
+    );
+
+    lua.context(|lua| {
+        match lua
+            .load(
+                r#"
+                    local function fail()
+                        error("boom")
+                    end
+                    local function forward()
+                        return fail()
+                    end
+                    forward()
+                "#,
+            )
+            .exec()
+        {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains("(...tail calls...)")),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn tail_call_stack() {
+    let captured = Arc::new(Mutex::new(None));
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let capture_into = captured.clone();
+        let capture = lua
+            .create_function(move |lua, ()| {
+                *capture_into.lock().unwrap() = Some(lua.call_stack());
+                Ok(())
+            })
+            .unwrap();
+        lua.globals().set("capture", capture).unwrap();
+        lua.load(
+            r#"local function leaf()
+                capture()
+                return 1
+            end
+            local function middle()
+                return leaf()
+            end
+            local function top()
+                local result = middle()
+                return result
+            end
+            top()"#,
+        )
+        .set_name("=stack")
+        .unwrap()
+        .exec()
+        .unwrap();
+    });
+
+    let stack = captured.lock().unwrap().take().unwrap();
+    let whats: Vec<_> = stack.0.iter().map(|frame| frame.what).collect();
+    assert_eq!(whats, vec!["C", "Lua", "Lua", "main"]);
+    assert_eq!(stack.0[0].name.as_deref(), Some("capture"));
+    assert_eq!(stack.0[1].line_defined, Some(1));
+    assert_eq!(stack.0[1].current_line, Some(2));
+    assert!(stack.0[1].is_tail_call);
+    assert_eq!(stack.0[1].name, None);
+    assert_eq!(stack.0[2].name.as_deref(), Some("top"));
+
+    // `leaf` was called by `middle`, which is gone, rather than by `top`.
+    assert_eq!(stack.caller(0), Some(&stack.0[1]));
+    assert_eq!(stack.caller(1), None);
+    assert_eq!(stack.caller(2), Some(&stack.0[3]));
+    assert_eq!(stack.caller(3), None);
+    assert!(stack.to_string().contains(
+        "stack:2: in function <stack:1>\n\t(...tail calls...)\n\tstack:9: in function 'top'"
+    ));
+
+    // Hooks see the tail call as the innermost frame.
+    let tail_calls = Arc::new(Mutex::new(Vec::new()));
+    let hook_tail_calls = tail_calls.clone();
+    lua.set_hook(HookTriggers::new().on_calls(), move |lua, debug| {
+        if debug.event() == DebugEvent::TailCall {
+            let stack = lua.call_stack();
+            assert!(stack.0[0].is_tail_call);
+            assert_eq!(stack.caller(0), None);
+            hook_tail_calls
+                .lock()
+                .unwrap()
+                .push(stack.0[0].line_defined);
+        }
+        Ok(())
+    });
+    lua.context(|lua| {
+        lua.load("local function f() return 1 end local function g() return f() end g()")
+            .exec()
+            .unwrap();
+    });
+    lua.remove_hook();
+    assert_eq!(*tail_calls.lock().unwrap(), vec![Some(1)]);
+}
@@ -0,0 +1,208 @@
+use std::sync::{Arc, Mutex};
+
+use rlua::{Deprecation, DeprecationEvent, DeprecationUsage, Error, Lua};
+
+#[test]
+fn test_deprecated_functions() {
+    let lua = Lua::new();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let hook_events = events.clone();
+    lua.set_deprecation_hook(move |_, event| {
+        hook_events.lock().unwrap().push(event.clone());
+        if event.function == "removed" {
+            Err(Error::RuntimeError(
+                "removed is no longer available".to_owned(),
+            ))
+        } else {
+            Ok(())
+        }
+    });
+
+    lua.context(|lua| {
+        let host = lua.create_host_api("3.1.0").unwrap();
+        assert_eq!(host.api_version().unwrap(), "3.1.0");
+        host.set_function("add", |_, (a, b): (i64, i64)| Ok(a + b))
+            .unwrap();
+        host.set_deprecated_function(
+            "sum",
+            Deprecation {
+                since: "3.0.0".to_owned(),
+                ..Deprecation::default()
+            },
+            |_, (a, b): (i64, i64)| Ok(a + b),
+        )
+        .unwrap();
+        host.set_deprecated_function(
+            "removed",
+            Deprecation {
+                since: "2.0.0".to_owned(),
+                message: Some("gone".to_owned()),
+                ..Deprecation::default()
+            },
+            |_, ()| Ok(()),
+        )
+        .unwrap();
+        lua.globals().set("host", host.table()).unwrap();
+
+        lua.load(
+            r#"
+                assert(host.api_version == "3.1.0")
+                assert(host.add(1, 2) == 3)
+                assert(host.sum(1, 2) == 3)
+                assert(host.deprecated.add == nil)
+                assert(host.deprecated.sum.since == "3.0.0")
+                assert(host.deprecated.sum.message == nil)
+                assert(host.deprecated.removed.message == "gone")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        match lua.load("host.removed()").exec() {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::RuntimeError(ref s) => assert_eq!(s, "removed is no longer available"),
+                ref e => panic!("wrong error kind {:?}", e),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            DeprecationEvent {
+                function: "sum".to_owned(),
+                deprecation: Deprecation {
+                    since: "3.0.0".to_owned(),
+                    ..Deprecation::default()
+                },
+                call_site: Some("[string \"?\"]:4".to_owned()),
+            },
+            DeprecationEvent {
+                function: "removed".to_owned(),
+                deprecation: Deprecation {
+                    since: "2.0.0".to_owned(),
+                    replacement: None,
+                    message: Some("gone".to_owned()),
+                },
+                call_site: Some("[string \"?\"]:1".to_owned()),
+            },
+        ]
+    );
+
+    lua.remove_deprecation_hook();
+    lua.context(|lua| lua.load("host.removed()").exec())
+        .unwrap();
+    assert_eq!(events.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_shims() {
+    let lua = Lua::new();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let hook_events = events.clone();
+    lua.set_deprecation_hook(move |_, event| {
+        hook_events.lock().unwrap().push(event.clone());
+        Ok(())
+    });
+
+    lua.context(|lua| {
+        let host = lua.create_host_api("2.0.0").unwrap();
+        let entities = lua.create_table().unwrap();
+        entities
+            .set(
+                "spawn",
+                lua.create_function(|_, (kind, count): (String, i64)| Ok((kind, count)))
+                    .unwrap(),
+            )
+            .unwrap();
+        host.table().set("entities", entities).unwrap();
+        host.set_shim(
+            "spawn",
+            "entities.spawn",
+            Deprecation {
+                since: "2.0.0".to_owned(),
+                ..Deprecation::default()
+            },
+        )
+        .unwrap();
+        lua.globals().set("host", host.table()).unwrap();
+
+        lua.load(
+            r#"
+                local function spawn_all()
+                    for i = 1, 2 do
+                        local kind, count = host.spawn("orc", i)
+                        assert(kind == "orc" and count == i)
+                    end
+                end
+                spawn_all()
+                host.spawn("elf", 1)
+                assert(host.deprecated.spawn.replacement == "entities.spawn")
+
+                -- Shims forward to the current implementation
+                host.entities.spawn = function() return "replaced" end
+                assert(host.spawn() == "replaced")
+            "#,
+        )
+        .set_name("=mod.lua")
+        .unwrap()
+        .exec()
+        .unwrap();
+
+        host.table().set("entities", 1).unwrap();
+        assert!(lua.load("host.spawn()").exec().is_err());
+    });
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 5);
+    assert_eq!(
+        events[0].deprecation.replacement,
+        Some("entities.spawn".to_owned())
+    );
+
+    assert_eq!(
+        lua.deprecation_usage(),
+        vec![
+            DeprecationUsage {
+                function: "spawn".to_owned(),
+                call_site: Some("[string \"?\"]:1".to_owned()),
+                count: 1,
+            },
+            DeprecationUsage {
+                function: "spawn".to_owned(),
+                call_site: Some("mod.lua:14".to_owned()),
+                count: 1,
+            },
+            DeprecationUsage {
+                function: "spawn".to_owned(),
+                call_site: Some("mod.lua:4".to_owned()),
+                count: 2,
+            },
+            DeprecationUsage {
+                function: "spawn".to_owned(),
+                call_site: Some("mod.lua:9".to_owned()),
+                count: 1,
+            },
+        ]
+    );
+
+    lua.clear_deprecation_usage();
+    assert!(lua.deprecation_usage().is_empty());
+
+    // The shims do not keep the host table alive
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                local weak = setmetatable({ host }, { __mode = "v" })
+                host = nil
+                collectgarbage()
+                assert(weak[1] == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
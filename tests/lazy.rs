@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rlua::{Context, Error, Lazy, LazyTable, LazyTableProvider, Lua, Result, ToLua, Value};
+
+#[test]
+fn test_lazy() {
+    Lua::new().context(|lua| {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let counter = computed.clone();
+        let numbers = lua
+            .create_function(move |_, n: i64| {
+                let counter = counter.clone();
+                Ok(Lazy::new(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    (1..=n).collect::<Vec<_>>()
+                }))
+            })
+            .unwrap();
+        lua.globals().set("numbers", numbers).unwrap();
+
+        lua.load(
+            r#"
+                unused = numbers(1000)
+                local list = numbers(100)
+                assert(type(list) == "userdata")
+                assert(list[1] == 1 and list[100] == 100 and list[101] == nil)
+                assert(#list == 100)
+                local sum = 0
+                for _, n in ipairs(list) do
+                    sum = sum + n
+                end
+                assert(sum == 5050)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+
+        let one = lua.create_function(|_, ()| Ok(Lazy::new(|| 1))).unwrap();
+        lua.globals().set("one", one).unwrap();
+        match lua.load("one()()").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::RuntimeError(ref msg) => {
+                    assert!(msg.contains("attempt to call a lazy integer value"))
+                }
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_lazy_not_registered() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let value = Lazy::new(|| vec![1]).to_lua(lua).unwrap();
+        lua.globals().set("value", value).unwrap();
+        lua.load("assert(value[1] == 1)").exec().unwrap();
+    });
+    assert!(lua.registered_types().is_empty());
+}
+
+#[test]
+fn test_lazy_table() {
+    struct Range {
+        len: i64,
+        cache: bool,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl LazyTableProvider for Range {
+        fn get<'lua>(&self, lua: Context<'lua>, key: Value<'lua>) -> Result<Value<'lua>> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            match key {
+                Value::Integer(i) if i >= 1 && i <= self.len => (i * 10).to_lua(lua),
+                _ => Ok(Value::Nil),
+            }
+        }
+
+        fn next_key<'lua>(&self, _: Context<'lua>, key: Value<'lua>) -> Result<Value<'lua>> {
+            Ok(match key {
+                Value::Nil if self.len > 0 => Value::Integer(1),
+                Value::Integer(i) if i < self.len => Value::Integer(i + 1),
+                _ => Value::Nil,
+            })
+        }
+
+        fn length(&self) -> Result<i64> {
+            Ok(self.len)
+        }
+
+        fn cache(&self) -> bool {
+            self.cache
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let table = lua
+            .create_lazy_table(Range {
+                len: 100_000,
+                cache: true,
+                lookups: lookups.clone(),
+            })
+            .unwrap();
+        lua.globals().set("range", table.clone()).unwrap();
+        lua.load(
+            r#"
+                assert(type(range) == "table" and #range == 100000)
+                assert(range[5] == 50 and range[5] == 50 and range.x == nil)
+                assert(getmetatable(range) == false)
+                assert(not pcall(function() range[1] = 1 end))
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+        assert_eq!(table.table().raw_get::<_, i64>(5).unwrap(), 50);
+        assert_eq!(table.table().get::<_, i64>(7).unwrap(), 70);
+
+        table.invalidate(5).unwrap();
+        table.invalidate(Value::Nil).unwrap();
+        assert!(matches!(
+            table.table().raw_get::<_, Value>(5).unwrap(),
+            Value::Nil
+        ));
+        assert!(table.table().contains_key(7).unwrap());
+        table.invalidate_all().unwrap();
+        assert!(table.table().is_empty());
+        assert_eq!(lua.load("range[5]").eval::<i64>().unwrap(), 50);
+        assert_eq!(lookups.load(Ordering::SeqCst), 4);
+
+        let key = lua.create_registry_value(table).unwrap();
+        let table: LazyTable = lua.registry_value(&key).unwrap();
+        table.invalidate(5).unwrap();
+        assert!(lua
+            .unpack::<LazyTable>(Value::Table(lua.create_table().unwrap()))
+            .is_err());
+
+        let small = lua
+            .create_lazy_table(Range {
+                len: 3,
+                cache: false,
+                lookups: lookups.clone(),
+            })
+            .unwrap();
+        lua.globals().set("small", small.clone()).unwrap();
+        let sum: i64 = lua
+            .load(
+                r#"
+                    local sum = 0
+                    for k, v in pairs(small) do sum = sum + k * v end
+                    return sum
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(sum, 140);
+        assert_eq!(small.table().raw_len(), 0);
+
+        struct Empty;
+
+        impl LazyTableProvider for Empty {
+            fn get<'lua>(&self, _: Context<'lua>, _: Value<'lua>) -> Result<Value<'lua>> {
+                Ok(Value::Nil)
+            }
+        }
+
+        let empty = lua.create_lazy_table(Empty).unwrap();
+        lua.globals().set("empty", empty).unwrap();
+        match lua.load("for _ in pairs(empty) do end").exec() {
+            Err(Error::CallbackError { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
@@ -0,0 +1,136 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+
+use rlua::{lua_State, Context, Error, Lua, Result, Table};
+use rlua_derive::lua_module;
+
+#[lua_module]
+fn counter(lua: Context) -> Result<Table> {
+    let module = lua.create_table()?;
+    module.set("count", 0)?;
+    module.set(
+        "bump",
+        lua.create_function(|_, module: Table| {
+            let count = module.get::<_, i64>("count")? + 1;
+            module.set("count", count)?;
+            Ok(count)
+        })?,
+    )?;
+    Ok(module)
+}
+
+#[lua_module(name = "game.broken")]
+fn broken(_: Context) -> Result<Table> {
+    Err(Error::RuntimeError("cannot open module".to_owned()))
+}
+
+type CFunction = unsafe extern "C" fn(*mut lua_State) -> c_int;
+
+// The parts of the Lua C API a host application would use, linked from rlua's copy of Lua.
+extern "C" {
+    fn luaL_newstate() -> *mut lua_State;
+    fn luaL_openlibs(state: *mut lua_State);
+    fn luaL_loadstring(state: *mut lua_State, s: *const c_char) -> c_int;
+    fn lua_pcallk(
+        state: *mut lua_State,
+        nargs: c_int,
+        nresults: c_int,
+        msgh: c_int,
+        ctx: isize,
+        k: *const u8,
+    ) -> c_int;
+    fn lua_pushcclosure(state: *mut lua_State, f: CFunction, n: c_int);
+    fn lua_setglobal(state: *mut lua_State, name: *const c_char);
+    fn lua_tolstring(state: *mut lua_State, index: c_int, len: *mut usize) -> *const c_char;
+    fn lua_settop(state: *mut lua_State, index: c_int);
+    fn lua_close(state: *mut lua_State);
+}
+
+// Makes the modules available to `require`, as the searcher for C libraries would.
+unsafe fn preload(state: *mut lua_State) {
+    lua_pushcclosure(state, luaopen_counter, 0);
+    lua_setglobal(state, "luaopen_counter\0".as_ptr() as *const c_char);
+    lua_pushcclosure(state, luaopen_game_broken, 0);
+    lua_setglobal(state, "luaopen_game_broken\0".as_ptr() as *const c_char);
+    host_eval(
+        state,
+        "package.preload.counter = luaopen_counter \
+         package.preload['game.broken'] = luaopen_game_broken",
+    )
+    .unwrap();
+}
+
+// Runs `source` and returns its result converted to a string, or the error message.
+unsafe fn host_eval(state: *mut lua_State, source: &str) -> std::result::Result<String, String> {
+    let source = format!("{}\0", source);
+    let status = match luaL_loadstring(state, source.as_ptr() as *const c_char) {
+        0 => lua_pcallk(state, 0, 1, 0, 0, std::ptr::null()),
+        status => status,
+    };
+    let result = lua_tolstring(state, -1, std::ptr::null_mut());
+    let result = if result.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(result).to_string_lossy().into_owned()
+    };
+    lua_settop(state, 0);
+    if status == 0 {
+        Ok(result)
+    } else {
+        Err(result)
+    }
+}
+
+#[test]
+fn test_lua_module() {
+    unsafe {
+        let state = luaL_newstate();
+        luaL_openlibs(state);
+        preload(state);
+        host_eval(
+            state,
+            "co = coroutine.create(function() \
+                 package.loaded.counter = nil \
+                 return require('counter'):bump() \
+             end)",
+        )
+        .unwrap();
+
+        assert_eq!(
+            host_eval(
+                state,
+                "local c = require('counter') c:bump() return c:bump()"
+            )
+            .unwrap(),
+            "2"
+        );
+        // Threads which existed before rlua was set up can load modules too.
+        assert_eq!(
+            host_eval(state, "return select(2, coroutine.resume(co))").unwrap(),
+            "1"
+        );
+        let err = host_eval(
+            state,
+            "return tostring(select(2, pcall(require, 'game.broken')))",
+        )
+        .unwrap();
+        assert!(err.contains("cannot open module"), "{}", err);
+
+        lua_close(state);
+    }
+}
+
+#[test]
+fn test_lua_module_in_rlua_state() {
+    let lua = Lua::new();
+    lua.context(|lua| lua.globals().set("answer", 42)).unwrap();
+    let state = lua.into_raw();
+    unsafe {
+        preload(state);
+        assert_eq!(
+            host_eval(state, "return require('counter'):bump() + answer").unwrap(),
+            "43"
+        );
+        lua_close(state);
+    }
+}
@@ -1,6 +1,7 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use rlua::{Error, Lua, Nil, UserData};
+use rlua::{Context, Error, FromLua, Lua, Nil, OomBehavior, Result, UserData, Value};
 
 #[test]
 fn test_memory_limit() {
@@ -29,6 +30,65 @@ fn test_memory_limit() {
     });
 }
 
+#[test]
+fn test_oom_behavior() {
+    let lua = Lua::new();
+    assert_eq!(lua.oom_behavior(), OomBehavior::Catchable);
+    lua.set_memory_limit(Some(lua.used_memory() + 100_000));
+    lua.set_string_length_limit(Some(1000));
+
+    lua.context(|ctx| {
+        let long_string = ctx
+            .create_function(|ctx, ()| ctx.create_string(&vec![b'x'; 2000]))
+            .unwrap();
+        ctx.globals().set("long_string", long_string).unwrap();
+        let grow = r#"
+            local t = {}
+            for i = 1, 1e6 do t[i] = i end
+        "#;
+        ctx.globals()
+            .set("grow", ctx.load(grow).into_function().unwrap())
+            .unwrap();
+        let caught = |script: &str| ctx.load(script).eval::<bool>();
+
+        assert!(!caught("return pcall(grow)").unwrap());
+        assert!(!caught("return xpcall(grow, tostring)").unwrap());
+        assert!(!caught("return pcall(string.rep, 'x', 2000)").unwrap());
+        assert!(!caught("return pcall(long_string)").unwrap());
+        assert!(caught("return pcall(error, 'not memory') == false").unwrap());
+
+        lua.set_oom_behavior(OomBehavior::Propagate);
+        for script in &[
+            "return pcall(grow)",
+            "return xpcall(grow, function() return 'handled' end)",
+            "return pcall(pcall, grow)",
+        ] {
+            match caught(script) {
+                Err(Error::MemoryError(_)) => {}
+                r => panic!("{} did not propagate the memory error: {:?}", script, r),
+            }
+        }
+        match caught("return pcall(string.rep, 'x', 2000)") {
+            Err(Error::StringLimitExceeded { limit: 1000, .. }) => {}
+            r => panic!("did not propagate the string limit error: {:?}", r),
+        }
+        match caught("return pcall(long_string)") {
+            Err(Error::StringLimitExceeded { limit: 1000, .. }) => {}
+            r => panic!("did not propagate the callback error: {:?}", r),
+        }
+        assert!(caught("return pcall(error, 'not memory') == false").unwrap());
+        assert!(
+            caught("return select(2, coroutine.resume(coroutine.create(grow))) ~= nil").unwrap()
+        );
+        assert!(caught("return select('#', pcall(grow)) == 0").is_err());
+    });
+
+    lua.set_oom_behavior(OomBehavior::Catchable);
+    lua.context(|ctx| {
+        assert!(!ctx.load("return pcall(grow)").eval::<bool>().unwrap());
+    });
+}
+
 #[test]
 fn test_gc_control() {
     let lua = Lua::new();
@@ -83,3 +143,101 @@ fn test_gc_error() {
         }
     });
 }
+
+#[test]
+fn test_string_length_limit() {
+    let lua = Lua::new();
+    lua.set_string_length_limit(Some(1024));
+
+    lua.context(|ctx| {
+        match ctx.load(r#"return string.rep("a", 2^20)"#).exec() {
+            Err(Error::StringLimitExceeded { length, limit }) => {
+                assert_eq!(length, 1 << 20);
+                assert_eq!(limit, 1024);
+            }
+            r => panic!("did not trigger string limit: {:?}", r),
+        }
+        match ctx.create_string(&vec![b'a'; 2048]) {
+            Err(Error::StringLimitExceeded { length: 2048, .. }) => {}
+            r => panic!("did not trigger string limit: {:?}", r),
+        }
+
+        // Scripts can catch the error, and it does not affect later memory errors.
+        let caught: bool = ctx
+            .load(r#"return not pcall(string.rep, "a", 2000)"#)
+            .eval()
+            .unwrap();
+        assert!(caught);
+        ctx.create_string(&vec![b'a'; 1024]).unwrap();
+    });
+
+    lua.set_string_length_limit(None);
+    lua.context(|ctx| ctx.load(r#"local s = string.rep("a", 2^20)"#).exec())
+        .unwrap();
+}
+
+#[test]
+fn test_table_size_limit() {
+    let lua = Lua::new();
+    lua.set_table_size_limit(Some(3));
+
+    lua.context(|ctx| {
+        let small = ctx.load("{1, 2, 3}").eval::<Vec<i64>>().unwrap();
+        assert_eq!(small, vec![1, 2, 3]);
+
+        match ctx.load("{1, 2, 3, 4}").eval::<Vec<i64>>() {
+            Err(Error::TableLimitExceeded { limit: 3 }) => {}
+            r => panic!("did not trigger table limit: {:?}", r),
+        }
+        match ctx
+            .load("{a = 1, b = 2, c = 3, d = 4}")
+            .eval::<HashMap<String, i64>>()
+        {
+            Err(Error::TableLimitExceeded { limit: 3 }) => {}
+            r => panic!("did not trigger table limit: {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_conversion_depth_limit() {
+    // A recursive type, which never stops converting a table that contains itself.
+    #[derive(Debug)]
+    struct Tree(Vec<Tree>);
+
+    impl<'lua> FromLua<'lua> for Tree {
+        fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Tree> {
+            Vec::from_lua(value, lua).map(Tree)
+        }
+    }
+
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        match ctx.load("local t = {} t[1] = t return t").eval::<Tree>() {
+            Err(Error::DepthLimitExceeded { limit: 128 }) => {}
+            r => panic!("did not trigger depth limit: {:?}", r),
+        }
+        let tree = ctx.load("{ {}, { {} } }").eval::<Tree>().unwrap();
+        assert_eq!((tree.0)[1].0.len(), 1);
+    });
+
+    lua.set_conversion_depth_limit(Some(2));
+    lua.context(|ctx| {
+        ctx.load("{ { 1 }, { 2 } }")
+            .eval::<Vec<Vec<i64>>>()
+            .unwrap();
+        match ctx.load("{ { { 1 } } }").eval::<Vec<Vec<Vec<i64>>>>() {
+            Err(Error::DepthLimitExceeded { limit: 2 }) => {}
+            r => panic!("did not trigger depth limit: {:?}", r),
+        }
+        assert!(ctx.pack(vec![vec![vec![1]]]).is_err());
+        ctx.pack(vec![vec![1]]).unwrap();
+    });
+
+    lua.set_conversion_depth_limit(None);
+    lua.context(|ctx| {
+        ctx.load("{ { { 1 } } }")
+            .eval::<Vec<Vec<Vec<i64>>>>()
+            .unwrap();
+    });
+}
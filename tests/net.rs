@@ -0,0 +1,195 @@
+#![cfg(feature = "net")]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use rlua::{Error, Function, Lua, NetPolicy};
+
+mod common;
+
+use common::block_on;
+
+fn local_policy() -> NetPolicy {
+    NetPolicy {
+        allow_tcp: true,
+        allow_udp: true,
+        allowed_hosts: vec!["127.0.0.1".to_owned()],
+        timeout: Some(Duration::from_secs(10)),
+    }
+}
+
+#[test]
+fn test_net_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf.to_ascii_uppercase()).unwrap();
+
+        // Never answers the second request, so the client times out.
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+    });
+
+    Lua::new().context(|lua| {
+        let net = lua.create_net_module(local_policy()).unwrap();
+        lua.globals().set("net", net).unwrap();
+
+        let script: Function = lua
+            .load(
+                r#"
+                    function(port)
+                        local socket = net.connect("127.0.0.1", port)
+                        assert(socket:send("hello") == 5)
+                        local response = ""
+                        while #response < 5 do
+                            response = response .. socket:receive(5 - #response)
+                        end
+                        socket:close()
+                        assert(not pcall(socket.send, socket, "closed"))
+                        return response
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            block_on(script.call_async::<_, String>(port)).unwrap(),
+            "HELLO"
+        );
+
+        let slow: Function = lua
+            .load(
+                r#"
+                    function(port)
+                        slow = net.connect("127.0.0.1", port, 0.05)
+                        slow:receive(10)
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        match block_on(slow.call_async::<_, ()>(port)) {
+            Err(Error::CallbackError { cause, .. }) => match &*cause {
+                Error::RuntimeError(message) => assert_eq!(message, "receive timed out"),
+                err => panic!("unexpected error {:?}", err),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+        lua.load("slow:close()").exec().unwrap();
+    });
+    server.join().unwrap();
+}
+
+#[test]
+fn test_net_huge_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"hi").unwrap();
+    });
+
+    // Timeouts too large to represent fall back to the policy timeout.
+    Lua::new().context(|lua| {
+        let net = lua.create_net_module(local_policy()).unwrap();
+        lua.globals().set("net", net).unwrap();
+        let script: Function = lua
+            .load(
+                r#"
+                    function(port)
+                        local socket = net.connect("127.0.0.1", port, 1e300)
+                        local response = socket:receive(2, 1e300)
+                        socket:close()
+                        return response
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            block_on(script.call_async::<_, String>(port)).unwrap(),
+            "hi"
+        );
+    });
+    server.join().unwrap();
+}
+
+#[test]
+fn test_net_udp() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut buf = [0; 16];
+        let (len, from) = server.recv_from(&mut buf).unwrap();
+        server.send_to(&buf[..len], from).unwrap();
+    });
+
+    Lua::new().context(|lua| {
+        let net = lua.create_net_module(local_policy()).unwrap();
+        lua.globals().set("net", net).unwrap();
+
+        let script: Function = lua
+            .load(
+                r#"
+                    function(port)
+                        local socket = net.connect_udp("127.0.0.1", port)
+                        socket:send("ping")
+                        return socket:receive(16)
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            block_on(script.call_async::<_, String>(port)).unwrap(),
+            "ping"
+        );
+    });
+    server.join().unwrap();
+}
+
+#[test]
+fn test_net_policy() {
+    Lua::new().context(|lua| {
+        let net = lua
+            .create_net_module(NetPolicy {
+                allow_udp: false,
+                ..local_policy()
+            })
+            .unwrap();
+        lua.globals().set("net", net).unwrap();
+
+        let connect = |code: &str| {
+            let function: Function = lua.load(code).eval().unwrap();
+            match block_on(function.call_async::<_, ()>(())) {
+                Err(Error::CallbackError { cause, .. }) => match &*cause {
+                    Error::RuntimeError(message) => message.clone(),
+                    err => panic!("unexpected error {:?}", err),
+                },
+                r => panic!("unexpected result {:?}", r),
+            }
+        };
+
+        assert_eq!(
+            connect("function() net.connect('localhost', 1) end"),
+            "connecting to localhost:1 is not allowed"
+        );
+        assert_eq!(
+            connect("function() net.connect_udp('127.0.0.1', 1) end"),
+            "UDP connections are not allowed"
+        );
+        assert_eq!(
+            connect("function() net.connect('127.0.0.1', 1, -1) end"),
+            "timeout must be a positive number of seconds"
+        );
+
+        // Outside of an async thread the functions cannot wait for anything.
+        assert!(lua.load("net.connect('127.0.0.1', 1)").exec().is_err());
+    });
+}
@@ -0,0 +1,177 @@
+use std::{env, fs, process};
+
+use rlua::{Error, Lua, LuaBuilder, PackagePolicy, StdLib};
+
+#[test]
+fn test_package_path() {
+    let dir = env::temp_dir().join(format!("rlua-package-{}", process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("greeting.lua"), "return 'hello'").unwrap();
+    fs::write(dir.join("lib").join("init.lua"), "return 'lib'").unwrap();
+
+    let dir_str = dir.to_str().unwrap();
+    let lua = LuaBuilder::new()
+        .package_policy(PackagePolicy {
+            path: Some(vec![
+                format!("{}/?.lua", dir_str),
+                format!("{}/?/init.lua", dir_str),
+            ]),
+            cpath: Some(vec![]),
+            ..PackagePolicy::default()
+        })
+        .build()
+        .unwrap();
+    lua.context(|lua| {
+        assert_eq!(
+            lua.load("require('greeting') .. ' ' .. require('lib')")
+                .eval::<String>()
+                .unwrap(),
+            "hello lib"
+        );
+        assert_eq!(lua.load("package.cpath").eval::<String>().unwrap(), "");
+        assert!(lua.load("require('missing')").exec().is_err());
+    });
+
+    match lua.set_package_policy(PackagePolicy {
+        path: Some(vec!["a/?.lua;b/?.lua".to_owned()]),
+        ..PackagePolicy::default()
+    }) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("contains ';'"), "{}", msg),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_package_c_modules() {
+    let lua = Lua::new();
+    let count_searchers =
+        || lua.context(|lua| lua.load("#package.searchers").eval::<usize>().unwrap());
+    let has_loadlib =
+        || lua.context(|lua| lua.load("package.loadlib ~= nil").eval::<bool>().unwrap());
+    assert_eq!(count_searchers(), 4);
+
+    let no_c_modules = PackagePolicy {
+        c_modules: false,
+        ..PackagePolicy::default()
+    };
+    lua.set_package_policy(no_c_modules.clone()).unwrap();
+    assert_eq!(count_searchers(), 2);
+    assert!(!has_loadlib());
+    lua.set_package_policy(no_c_modules).unwrap();
+    assert_eq!(count_searchers(), 2);
+
+    // Searchers added by the host are kept, and the C searchers go after them once restored.
+    lua.context(|lua| {
+        lua.load("table.insert(package.searchers, function() return 'custom' end)")
+            .exec()
+            .unwrap()
+    });
+    lua.set_package_policy(PackagePolicy::default()).unwrap();
+    assert_eq!(count_searchers(), 5);
+    assert!(has_loadlib());
+    lua.set_package_policy(PackagePolicy::default()).unwrap();
+    assert_eq!(count_searchers(), 5);
+    lua.context(|lua| {
+        assert_eq!(
+            lua.load("package.searchers[3]()").eval::<String>().unwrap(),
+            "custom"
+        );
+    });
+
+    // The host can still load C modules while scripts may not.
+    lua.set_package_policy(PackagePolicy {
+        c_modules: false,
+        ..PackagePolicy::default()
+    })
+    .unwrap();
+    lua.context(
+        |lua| match unsafe { lua.load_c_module("/nonexistent/module.so", "module") } {
+            Err(Error::RuntimeError(msg)) => {
+                assert!(msg.contains("cannot load C module"), "{}", msg)
+            }
+            r => panic!("unexpected result {:?}", r),
+        },
+    );
+
+    let lua = Lua::new_with(StdLib::BASE);
+    assert!(lua.set_package_policy(PackagePolicy::default()).is_err());
+}
+
+#[test]
+fn test_module_graph() {
+    let lua = Lua::new();
+    let tracking = PackagePolicy {
+        track_dependencies: true,
+        ..PackagePolicy::default()
+    };
+    lua.set_package_policy(tracking.clone()).unwrap();
+    lua.set_package_policy(tracking).unwrap();
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                package.preload.base = function() return {} end
+                package.preload.model = function() require("base") return {} end
+                package.preload.view = function() require("model") require("base") return {} end
+                package.preload.broken = function() require("base") error({code = 1}) end
+                require("view")
+                require("model")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        // Errors, including error objects, reach scripts unchanged through the wrapper.
+        let code = lua
+            .load("local ok, err = pcall(require, 'broken') return err.code")
+            .eval::<i64>()
+            .unwrap();
+        assert_eq!(code, 1);
+        let message = lua
+            .load("local ok, err = pcall(require, 'missing') return err")
+            .eval::<String>()
+            .unwrap();
+        assert!(
+            message.contains("module 'missing' not found"),
+            "{}",
+            message
+        );
+    });
+
+    let graph = lua.module_graph();
+    assert_eq!(graph.modules(), vec!["base", "model", "view"]);
+    assert_eq!(graph.roots(), vec!["model", "view"]);
+    assert_eq!(graph.dependencies("view"), vec!["base", "model"]);
+    assert_eq!(graph.dependents("base"), vec!["model", "view"]);
+    assert_eq!(graph.affected_by("base"), vec!["model", "view"]);
+    assert_eq!(graph.affected_by("view"), Vec::<&str>::new());
+    assert_eq!(graph.load_order(), vec!["base", "model", "view"]);
+
+    // Reloading a module records its dependencies anew.
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                package.loaded.model = nil
+                package.preload.model = function() return {} end
+                require("model")
+            "#,
+        )
+        .exec()
+        .unwrap()
+    });
+    let graph = lua.module_graph();
+    assert_eq!(graph.dependencies("model"), Vec::<&str>::new());
+    assert_eq!(graph.affected_by("base"), vec!["view"]);
+
+    // Turning tracking off puts back the standard `require`.
+    lua.set_package_policy(PackagePolicy::default()).unwrap();
+    lua.context(|lua| {
+        let standard = lua
+            .load("package.preload.extra = function() return 1 end return require('extra')")
+            .eval::<i64>()
+            .unwrap();
+        assert_eq!(standard, 1);
+    });
+    assert!(!lua.module_graph().modules().contains(&"extra"));
+}
@@ -0,0 +1,65 @@
+#![cfg(all(feature = "process", unix))]
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rlua::{Error, Lua, ProcessPolicy};
+
+fn run_error(lua: rlua::Context, code: &str) -> String {
+    match lua.load(code).exec() {
+        Err(Error::CallbackError { cause, .. }) => match &*cause {
+            Error::RuntimeError(message) => message.clone(),
+            err => panic!("unexpected error {:?}", err),
+        },
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_process_module() {
+    Lua::new().context(|lua| {
+        let process = lua
+            .create_process_module(ProcessPolicy {
+                allowed_programs: vec!["/bin/sh".to_owned(), "/bin/cat".to_owned()],
+                validate_args: Some(Arc::new(|program, args| {
+                    program != "/bin/sh" || args.first().map(String::as_str) == Some("-c")
+                })),
+                max_output_size: Some(1024),
+                timeout: Some(Duration::from_millis(500)),
+            })
+            .unwrap();
+        lua.globals().set("process", process).unwrap();
+
+        lua.load(
+            r#"
+                local status, stdout, stderr = process.run("/bin/sh", {"-c", "echo out; echo err >&2; exit 3"})
+                assert(status == 3 and stdout == "out\n" and stderr == "err\n")
+
+                status, stdout = process.run("/bin/cat", {}, "input")
+                assert(status == 0 and stdout == "input")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        assert_eq!(
+            run_error(lua, "process.run('/bin/echo', {'hi'})"),
+            "running '/bin/echo' is not allowed"
+        );
+        assert_eq!(
+            run_error(lua, "process.run('/bin/sh', {'script.sh'})"),
+            "arguments to '/bin/sh' rejected by policy"
+        );
+        assert_eq!(
+            run_error(lua, "process.run('/bin/sh', {'-c', 'while :; do echo spam; done'})"),
+            "output of '/bin/sh' exceeded the limit"
+        );
+
+        let start = Instant::now();
+        assert_eq!(
+            run_error(lua, "process.run('/bin/sh', {'-c', 'exec sleep 10'})"),
+            "'/bin/sh' timed out"
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+    });
+}
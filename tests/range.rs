@@ -0,0 +1,53 @@
+use rlua::{Error, Integer, IntegerRange, Lua};
+
+#[test]
+fn test_integer_range() {
+    let range = IntegerRange::new(10, 1, -3).unwrap();
+    assert_eq!(range.len(), 4);
+    assert_eq!(range.get(4), Some(1));
+    assert_eq!(range.get(5), None);
+    assert!(IntegerRange::new(1, 0, 1).unwrap().is_empty());
+    assert_eq!(IntegerRange::from(Integer::MIN..Integer::MIN).len(), 0);
+    assert_eq!(
+        IntegerRange::from(Integer::MIN..=Integer::MAX).len(),
+        Integer::MAX
+    );
+    match IntegerRange::new(1, 2, 0) {
+        Err(Error::RuntimeError(_)) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals.set("range", range).unwrap();
+        globals.set("empty", IntegerRange::from(1..1)).unwrap();
+        globals
+            .set(
+                "top",
+                IntegerRange::new(Integer::MAX - 1, Integer::MAX, 1).unwrap(),
+            )
+            .unwrap();
+        lua.load(
+            r#"
+                local values = {}
+                for i in range do values[#values + 1] = i end
+                assert(table.concat(values, ",") == "10,7,4,1")
+
+                local sum = 0
+                for i, v in ipairs(range) do sum = sum + i * v end
+                assert(sum == 10 + 14 + 12 + 4)
+
+                assert(#range == 4 and range[1] == 10 and range[2.0] == 7 and range[0] == nil)
+                assert(range.x == nil)
+
+                for _ in empty do error("empty range iterated") end
+
+                local count = 0
+                for _ in top do count = count + 1 end
+                assert(count == 2)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
@@ -0,0 +1,206 @@
+use std::os::raw::{c_char, c_int, c_longlong};
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use rlua::{lua_State, Error, Function, Lua, StateStatus};
+
+// The parts of the Lua C API a host application would use, linked from rlua's copy of Lua.
+extern "C" {
+    fn luaL_newstate() -> *mut lua_State;
+    fn luaL_openlibs(state: *mut lua_State);
+    fn luaL_loadstring(state: *mut lua_State, s: *const c_char) -> c_int;
+    fn lua_pcallk(
+        state: *mut lua_State,
+        nargs: c_int,
+        nresults: c_int,
+        msgh: c_int,
+        ctx: isize,
+        k: *const u8,
+    ) -> c_int;
+    fn lua_tointegerx(state: *mut lua_State, index: c_int, isnum: *mut c_int) -> c_longlong;
+    fn lua_settop(state: *mut lua_State, index: c_int);
+    fn lua_pushinteger(state: *mut lua_State, n: c_longlong);
+    fn lua_pushnumber(state: *mut lua_State, n: f64);
+    fn lua_pushboolean(state: *mut lua_State, b: c_int);
+    fn lua_pushlstring(state: *mut lua_State, s: *const c_char, len: usize) -> *const c_char;
+    fn lua_pushnil(state: *mut lua_State);
+    fn lua_createtable(state: *mut lua_State, narr: c_int, nrec: c_int);
+    fn lua_close(state: *mut lua_State);
+    fn lua_resume(state: *mut lua_State, from: *mut lua_State, nargs: c_int) -> c_int;
+}
+
+unsafe fn host_eval_integer(state: *mut lua_State, source: &[u8]) -> c_longlong {
+    assert_eq!(luaL_loadstring(state, source.as_ptr() as *const c_char), 0);
+    assert_eq!(lua_pcallk(state, 0, 1, 0, 0, std::ptr::null()), 0);
+    let result = lua_tointegerx(state, -1, std::ptr::null_mut());
+    lua_settop(state, 0);
+    result
+}
+
+#[test]
+fn test_init_from_ptr() {
+    unsafe {
+        let state = luaL_newstate();
+        luaL_openlibs(state);
+        assert_eq!(host_eval_integer(state, b"answer = 21 return answer\0"), 21);
+
+        let lua = Lua::init_from_ptr(state);
+        assert_eq!(lua.as_raw(), state);
+        lua.context(|lua| {
+            let globals = lua.globals();
+            assert_eq!(globals.get::<_, i64>("answer").unwrap(), 21);
+            let double: Function = lua.load("function(x) return x * 2 end").eval().unwrap();
+            globals
+                .set(
+                    "double",
+                    lua.create_function(move |_, x: i64| Ok(x * 2)).unwrap(),
+                )
+                .unwrap();
+            assert_eq!(double.call::<_, i64>(4).unwrap(), 8);
+        });
+        assert_eq!(host_eval_integer(state, b"return double(answer)\0"), 42);
+        // The host's scripts keep working with rlua's replacement `pcall`.
+        assert_eq!(
+            host_eval_integer(state, b"return select('#', pcall(error, 'x'))\0"),
+            2
+        );
+
+        drop(lua);
+        lua_close(state);
+    }
+}
+
+#[test]
+fn test_into_raw() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.globals()
+            .set(
+                "triple",
+                lua.create_function(|_, x: i64| Ok(x * 3)).unwrap(),
+            )
+            .unwrap();
+    });
+    let state = lua.into_raw();
+    unsafe {
+        assert_eq!(host_eval_integer(state, b"return triple(5)\0"), 15);
+        lua_close(state);
+    }
+}
+
+#[test]
+fn test_stack_dump() {
+    let lua = Lua::new();
+    let state = lua.as_raw();
+    let long = "a".repeat(50);
+    unsafe {
+        lua_pushinteger(state, 1);
+        lua_pushnumber(state, 2.5);
+        lua_pushboolean(state, 1);
+        lua_pushlstring(state, long.as_ptr() as *const c_char, long.len());
+        lua_pushnil(state);
+        lua_createtable(state, 0, 0);
+    }
+
+    lua.context(|lua_context| {
+        let _handle = lua_context.create_table().unwrap();
+        let dump = lua.stack_dump();
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert!(lines[0].ends_with(": 6 values, 1 rlua references in use"));
+        assert_eq!(lines[1], "    1   -6  integer 1");
+        assert_eq!(lines[2], "    2   -5  number 2.5");
+        assert_eq!(lines[3], "    3   -4  boolean true");
+        assert_eq!(
+            lines[4],
+            format!("    4   -3  string {:?}... (50 bytes)", "a".repeat(40))
+        );
+        assert_eq!(lines[5], "    5   -2  nil");
+        assert!(lines[6].starts_with("    6   -1  table 0x"));
+        assert_eq!(lua_context.stack_dump(), dump);
+    });
+
+    unsafe { lua_settop(state, 0) };
+    assert!(lua
+        .stack_dump()
+        .ends_with(": 0 values, 0 rlua references in use\n"));
+}
+
+#[test]
+fn test_state_status() {
+    let lua = Lua::new();
+    assert_eq!(lua.status(), StateStatus::Ok);
+
+    // A panic in a callback is propagated out of `context`.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        lua.context(|lua| {
+            let panic = lua
+                .create_function(|_, ()| -> rlua::Result<()> { panic!("callback panic") })
+                .unwrap();
+            panic.call::<_, ()>(())
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(lua.status(), StateStatus::Panicked);
+    assert_eq!(
+        lua.context(|lua| lua.load("1 + 1").eval::<i64>().unwrap()),
+        2
+    );
+    lua.clear_panicked();
+    assert_eq!(lua.status(), StateStatus::Ok);
+
+    // Running the main thread as a coroutine which fails leaves it dead.
+    unsafe {
+        let state = lua.as_raw();
+        assert_eq!(
+            luaL_loadstring(state, b"error('boom')\0".as_ptr() as *const c_char),
+            0
+        );
+        assert_ne!(lua_resume(state, std::ptr::null_mut(), 0), 0);
+    }
+    assert_eq!(lua.status(), StateStatus::Broken);
+    assert!(catch_unwind(AssertUnwindSafe(|| lua.context(|_| ()))).is_err());
+    drop(lua);
+}
+
+#[test]
+fn test_check_stack() {
+    let lua = Lua::new();
+    lua.check_stack(1000).unwrap();
+    unsafe {
+        let state = lua.as_raw();
+        for i in 0..1000 {
+            lua_pushinteger(state, i);
+        }
+        lua_settop(state, 0);
+    }
+    match lua.check_stack(10_000_000) {
+        Err(Error::StackError) => {}
+        r => panic!("expected StackError, got {:?}", r),
+    }
+
+    lua.context(|lua| {
+        let check = lua
+            .create_function(|lua, n: usize| Ok(lua.check_stack(n).is_ok()))
+            .unwrap();
+        assert!(check.call::<_, bool>(100).unwrap());
+        assert!(!check.call::<_, bool>(10_000_000).unwrap());
+    });
+
+    // Callbacks fail before running when the reserve cannot be met.
+    lua.set_stack_reserve(10_000_000);
+    lua.context(|lua| {
+        let callback = lua.create_function(|_, ()| Ok(())).unwrap();
+        match callback.call::<_, ()>(()) {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::StackError => {}
+                ref other => panic!("expected StackError, got {:?}", other),
+            },
+            r => panic!("expected StackError, got {:?}", r),
+        }
+    });
+    lua.set_stack_reserve(0);
+    lua.context(|lua| {
+        let callback = lua.create_function(|_, ()| Ok(())).unwrap();
+        callback.call::<_, ()>(()).unwrap();
+    });
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rlua::{Clock, Function, LoadPolicy, LoadQuota, Lua, StdLib, Table};
+
+#[test]
+fn test_sandboxed_load() {
+    Lua::new().context(|lua| {
+        let env = lua.create_table().unwrap();
+        for name in &["assert", "error", "pcall"] {
+            env.set(*name, lua.globals().get::<_, Function>(*name).unwrap())
+                .unwrap();
+        }
+        env.set("string", lua.globals().get::<_, Table>("string").unwrap())
+            .unwrap();
+        env.set("secret", 42).unwrap();
+        let load = lua
+            .create_sandboxed_load(
+                env.clone(),
+                LoadPolicy {
+                    max_source_size: Some(64),
+                    quota: None,
+                },
+            )
+            .unwrap();
+        env.set("load", load).unwrap();
+        lua.globals().set("global_only", true).unwrap();
+
+        lua.load(
+            r#"
+                -- The environment is always the sandbox, even if another one is requested.
+                assert(load("return secret")() == 42)
+                assert(load("return global_only")() == nil)
+                assert(load("return secret", "chunk", "t", {})() == 42)
+
+                -- Reader functions are supported.
+                local pieces = { "return ", "1 + ", "2" }
+                local i = 0
+                assert(load(function() i = i + 1; return pieces[i] end)() == 3)
+
+                -- Rejected chunks return nil and a message, like the standard load.
+                local f, err = load("return (")
+                assert(f == nil and err:find("unexpected symbol"))
+                f, err = load("\27Lua")
+                assert(f == nil and err:find("binary chunk"))
+                f, err = load("return 1", "chunk", "b")
+                assert(f == nil and err:find("text chunk"))
+                f, err = load(string.rep(" ", 65))
+                assert(f == nil and err:find("too large"))
+                f, err = load(function() return string.rep(" ", 40) end)
+                assert(f == nil and err:find("too large"))
+            "#,
+        )
+        .set_environment(env.clone())
+        .unwrap()
+        .exec()
+        .unwrap();
+
+        let name: String = lua
+            .load(
+                r#"
+                    local f = load("error('boom')", "=named")
+                    local ok, err = pcall(f)
+                    return err
+                "#,
+            )
+            .set_environment(env)
+            .unwrap()
+            .eval()
+            .unwrap();
+        assert_eq!(name, "named:1: boom");
+    });
+
+    // The load function does not keep its environment alive
+    Lua::new().context(|lua| {
+        let env = lua.create_table().unwrap();
+        env.set(
+            "load",
+            lua.create_sandboxed_load(env.clone(), LoadPolicy::default())
+                .unwrap(),
+        )
+        .unwrap();
+        lua.globals().set("sandbox", env).unwrap();
+        lua.load(
+            r#"
+                local weak = setmetatable({ sandbox }, { __mode = "v" })
+                sandbox = nil
+                collectgarbage()
+                assert(weak[1] == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_load_quota() {
+    let quota = LoadQuota::new(25);
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let load = lua
+            .create_sandboxed_load(
+                lua.globals(),
+                LoadPolicy {
+                    max_source_size: None,
+                    quota: Some(quota.clone()),
+                },
+            )
+            .unwrap();
+        lua.globals().set("load", load).unwrap();
+
+        let results: Table = lua
+            .load(
+                r#"
+                    local results = {}
+                    for i = 1, 3 do
+                        local f, err = load("return 1234")
+                        results[i] = f and f() or err
+                    end
+                    return results
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(results.get::<_, i64>(1).unwrap(), 1234);
+        assert_eq!(results.get::<_, i64>(2).unwrap(), 1234);
+        assert_eq!(results.get::<_, String>(3).unwrap(), "load quota exceeded");
+    });
+    assert_eq!(quota.remaining(), 25 - 2 * "return 1234".len());
+
+    quota.refill(100);
+    assert_eq!(quota.remaining(), 103);
+
+    // A reader is stopped as soon as its source exceeds the remaining quota.
+    lua.context(|lua| {
+        let calls: i64 = lua
+            .load(
+                r#"
+                    local calls = 0
+                    local f, err = load(function()
+                        calls = calls + 1
+                        return "-- endless comment"
+                    end)
+                    assert(f == nil and err == "load quota exceeded")
+                    return calls
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(calls, 6);
+    });
+    assert_eq!(quota.remaining(), 103);
+}
+
+#[test]
+fn test_load_reader_limit() {
+    Lua::new().context(|lua| {
+        let load = lua
+            .create_sandboxed_load(lua.globals(), LoadPolicy::default())
+            .unwrap();
+        lua.globals().set("load", load).unwrap();
+        lua.globals()
+            .set("piece_size", LoadPolicy::READER_SOURCE_LIMIT / 8)
+            .unwrap();
+
+        let calls: i64 = lua
+            .load(
+                r#"
+                    local piece = string.rep(" ", piece_size)
+                    local calls = 0
+                    local f, err = load(function()
+                        calls = calls + 1
+                        return piece
+                    end)
+                    assert(f == nil and err:find("too large"))
+                    return calls
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(calls, 9);
+    });
+}
+
+#[test]
+fn test_sandboxed_os() {
+    struct ManualClock(Arc<AtomicU64>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(self.0.load(Ordering::SeqCst))
+        }
+
+        fn cpu_time(&self) -> Duration {
+            Duration::from_millis(1500)
+        }
+    }
+
+    let lua = Lua::new_with(StdLib::BASE | StdLib::STRING);
+    let time = Arc::new(AtomicU64::new(86400));
+    lua.set_clock(ManualClock(time.clone()));
+    let mut env = HashMap::new();
+    env.insert("LANG".to_owned(), "C".to_owned());
+    lua.set_env_provider(env);
+
+    lua.context(|lua| {
+        let os = lua.create_sandboxed_os().unwrap();
+        lua.globals().set("os", os).unwrap();
+        lua.load(
+            r#"
+                assert(os.time() == 86400)
+                assert(os.date("!%Y-%m-%d") == "1970-01-02")
+                assert(os.date("!*t").day == 2)
+                assert(os.date("!%Y", 0) == "1970")
+                assert(os.clock() == 1.5)
+                assert(os.difftime(os.time(), 0) == 86400)
+                assert(type(os.time({year = 2000, month = 1, day = 1})) == "number")
+                assert(os.getenv("LANG") == "C" and os.getenv("HOME") == nil)
+                assert(os.execute == nil and os.exit == nil and os.remove == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        time.store(2 * 86400, Ordering::SeqCst);
+        lua.load(r#"assert(os.date("!%d") == "03")"#)
+            .exec()
+            .unwrap();
+    });
+}
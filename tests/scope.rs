@@ -1,7 +1,10 @@
 use std::cell::Cell;
 use std::rc::Rc;
+use std::sync::Arc;
 
-use rlua::{Error, Function, Lua, MetaMethod, String, UserData, UserDataMethods};
+use rlua::{
+    AnyUserData, Error, Function, Lua, MetaMethod, String, UserData, UserDataMethods, Value,
+};
 
 #[test]
 fn scope_func() {
@@ -66,6 +69,35 @@ fn scope_drop() {
     });
 }
 
+#[test]
+fn scope_table_string() {
+    struct MyUserdata(Arc<()>);
+    impl UserData for MyUserdata {}
+
+    Lua::new().context(|lua| {
+        let rc = Arc::new(());
+
+        let table = lua.scope(|scope| {
+            let table = scope.create_table().unwrap();
+            table.set("data", MyUserdata(rc.clone())).unwrap();
+            table.set(1, scope.create_string("one").unwrap()).unwrap();
+            table
+                .set_metatable(Some(lua.create_table().unwrap()))
+                .unwrap();
+            lua.globals().set("kept", table.clone()).unwrap();
+            assert_eq!(lua.load("kept[1]").eval::<String>().unwrap(), "one");
+            table
+        });
+
+        assert_eq!(table.raw_len(), 0);
+        assert!(table.get_metatable().is_none());
+        assert!(lua.load("next(kept) == nil").eval::<bool>().unwrap());
+
+        lua.load(r#"collectgarbage("collect")"#).exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 1);
+    });
+}
+
 #[test]
 fn scope_capture() {
     let lua = Lua::new();
@@ -193,6 +225,60 @@ fn scope_userdata_functions() {
     });
 }
 
+#[test]
+fn scope_userdata_borrow_scoped() {
+    struct MyUserData<'a>(&'a Cell<i64>);
+    struct OtherUserData;
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_function("get", |_, ud: AnyUserData| unsafe {
+                ud.borrow_scoped::<MyUserData, _>(|data| data.0.get())
+            });
+        }
+    }
+
+    impl UserData for OtherUserData {}
+
+    let i = Cell::new(42);
+    Lua::new().context(|lua| {
+        let ud = lua.scope(|scope| {
+            let ud = scope.create_nonstatic_userdata(MyUserData(&i)).unwrap();
+            let other = scope.create_nonstatic_userdata(OtherUserData).unwrap();
+            lua.globals().set("ud", ud.clone()).unwrap();
+            lua.globals().set("other", other.clone()).unwrap();
+            assert_eq!(lua.load("ud.get(ud)").eval::<i64>().unwrap(), 42);
+
+            unsafe {
+                assert_eq!(
+                    ud.borrow_scoped::<MyUserData, _>(|data| data.0.get() + 1)
+                        .unwrap(),
+                    43
+                );
+                match other.borrow_scoped::<MyUserData, _>(|_| ()) {
+                    Err(Error::UserDataTypeMismatch) => {}
+                    r => panic!("improper return for mismatched userdata: {:?}", r),
+                }
+            }
+            match lua.load("ud.get(other)").exec() {
+                Err(Error::CallbackError { ref cause, .. }) => match *cause.as_ref() {
+                    Error::UserDataTypeMismatch => {}
+                    ref other => panic!("wrong error type {:?}", other),
+                },
+                r => panic!("improper return for mismatched userdata: {:?}", r),
+            }
+            ud
+        });
+
+        unsafe {
+            match ud.borrow_scoped::<MyUserData, _>(|_| ()) {
+                Err(Error::UserDataTypeMismatch) => {}
+                r => panic!("improper return for destructed userdata: {:?}", r),
+            }
+        }
+    });
+}
+
 #[test]
 fn scope_userdata_mismatch() {
     struct MyUserData<'a>(&'a Cell<i64>);
@@ -243,3 +329,127 @@ fn scope_userdata_mismatch() {
         });
     });
 }
+
+#[test]
+fn scope_userdata_user_value() {
+    struct MyUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, data, ()| Ok(data.0.get()));
+        }
+    }
+
+    let i = Cell::new(7);
+    Lua::new().context(|lua| {
+        let ud = lua.scope(|scope| {
+            let ud = scope.create_nonstatic_userdata(MyUserData(&i)).unwrap();
+            ud.set_user_value("cached").unwrap();
+            assert_eq!(ud.get_user_value::<String>().unwrap(), "cached");
+            assert_eq!(
+                lua.load("return (...):get()")
+                    .call::<_, i64>(ud.clone())
+                    .unwrap(),
+                7
+            );
+            unsafe {
+                assert_eq!(
+                    ud.borrow_scoped::<MyUserData, _>(|data| data.0.get())
+                        .unwrap(),
+                    7
+                );
+            }
+            ud
+        });
+
+        unsafe {
+            match ud.borrow_scoped::<MyUserData, _>(|_| ()) {
+                Err(Error::UserDataTypeMismatch) => {}
+                r => panic!("improper return for destructed userdata: {:?}", r),
+            }
+        }
+    });
+}
+
+#[test]
+fn scope_userdata_fields() {
+    struct MyUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_field_method_get("value", |_, data| Ok(data.0.get()));
+            methods.add_field_method_set("value", |_, data, value| {
+                data.0.set(value);
+                Ok(())
+            });
+        }
+    }
+
+    let i = Cell::new(1);
+    Lua::new().context(|lua| {
+        lua.scope(|scope| {
+            let ud = scope.create_nonstatic_userdata(MyUserData(&i)).unwrap();
+            lua.globals().set("ud", ud).unwrap();
+            lua.load("ud.value = ud.value + 41").exec().unwrap();
+        });
+        assert!(lua.load("return ud.value").exec().is_err());
+    });
+    assert_eq!(i.get(), 42);
+}
+
+#[test]
+fn scope_userdata_meta_methods() {
+    struct Slice<'a>(&'a [i64]);
+
+    impl<'a> UserData for Slice<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Index, |_, this, i: usize| {
+                Ok(i.checked_sub(1).and_then(|i| this.0.get(i)).copied())
+            });
+            methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.0.len()));
+            methods.add_meta_method(MetaMethod::Pairs, |lua, this, ()| {
+                let items = lua.create_sequence_from(this.0.iter().copied())?;
+                let next: Function = lua.globals().get("next")?;
+                Ok((next, items, Value::Nil))
+            });
+            methods.add_meta_method(MetaMethod::IDiv, |_, this, n: i64| {
+                Ok(this.0.iter().sum::<i64>() / n)
+            });
+            methods.add_meta_method(MetaMethod::BAnd, |_, this, mask: i64| {
+                Ok(this.0.iter().fold(0, |acc, &x| acc | x) & mask)
+            });
+            methods.add_meta_method(MetaMethod::Shl, |_, this, n: u32| Ok(this.0[0] << n));
+            methods.add_meta_method(MetaMethod::BNot, |_, this, _: Value| Ok(!this.0[0]));
+        }
+    }
+
+    let items = vec![1, 2, 4];
+    Lua::new().context(|lua| {
+        let check = lua
+            .load(
+                r#"
+                    return function(s)
+                        local sum = 0
+                        for i, v in pairs(s) do sum = sum + i * v end
+                        assert(sum == 17)
+                        sum = 0
+                        for i, v in ipairs(s) do sum = sum + i * v end
+                        assert(sum == 17)
+                        assert(#s == 3)
+                        assert(s // 2 == 3)
+                        assert(s & 6 == 6)
+                        assert(s << 3 == 8)
+                        assert(~s == -2)
+                    end
+                "#,
+            )
+            .eval::<Function>()
+            .unwrap();
+
+        lua.scope(|scope| {
+            check
+                .call::<_, ()>(scope.create_nonstatic_userdata(Slice(&items)).unwrap())
+                .unwrap();
+        });
+    });
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "serde")]
+
+use std::collections::BTreeMap;
+
+use rlua::{from_value, to_value, Lua, Table, Value};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Point,
+    Circle(f64),
+    Rect { width: u32, height: u32 },
+    Line(i32, i32),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Scene {
+    name: String,
+    shapes: Vec<Shape>,
+    origin: (i64, i64),
+    parent: Option<Box<Scene>>,
+    tags: BTreeMap<String, u8>,
+    hidden: bool,
+}
+
+#[test]
+fn test_round_trip() {
+    let mut tags = BTreeMap::new();
+    tags.insert("layer".to_owned(), 3);
+    let scene = Scene {
+        name: "level".to_owned(),
+        shapes: vec![
+            Shape::Point,
+            Shape::Circle(2.5),
+            Shape::Rect {
+                width: 4,
+                height: 2,
+            },
+            Shape::Line(-1, 1),
+        ],
+        origin: (10, -20),
+        parent: Some(Box::new(Scene {
+            name: "world".to_owned(),
+            shapes: Vec::new(),
+            origin: (0, 0),
+            parent: None,
+            tags: BTreeMap::new(),
+            hidden: true,
+        })),
+        tags,
+        hidden: false,
+    };
+
+    Lua::new().context(|lua| {
+        let value = to_value(lua, &scene).unwrap();
+        lua.globals().set("scene", value.clone()).unwrap();
+        lua.load(
+            r#"
+                assert(scene.name == "level")
+                assert(scene.shapes[1] == "Point")
+                assert(scene.shapes[2].Circle == 2.5)
+                assert(scene.shapes[3].Rect.width == 4)
+                assert(scene.shapes[4].Line[1] == -1)
+                assert(scene.origin[2] == -20)
+                assert(scene.parent.name == "world")
+                assert(scene.parent.parent == nil)
+                assert(scene.tags.layer == 3)
+                assert(scene.hidden == false)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        assert_eq!(from_value::<Scene>(value).unwrap(), scene);
+    });
+}
+
+#[test]
+fn test_from_lua_tables() {
+    Lua::new().context(|lua| {
+        let scene: Value = lua
+            .load(
+                r#"
+                    return {
+                        name = "scripted",
+                        shapes = { { Circle = 1 }, { Rect = { width = 2.0, height = 3 } } },
+                        origin = { 1, 2 },
+                        tags = {},
+                        hidden = true,
+                    }
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let scene: Scene = from_value(scene).unwrap();
+        assert_eq!(scene.shapes[0], Shape::Circle(1.0));
+        assert_eq!(
+            scene.shapes[1],
+            Shape::Rect {
+                width: 2,
+                height: 3
+            }
+        );
+        assert_eq!(scene.origin, (1, 2));
+        assert!(scene.parent.is_none());
+        assert!(scene.tags.is_empty());
+
+        let empty: Table = lua.create_table().unwrap();
+        assert_eq!(
+            from_value::<Vec<i32>>(Value::Table(empty)).unwrap(),
+            Vec::<i32>::new()
+        );
+
+        let bad: Value = lua
+            .load("return { width = 1.5, height = 1 }")
+            .eval()
+            .unwrap();
+        assert!(from_value::<BTreeMap<String, u32>>(bad).is_err());
+        let function: Value = lua.load("return print").eval().unwrap();
+        assert!(from_value::<i32>(function).is_err());
+    });
+}
@@ -0,0 +1,76 @@
+use rlua::{Bytes, Error, Lua, Numbers, Result, String};
+
+#[test]
+fn test_bytes() {
+    Lua::new().context(|lua| {
+        let count = lua
+            .create_function(|_, (data, byte): (Bytes, u8)| {
+                Ok(data.iter().filter(|&&b| b == byte).count())
+            })
+            .unwrap();
+        lua.globals().set("count", count).unwrap();
+        assert_eq!(
+            lua.load(r#"count("a\0b\0\255", 0)"#)
+                .eval::<usize>()
+                .unwrap(),
+            2
+        );
+        assert_eq!(lua.load("count(100, 48)").eval::<usize>().unwrap(), 2);
+        assert!(lua.load("count({}, 0)").exec().is_err());
+
+        let bytes: Bytes = lua.load(r#""\255\0""#).eval().unwrap();
+        assert_eq!(&*bytes, b"\xff\0");
+        assert_eq!(bytes.as_ref(), b"\xff\0");
+        let string: String = bytes.clone().into_string();
+        assert_eq!(string.as_bytes(), &bytes[..]);
+        lua.globals().set("bytes", bytes).unwrap();
+        assert!(lua.load(r#"bytes == "\255\0""#).eval::<bool>().unwrap());
+    });
+}
+
+#[test]
+fn test_numbers() {
+    Lua::new().context(|lua| {
+        let mix = lua
+            .create_function(|_, (a, b): (Numbers<f64>, Numbers<f64>)| {
+                Ok(Numbers::from(
+                    a.iter()
+                        .zip(b.iter())
+                        .map(|(x, y)| x + y)
+                        .collect::<Vec<_>>(),
+                ))
+            })
+            .unwrap();
+        lua.globals().set("mix", mix).unwrap();
+        assert_eq!(
+            lua.load("mix({ 1, 2, 3 }, { 0.5, 0.25, 0.125 })")
+                .eval::<Numbers<f64>>()
+                .unwrap()
+                .into_inner(),
+            vec![1.5, 2.25, 3.125]
+        );
+
+        let indices: Numbers<u16> = lua.load("{ 0, 1, 2, 2, 3, 0 }").eval().unwrap();
+        assert_eq!(*indices, vec![0, 1, 2, 2, 3, 0]);
+        assert!(lua.load("{ 1, -1 }").eval::<Numbers<u16>>().is_err());
+        assert!(lua.load("{ 1, {} }").eval::<Numbers<f32>>().is_err());
+        match lua.load("'1, 2'").eval::<Numbers<f32>>() {
+            Err(Error::FromLuaConversionError { from, to, .. }) => {
+                assert_eq!(from, "string");
+                assert_eq!(to, "Numbers");
+            }
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+
+        let large: Vec<i32> = (0..10_000).collect();
+        lua.globals()
+            .set("large", Numbers::from(large.clone()))
+            .unwrap();
+        assert!(lua
+            .load("#large == 10000 and large[10000] == 9999 and math.type(large[1]) == 'integer'")
+            .eval::<bool>()
+            .unwrap());
+        let back: Result<Numbers<i32>> = lua.globals().get("large");
+        assert_eq!(back.unwrap().into_inner(), large);
+    });
+}
@@ -1,4 +1,10 @@
-use rlua::{Lua, Nil, Result, Table, Value};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rlua::{Context, Error, Lua, Nil, Result, Table, TypedTable, Value};
 
 #[test]
 fn test_set_get() {
@@ -136,17 +142,25 @@ fn test_metatable() {
                 lua.create_function(|_, ()| Ok("index_value")).unwrap(),
             )
             .unwrap();
-        table.set_metatable(Some(metatable));
+        table.set_metatable(Some(metatable)).unwrap();
         assert_eq!(table.get::<_, String>("any_key").unwrap(), "index_value");
         match table.raw_get::<_, Value>("any_key").unwrap() {
             Nil => {}
             _ => panic!(),
         }
-        table.set_metatable(None);
+        table.set_metatable(None).unwrap();
         match table.get::<_, Value>("any_key").unwrap() {
             Nil => {}
             _ => panic!(),
         };
+
+        let finalized: Table = lua.load("{ __gc = function() end }").eval().unwrap();
+        assert!(table.set_metatable(Some(finalized.clone())).is_err());
+        assert!(table.get_metatable().is_none());
+        lua.globals().set("finalized", finalized).unwrap();
+        lua.globals().set("t", table.clone()).unwrap();
+        lua.load("setmetatable(t, finalized)").exec().unwrap();
+        assert!(table.get_metatable().is_some());
     });
 }
 
@@ -182,3 +196,447 @@ fn test_table_error() {
         assert_eq!(bad_table.raw_len(), 1);
     });
 }
+
+#[test]
+fn test_parallel_map() {
+    Lua::new().context(|lua| {
+        let input: Table = lua
+            .load(r#"{ 1, 2, 3, 4, 5, a = "x", b = { n = 7 } }"#)
+            .eval()
+            .unwrap();
+
+        let output = lua
+            .parallel_map(input, 3, |lua, value| match value {
+                Value::Integer(i) => Ok(Value::Integer(i * 10)),
+                Value::String(s) => lua
+                    .create_string(&format!("{}!", s.to_str()?))
+                    .map(Value::String),
+                Value::Table(t) => Ok(Value::Integer(t.get("n")?)),
+                v => Ok(v),
+            })
+            .unwrap();
+
+        assert_eq!(
+            output
+                .clone()
+                .sequence_values::<i64>()
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![10, 20, 30, 40, 50]
+        );
+        assert_eq!(output.get::<_, String>("a").unwrap(), "x!");
+        assert_eq!(output.get::<_, i64>("b").unwrap(), 7);
+
+        let input = lua.create_sequence_from(vec![1, 2]).unwrap();
+        input
+            .set(3, lua.create_function(|_, ()| Ok(())).unwrap())
+            .unwrap();
+        match lua.parallel_map(input, 2, |_, v| Ok(v)) {
+            Err(Error::FromLuaConversionError { .. }) => {}
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+
+        let input: Table = lua
+            .load("local t = {} for i = 1, 100000 do t = { t } end return { t }")
+            .eval()
+            .unwrap();
+        match lua.parallel_map(input, 2, |_, v| Ok(v)) {
+            Err(Error::DepthLimitExceeded { limit: 128 }) => {}
+            r => panic!("expected DepthLimitExceeded, got {:?}", r),
+        }
+
+        let input = lua.create_sequence_from(vec![1, 2, 3]).unwrap();
+        match lua.parallel_map(input, 2, |_, _| -> Result<Value> {
+            Err(Error::RuntimeError("boom".to_owned()))
+        }) {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+
+        // Every shard finishes before a panic is resumed, and panicked workers are replaced
+        let finished = Arc::new(AtomicUsize::new(0));
+        let shard_finished = finished.clone();
+        let input = lua.create_sequence_from(vec![1, 2, 3, 4]).unwrap();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            lua.parallel_map(input.clone(), 4, move |_, v| {
+                if let Value::Integer(1) = v {
+                    panic!("shard panic");
+                }
+                thread::sleep(Duration::from_millis(50));
+                shard_finished.fetch_add(1, Ordering::SeqCst);
+                Ok(v)
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+        let output = lua.parallel_map(input, 4, |_, v| Ok(v)).unwrap();
+        assert_eq!(output.raw_len(), 4);
+    });
+
+    // Worker states are kept between calls, this state only ever has a single worker
+    fn count<'lua>(lua: Context<'lua>, _: Value<'lua>) -> Result<Value<'lua>> {
+        let calls = lua.globals().get::<_, Option<i64>>("calls")?.unwrap_or(0) + 1;
+        lua.globals().set("calls", calls)?;
+        Ok(Value::Integer(calls))
+    }
+    Lua::new().context(|lua| {
+        let input = lua.create_sequence_from(vec![0, 0]).unwrap();
+        let first = lua.parallel_map(input.clone(), 1, count).unwrap();
+        let second = lua.parallel_map(input, 1, count).unwrap();
+        assert_eq!(first.get::<_, i64>(2).unwrap(), 2);
+        assert_eq!(second.get::<_, i64>(2).unwrap(), 4);
+    });
+}
+
+#[test]
+fn test_table_iteration_by_reference() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load("{ 10, 20, 30, [5] = 50, name = 60 }")
+            .eval()
+            .unwrap();
+
+        let mut pairs = table
+            .iter::<Value, i64>()
+            .filter_map(|pair| match pair.unwrap() {
+                (Value::Integer(k), v) => Some((k, v)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs, vec![(1, 10), (2, 20), (3, 30), (5, 50)]);
+        assert_eq!(
+            table
+                .sequence_iter::<i64>()
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![10, 20, 30]
+        );
+        // The table is still usable after iterating.
+        assert_eq!(table.get::<_, i64>("name").unwrap(), 60);
+
+        let mut visited = Vec::new();
+        table
+            .for_each(|k: Value, v: Value| {
+                // Lua values may be created and used while iterating.
+                let pair = lua.create_sequence_from(vec![k, v])?;
+                visited.push(pair.raw_len());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(visited, vec![2; 5]);
+
+        let mut count = 0;
+        match table.for_each(|_: Value, v: i64| {
+            count += 1;
+            if v == 20 {
+                Err(Error::RuntimeError("stop".to_owned()))
+            } else {
+                Ok(())
+            }
+        }) {
+            Err(Error::RuntimeError(ref msg)) if msg == "stop" => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(count <= 4);
+        assert!(table.for_each(|_: i64, _: i64| Ok(())).is_err());
+    });
+}
+
+#[test]
+fn test_table_get_all() {
+    Lua::new().context(|lua| {
+        let mods: Table = lua
+            .load(
+                r#"
+                    {
+                        alpha = { 1, 2 },
+                        beta = { 3 },
+                        gamma = "broken",
+                        [4] = { 5 },
+                        delta = { 6, "x" },
+                    }
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let (values, errors) = mods.get_all::<String, Vec<i64>>().unwrap();
+        let mut names = values.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        // Integer keys are coerced to strings.
+        assert_eq!(names, vec!["4", "alpha", "beta"]);
+        assert_eq!(values["alpha"], vec![1, 2]);
+
+        let mut failed = errors
+            .iter()
+            .map(|(key, err)| match (key, err) {
+                (Value::String(key), Error::FromLuaConversionError { .. }) => {
+                    key.to_str().unwrap().to_owned()
+                }
+                r => panic!("unexpected error {:?}", r),
+            })
+            .collect::<Vec<_>>();
+        failed.sort();
+        assert_eq!(failed, vec!["delta", "gamma"]);
+
+        let (values, errors) = lua.create_table().unwrap().get_all::<i64, i64>().unwrap();
+        assert!(values.is_empty() && errors.is_empty());
+    });
+}
+
+#[test]
+fn test_table_helpers() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load(
+                r#"
+                    local t = setmetatable({ 1, 2, name = "x", nested = {} }, {
+                        __index = function() return "default" end,
+                    })
+                    return t
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert!(!table.is_empty());
+
+        let copy = table.shallow_clone().unwrap();
+        assert!(copy.get_metatable().is_none());
+        assert!(copy.contains_key("name").unwrap());
+        assert!(copy.contains_key(2).unwrap());
+        assert!(!copy.contains_key("missing").unwrap());
+        assert_eq!(copy.raw_len(), 2);
+        assert_eq!(copy.get::<_, String>("name").unwrap(), "x");
+        let nested: Table = copy.get("nested").unwrap();
+        nested.set("shared", true).unwrap();
+        assert!(table
+            .get::<_, Table>("nested")
+            .unwrap()
+            .get::<_, bool>("shared")
+            .unwrap());
+
+        table.clear().unwrap();
+        assert!(table.is_empty());
+        assert_eq!(table.raw_len(), 0);
+        assert_eq!(table.get::<_, String>("name").unwrap(), "default");
+        assert!(!copy.is_empty());
+        assert!(lua.create_table().unwrap().is_empty());
+    });
+}
+
+#[test]
+fn test_typed_table() {
+    Lua::new().context(|lua| {
+        let stats: TypedTable<String, i64> = lua
+            .load("stats = { hp = 10, mp = 5 } return stats")
+            .eval()
+            .unwrap();
+        assert_eq!(stats.get("hp").unwrap(), 10);
+        assert_eq!(stats.get("hp").unwrap(), 10);
+        assert!(stats.contains_key("mp").unwrap());
+        assert!(!stats.contains_key("speed").unwrap());
+        assert!(stats.get("speed").is_err());
+
+        stats.set("speed".to_owned(), 3).unwrap();
+        let mut pairs = stats.iter().collect::<Result<Vec<_>>>().unwrap();
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            vec![
+                ("hp".to_owned(), 10),
+                ("mp".to_owned(), 5),
+                ("speed".to_owned(), 3)
+            ]
+        );
+        assert_eq!(lua.load("stats.speed").eval::<i64>().unwrap(), 3);
+
+        lua.globals().set("copy", stats.clone()).unwrap();
+        assert!(lua.load("copy == stats").eval::<bool>().unwrap());
+
+        lua.load("stats.hp = 'full'").exec().unwrap();
+        assert!(stats.get("hp").is_err());
+        assert!(lua
+            .load("{ [true] = 1 }")
+            .eval::<TypedTable<String, i64>>()
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap()
+            .is_err());
+    });
+}
+
+#[test]
+fn test_table_raw_sequence() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load(
+                r#"
+                setmetatable({ 1, 2, 3 }, {
+                    __index = function() return 0 end,
+                    __newindex = function() error("read only") end,
+                })
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(table.set(4, 4).is_err());
+
+        let values = |table: &Table| {
+            table
+                .raw_sequence_values()
+                .collect::<Result<Vec<i64>>>()
+                .unwrap()
+        };
+        table.raw_push(4).unwrap();
+        table.raw_insert(1, 0).unwrap();
+        table.raw_insert(3, 10).unwrap();
+        assert_eq!(values(&table), vec![0, 1, 10, 2, 3, 4]);
+        match table.raw_insert(8, 8) {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("expected RuntimeError, got {:?}", r),
+        }
+        assert!(table.raw_insert(0, 8).is_err());
+
+        table.raw_remove(3).unwrap();
+        table.raw_remove(5).unwrap();
+        table.raw_remove(1).unwrap();
+        assert_eq!(values(&table), vec![1, 2, 3]);
+        assert_eq!(table.raw_len(), 3);
+
+        table.raw_set("key", true).unwrap();
+        table.raw_remove("key").unwrap();
+        assert_eq!(table.raw_get::<_, Option<bool>>("key").unwrap(), None);
+        assert_eq!(table.get::<_, i64>("key").unwrap(), 0);
+    });
+}
+
+#[test]
+fn test_table_slices() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load("setmetatable({ 1, 2.5, '3', 4 }, { __index = function() return 0 end })")
+            .eval()
+            .unwrap();
+        assert_eq!(
+            table.as_slice_of::<f64>().unwrap(),
+            vec![1.0, 2.5, 3.0, 4.0]
+        );
+        table.raw_set(2, 1000).unwrap();
+        match table.as_slice_of::<i8>() {
+            Err(Error::FromLuaConversionError { from, to, .. }) => {
+                assert_eq!(from, "integer");
+                assert_eq!(to, "i8");
+            }
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+
+        let mut buf = vec![7u8; 10];
+        table.raw_set(2, 2).unwrap();
+        table.read_slice_into(&mut buf).unwrap();
+        assert_eq!(buf, vec![1, 2, 3, 4]);
+
+        let samples: Vec<f32> = (0..1000).map(|i| i as f32 / 4.0).collect();
+        let copy = lua.create_table().unwrap();
+        copy.write_slice(&samples).unwrap();
+        assert_eq!(copy.raw_len(), 1000);
+        assert_eq!(copy.as_slice_of::<f32>().unwrap(), samples);
+        assert_eq!(copy.get::<_, f32>(1000).unwrap(), 249.75);
+
+        copy.write_slice(&[u64::MAX, 1]).unwrap();
+        assert_eq!(copy.get::<_, f64>(1).unwrap(), u64::MAX as f64);
+        assert!(lua
+            .load("return math.type(...) == 'float'")
+            .call::<_, bool>(copy.get::<_, Value>(1).unwrap())
+            .unwrap());
+        assert_eq!(copy.raw_len(), 1000);
+
+        copy.raw_set(3, "three").unwrap();
+        let mut floats = Vec::<f64>::new();
+        assert!(copy.read_slice_into(&mut floats).is_err());
+        assert_eq!(floats, vec![u64::MAX as f64, 1.0]);
+    });
+}
+
+#[test]
+fn test_table_readonly() {
+    Lua::new().context(|lua| {
+        let api: Table = lua
+            .load(
+                r#"
+                setmetatable({ 10, 20, name = "api" }, {
+                    __index = function(_, key)
+                        if type(key) == "string" then return "default " .. key end
+                    end,
+                    __call = function(self, x) return x * 2 end,
+                })
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert!(!api.is_readonly());
+        api.set_readonly(true).unwrap();
+        api.set_readonly(true).unwrap();
+        assert!(api.is_readonly());
+        lua.globals().set("api", api.clone()).unwrap();
+
+        let check = |script: &str| lua.load(script).eval::<bool>().unwrap();
+        assert!(check("api.name == 'api' and api[2] == 20 and #api == 2"));
+        assert!(check("api.missing == 'default missing'"));
+        assert!(check("api(21) == 42"));
+        assert!(check(
+            r#"
+                local n = 0
+                for k, v in pairs(api) do n = n + 1 end
+                local sum = 0
+                for i, v in ipairs(api) do sum = sum + v end
+                return n == 3 and sum == 30
+            "#
+        ));
+        for script in &[
+            "api.name = 'changed'",
+            "api.new = true",
+            "api[1] = nil",
+            "table.insert(api, 30)",
+            "setmetatable(api, nil)",
+        ] {
+            assert!(lua.load(script).exec().is_err(), "{} succeeded", script);
+        }
+        assert!(check("getmetatable(api).__call ~= nil"));
+        assert!(api.set("name", "changed").is_err());
+        assert_eq!(api.get::<_, String>("name").unwrap(), "api");
+
+        api.set_readonly(false).unwrap();
+        assert!(!api.is_readonly());
+        lua.load("api.name = 'changed' api[3] = 30").exec().unwrap();
+        assert_eq!(api.raw_len(), 3);
+        assert_eq!(api.raw_get::<_, String>("name").unwrap(), "changed");
+        assert!(check("api.missing == 'default missing' and api(1) == 2"));
+        assert!(check("setmetatable(api, nil) == api"));
+
+        let plain = lua.create_table().unwrap();
+        plain.set_readonly(true).unwrap();
+        assert!(plain.set(1, true).is_err());
+        plain.set_readonly(false).unwrap();
+        assert!(plain.get_metatable().is_none());
+
+        // The finalizer of the previous metatable only runs for the table holding the contents.
+        lua.load(
+            r#"
+                finalized = 0
+                local t = setmetatable({}, { __gc = function() finalized = finalized + 1 end })
+                return t
+            "#,
+        )
+        .eval::<Table>()
+        .unwrap()
+        .set_readonly(true)
+        .unwrap();
+        lua.load("collectgarbage() collectgarbage()")
+            .exec()
+            .unwrap();
+        assert_eq!(lua.globals().get::<_, i64>("finalized").unwrap(), 1);
+    });
+}
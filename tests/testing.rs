@@ -0,0 +1,266 @@
+use std::fs;
+use std::path::PathBuf;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use rlua::testing::{
+    assert_script_tests, assert_snapshot, pretty_print, pretty_print_to, run_script_tests, Fixture,
+};
+use rlua::{assert_lua_eq, Error, Lua, StdLib, Value};
+
+// Creates an empty directory for this test's Lua files.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rlua-testing-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    dir
+}
+
+#[test]
+fn test_assert_lua_eq() {
+    Lua::new().context(|lua| {
+        lua.globals().set("answer", 42).unwrap();
+        assert_lua_eq!(lua, "answer", 42);
+        assert_lua_eq!(lua, "answer / 4", 10.5);
+        assert_lua_eq!(lua, "tostring(answer)", "42".to_owned());
+        assert_lua_eq!(lua, "{1, 2, 3}", vec![1, 2, 3], "sequences convert to Vec");
+    });
+}
+
+#[test]
+#[should_panic(expected = "Lua expression `1 + 1`")]
+fn test_assert_lua_eq_mismatch() {
+    Lua::new().context(|lua| assert_lua_eq!(lua, "1 + 1", 3));
+}
+
+#[test]
+fn test_fixture() {
+    let fixture = Fixture::new()
+        .libs(StdLib::BASE | StdLib::MATH)
+        .setup(|lua| lua.globals().set("scale", 10))
+        .script("helpers", "function scaled(x) return x * scale end");
+
+    for _ in 0..2 {
+        fixture.build().unwrap().context(|lua| {
+            assert_lua_eq!(lua, "scaled(4)", 40);
+            assert_lua_eq!(lua, "string", None::<bool>);
+            lua.load("scale = 0").exec().unwrap();
+        });
+    }
+
+    let broken = Fixture::new().script("broken", "error('setup failed')");
+    assert!(broken.build().is_err());
+}
+
+#[test]
+fn test_script_tests() {
+    let dir = test_dir("scripts");
+    fs::write(
+        dir.join("math_test.lua"),
+        r#"
+            function test_add() assert(add(1, 2) == 3) end
+            function test_wrong() assert(add(1, 2) == 4, "wrong sum") end
+            function helper() error("not a test") end
+        "#,
+    )
+    .unwrap();
+    fs::write(dir.join("nested/broken_test.lua"), "this is not lua").unwrap();
+    fs::write(dir.join("ignored.lua"), "error('not a test file')").unwrap();
+
+    let fixture = Fixture::new().script("lib", "function add(a, b) return a + b end");
+    let results = run_script_tests(&dir, &fixture).unwrap();
+    let summary = results
+        .iter()
+        .map(|r| {
+            (
+                r.file.file_name().unwrap().to_str().unwrap().to_owned(),
+                r.name.clone(),
+                r.passed(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "math_test.lua".to_owned(),
+                Some("test_add".to_owned()),
+                true
+            ),
+            (
+                "math_test.lua".to_owned(),
+                Some("test_wrong".to_owned()),
+                false
+            ),
+            ("broken_test.lua".to_owned(), None, false),
+        ]
+    );
+    assert!(results[1]
+        .error
+        .as_ref()
+        .unwrap()
+        .to_string()
+        .contains("wrong sum"));
+
+    fs::remove_file(dir.join("nested/broken_test.lua")).unwrap();
+    fs::write(
+        dir.join("math_test.lua"),
+        "function test_add() assert(add(1, 2) == 3) end",
+    )
+    .unwrap();
+    assert_script_tests(&dir, &fixture);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pretty_print() {
+    Lua::new().context(|lua| {
+        let value: Value = lua
+            .load(
+                r#"
+                    local t = {
+                        "first", 2, 3.0, 1e100, 0/0, -1/0,
+                        name = "a \"quoted\"\n\0 string",
+                        ["not an id"] = false,
+                        ["end"] = print,
+                        [2.5] = coroutine.create(print),
+                        [true] = {},
+                        nested = { z = 1, a = { 1 } },
+                    }
+                    t.self = t
+                    setmetatable(t, { __index = function() return 1 end })
+                    return t
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            pretty_print(&value).unwrap(),
+            r#"{
+  [true] = {},
+  [1] = "first",
+  [2] = 2,
+  [2.5] = <thread>,
+  [3] = 3.0,
+  [4] = 1e100,
+  [5] = nan,
+  [6] = -inf,
+  ["end"] = <function>,
+  name = "a \"quoted\"\n\000 string",
+  nested = {
+    a = {
+      [1] = 1,
+    },
+    z = 1,
+  },
+  ["not an id"] = false,
+  self = <cycle>,
+}"#
+        );
+
+        let shared: Value = lua.load("local s = {} return { s, s }").eval().unwrap();
+        assert_eq!(
+            pretty_print(&shared).unwrap(),
+            "{\n  [1] = {},\n  [2] = {},\n}"
+        );
+        let bytes = Value::String(lua.create_string(b"\xff\t").unwrap());
+        assert_eq!(pretty_print(&bytes).unwrap(), r#""\255\t""#);
+    });
+}
+
+#[test]
+fn test_pretty_print_to() {
+    Lua::new().context(|lua| {
+        let value: Value = lua
+            .load("local t = { list = {} } for i = 1, 1000 do t.list[i] = { i, tostring(i) } end return t")
+            .eval()
+            .unwrap();
+        let mut out = Vec::new();
+        pretty_print_to(&mut out, &value).unwrap();
+        assert_eq!(out, pretty_print(&value).unwrap().into_bytes());
+
+        let path = test_dir("pretty_print_to").join("value.txt");
+        pretty_print_to(fs::File::create(&path).unwrap(), &value).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), out);
+
+        let mut small = [0u8; 64];
+        match pretty_print_to(&mut small[..], &value) {
+            Err(Error::ExternalError(_)) => {}
+            r => panic!("expected ExternalError, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_assert_snapshot() {
+    let dir = test_dir("snapshots");
+    let path = dir.join("nested/value.snap");
+    Lua::new().context(|lua| {
+        let value: Value = lua.load("{ a = 1, b = { 2, 3 } }").eval().unwrap();
+        assert_snapshot(&path, &value);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\n  a = 1,\n  b = {\n    [1] = 2,\n    [2] = 3,\n  },\n}\n"
+        );
+        assert_snapshot(&path, &value);
+
+        let changed: Value = lua.load("{ a = 1, b = { 2, 4 } }").eval().unwrap();
+        let panic =
+            panic::catch_unwind(AssertUnwindSafe(|| assert_snapshot(&path, &changed))).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(message.contains("     [1] = 2,\n-    [2] = 3,\n+    [2] = 4,\n"));
+    });
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "proptest")]
+mod round_trip {
+    use std::collections::HashMap;
+    use std::panic;
+
+    use proptest::prelude::*;
+    use rlua::testing::check_round_trip;
+    use rlua::{Context, FromLua, Result, ToLua, Value};
+
+    #[test]
+    fn test_round_trip() {
+        check_round_trip(any::<i64>());
+        check_round_trip(any::<bool>());
+        check_round_trip(".*");
+        check_round_trip(proptest::option::of(any::<i32>()));
+        check_round_trip(proptest::collection::vec(any::<u8>(), 0..16));
+        check_round_trip(proptest::collection::btree_map(any::<i64>(), ".*", 0..8));
+        check_round_trip(proptest::collection::hash_map(".*", any::<f64>(), 0..8));
+    }
+
+    // Converts to a Lua sequence, but back from a table of keys to `true`.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tags(Vec<String>);
+
+    impl<'lua> ToLua<'lua> for Tags {
+        fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+            self.0.to_lua(lua)
+        }
+    }
+
+    impl<'lua> FromLua<'lua> for Tags {
+        fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Tags> {
+            let set = HashMap::<String, bool>::from_lua(value, lua)?;
+            let mut tags = set.into_iter().map(|(tag, _)| tag).collect::<Vec<_>>();
+            tags.sort();
+            Ok(Tags(tags))
+        }
+    }
+
+    #[test]
+    fn test_round_trip_failure() {
+        let strategy = proptest::collection::vec("[a-z]", 0..8).prop_map(Tags);
+        let panic = panic::catch_unwind(|| check_round_trip(strategy)).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            message,
+            "Tags([\"a\"]) does not round trip: converted to Lua value {\n  [1] = \"a\",\n} \
+             and back to Tags([\"1\"])"
+        );
+    }
+}
@@ -1,10 +1,18 @@
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::OsString;
 use std::iter::FromIterator;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::{NonZeroI64, NonZeroU32};
 use std::panic::catch_unwind;
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{error, f32, f64, fmt};
 
 use rlua::{
-    Error, ExternalError, Function, Lua, Nil, Result, StdLib, String, Table, UserData, Value,
+    AnyUserData, ConversionOptions, DurationFormat, Error, ExternalError, Function, LightUserData,
+    Lua, Nil, PtrKey, Result, StdLib, Strict, String, Table, TableTuple, UserData, Value, Varargs,
     Variadic,
 };
 
@@ -41,6 +49,28 @@ fn test_new_with_debug_panic() {
     let _lua = Lua::new_with(StdLib::DEBUG);
 }
 
+#[test]
+fn test_new_with_selected_libraries() {
+    let lua = Lua::new_with(StdLib::BASE | StdLib::STRING | StdLib::MATH);
+    lua.context(|lua| {
+        let globals = lua.globals();
+        for name in &["string", "math"] {
+            assert!(
+                globals.get::<_, Table>(*name).is_ok(),
+                "{} not loaded",
+                name
+            );
+        }
+        for name in &["io", "os", "table", "coroutine", "utf8", "package", "debug"] {
+            match globals.get::<_, Value>(*name).unwrap() {
+                Value::Nil => {}
+                _ => panic!("{} loaded", name),
+            }
+        }
+        lua.load("assert(math.max(1, 2) == 2)").exec().unwrap();
+    });
+}
+
 #[test]
 fn test_exec() {
     Lua::new().context(|lua| {
@@ -340,6 +370,335 @@ fn test_error() {
     };
 }
 
+#[test]
+fn test_custom_error() {
+    #[derive(Debug, PartialEq)]
+    struct Quota {
+        used: u32,
+    }
+
+    Lua::new().context(|lua| {
+        let spend = lua
+            .create_function(|_, used: u32| -> Result<()> {
+                Err(Error::custom("quota", Quota { used }))
+            })
+            .unwrap();
+        lua.globals().set("spend", spend).unwrap();
+
+        let err = lua.load("spend(7)").exec().unwrap_err();
+        assert_eq!(err.custom_kind(), Some("quota"));
+        assert_eq!(err.downcast_custom::<Quota>(), Some(&Quota { used: 7 }));
+        assert_eq!(err.downcast_custom::<String>(), None);
+
+        // The error keeps its kind when caught by Lua and handed back to Rust.
+        let caught = lua
+            .load("local ok, err = pcall(spend, 3); return err")
+            .eval::<Error>()
+            .unwrap();
+        assert_eq!(caught.custom_kind(), Some("quota"));
+        assert_eq!(caught.downcast_custom::<Quota>(), Some(&Quota { used: 3 }));
+
+        let plain = lua.load("error('plain')").exec().unwrap_err();
+        assert_eq!(plain.custom_kind(), None);
+    });
+}
+
+#[test]
+fn test_error_downcast() {
+    #[derive(Debug, PartialEq)]
+    struct Denied(&'static str);
+
+    impl fmt::Display for Denied {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmt, "access to {} denied", self.0)
+        }
+    }
+
+    impl error::Error for Denied {}
+
+    Lua::new().context(|lua| {
+        let open = lua
+            .create_function(|_, ()| -> Result<()> { Err(Denied("file").to_lua_err()) })
+            .unwrap();
+        // Wraps the error of a nested call in another external error.
+        let outer = lua
+            .create_function(|_, f: Function| -> Result<()> {
+                f.call::<_, ()>(()).map_err(Error::external)
+            })
+            .unwrap();
+        lua.globals().set("open", open).unwrap();
+        lua.globals().set("outer", outer).unwrap();
+
+        let err = lua.load("outer(open)").exec().unwrap_err();
+        assert_eq!(err.downcast_ref::<Denied>(), Some(&Denied("file")));
+        assert!(err.downcast_ref::<fmt::Error>().is_none());
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::CallbackError { .. })
+        ));
+        match err.root_cause() {
+            Error::ExternalError(inner) => assert_eq!(inner.to_string(), "access to file denied"),
+            e => panic!("unexpected root cause {:?}", e),
+        }
+        assert_eq!(err.kind_name(), "external");
+
+        let err = lua.load("error('plain')").exec().unwrap_err();
+        assert!(err.downcast_ref::<Denied>().is_none());
+        assert!(matches!(err.root_cause(), Error::RuntimeError(_)));
+    });
+}
+
+#[test]
+fn test_error_fields() {
+    Lua::new().context(|lua| {
+        let fail = lua
+            .create_function(|_, custom: bool| -> Result<()> {
+                Err(if custom {
+                    Error::custom("permission_denied", ())
+                } else {
+                    Error::RuntimeError("plain failure".to_owned())
+                })
+            })
+            .unwrap();
+        lua.globals().set("fail", fail).unwrap();
+        let expect_i64 = lua.create_function(|_, _: i64| Ok(())).unwrap();
+        lua.globals().set("expect_i64", expect_i64).unwrap();
+
+        lua.load(
+            r#"
+                local ok, err = pcall(fail, true)
+                assert(not ok)
+                assert(err.kind == "permission_denied")
+                assert(err.message == "permission_denied error")
+                assert(err.traceback:find("stack traceback"))
+                assert(err.unknown == nil)
+                assert(tostring(err) == "permission_denied error")
+
+                ok, err = pcall(fail, false)
+                assert(err.kind == "runtime")
+                assert(err.message == "runtime error: plain failure")
+
+                ok, err = pcall(expect_i64, {})
+                assert(err.kind == "from_lua_conversion")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        // Errors which went through rlua's error handler carry a traceback.
+        let err = lua.load("fail(true)").exec().unwrap_err();
+        assert_eq!(err.kind_name(), "permission_denied");
+        lua.globals().set("err", err).unwrap();
+        lua.load(
+            r#"
+                assert(err.kind == "permission_denied")
+                assert(err.traceback:find("stack traceback"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_handle_debug() {
+    Lua::new().context(|lua| {
+        let table = lua.create_sequence_from(vec![1, 2, 3]).unwrap();
+        let debug = format!("{:?}", table);
+        assert!(debug.starts_with("Table(0x"), "{}", debug);
+        assert!(debug.ends_with(", len = 3)"), "{}", debug);
+        assert_eq!(format!("{:?}", table.clone()), debug);
+
+        let print: Function = lua.globals().get("print").unwrap();
+        assert!(format!("{:?}", print).ends_with(", C)"));
+        let func: Function = lua
+            .load("\n\nreturn function() end")
+            .set_name("=chunk")
+            .unwrap()
+            .eval()
+            .unwrap();
+        assert!(format!("{:?}", func).ends_with(", chunk:3)"));
+
+        let thread = lua.create_thread(func.clone()).unwrap();
+        assert!(format!("{:?}", thread).ends_with(", Resumable)"));
+
+        let values = lua.pack_multi((table, func)).unwrap();
+        let debug = format!("{:?}", values);
+        assert!(debug.contains("Table(0x"), "{}", debug);
+        assert!(debug.contains("Function(0x"), "{}", debug);
+    });
+}
+
+#[test]
+fn test_traceback_enabled() {
+    let lua = Lua::new();
+    let check = |enabled: bool| {
+        lua.context(|lua| {
+            let fail = lua
+                .create_function(|_, ()| -> Result<()> { Err(Error::custom("failed", ())) })
+                .unwrap();
+            lua.globals().set("fail", fail).unwrap();
+
+            let err = lua
+                .load("local function f() error('oops') end f()")
+                .exec()
+                .unwrap_err();
+            match err {
+                Error::RuntimeError(ref msg) => {
+                    assert!(msg.contains("oops"));
+                    assert_eq!(msg.contains("stack traceback"), enabled);
+                }
+                ref e => panic!("unexpected error {:?}", e),
+            }
+            match err.traceback() {
+                Some(traceback) => {
+                    assert!(enabled);
+                    assert!(traceback.starts_with("stack traceback:"));
+                    assert!(traceback.contains("in local 'f'"));
+                }
+                None => assert!(!enabled),
+            }
+
+            let err = lua.load("fail()").exec().unwrap_err();
+            assert_eq!(err.custom_kind(), Some("failed"));
+            assert_eq!(err.traceback().is_some(), enabled);
+            assert!(err.to_string().starts_with("callback error: "));
+
+            let has_field = lua
+                .load("local ok, err = pcall(fail) return err.traceback ~= nil")
+                .eval::<bool>()
+                .unwrap();
+            assert_eq!(has_field, enabled);
+        });
+    };
+
+    check(true);
+    lua.set_traceback_enabled(false);
+    check(false);
+    lua.set_traceback_enabled(true);
+    check(true);
+}
+
+#[test]
+fn test_lua_error_values() {
+    Lua::new().context(|lua| {
+        let err = lua
+            .load("error(setmetatable({code = 404}, {__tostring = function() return 'nope' end}))")
+            .exec()
+            .unwrap_err();
+        match err {
+            Error::LuaError { ref message, .. } => {
+                assert_eq!(message, "(error object is a table value)")
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(err.kind_name(), "lua_error");
+        assert_eq!(err.traceback(), None);
+        let value: Table = err.lua_value(lua).unwrap().unwrap();
+        assert_eq!(value.get::<_, i64>("code").unwrap(), 404);
+
+        // Error objects passing through a callback reach `pcall` unchanged.
+        let rethrow = lua
+            .create_function(|_, f: Function| f.call::<_, ()>(()))
+            .unwrap();
+        lua.globals().set("rethrow", rethrow).unwrap();
+        let same = lua
+            .load(
+                r#"
+                    local original = {code = 500}
+                    local ok, err = pcall(rethrow, function() error(original) end)
+                    return not ok and rawequal(err, original)
+                "#,
+            )
+            .eval::<bool>()
+            .unwrap();
+        assert!(same);
+
+        let err = lua
+            .load("rethrow(function() error({code = 1}) end)")
+            .exec()
+            .unwrap_err();
+        let value: Table = err.lua_value(lua).unwrap().unwrap();
+        assert_eq!(value.get::<_, i64>("code").unwrap(), 1);
+
+        let fail = lua
+            .create_function(|lua, code: i64| -> Result<()> {
+                let details = lua.create_table()?;
+                details.set("code", code)?;
+                Err(lua.create_error(details)?)
+            })
+            .unwrap();
+        lua.globals().set("fail", fail).unwrap();
+        assert_eq!(
+            lua.load("local ok, err = pcall(fail, 7) return err.code")
+                .eval::<i64>()
+                .unwrap(),
+            7
+        );
+        let err = lua.create_error("plain message").unwrap();
+        assert_eq!(err.to_string(), "runtime error: plain message");
+        assert_eq!(
+            err.lua_value::<std::string::String>(lua).unwrap().unwrap(),
+            "plain message"
+        );
+
+        // Errors raised from Rust and string errors have no error object.
+        let err = lua.load("error('oops')").exec().unwrap_err();
+        assert!(err.lua_value::<Value>(lua).unwrap().is_none());
+    });
+}
+
+#[test]
+fn test_protect() {
+    Lua::new().context(|lua| {
+        let value = lua
+            .protect(|lua| lua.load("return 1 + 2").eval::<i64>())
+            .unwrap();
+        assert_eq!(value, 3);
+
+        match lua.protect(|lua| lua.load("error('inner')").exec()) {
+            Err(Error::CallbackError { traceback, cause }) => {
+                assert!(traceback.contains("stack traceback"));
+                match *cause {
+                    Error::RuntimeError(ref msg) => assert!(msg.contains("inner")),
+                    ref e => panic!("unexpected cause {:?}", e),
+                }
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut cleaned_up = Vec::new();
+        let result = lua.protect_finally(
+            |_| -> Result<()> { Err(Error::custom("aborted", ())) },
+            |_, err| {
+                cleaned_up.push(err.map(|e| e.kind_name()));
+                Err(Error::RuntimeError("cleanup failed".to_owned()))
+            },
+        );
+        assert_eq!(result.unwrap_err().custom_kind(), Some("aborted"));
+
+        let result = lua.protect_finally(
+            |lua| lua.load("return 'done'").eval::<std::string::String>(),
+            |_, err| {
+                cleaned_up.push(err.map(|e| e.kind_name()));
+                Ok(())
+            },
+        );
+        assert_eq!(result.unwrap(), "done");
+
+        let result = lua.protect_finally(
+            |_| Ok(()),
+            |_, _| Err(Error::RuntimeError("cleanup failed".to_owned())),
+        );
+        match result {
+            Err(Error::RuntimeError(msg)) => assert_eq!(msg, "cleanup failed"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(cleaned_up, vec![Some("aborted"), None]);
+    });
+}
+
 #[test]
 fn test_result_conversions() {
     Lua::new().context(|lua| {
@@ -437,6 +796,284 @@ fn test_num_conversion() {
     });
 }
 
+#[test]
+fn test_collection_conversions() {
+    Lua::new().context(|lua| {
+        let set: HashSet<std::string::String> =
+            lua.load("{ a = true, b = 1, [1] = true }").eval().unwrap();
+        let mut keys = set.into_iter().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["1", "a", "b"]);
+
+        let set: BTreeSet<i64> = lua.load("{ [3] = true, [1] = true }").eval().unwrap();
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![1, 3]);
+        let set = BTreeSet::from_iter(vec![2, 4]);
+        lua.globals().set("set", set).unwrap();
+        assert!(lua
+            .load("set[2] == true and set[4] == true and next(set, next(set, next(set))) == nil")
+            .eval::<bool>()
+            .unwrap());
+        let set = HashSet::<i64>::from_iter(vec![5]);
+        lua.globals().set("set", set).unwrap();
+        assert!(lua.load("set[5]").eval::<bool>().unwrap());
+        assert!(lua.load("1").eval::<HashSet<i64>>().is_err());
+
+        let array: [i64; 3] = lua.load("{ 1, 2, 3 }").eval().unwrap();
+        assert_eq!(array, [1, 2, 3]);
+        assert!(lua.load("{ 1, 2 }").eval::<[i64; 3]>().is_err());
+        match lua.load("{ 1, 2, 3, 4 }").eval::<[i64; 3]>() {
+            Err(Error::FromLuaConversionError { to: "array", .. }) => {}
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+        let nested: [[f64; 2]; 2] = lua.load("{ { 1, 2 }, { 3, 4 } }").eval().unwrap();
+        assert_eq!(nested, [[1.0, 2.0], [3.0, 4.0]]);
+        lua.globals().set("array", [[1, 2], [3, 4]]).unwrap();
+        assert!(lua
+            .load("#array == 2 and array[2][1] == 3")
+            .eval::<bool>()
+            .unwrap());
+        let empty: [i64; 0] = lua.load("{}").eval().unwrap();
+        assert_eq!(empty, []);
+    });
+}
+
+#[test]
+fn test_std_type_conversions() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let path: PathBuf = lua.load("'/tmp/a b.txt'").eval().unwrap();
+        assert_eq!(path, Path::new("/tmp/a b.txt"));
+        lua.globals().set("path", Path::new("dir/file")).unwrap();
+        assert_eq!(
+            lua.load("path").eval::<std::string::String>().unwrap(),
+            "dir/file"
+        );
+        let os: OsString = lua.load("'x'").eval().unwrap();
+        assert_eq!(os, OsString::from("x"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let path: PathBuf = lua.load("'\\xff'").eval().unwrap();
+            assert_eq!(path.as_os_str().as_bytes(), b"\xff");
+        }
+
+        let addr: IpAddr = lua.load("'127.0.0.1'").eval().unwrap();
+        assert_eq!(addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let addr: SocketAddr = lua.load("'[::1]:8080'").eval().unwrap();
+        assert_eq!(addr.port(), 8080);
+        lua.globals().set("addr", addr).unwrap();
+        assert_eq!(
+            lua.load("addr").eval::<std::string::String>().unwrap(),
+            "[::1]:8080"
+        );
+        assert!(lua.load("'localhost'").eval::<IpAddr>().is_err());
+
+        let n: NonZeroU32 = lua.load("5").eval().unwrap();
+        assert_eq!(n.get(), 5);
+        assert!(lua.load("0").eval::<NonZeroU32>().is_err());
+        assert!(lua.load("-1").eval::<NonZeroU32>().is_err());
+        assert!(matches!(
+            lua.pack(NonZeroI64::new(-3)).unwrap(),
+            Value::Integer(-3)
+        ));
+
+        let d: Duration = lua.load("1.5").eval().unwrap();
+        assert_eq!(d, Duration::from_millis(1500));
+        assert!(lua.load("-1").eval::<Duration>().is_err());
+        assert!(matches!(
+            lua.pack(Duration::from_millis(250)).unwrap(),
+            Value::Number(n) if n == 0.25
+        ));
+        let t: SystemTime = lua.load("-10").eval().unwrap();
+        assert_eq!(
+            UNIX_EPOCH.duration_since(t).unwrap(),
+            Duration::from_secs(10)
+        );
+        let now: f64 = lua.unpack(lua.pack(SystemTime::now()).unwrap()).unwrap();
+        let os_time: f64 = lua.load("os.time()").eval().unwrap();
+        assert!((now - os_time).abs() < 2.0);
+    });
+
+    lua.set_duration_format(DurationFormat::Milliseconds);
+    lua.context(|lua| {
+        let d: Duration = lua.load("1500").eval().unwrap();
+        assert_eq!(d, Duration::from_millis(1500));
+        assert!(matches!(
+            lua.pack(Duration::from_micros(2500)).unwrap(),
+            Value::Integer(2)
+        ));
+        let t: SystemTime = lua.load("1000").eval().unwrap();
+        assert_eq!(t, UNIX_EPOCH + Duration::from_secs(1));
+        assert!(matches!(
+            lua.pack(UNIX_EPOCH - Duration::from_secs(1)).unwrap(),
+            Value::Integer(-1000)
+        ));
+    });
+}
+
+#[test]
+fn test_table_tuple_conversions() {
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals
+            .set("position", TableTuple((1.5f32, -2.0f32, 0.0f32)))
+            .unwrap();
+        assert_eq!(
+            lua.load("#position").eval::<i64>().unwrap(),
+            3,
+            "tuple should convert to a sequence"
+        );
+        let position: TableTuple<(f32, f32, f32)> = globals.get("position").unwrap();
+        assert_eq!(position, TableTuple((1.5, -2.0, 0.0)));
+
+        let TableTuple((name, corners)): TableTuple<(std::string::String, [[i64; 2]; 2])> =
+            lua.load("{'box', {{0, 0}, {4, 3}}}").eval().unwrap();
+        assert_eq!(name, "box");
+        assert_eq!(corners, [[0, 0], [4, 3]]);
+
+        let TableTuple((a, b)): TableTuple<(i64, Option<i64>)> = lua.load("{1}").eval().unwrap();
+        assert_eq!((a, b), (1, None));
+        globals
+            .set("partial", TableTuple((Some(1), None::<i64>, Some(3))))
+            .unwrap();
+        assert_eq!(lua.load("partial[3]").eval::<i64>().unwrap(), 3);
+
+        match lua.load("{1, 2, 3}").eval::<TableTuple<(i64, i64)>>() {
+            Err(Error::FromLuaConversionError { .. }) => {}
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+        match lua.load("1").eval::<TableTuple<(i64,)>>() {
+            Err(Error::FromLuaConversionError { .. }) => {}
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_conversion_options() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let describe = lua
+            .create_function(|_, (a, b): (Option<Value>, Option<i64>)| {
+                Ok(format!(
+                    "{} {:?}",
+                    match a {
+                        None => "none",
+                        Some(Value::Nil) => "nil",
+                        Some(_) => "some",
+                    },
+                    b
+                ))
+            })
+            .unwrap();
+        lua.globals().set("describe", describe).unwrap();
+        lua.globals()
+            .set("null", LightUserData(ptr::null_mut()))
+            .unwrap();
+        assert_eq!(
+            lua.load("describe(nil)")
+                .eval::<std::string::String>()
+                .unwrap(),
+            "none None"
+        );
+        assert!(matches!(lua.pack(None::<i64>).unwrap(), Value::Nil));
+    });
+
+    lua.set_conversion_options(ConversionOptions {
+        none_as_sentinel: true,
+        ..ConversionOptions::default()
+    });
+    lua.context(|lua| {
+        assert_eq!(
+            lua.load("describe(nil)")
+                .eval::<std::string::String>()
+                .unwrap(),
+            "nil None"
+        );
+        assert_eq!(
+            lua.load("describe(null, 1)")
+                .eval::<std::string::String>()
+                .unwrap(),
+            "none Some(1)"
+        );
+        assert!(lua.load("describe(1, nil)").exec().is_err());
+        let none = lua.pack(None::<i64>).unwrap();
+        assert!(matches!(none, Value::LightUserData(ud) if ud.0.is_null()));
+        assert_eq!(lua.unpack::<Option<i64>>(none).unwrap(), None);
+    });
+
+    lua.set_conversion_options(ConversionOptions {
+        missing_as_none: false,
+        ..ConversionOptions::default()
+    });
+    lua.context(|lua| {
+        assert_eq!(
+            lua.load("describe(nil, nil)")
+                .eval::<std::string::String>()
+                .unwrap(),
+            "none None"
+        );
+        match lua.load("describe(nil)").exec() {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::FromLuaConversionError { .. } => {}
+                ref err => panic!("expected FromLuaConversionError, got {:?}", err),
+            },
+            r => panic!("expected CallbackError, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_strict_numbers() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        assert_eq!(lua.load("'12'").eval::<i64>().unwrap(), 12);
+        assert_eq!(lua.load("2.75").eval::<i64>().unwrap(), 2);
+
+        let Strict(n): Strict<i64> = lua.load("12.0").eval().unwrap();
+        assert_eq!(n, 12);
+        assert!(lua.load("'12'").eval::<Strict<i64>>().is_err());
+        assert!(lua.load("2.75").eval::<Strict<i64>>().is_err());
+        assert!(lua.load("300").eval::<Strict<u8>>().is_err());
+        assert!(lua.load("-1").eval::<Strict<u32>>().is_err());
+        assert!(lua.load("2^63").eval::<Strict<i64>>().is_err());
+        assert!(lua.load("0/0").eval::<Strict<i64>>().is_err());
+        assert!(lua.load("{1, 2.5}").eval::<Strict<Vec<i64>>>().is_err());
+        assert_eq!(
+            lua.load("{1, 2}").eval::<Strict<Vec<i64>>>().unwrap(),
+            Strict(vec![1, 2])
+        );
+
+        let Strict(x): Strict<f64> = lua.load("2^53").eval().unwrap();
+        assert_eq!(x, 9007199254740992.0);
+        assert!(lua
+            .load("math.tointeger(2^53) + 1")
+            .eval::<Strict<f64>>()
+            .is_err());
+        assert!(lua.load("'1.5'").eval::<Strict<f64>>().is_err());
+        assert_eq!(lua.load("0.5").eval::<Strict<f32>>().unwrap(), Strict(0.5));
+        assert!(lua.load("0.1").eval::<Strict<f32>>().is_err());
+        assert!(lua.load("0/0").eval::<Strict<f32>>().unwrap().0.is_nan());
+
+        // The strict mode ends with the conversion.
+        assert_eq!(lua.load("2.75").eval::<i64>().unwrap(), 2);
+    });
+
+    lua.set_conversion_options(ConversionOptions {
+        strict_numbers: true,
+        ..ConversionOptions::default()
+    });
+    lua.context(|lua| {
+        let add = lua
+            .create_function(|_, (a, b): (u32, u32)| Ok(a + b))
+            .unwrap();
+        lua.globals().set("add", add).unwrap();
+        assert_eq!(lua.load("add(1, 2.0)").eval::<u32>().unwrap(), 3);
+        assert!(lua.load("add(1, 2.5)").exec().is_err());
+        assert!(lua.load("add('1', 2)").exec().is_err());
+    });
+}
+
 #[test]
 fn test_pcall_xpcall() {
     Lua::new().context(|lua| {
@@ -651,6 +1288,53 @@ fn test_mismatched_registry_key() {
     });
 }
 
+#[test]
+fn test_ptr_values() {
+    struct MyUserdata(Arc<()>);
+
+    impl UserData for MyUserdata {}
+
+    struct Object {
+        key: Option<PtrKey>,
+    }
+
+    Lua::new().context(|lua| {
+        let rc = Arc::new(());
+
+        let mut object = Box::new(Object { key: None });
+        let key = lua.create_ptr_key(&*object);
+        assert_eq!(key.as_ptr(), &*object as *const Object as *const _);
+        assert!(lua.ptr_value::<Option<i64>>(&key).unwrap().is_none());
+        lua.set_ptr_value(&key, MyUserdata(rc.clone())).unwrap();
+        object.key = Some(key);
+        assert!(lua
+            .ptr_value::<AnyUserData>(object.key.as_ref().unwrap())
+            .unwrap()
+            .is::<MyUserdata>());
+
+        let other = Box::new(Object { key: None });
+        let other_key = lua.create_ptr_key(&*other);
+        lua.set_ptr_value(&other_key, "other").unwrap();
+
+        drop(object);
+        lua.expire_registry_values();
+        lua.load(r#"collectgarbage("collect")"#).exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 1);
+        assert_eq!(lua.ptr_value::<String>(&other_key).unwrap(), "other");
+
+        lua.set_ptr_value(&other_key, Nil).unwrap();
+        assert!(lua
+            .ptr_value::<Option<String>>(&other_key)
+            .unwrap()
+            .is_none());
+
+        Lua::new().context(|lua2| match lua2.ptr_value::<Value>(&other_key) {
+            Err(Error::MismatchedRegistryKey) => {}
+            r => panic!("wrong result type for mismatched ptr key, {:?}", r),
+        });
+    });
+}
+
 #[test]
 fn too_many_returns() {
     Lua::new().context(|lua| {
@@ -822,3 +1506,51 @@ fn chunk_env() {
         );
     });
 }
+
+#[test]
+fn test_variadic_and_varargs() {
+    let mut variadic: Variadic<i64> = vec![1, 2, 3].into();
+    for v in &mut variadic {
+        *v *= 2;
+    }
+    assert_eq!((&variadic).into_iter().sum::<i64>(), 12);
+    assert_eq!(variadic.as_ref(), &[2, 4, 6]);
+    assert_eq!(Vec::from(variadic), vec![2, 4, 6]);
+
+    Lua::new().context(|lua| {
+        let describe = lua
+            .create_function(|_, (name, rest): (String, Varargs)| {
+                let count = rest.get::<Option<i64>>(0)?.unwrap_or(1);
+                let loud = rest.get::<Option<bool>>(1)?.unwrap_or(false);
+                let mut text = format!("{}x{}", name.to_str()?, count);
+                if loud {
+                    text = text.to_uppercase();
+                }
+                Ok((text, rest.len()))
+            })
+            .unwrap();
+        lua.globals().set("describe", describe).unwrap();
+
+        let (text, len) = lua
+            .load("describe('a')")
+            .eval::<(std::string::String, usize)>()
+            .unwrap();
+        assert_eq!((text.as_str(), len), ("ax1", 0));
+        let (text, len) = lua
+            .load("describe('b', 3, true)")
+            .eval::<(std::string::String, usize)>()
+            .unwrap();
+        assert_eq!((text.as_str(), len), ("BX3", 2));
+        assert!(lua.load("describe('c', 'many')").exec().is_err());
+
+        let forward = lua
+            .create_function(|_, args: Varargs| {
+                assert!(!args.is_empty());
+                assert!(args.iter().all(|v| matches!(v, Value::Integer(_))));
+                Ok(args)
+            })
+            .unwrap();
+        let (a, b) = forward.call::<_, (i64, i64)>((5, 6)).unwrap();
+        assert_eq!((a, b), (5, 6));
+    });
+}
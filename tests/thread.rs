@@ -1,6 +1,8 @@
 use std::panic::catch_unwind;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use rlua::{Error, Function, Lua, Result, Thread, ThreadStatus};
+use rlua::{Error, Function, Linda, Lua, Result, ResumeResult, Thread, ThreadSpan, ThreadStatus};
 
 #[test]
 fn test_thread() {
@@ -126,3 +128,321 @@ fn coroutine_panic() {
         Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
     }
 }
+
+#[test]
+fn test_linda() {
+    let linda = Linda::new();
+
+    let worker_linda = linda.clone();
+    let worker = thread::spawn(move || {
+        Lua::new().context(|lua| {
+            lua.globals().set("linda", worker_linda).unwrap();
+            lua.load(
+                r#"
+                    while true do
+                        local key, value = linda:receive({ "quit", "work" })
+                        if key == "quit" then
+                            break
+                        end
+                        linda:send("result", { value[1] + value[2], tag = value.tag })
+                    end
+                "#,
+            )
+            .exec()
+            .unwrap();
+        })
+    });
+
+    Lua::new().context(|lua| {
+        lua.globals().set("linda", linda.clone()).unwrap();
+        lua.load(
+            r#"
+                linda:limit("work", 1)
+                linda:send("work", { 1, 2, tag = "a" })
+                local key, result = linda:receive("result", 5)
+                assert(key == "result" and result[1] == 3 and result.tag == "a")
+
+                assert(linda:receive("nothing", 0) == nil)
+
+                linda:send("huge", 1, 1e300)
+                linda:send("huge", 2, 1e15)
+                assert(select(2, linda:receive("huge", 1e300)) == 1)
+                assert(select(2, linda:receive("huge", 1e15)) == 2)
+                assert(linda:count("work") == 0)
+
+                linda:send("work", { 3, 4 })
+                assert(select(2, linda:receive("result")).tag == nil)
+                linda:send("quit", true)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        match lua.load(r#"linda:send("work", function() end)"#).exec() {
+            Err(Error::CallbackError { .. }) => {}
+            r => panic!("expected CallbackError, got {:?}", r),
+        }
+    });
+
+    worker.join().unwrap();
+}
+
+#[test]
+fn test_thread_data() {
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tenant(String);
+
+    Lua::new().context(|lua| {
+        let tenant = lua
+            .create_function(|lua, ()| {
+                Ok(lua.current_thread_data::<Tenant>()?.map(|tenant| tenant.0))
+            })
+            .unwrap();
+        lua.globals().set("tenant", tenant).unwrap();
+
+        let handler: Function = lua
+            .load(
+                r#"
+                    function()
+                        local first = tenant()
+                        coroutine.yield(first)
+                        -- Coroutines started from here have their own storage.
+                        local inner = coroutine.wrap(function() return tenant() end)()
+                        return tenant(), inner
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let a = lua.create_thread(handler.clone()).unwrap();
+        let b = lua.create_thread(handler).unwrap();
+        assert_eq!(a.set_data(Tenant("a".into())).unwrap(), None);
+        assert_eq!(b.set_data(Tenant("old".into())).unwrap(), None);
+        assert_eq!(
+            b.set_data(Tenant("b".into())).unwrap(),
+            Some(Tenant("old".into()))
+        );
+
+        assert_eq!(a.resume::<_, String>(()).unwrap(), "a");
+        assert_eq!(b.resume::<_, String>(()).unwrap(), "b");
+        assert_eq!(
+            a.resume::<_, (String, Option<String>)>(()).unwrap(),
+            ("a".to_owned(), None)
+        );
+
+        // Data of other types is separate.
+        assert_eq!(b.data::<u32>().unwrap(), None);
+        b.set_data(7u32).unwrap();
+        assert_eq!(b.data::<u32>().unwrap(), Some(7));
+        assert_eq!(b.remove_data::<Tenant>().unwrap(), Some(Tenant("b".into())));
+        assert_eq!(b.data::<Tenant>().unwrap(), None);
+        assert_eq!(b.data::<u32>().unwrap(), Some(7));
+
+        // The main thread has storage too.
+        assert_eq!(lua.current_thread_data::<Tenant>().unwrap(), None);
+        lua.current_thread()
+            .set_data(Tenant("main".into()))
+            .unwrap();
+        assert_eq!(lua.load("tenant()").eval::<String>().unwrap(), "main");
+
+        // Stored data is dropped along with the thread.
+        let data = Arc::new(());
+        let thread = lua
+            .create_thread(lua.create_function(|_, ()| Ok(())).unwrap())
+            .unwrap();
+        thread.set_data(data.clone()).unwrap();
+        drop((a, b, thread));
+        lua.load("collectgarbage()").exec().unwrap();
+        assert_eq!(Arc::strong_count(&data), 1);
+    });
+}
+
+#[test]
+fn test_thread_spans() {
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ThreadSpan for Recorder {
+        fn enter(&mut self) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("enter {}", self.name));
+        }
+
+        fn exit(&mut self) {
+            self.log.lock().unwrap().push(format!("exit {}", self.name));
+        }
+    }
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    Lua::new().context(|lua| {
+        let record = {
+            let log = log.clone();
+            lua.create_function(move |_, message: String| {
+                log.lock().unwrap().push(message);
+                Ok(())
+            })
+            .unwrap()
+        };
+        lua.globals().set("record", record).unwrap();
+
+        let thread = lua
+            .create_thread(
+                lua.load(
+                    r#"
+                        function()
+                            record("first")
+                            coroutine.yield()
+                            record("second")
+                            error("failed")
+                        end
+                    "#,
+                )
+                .eval()
+                .unwrap(),
+            )
+            .unwrap();
+        for name in &["outer", "inner"] {
+            thread
+                .add_span(Recorder {
+                    name,
+                    log: log.clone(),
+                })
+                .unwrap();
+        }
+
+        thread.resume::<_, ()>(()).unwrap();
+        assert!(thread.resume::<_, ()>(()).is_err());
+        // Inactive threads are not entered.
+        assert!(thread.resume::<_, ()>(()).is_err());
+    });
+
+    let expected = [
+        "enter outer",
+        "enter inner",
+        "first",
+        "exit inner",
+        "exit outer",
+        "enter outer",
+        "enter inner",
+        "second",
+        "exit inner",
+        "exit outer",
+    ];
+    assert_eq!(*log.lock().unwrap(), expected);
+}
+
+#[test]
+fn test_resume_result() {
+    Lua::new().context(|lua| {
+        let thread: Thread = lua
+            .load(
+                r#"
+                    coroutine.create(function(a)
+                        local b = coroutine.yield(a + 1)
+                        return a, b
+                    end)
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(
+            thread.resume_result::<_, i64, ()>(1).unwrap(),
+            ResumeResult::Yielded(2)
+        );
+        assert_eq!(
+            thread.resume_result::<_, (), (i64, i64)>(5).unwrap(),
+            ResumeResult::Returned((1, 5))
+        );
+        match thread.resume_result::<_, (), ()>(()) {
+            Err(Error::CoroutineInactive) => {}
+            r => panic!("expected CoroutineInactive, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_yield_with() {
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals
+            .set(
+                "wait",
+                lua.create_function(|lua, (event, fail): (String, bool)| {
+                    lua.yield_with(event)?;
+                    if fail {
+                        Err(Error::RuntimeError("wait failed".to_owned()))
+                    } else {
+                        Ok("ignored")
+                    }
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        globals
+            .set(
+                "call",
+                lua.create_function(|_, f: Function| f.call::<_, ()>(()))
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let thread: Thread = lua
+            .load(
+                r#"
+                    coroutine.create(function()
+                        local a, b = wait("first", false)
+                        local c = wait(a .. b, false)
+                        -- Coroutines resumed from Lua can yield from callbacks too.
+                        local results = table.pack(coroutine.resume(coroutine.create(function()
+                            return wait("inner", false)
+                        end)))
+                        return c, results[1], results[2]
+                    end)
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(
+            thread.resume_result::<_, String, ()>(()).unwrap(),
+            ResumeResult::Yielded("first".to_owned())
+        );
+        assert_eq!(
+            thread.resume_result::<_, String, ()>(("x", "y")).unwrap(),
+            ResumeResult::Yielded("xy".to_owned())
+        );
+        assert_eq!(
+            thread
+                .resume_result::<_, (), (String, bool, String)>("last")
+                .unwrap(),
+            ResumeResult::Returned(("last".to_owned(), true, "inner".to_owned()))
+        );
+
+        // An error returned after `yield_with` is raised instead of yielding.
+        let thread: Thread = lua
+            .load("coroutine.create(function() return wait('never', true) end)")
+            .eval()
+            .unwrap();
+        match thread.resume::<_, ()>(()) {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::RuntimeError(ref msg) => assert_eq!(msg, "wait failed"),
+                ref err => panic!("unexpected error {:?}", err),
+            },
+            r => panic!("expected CallbackError, got {:?}", r),
+        }
+
+        // Callbacks outside of coroutines, or called from Rust, cannot yield.
+        assert!(lua.load("wait('main', false)").exec().is_err());
+        let thread: Thread = lua
+            .load("coroutine.create(function() call(function() wait('nested', false) end) end)")
+            .eval()
+            .unwrap();
+        assert!(thread.resume::<_, ()>(()).is_err());
+    });
+}
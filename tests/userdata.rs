@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use rlua::{ExternalError, Function, Lua, MetaMethod, String, UserData, UserDataMethods};
+use rlua::{
+    AnyUserData, DynUserData, Error, ExternalError, Function, Lua, MetaMethod, RegisteredFunction,
+    String, UserData, UserDataMethods, Value,
+};
 
 #[test]
 fn test_user_data() {
@@ -188,3 +191,771 @@ fn user_value() {
         assert!(ud.get_user_value::<u32>().is_err());
     });
 }
+
+#[test]
+fn user_value_cycle() {
+    struct MyUserData(Arc<()>);
+    impl UserData for MyUserData {}
+
+    Lua::new().context(|lua| {
+        let rc = Arc::new(());
+        let ud = lua.create_userdata(MyUserData(rc.clone())).unwrap();
+
+        // A user value referring back to its userdata does not keep it alive.
+        let cache = lua.create_table().unwrap();
+        cache.set("owner", ud.clone()).unwrap();
+        ud.set_user_value(cache).unwrap();
+        drop(ud);
+        lua.load(r#"collectgarbage("collect")"#).exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 1);
+    });
+}
+
+#[test]
+fn test_registered_api() {
+    struct MyUserData;
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, _, ()| Ok(1));
+            methods.add_method("set", |_, _, ()| Ok(()));
+            methods.add_meta_method(MetaMethod::Len, |_, _, ()| Ok(0));
+        }
+    }
+
+    let lua = Lua::new();
+    assert!(lua.registered_types().is_empty());
+
+    lua.context(|lua| {
+        let globals = lua.globals();
+        globals.set("ud", MyUserData).unwrap();
+        globals.set("ud2", MyUserData).unwrap();
+
+        let host = lua.create_table().unwrap();
+        host.set("log", lua.create_function(|_, ()| Ok(())).unwrap())
+            .unwrap();
+        host.set("host", host.clone()).unwrap();
+        globals.set("host", host).unwrap();
+        globals
+            .set("exit", lua.create_function(|_, ()| Ok(())).unwrap())
+            .unwrap();
+        lua.load("function lua_function() end").exec().unwrap();
+    });
+
+    let types = lua.registered_types();
+    assert_eq!(types.len(), 1);
+    assert!(types[0].type_name.ends_with("MyUserData"));
+    assert_eq!(types[0].methods, vec!["get".to_owned(), "set".to_owned()]);
+    assert_eq!(types[0].meta_methods, vec!["__len".to_owned()]);
+
+    assert_eq!(
+        lua.registered_functions().unwrap(),
+        vec![
+            RegisteredFunction {
+                path: "exit".to_owned()
+            },
+            RegisteredFunction {
+                path: "host.log".to_owned()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_reentrant_borrows() {
+    struct Node(i64);
+
+    impl UserData for Node {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("value", |_, node, ()| Ok(node.0));
+            methods.add_method("visit", |_, node, f: Function| {
+                f.call::<_, i64>(()).map(|v| v + node.0)
+            });
+            methods.add_method_mut("update", |_, node, f: Function| {
+                node.0 = f.call::<_, i64>(())?;
+                Ok(())
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.globals().set("node", Node(20)).unwrap();
+
+        // Read-only methods may re-enter read-only methods of the same userdata.
+        let total: i64 = lua
+            .load("return node:visit(function() return node:value() end)")
+            .eval()
+            .unwrap();
+        assert_eq!(total, 40);
+
+        // A mutable borrow still excludes any other access.
+        match lua
+            .load("node:update(function() return node:value() end)")
+            .exec()
+        {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::CallbackError { ref cause, .. } => match **cause {
+                    Error::UserDataBorrowError => {}
+                    ref e => panic!("unexpected error {:?}", e),
+                },
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_nested_shared_borrows() {
+    struct Counter(i64);
+
+    impl UserData for Counter {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, counter, ()| Ok(counter.0));
+            methods.add_method_mut("increment", |_, counter, ()| {
+                counter.0 += 1;
+                Ok(())
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let counter = lua.create_userdata(Counter(1)).unwrap();
+        lua.globals().set("counter", counter.clone()).unwrap();
+        let read_in_rust = lua
+            .create_function(|lua, ()| {
+                let counter: AnyUserData = lua.globals().get("counter")?;
+                let outer = counter.borrow::<Counter>()?;
+                let inner: i64 = lua.load("return counter:get()").eval()?;
+                Ok(outer.0 + inner)
+            })
+            .unwrap();
+        lua.globals().set("read_in_rust", read_in_rust).unwrap();
+
+        // Shared borrows held at every level of a Lua -> Rust -> Lua -> Rust chain.
+        let borrowed = counter.borrow::<Counter>().unwrap();
+        assert_eq!(lua.load("return read_in_rust()").eval::<i64>().unwrap(), 2);
+
+        // Only an overlapping mutable borrow is an error.
+        match lua.load("counter:increment()").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::UserDataBorrowMutError => {}
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+        drop(borrowed);
+        lua.load("counter:increment()").exec().unwrap();
+        assert_eq!(counter.borrow::<Counter>().unwrap().0, 2);
+    });
+}
+
+#[test]
+fn test_userdata_ptr_lookup() {
+    struct Tracked(i64);
+    impl UserData for Tracked {}
+    struct Untracked;
+    impl UserData for Untracked {}
+
+    let lua = Lua::new();
+    let ptr = lua.context(|lua| {
+        let early = lua.create_userdata(Tracked(0)).unwrap();
+        lua.enable_userdata_ptr_lookup::<Tracked>().unwrap();
+        let tracked = lua.create_userdata(Tracked(42)).unwrap();
+        let untracked = lua.create_userdata(Untracked).unwrap();
+
+        let get = |ptr| lua.try_borrow_userdata_by_ptr(ptr, |t: &Tracked| t.0);
+        assert_eq!(get(tracked.to_pointer()).unwrap(), Some(42));
+        assert_eq!(get(early.to_pointer()).unwrap(), None);
+        assert_eq!(get(untracked.to_pointer()).unwrap(), None);
+        assert_eq!(
+            lua.try_borrow_userdata_by_ptr(untracked.to_pointer(), |_: &Untracked| ())
+                .unwrap(),
+            None
+        );
+
+        let guard = tracked.borrow_mut::<Tracked>().unwrap();
+        match get(tracked.to_pointer()) {
+            Err(Error::UserDataBorrowError) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        drop(guard);
+
+        // Taken userdata can no longer be found
+        let taken = lua.create_userdata(Tracked(7)).unwrap();
+        assert_eq!(get(taken.to_pointer()).unwrap(), Some(7));
+        assert_eq!(taken.take::<Tracked>().unwrap().0, 7);
+        assert_eq!(get(taken.to_pointer()).unwrap(), None);
+
+        tracked.to_pointer() as usize
+    });
+
+    // The lookup table does not keep userdata alive.
+    lua.gc_collect().unwrap();
+    lua.context(|lua| {
+        assert_eq!(
+            lua.try_borrow_userdata_by_ptr(ptr as *const _, |t: &Tracked| t.0)
+                .unwrap(),
+            None
+        );
+    });
+}
+
+#[test]
+fn test_extend_foreign_userdata() {
+    Lua::new().context(|lua| {
+        let file_metatable: rlua::Table = lua.named_registry_value("FILE*").unwrap();
+        let first = lua.create_table().unwrap();
+        first
+            .set(
+                "kind",
+                lua.create_function(|_, _: AnyUserData| Ok("file")).unwrap(),
+            )
+            .unwrap();
+        first.set("close", "shadowed").unwrap();
+        lua.extend_foreign_userdata(file_metatable.clone(), first)
+            .unwrap();
+
+        let second = lua.create_table().unwrap();
+        second
+            .set(
+                "kind",
+                lua.create_function(|_, _: AnyUserData| Ok("extended file"))
+                    .unwrap(),
+            )
+            .unwrap();
+        lua.extend_foreign_userdata(file_metatable, second).unwrap();
+
+        lua.load(
+            r#"
+                assert(io.stdout:kind() == "extended file")
+                assert(io.stdout.close == "shadowed")
+                assert(type(io.stdout.write) == "function")
+                assert(io.stdout.missing == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        // An `__index` function is still called for keys which are not extension methods.
+        let metatable: rlua::Table = lua
+            .load(
+                r#"
+                    local mt = { __index = function(_, k) return k .. "!" end }
+                    object = setmetatable({}, mt)
+                    return mt
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let methods = lua.create_table().unwrap();
+        methods.set("added", true).unwrap();
+        lua.extend_foreign_userdata(metatable, methods).unwrap();
+        lua.load(r#"assert(object.added == true and object.other == "other!")"#)
+            .exec()
+            .unwrap();
+    });
+}
+
+#[test]
+fn test_extend_foreign_userdata_collectable() {
+    Lua::new().context(|lua| {
+        let (metatable, methods): (rlua::Table, rlua::Table) = lua
+            .load(
+                r#"
+                    local mt = {}
+                    mt.__index = mt
+                    local methods = {}
+                    weak = setmetatable({ mt, methods }, { __mode = "v" })
+                    return mt, methods
+                "#,
+            )
+            .eval()
+            .unwrap();
+        lua.extend_foreign_userdata(metatable, methods).unwrap();
+
+        lua.load(
+            r#"
+                collectgarbage()
+                collectgarbage()
+                assert(weak[1] == nil and weak[2] == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_user_data_fields() {
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    impl UserData for Point {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_field_method_get("x", |_, this| Ok(this.x));
+            methods.add_field_method_set("x", |_, this, x| {
+                this.x = x;
+                Ok(())
+            });
+            methods.add_field_function_get("y", |_, ud| Ok(ud.borrow::<Point>()?.y));
+            methods.add_field_function_set("y", |_, ud, y| {
+                ud.borrow_mut::<Point>()?.y = y;
+                Ok(())
+            });
+            methods.add_field_method_get("sum", |_, this| Ok(this.x + this.y));
+            methods.add_field_method_get("shadowed", |_, _| Ok("field"));
+            methods.add_method("shadowed", |_, _, ()| Ok("method"));
+            methods.add_meta_method(MetaMethod::Index, |_, _, key: String| {
+                Ok(format!("index {}", key.to_str()?))
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let point = lua.create_userdata(Point { x: 1, y: 2 }).unwrap();
+        lua.globals().set("point", point.clone()).unwrap();
+        lua.load(
+            r#"
+                assert(point.x == 1 and point.y == 2 and point.sum == 3)
+                point.x = 10
+                point.y = point.y * 2
+                assert(point.sum == 14)
+                assert(point:shadowed() == "method")
+                assert(point.other == "index other")
+                assert(not pcall(function() point.x = "ten" end))
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        match lua.load("point.sum = 1").exec() {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains("cannot set field 'sum'")),
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::RuntimeError(ref msg) => assert!(msg.contains("cannot set field 'sum'")),
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let point = point.borrow::<Point>().unwrap();
+        assert_eq!((point.x, point.y), (10, 4));
+    });
+}
+
+#[test]
+fn test_c_module_interop() {
+    struct MyUserData;
+
+    impl UserData for MyUserData {}
+
+    Lua::new().context(|lua| {
+        match unsafe { lua.load_c_module("/nonexistent/socket.so", "socket.core") } {
+            Err(Error::RuntimeError(message)) => {
+                assert!(message.contains("cannot load C module 'socket.core'"))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let stdout: AnyUserData = lua.load("io.stdout").eval().unwrap();
+        let ptr = stdout.foreign_ptr("FILE*").unwrap().unwrap();
+        assert_eq!(ptr as *const _, stdout.to_pointer());
+        assert!(stdout.foreign_ptr("no such type").unwrap().is_none());
+        let userdata = lua.create_userdata(MyUserData).unwrap();
+        assert!(userdata.foreign_ptr("FILE*").unwrap().is_none());
+
+        let open: Function = lua.load("io.open").eval().unwrap();
+        match open.call_checked::<_, AnyUserData>("/nonexistent/file") {
+            Err(Error::RuntimeError(message)) => assert!(message.contains("/nonexistent/file")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let find: Function = lua.load("string.find").eval().unwrap();
+        assert_eq!(
+            find.call_checked::<_, Option<i64>>(("abc", "x")).unwrap(),
+            None
+        );
+    });
+}
+
+#[test]
+fn test_user_data_base() {
+    struct Base {
+        value: i64,
+    }
+
+    impl UserData for Base {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, this, ()| Ok(this.value));
+            methods.add_method_mut("set", |_, this, value| {
+                this.value = value;
+                Ok(())
+            });
+            methods.add_method("name", |_, _, ()| Ok("base"));
+            methods.add_field_method_get("value", |_, this| Ok(this.value));
+            methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.value));
+        }
+    }
+
+    struct Derived {
+        base: Base,
+        extra: i64,
+    }
+
+    impl AsRef<Base> for Derived {
+        fn as_ref(&self) -> &Base {
+            &self.base
+        }
+    }
+
+    impl AsMut<Base> for Derived {
+        fn as_mut(&mut self) -> &mut Base {
+            &mut self.base
+        }
+    }
+
+    impl UserData for Derived {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_base::<Base>();
+            methods.add_method("name", |_, _, ()| Ok("derived"));
+            methods.add_method("extra", |_, this, ()| Ok(this.extra));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals
+            .set(
+                "derived",
+                Derived {
+                    base: Base { value: 1 },
+                    extra: 2,
+                },
+            )
+            .unwrap();
+        globals.set("base", Base { value: 3 }).unwrap();
+        lua.load(
+            r#"
+                assert(derived:get() == 1 and derived.value == 1 and #derived == 1)
+                derived:set(5)
+                assert(derived:get() == 5 and derived.value == 5)
+                assert(derived:name() == "derived" and derived:extra() == 2)
+                assert(base:name() == "base" and base.extra == nil)
+                assert(not pcall(derived.get, base))
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let derived: AnyUserData = globals.get("derived").unwrap();
+        assert_eq!(derived.borrow::<Derived>().unwrap().base.value, 5);
+    });
+}
+
+#[test]
+fn test_user_data_type_name() {
+    struct MyUserData;
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("method", |_, _, ()| Ok(()));
+            methods.add_meta_method(MetaMethod::ToString, |_, _, ()| Ok("MyUserData"));
+        }
+    }
+
+    struct Scoped<'a>(&'a i64);
+
+    impl<'a> UserData for Scoped<'a> {}
+
+    Lua::new().context(|lua| {
+        let userdata = lua.create_userdata(MyUserData).unwrap();
+        assert!(userdata.is::<MyUserData>());
+        assert_eq!(
+            userdata.type_name(),
+            Some(std::any::type_name::<MyUserData>())
+        );
+
+        let metatable = userdata.metatable().unwrap().unwrap();
+        assert!(metatable.contains_key("__tostring").unwrap());
+        metatable.set("__tostring", Value::Nil).unwrap();
+        assert_eq!(
+            lua.load("return tostring(...)")
+                .call::<_, std::string::String>(userdata.clone())
+                .unwrap(),
+            "MyUserData"
+        );
+
+        let stdout: AnyUserData = lua.load("io.stdout").eval().unwrap();
+        assert_eq!(stdout.type_name(), None);
+        assert!(stdout.metatable().unwrap().is_some());
+
+        let value = 1;
+        lua.scope(|scope| {
+            let scoped = scope.create_nonstatic_userdata(Scoped(&value)).unwrap();
+            assert_eq!(scoped.type_name(), Some(std::any::type_name::<Scoped>()));
+        });
+    });
+}
+
+#[test]
+fn test_register_userdata_type() {
+    struct First;
+    struct Second;
+
+    impl UserData for First {}
+
+    impl UserData for Second {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, _, ()| Ok(2));
+        }
+    }
+
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.create_userdata(First).unwrap();
+        match lua.create_registered_userdata(First) {
+            Err(Error::UserDataTypeNotRegistered(type_name)) => {
+                assert!(type_name.ends_with("First"))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        lua.register_userdata_type::<Second>().unwrap();
+        lua.register_userdata_type::<First>().unwrap();
+        lua.register_userdata_type::<Second>().unwrap();
+        let second = lua.create_registered_userdata(Second).unwrap();
+        assert_eq!(
+            lua.load("return (...):get()")
+                .call::<_, i64>(second)
+                .unwrap(),
+            2
+        );
+        lua.create_registered_userdata(First).unwrap();
+    });
+
+    let types = lua.registered_types();
+    assert_eq!(types.len(), 2);
+    assert!(types[0].type_name.ends_with("First"));
+    assert!(types[1].type_name.ends_with("Second"));
+}
+
+#[test]
+fn test_user_data_call() {
+    struct Counter {
+        step: i64,
+        count: i64,
+    }
+
+    impl UserData for Counter {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("count", |_, this, ()| Ok(this.count));
+            methods.add_call_mut(|_, this, times: Option<i64>| {
+                this.count += this.step * times.unwrap_or(1);
+                Ok(this.count)
+            });
+        }
+    }
+
+    struct Adder(i64);
+
+    impl UserData for Adder {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_call(|_, this, (a, b): (i64, i64)| Ok(this.0 + a + b));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals
+            .set("counter", Counter { step: 2, count: 0 })
+            .unwrap();
+        globals.set("add", Adder(100)).unwrap();
+        lua.load(
+            r#"
+                assert(counter() == 2)
+                assert(counter(3) == 8)
+                assert(counter:count() == 8)
+                assert(add(1, 2) == 103)
+                assert(not pcall(add, "x", 2))
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_dyn_user_data() {
+    struct Token(u32);
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals
+            .set(
+                "issue",
+                lua.create_function(|_, id: u32| Ok(DynUserData::new(Token(id))))
+                    .unwrap(),
+            )
+            .unwrap();
+        globals
+            .set(
+                "redeem",
+                lua.create_function(|_, token: AnyUserData| {
+                    let mut token = token.borrow_dyn_mut::<Token>()?;
+                    token.0 += 1;
+                    Ok(token.0)
+                })
+                .unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            lua.load("local t = issue(41); redeem(t); return redeem(t)")
+                .eval::<u32>()
+                .unwrap(),
+            43
+        );
+        assert!(lua.load("issue(1).field").exec().is_err());
+
+        let other: AnyUserData = lua.create_userdata(DynUserData::new("text")).unwrap();
+        assert_eq!(other.type_name(), Some("rlua::dynamic::DynUserData"));
+        match other.borrow_dyn::<Token>() {
+            Err(Error::UserDataTypeMismatch) => {}
+            r => panic!("expected UserDataTypeMismatch, got {:?}", r.map(|_| ())),
+        }
+        assert_eq!(*other.borrow_dyn::<&str>().unwrap(), "text");
+        match lua.load("redeem(issue)").exec() {
+            Err(Error::CallbackError { .. }) => {}
+            r => panic!("expected CallbackError, got {:?}", r),
+        }
+    });
+
+    let dynamic = DynUserData::new(Token(7));
+    assert!(dynamic.is::<Token>());
+    assert!(dynamic.type_name().ends_with("Token"));
+    let dynamic = dynamic.downcast::<Vec<u8>>().unwrap_err();
+    assert_eq!(dynamic.downcast::<Token>().unwrap().0, 7);
+}
+
+#[test]
+fn test_user_data_destroy() {
+    struct Resource(Arc<()>);
+    struct Other;
+
+    impl UserData for Other {}
+
+    impl UserData for Resource {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("ping", |_, _, ()| Ok("pong"));
+            methods.add_function("close", |_, ud: AnyUserData| ud.destroy::<Resource>());
+        }
+    }
+
+    let rc = Arc::new(());
+    Lua::new().context(|lua| {
+        let ud = lua.create_userdata(Resource(rc.clone())).unwrap();
+        lua.globals().set("res", ud.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&rc), 2);
+
+        match ud.take::<Other>() {
+            Err(Error::UserDataTypeMismatch) => {}
+            r => panic!("expected UserDataTypeMismatch, got {:?}", r.map(|_| ())),
+        }
+        lua.load("res:close()").exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 1);
+        assert!(!ud.is::<Resource>());
+
+        match lua.load("res:ping()").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::UserDataDestructed => {}
+                ref err => panic!("expected UserDataDestructed, got {:?}", err),
+            },
+            r => panic!("expected CallbackError, got {:?}", r),
+        }
+        match ud.take::<Resource>() {
+            Err(Error::UserDataDestructed) => {}
+            r => panic!("expected UserDataDestructed, got {:?}", r.map(|_| ())),
+        }
+        match ud.borrow::<Resource>() {
+            Err(Error::UserDataDestructed) => {}
+            r => panic!("expected UserDataDestructed, got {:?}", r.map(|_| ())),
+        }
+
+        let busy = lua.create_userdata(Resource(rc.clone())).unwrap();
+        let borrowed = busy.borrow::<Resource>().unwrap();
+        match busy.destroy::<Resource>() {
+            Err(Error::UserDataBorrowMutError) => {}
+            r => panic!("expected UserDataBorrowMutError, got {:?}", r),
+        }
+        drop(borrowed);
+        let Resource(inner) = busy.take::<Resource>().unwrap();
+        assert!(Arc::ptr_eq(&inner, &rc));
+        drop(inner);
+        assert_eq!(Arc::strong_count(&rc), 1);
+
+        lua.scope(|scope| {
+            let ud = scope.create_static_userdata(Resource(rc.clone())).unwrap();
+            ud.destroy::<Resource>().unwrap();
+            assert_eq!(Arc::strong_count(&rc), 1);
+            let scoped = scope.create_static_userdata(Resource(rc.clone())).unwrap();
+            lua.globals().set("scoped", scoped).unwrap();
+        });
+        assert_eq!(Arc::strong_count(&rc), 1);
+
+        // Userdata of a scope which has ended are still reported as destructed callbacks.
+        match lua.load("scoped:ping()").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match **cause {
+                Error::CallbackDestructed => {}
+                ref err => panic!("expected CallbackDestructed, got {:?}", err),
+            },
+            r => panic!("expected CallbackError, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_deferred_finalization() {
+    struct Heavy(Arc<()>);
+    impl UserData for Heavy {}
+
+    struct Light(Arc<()>);
+    impl UserData for Light {}
+
+    let rc = Arc::new(());
+    let lua = Lua::new();
+    lua.set_deferred_finalization::<Heavy>(true);
+    lua.context(|lua| {
+        lua.globals().set("heavy", Heavy(rc.clone())).unwrap();
+        lua.globals().set("light", Light(rc.clone())).unwrap();
+        lua.load("heavy, light = nil, nil").exec().unwrap();
+    });
+    lua.gc_collect().unwrap();
+    lua.gc_collect().unwrap();
+    assert_eq!(Arc::strong_count(&rc), 2);
+    assert_eq!(lua.pending_finalizers(), 1);
+    assert_eq!(lua.drain_finalizers(), 1);
+    assert_eq!(Arc::strong_count(&rc), 1);
+    assert_eq!(lua.drain_finalizers(), 0);
+
+    lua.context(|lua| {
+        lua.globals().set("heavy", Heavy(rc.clone())).unwrap();
+        lua.load("heavy = nil").exec().unwrap();
+    });
+    lua.gc_collect().unwrap();
+    lua.gc_collect().unwrap();
+    assert_eq!(Arc::strong_count(&rc), 2);
+    drop(lua);
+    assert_eq!(Arc::strong_count(&rc), 1);
+
+    let lua = Lua::new();
+    lua.set_deferred_finalization::<Heavy>(true);
+    lua.set_deferred_finalization::<Heavy>(false);
+    lua.context(|lua| {
+        lua.globals().set("heavy", Heavy(rc.clone())).unwrap();
+        lua.load("heavy = nil").exec().unwrap();
+    });
+    lua.gc_collect().unwrap();
+    lua.gc_collect().unwrap();
+    assert_eq!(Arc::strong_count(&rc), 1);
+    assert_eq!(lua.pending_finalizers(), 0);
+}
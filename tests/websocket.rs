@@ -0,0 +1,88 @@
+#![cfg(feature = "websocket")]
+
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use rlua::{Error, Function, Lua, NetPolicy};
+use tungstenite::Message;
+
+mod common;
+
+use common::block_on;
+
+#[test]
+fn test_websocket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut ws = tungstenite::accept(stream).unwrap();
+        match ws.read().unwrap() {
+            Message::Text(text) => ws.send(Message::Text(text.to_uppercase())).unwrap(),
+            m => panic!("unexpected message {:?}", m),
+        }
+        match ws.read().unwrap() {
+            Message::Binary(data) => ws.send(Message::Binary(data)).unwrap(),
+            m => panic!("unexpected message {:?}", m),
+        }
+        ws.close(None).unwrap();
+        while ws.read().is_ok() {}
+    });
+
+    Lua::new().context(|lua| {
+        let websocket = lua
+            .create_websocket_module(NetPolicy {
+                allow_tcp: true,
+                allowed_hosts: vec![format!("127.0.0.1:{}", port)],
+                timeout: Some(Duration::from_secs(10)),
+                ..NetPolicy::default()
+            })
+            .unwrap();
+        lua.globals().set("websocket", websocket).unwrap();
+
+        let script: Function = lua
+            .load(
+                r#"
+                    function(url)
+                        local ws = websocket.connect(url)
+                        ws:send("hello")
+                        local text = ws:receive()
+                        ws:send_binary("\0\1\2")
+                        local data = ws:receive()
+                        assert(ws:receive() == nil)
+                        ws:close()
+                        return text, data
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let (text, data) = block_on(
+            script.call_async::<_, (String, rlua::String)>(format!("ws://127.0.0.1:{}/", port)),
+        )
+        .unwrap();
+        assert_eq!(text, "HELLO");
+        assert_eq!(data.as_bytes(), b"\0\x01\x02");
+
+        let connect = |url: &str| {
+            let connect: Function = lua.load("websocket.connect").eval().unwrap();
+            match block_on(connect.call_async::<_, ()>(url)) {
+                Err(Error::CallbackError { cause, .. }) => match &*cause {
+                    Error::RuntimeError(message) => message.clone(),
+                    err => panic!("unexpected error {:?}", err),
+                },
+                r => panic!("unexpected result {:?}", r),
+            }
+        };
+        assert_eq!(
+            connect("ws://127.0.0.1:1/"),
+            "connecting to 127.0.0.1:1 is not allowed"
+        );
+        assert_eq!(
+            connect("wss://127.0.0.1/"),
+            "unsupported websocket url scheme 'wss'"
+        );
+    });
+    server.join().unwrap();
+}